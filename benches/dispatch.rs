@@ -0,0 +1,56 @@
+//! Interpreter loop throughput on programs dominated by dispatch.
+//!
+//! Compare revisions with criterion baselines:
+//! `cargo bench --bench dispatch -- --save-baseline before`, then
+//! `cargo bench --bench dispatch -- --baseline before` on the change.
+
+use alya_vm::assembler;
+use alya_vm::execution::{VmEvent, VM};
+use alya_vm::instruction::Program;
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+
+const ITERATIONS: u64 = 10_000;
+
+/// Counts down from `ITERATIONS` with a handful of ALU operations per pass
+fn tight_loop() -> Program {
+    let source = format!(
+        "@n := {ITERATIONS}\n@one := 1\n@acc := 0\n@zero := 0\n\
+         loop:\n\
+         @acc := @acc + @n\n\
+         @acc := @acc ^ @one\n\
+         @n := @n - @one\n\
+         compare @n @zero\n\
+         jne loop\n\
+         halt\n"
+    );
+    assembler::assemble(&source, "tight_loop.alya").expect("benchmark program assembles")
+}
+
+fn bench_dispatch(c: &mut Criterion) {
+    let program = tight_loop();
+    let mut vm = VM::new();
+    vm.print_immediately = false;
+    vm.run(&program).expect("benchmark program runs");
+    let executed = vm.instruction_count;
+
+    let mut group = c.benchmark_group("dispatch");
+    group.throughput(Throughput::Elements(executed));
+    group.bench_function("tight_loop", |b| b.iter(|| {
+        vm.run(black_box(&program)).unwrap();
+    }));
+    group.bench_function("tight_loop_iter", |b| b.iter(|| {
+        for step in vm.run_iter(black_box(&program)) {
+            black_box(step.unwrap());
+        }
+    }));
+
+    // Observers see each executed instruction, so this path hands them out
+    vm.add_observer(|event: &VmEvent| { black_box(event); });
+    group.bench_function("tight_loop_observed", |b| b.iter(|| {
+        vm.run(black_box(&program)).unwrap();
+    }));
+    group.finish();
+}
+
+criterion_group!(benches, bench_dispatch);
+criterion_main!(benches);
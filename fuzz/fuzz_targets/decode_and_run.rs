@@ -0,0 +1,9 @@
+//! Raw bytecode through the decoder and dispatcher: `cargo fuzz run decode_and_run`
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = alya_vm::fuzz::fuzz_decode_and_run(data);
+});
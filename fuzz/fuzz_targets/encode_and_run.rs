@@ -0,0 +1,10 @@
+//! Well-formed instruction sequences: `cargo fuzz run encode_and_run`
+
+#![no_main]
+
+use alya_vm::instruction::Instruction;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|instructions: Vec<Instruction>| {
+    let _ = alya_vm::fuzz::fuzz_encode_and_run(&instructions);
+});
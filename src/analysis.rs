@@ -0,0 +1,218 @@
+//! Static analysis of bytecode.
+//!
+//! `analyze` splits a program into functions (the entry point plus every
+//! call target), builds the call graph between them, works out how deep
+//! calls can nest, and finds instructions no function can reach. Recursion
+//! makes the depth unbounded; the offending cycle is reported instead.
+//! Calls through a register are not followed, so a function reached only
+//! that way shows up as unreachable.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+use crate::instruction::disassembler::label_for;
+use crate::instruction::{Instruction, Program};
+
+/// Instructions reachable from one entry point without following calls
+#[derive(Debug, Clone, PartialEq)]
+pub struct Function {
+    pub entry: usize,
+    /// Indices of the instructions the function can execute
+    pub body: BTreeSet<usize>,
+    /// Entry points of the functions it calls
+    pub calls: BTreeSet<usize>,
+}
+
+/// Functions keyed by entry point. Entry 0 is the program entry.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CallGraph {
+    pub functions: BTreeMap<usize, Function>,
+}
+
+/// How deeply calls can nest at run time
+#[derive(Debug, Clone, PartialEq)]
+pub enum CallDepth {
+    /// At most this many frames on the call stack
+    Bounded(usize),
+    /// Recursion through these entry points, in call order
+    Unbounded { cycle: Vec<usize> },
+}
+
+/// Everything `analyze` found
+#[derive(Debug, Clone, PartialEq)]
+pub struct Analysis {
+    pub call_graph: CallGraph,
+    pub call_depth: CallDepth,
+    /// Instructions no function reaches
+    pub unreachable: Vec<usize>,
+}
+
+/// Display name for the function starting at `entry`: its symbol, a
+/// generated label, or `<entry>` for an unnamed program entry
+pub fn function_name(program: &Program, entry: usize) -> String {
+    match program.symbol_at(entry) {
+        Some(symbol) => symbol.to_string(),
+        None if entry == 0 => "<entry>".to_string(),
+        None => label_for(entry),
+    }
+}
+
+/// Where control can go after `pc` inside the same function
+fn successors(instruction: &Instruction, pc: usize) -> Vec<usize> {
+    match instruction {
+        Instruction::Halt | Instruction::Exit { .. } | Instruction::Return | Instruction::IRet => vec![],
+        Instruction::Jump { target } => vec![*target],
+        Instruction::Call { .. } => vec![pc + 1],
+        Instruction::Switch { count, .. } => (pc + 1..=pc + 1 + *count as usize).collect(),
+        other => match other.target() {
+            Some(target) => vec![target, pc + 1],
+            None => vec![pc + 1],
+        },
+    }
+}
+
+impl CallGraph {
+    /// Discover the functions of `program`, starting from instruction 0
+    pub fn build(program: &Program) -> Self {
+        let mut functions = BTreeMap::new();
+        let mut pending = if program.is_empty() { vec![] } else { vec![0] };
+
+        while let Some(entry) = pending.pop() {
+            if functions.contains_key(&entry) {
+                continue;
+            }
+            let mut function = Function { entry, body: BTreeSet::new(), calls: BTreeSet::new() };
+            let mut worklist = vec![entry];
+            while let Some(pc) = worklist.pop() {
+                if pc >= program.len() || !function.body.insert(pc) {
+                    continue;
+                }
+                let instruction = &program.instructions[pc];
+                if let Instruction::Call { target } = instruction {
+                    if *target < program.len() {
+                        function.calls.insert(*target);
+                        pending.push(*target);
+                    }
+                }
+                worklist.extend(successors(instruction, pc));
+            }
+            functions.insert(entry, function);
+        }
+        CallGraph { functions }
+    }
+
+    /// Longest chain of nested calls from the program entry
+    pub fn max_depth(&self) -> CallDepth {
+        let mut depth = BTreeMap::new();
+        let mut path = Vec::new();
+        match self.depth_from(0, &mut depth, &mut path) {
+            Ok(frames) => CallDepth::Bounded(frames),
+            Err(cycle) => CallDepth::Unbounded { cycle },
+        }
+    }
+
+    /// Frames needed below `entry`, or the recursion cycle found on the way
+    fn depth_from(&self, entry: usize, done: &mut BTreeMap<usize, usize>, path: &mut Vec<usize>) -> Result<usize, Vec<usize>> {
+        if let Some(&frames) = done.get(&entry) {
+            return Ok(frames);
+        }
+        if let Some(start) = path.iter().position(|&e| e == entry) {
+            return Err(path[start..].to_vec());
+        }
+        let Some(function) = self.functions.get(&entry) else { return Ok(0) };
+
+        path.push(entry);
+        let mut frames = 0;
+        for &callee in &function.calls {
+            frames = frames.max(1 + self.depth_from(callee, done, path)?);
+        }
+        path.pop();
+        done.insert(entry, frames);
+        Ok(frames)
+    }
+}
+
+/// Run every analysis over `program`
+pub fn analyze(program: &Program) -> Analysis {
+    let call_graph = CallGraph::build(program);
+    let call_depth = call_graph.max_depth();
+    let reached: BTreeSet<usize> = call_graph.functions.values().flat_map(|f| f.body.iter().copied()).collect();
+    let unreachable = (0..program.len()).filter(|pc| !reached.contains(pc)).collect();
+    Analysis { call_graph, call_depth, unreachable }
+}
+
+impl Analysis {
+    /// Human-readable summary, naming functions by symbol where known
+    pub fn report(&self, program: &Program) -> String {
+        let name = |entry: usize| function_name(program, entry);
+
+        let mut out = String::new();
+        let _ = writeln!(out, "Functions: {}", self.call_graph.functions.len());
+        for function in self.call_graph.functions.values() {
+            let _ = write!(out, "  {} @{}: {} instructions", name(function.entry), function.entry, function.body.len());
+            if !function.calls.is_empty() {
+                let callees: Vec<String> = function.calls.iter().map(|&c| name(c)).collect();
+                let _ = write!(out, ", calls {}", callees.join(", "));
+            }
+            out.push('\n');
+        }
+
+        match &self.call_depth {
+            CallDepth::Bounded(frames) => {
+                let _ = writeln!(out, "Max call depth: {}", frames);
+            }
+            CallDepth::Unbounded { cycle } => {
+                let names: Vec<String> = cycle.iter().chain(cycle.first()).map(|&e| name(e)).collect();
+                let _ = writeln!(out, "Max call depth: unbounded (recursion {})", names.join(" -> "));
+            }
+        }
+
+        if self.unreachable.is_empty() {
+            out.push_str("Unreachable instructions: none\n");
+        } else {
+            let pcs: Vec<String> = self.unreachable.iter().map(usize::to_string).collect();
+            let _ = writeln!(out, "Unreachable instructions: {}", pcs.join(", "));
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::Register;
+    use crate::instruction::ProgramBuilder;
+
+    #[test]
+    fn test_call_graph_depth_and_dead_code() {
+        let program = ProgramBuilder::new("t")
+            .call("outer")
+            .halt()
+            .load_imm(Register::R0, 1) // dead
+            .label("outer")
+            .call("inner")
+            .ret()
+            .label("inner")
+            .ret()
+            .build()
+            .unwrap();
+
+        let analysis = analyze(&program);
+        assert_eq!(analysis.call_graph.functions.len(), 3);
+        assert_eq!(analysis.call_depth, CallDepth::Bounded(2));
+        assert_eq!(analysis.unreachable, vec![2]);
+        assert!(analysis.report(&program).contains("outer @3: 2 instructions, calls inner"));
+
+        let recursive = ProgramBuilder::new("r")
+            .call("a")
+            .halt()
+            .label("a")
+            .call("b")
+            .ret()
+            .label("b")
+            .call("a")
+            .ret()
+            .build()
+            .unwrap();
+        assert_eq!(analyze(&recursive).call_depth, CallDepth::Unbounded { cycle: vec![2, 4] });
+    }
+}
@@ -0,0 +1,162 @@
+//! Static library archives: object files bundled with an index of the
+//! labels they define.
+//!
+//! Linking against an archive pulls in only the members that define a
+//! label some object still needs, together with whatever those members
+//! need in turn, so a program pays only for the routines it calls.
+//!
+//! An archive starts with the `ALYL` magic and the format version, then
+//!
+//! - index: (name length, name, member number) per label
+//! - members: (name length, name, object length, object file) per member
+//!
+//! When several members define a label, the index names the first.
+
+use std::collections::{BTreeMap, HashSet, VecDeque};
+use crate::error::VmResult;
+use crate::linker::ObjectFile;
+use crate::loader::{self, Reader};
+
+/// First four bytes of every archive
+pub const ARCHIVE_MAGIC: &[u8; 4] = b"ALYL";
+
+/// An object file stored in an archive
+#[derive(Debug, Clone)]
+pub struct Member {
+    /// Name the object was archived under, usually its file name
+    pub name: String,
+    pub object: ObjectFile,
+}
+
+/// Object files and the labels each defines
+#[derive(Debug, Clone)]
+pub struct Archive {
+    pub members: Vec<Member>,
+    /// Label name to the member that defines it
+    pub index: BTreeMap<String, usize>,
+}
+
+impl Archive {
+    /// Bundle objects, indexing the labels they make visible to others
+    pub fn new(members: Vec<Member>) -> Self {
+        let mut index = BTreeMap::new();
+        for (i, member) in members.iter().enumerate() {
+            for name in defined_labels(&member.object) {
+                index.entry(name.to_string()).or_insert(i);
+            }
+        }
+        Archive { members, index }
+    }
+
+    /// Read an archive. Each member's program is named after the member.
+    pub fn load(bytes: &[u8]) -> VmResult<Self> {
+        let mut reader = Reader::after_header(bytes, ARCHIVE_MAGIC, "archive")?;
+        let mut index = BTreeMap::new();
+        for _ in 0..reader.count("index")? {
+            let name = String::from_utf8_lossy(reader.section("index")?).to_string();
+            index.insert(name, reader.count("index")?);
+        }
+        let mut members = Vec::new();
+        for _ in 0..reader.count("member")? {
+            let name = String::from_utf8_lossy(reader.section("member")?).to_string();
+            let mut object = ObjectFile::load(reader.section("member")?).map_err(|e| {
+                loader::invalid(format!("Invalid archive member '{}': {}", name, e.message()))
+            })?;
+            object.program.name = name.clone();
+            members.push(Member { name, object });
+        }
+        if let Some((name, _)) = index.iter().find(|&(_, &member)| member >= members.len()) {
+            return Err(loader::invalid(format!("Archive index points '{}' past its {} member(s)", name, members.len())));
+        }
+        Ok(Archive { members, index })
+    }
+
+    /// Encode the archive in the format `load` reads
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = loader::header(ARCHIVE_MAGIC);
+        loader::write_u64(&mut bytes, self.index.len());
+        for (name, &member) in &self.index {
+            loader::write_bytes(&mut bytes, name.as_bytes());
+            loader::write_u64(&mut bytes, member);
+        }
+        loader::write_u64(&mut bytes, self.members.len());
+        for member in &self.members {
+            loader::write_bytes(&mut bytes, member.name.as_bytes());
+            loader::write_bytes(&mut bytes, &member.object.encode());
+        }
+        bytes
+    }
+}
+
+/// Labels and data labels of an object that other objects can refer to
+fn defined_labels(object: &ObjectFile) -> impl Iterator<Item = &str> {
+    object.program.symbols.keys().chain(object.data_symbols.keys())
+        .map(String::as_str)
+        .filter(|name| !name.starts_with("__"))
+}
+
+/// `objects` followed by the archive members they need, directly or through
+/// other members, in the order they are pulled in. Archives are searched in
+/// order. Labels no archive defines are left for `linker::link` to report.
+pub fn select_members(objects: &[ObjectFile], archives: &[Archive]) -> Vec<ObjectFile> {
+    let mut selected = objects.to_vec();
+    let mut defined: HashSet<String> = HashSet::new();
+    let mut wanted: VecDeque<String> = VecDeque::new();
+    let mut pulled = HashSet::new();
+    let mut next = 0;
+    loop {
+        for object in &selected[next..] {
+            defined.extend(defined_labels(object).map(str::to_string));
+            wanted.extend(object.relocations.iter().map(|relocation| relocation.symbol.clone()));
+        }
+        next = selected.len();
+
+        let Some(name) = wanted.pop_front() else { break };
+        if defined.contains(&name) {
+            continue;
+        }
+        let found = archives.iter().enumerate()
+            .find_map(|(a, archive)| archive.index.get(&name).map(|&m| (a, m)));
+        if let Some((a, m)) = found.filter(|member| pulled.insert(*member)) {
+            selected.push(archives[a].members[m].object.clone());
+        }
+    }
+    selected
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler;
+    use crate::linker;
+
+    #[test]
+    fn test_archive_pulls_needed_members() {
+        let member = |name: &str, source: &str| Member {
+            name: name.to_string(),
+            object: assembler::assemble_object(source, name).unwrap(),
+        };
+        let archive = Archive::new(vec![
+            member("square.alya", "proc square(x)\n@y := @x * @x\nreturn @y\nendproc\n"),
+            member("quad.alya", "proc quad(x)\n@y := call square(@x)\n@z := call square(@y)\nreturn @z\nendproc\n"),
+            member("unused.alya", "unused:\nreturn\n"),
+        ]);
+        assert_eq!(archive.index["quad"], 1);
+        let archive = Archive::load(&archive.encode()).unwrap();
+        assert_eq!(archive.members[2].object.program.name, "unused.alya");
+
+        let main = assembler::assemble_object("@n := 3\n@q := call quad(@n)\nprint @q\nhalt\n", "main.alya").unwrap();
+        let objects = select_members(&[main], &[archive]);
+        let names: Vec<&str> = objects.iter().map(|object| object.program.name.as_str()).collect();
+        assert_eq!(names, ["main.alya", "quad.alya", "square.alya"]);
+
+        let program = linker::link(&objects).unwrap();
+        let mut vm = crate::execution::VM::new();
+        vm.print_immediately = false;
+        vm.run(&program).unwrap();
+        assert_eq!(vm.output(), ["81"]);
+
+        let err = Archive::load(b"ALYO\x01\x00").unwrap_err();
+        assert!(err.to_string().contains("missing ALYL header"), "{}", err);
+    }
+}
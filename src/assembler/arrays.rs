@@ -0,0 +1,126 @@
+//! Lowering of array declarations to heap allocations and of computed
+//! element accesses to plain indexed ones, run before code generation.
+//!
+//! `@arr := array n` allocates `n` qwords and zeroes them:
+//!
+//! ```text
+//! @__size := @n << 3
+//! @arr := alloc @__size
+//! @__zero := 0
+//! memset @arr @__zero @__size
+//! ```
+//!
+//! `@arr := [1, 2, 3]` keeps the values in the data section as `__array1`
+//! and copies them into a fresh allocation, since the data section is
+//! read-only. Every array shares the `__size`, `__zero` and `__src`
+//! variables, which are free again once the declaration is done.
+//!
+//! Element accesses other than a plain `@arr[@i]` compute their qword
+//! index into `__index` first, so `@arr[@i * 3 + 1]` becomes
+//!
+//! ```text
+//! @__index := @i * 3
+//! @__index := @__index + 1
+//! @x := @arr[@__index]
+//! ```
+
+use super::parser::ast::*;
+
+/// Replace `array` declarations, array literals and computed element
+/// accesses with the statements they stand for
+pub fn lower_arrays(statements: Vec<SpannedStatement>) -> Vec<SpannedStatement> {
+    let mut lowered = Vec::with_capacity(statements.len());
+    let mut literals = 0;
+    for stmt in statements {
+        let (line, file) = (stmt.line, stmt.file.clone());
+        let mut push = |node| lowered.push(SpannedStatement { node, line, file: file.clone() });
+        let var = |name: &str| name.to_string();
+        match stmt.node {
+            Statement::ArrayAlloc { dest, length } => {
+                let bytes = |left: String| Statement::BinOp { dest: var("__size"), left, op: BinOp::Shl, right: Operand::Immediate(3) };
+                match length {
+                    Operand::Immediate(length) => push(Statement::LoadImm { dest: var("__size"), value: length.wrapping_mul(8) }),
+                    Operand::Constant(name) => {
+                        push(Statement::LoadConst { dest: var("__size"), name });
+                        push(bytes(var("__size")));
+                    }
+                    Operand::Variable(name) => push(bytes(name)),
+                }
+                push(Statement::Alloc { dest: dest.clone(), size_var: var("__size") });
+                push(Statement::LoadImm { dest: var("__zero"), value: 0 });
+                push(Statement::MemSet { dest_var: dest, value_var: var("__zero"), size_var: var("__size") });
+            }
+            Statement::ArrayLiteral { dest, values } => {
+                literals += 1;
+                let label = format!("__array{}", literals);
+                let size = values.len() as u64 * 8;
+                push(Statement::Data { label: Some(label.clone()), item: DataItem::Qword(values) });
+                push(Statement::LoadConst { dest: var("__src"), name: label });
+                push(Statement::LoadImm { dest: var("__size"), value: size });
+                push(Statement::Alloc { dest: dest.clone(), size_var: var("__size") });
+                push(Statement::MemCopy { dest_var: dest, src_var: var("__src"), size_var: var("__size") });
+            }
+            Statement::LoadElement { dest, base_var, element } => {
+                let index_var = element_index(element, &mut push);
+                push(Statement::LoadIndexed { dest, base_var, index_var });
+            }
+            Statement::StoreElement { base_var, element, value } => {
+                let index_var = element_index(element, &mut push);
+                push(Statement::StoreIndexed { base_var, index_var, value });
+            }
+            node => push(node),
+        }
+    }
+    lowered
+}
+
+/// Emit the statements that compute an element's qword index and return the
+/// variable that holds it
+fn element_index(element: Element, push: &mut impl FnMut(Statement)) -> String {
+    let tmp = || "__index".to_string();
+    let mut index = match element.index {
+        Operand::Variable(name) => name,
+        Operand::Immediate(value) => {
+            push(Statement::LoadImm { dest: tmp(), value });
+            tmp()
+        }
+        Operand::Constant(name) => {
+            push(Statement::LoadConst { dest: tmp(), name });
+            tmp()
+        }
+    };
+    for (op, operand, identity) in [(BinOp::Mul, element.scale, 1), (BinOp::Add, element.offset, 0)] {
+        if operand != Operand::Immediate(identity) {
+            push(Statement::BinOp { dest: tmp(), left: index, op, right: operand });
+            index = tmp();
+        }
+    }
+    index
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::assembler;
+
+    #[test]
+    fn test_arrays() {
+        let source = "const N := 3\n@primes := [2, 3, 5, N]\n@n := 4\n@squares := array @n\n@zeros := array N\n\
+            @i := 0\nwhile @i < @n\n@p := @primes[@i]\n@p *= @p\n@squares[@i] := @p\n@i += 1\nend\n\
+            @i := 3\n@last := @squares[@i]\nprint @last\n@i := 2\n@z := @zeros[@i]\nprint @z\nhalt\n";
+        let program = assembler::assemble(source, "arrays").unwrap();
+        let mut vm = crate::execution::VM::new();
+        vm.run(&program).unwrap();
+        assert_eq!(vm.output(), &["9", "0"]);
+
+        // Two fields per point: x at offset 0, y at offset 1
+        let source = "const Y := 1\n@points := [1, 2, 3, 4]\n@i := 1\n@y := @points[@i * 2 + Y]\nprint @y\n\
+            @points[@i * 2] := 9\n@x := @points[2]\nprint @x\n@j := 3\n@last := @points[@j -1]\nprint @last\nhalt\n";
+        let program = assembler::assemble(source, "arrays").unwrap();
+        let mut vm = crate::execution::VM::new();
+        vm.run(&program).unwrap();
+        assert_eq!(vm.output(), &["4", "9", "9"]);
+
+        let error = assembler::assemble("@a := 1\n@b := [1, @a]\n", "arrays").unwrap_err();
+        assert!(error.to_string().contains("Array elements must be numbers or constants"));
+    }
+}
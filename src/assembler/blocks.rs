@@ -0,0 +1,166 @@
+//! Lowering of structured blocks to labels and jumps, run before code
+//! generation.
+//!
+//! `while @i < @n ... end` becomes
+//!
+//! ```text
+//! __loop1:
+//! if @i >= @n goto __end1
+//! ...
+//! goto __loop1
+//! __end1:
+//! ```
+//!
+//! `loop ... end` has no test. `break` jumps to the `end` of the innermost
+//! loop and `continue` back to its start.
+//!
+//! `if @a == @b ... else ... end` jumps over its first half to `__else1`
+//! when the comparison fails, and the first half jumps over the second to
+//! `__end1`. Blocks nest, but cannot span a `proc` or `endproc`.
+
+use std::sync::Arc;
+use crate::error::{ErrorCode, VmError};
+use super::parser::ast::*;
+
+/// A `while`, `loop` or `if` whose `end` has not been seen yet
+struct Block {
+    id: usize,
+    keyword: &'static str,
+    /// Whether an `if` block has reached its `else`
+    has_else: bool,
+    line: usize,
+    file: Option<Arc<str>>,
+}
+
+impl Block {
+    fn label(&self, kind: &str) -> String {
+        format!("__{}{}", kind, self.id)
+    }
+
+    fn is_loop(&self) -> bool {
+        self.keyword != "if"
+    }
+}
+
+/// Replace `while`, `loop`, `if` blocks, `else`, `break`, `continue` and
+/// `end` with the labels and jumps they stand for
+pub fn lower_blocks(statements: Vec<SpannedStatement>) -> Result<Vec<SpannedStatement>, VmError> {
+    let mut lowered = Vec::with_capacity(statements.len());
+    let mut open: Vec<Block> = Vec::new();
+    let mut count = 0;
+    for stmt in statements {
+        let (line, file) = (stmt.line, stmt.file.clone());
+        let at = |node| SpannedStatement { node, line, file: file.clone() };
+        let error = |message: &str| in_file(VmError::assembler(ErrorCode::Syntax, message).at_line(line), &file);
+        match stmt.node {
+            Statement::While { .. } | Statement::Loop | Statement::IfBlock { .. } => {
+                count += 1;
+                let keyword = match stmt.node {
+                    Statement::While { .. } => "while",
+                    Statement::Loop => "loop",
+                    _ => "if",
+                };
+                let block = Block { id: count, keyword, has_else: false, line, file: file.clone() };
+                match stmt.node {
+                    Statement::While { left, comparison, right } => {
+                        lowered.push(at(Statement::Label(block.label("loop"))));
+                        lowered.push(at(Statement::If { left, comparison: comparison.negate(), right, label: block.label("end") }));
+                    }
+                    Statement::IfBlock { left, comparison, right } => {
+                        lowered.push(at(Statement::If { left, comparison: comparison.negate(), right, label: block.label("else") }));
+                    }
+                    _ => lowered.push(at(Statement::Label(block.label("loop")))),
+                }
+                open.push(block);
+            }
+            Statement::Break | Statement::Continue => {
+                let word = if matches!(stmt.node, Statement::Break) { "break" } else { "continue" };
+                let Some(block) = open.iter().rev().find(|block| block.is_loop()) else {
+                    return Err(error(&format!("'{}' outside a loop", word)));
+                };
+                let label = block.label(if word == "break" { "end" } else { "loop" });
+                lowered.push(at(Statement::Goto(label)));
+            }
+            Statement::Else => {
+                let block = match open.last_mut() {
+                    Some(block) if !block.is_loop() && !block.has_else => block,
+                    Some(block) if block.has_else => return Err(error("'if' block already has an 'else'")),
+                    _ => return Err(error("'else' without 'if'")),
+                };
+                block.has_else = true;
+                lowered.push(at(Statement::Goto(block.label("end"))));
+                lowered.push(at(Statement::Label(block.label("else"))));
+            }
+            Statement::End => {
+                let Some(block) = open.pop() else {
+                    return Err(error("'end' without 'while', 'loop' or 'if'"));
+                };
+                if block.is_loop() {
+                    lowered.push(at(Statement::Goto(block.label("loop"))));
+                }
+                let skipped_else = !block.is_loop() && !block.has_else;
+                lowered.push(at(Statement::Label(block.label(if skipped_else { "else" } else { "end" }))));
+            }
+            // Reported below as the open block missing its `end`
+            Statement::Proc { .. } | Statement::EndProc if !open.is_empty() => break,
+            node => lowered.push(at(node)),
+        }
+    }
+    match open.pop() {
+        Some(block) => {
+            let error = VmError::assembler(ErrorCode::Syntax, format!("'{}' without 'end'", block.keyword));
+            Err(in_file(error.at_line(block.line), &block.file))
+        }
+        None => Ok(lowered),
+    }
+}
+
+fn in_file(error: VmError, file: &Option<Arc<str>>) -> VmError {
+    match file {
+        Some(file) => error.in_file(file),
+        None => error,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler;
+    use crate::assembler::parser;
+
+    #[test]
+    fn test_lower_blocks() {
+        let source = "@i := 0\n@sum := 0\nwhile @i < 10\n@i += 1\nif @i == 3 goto skip\n@sum += @i\nskip:\nend\nprint @sum\n\
+            loop\n@i -= 1\nif @i > 4 goto next\nbreak\nnext:\ncontinue\nend\nprint @i\nhalt\n";
+        let lowered = lower_blocks(parser::parse(source).unwrap()).unwrap();
+        assert_eq!(lowered[2].node, Statement::Label("__loop1".to_string()));
+        assert_eq!(lowered[3].node, Statement::If {
+            left: "i".to_string(), comparison: Comparison::GreaterEqual, right: Operand::Immediate(10), label: "__end1".to_string(),
+        });
+        let program = assembler::assemble(source, "blocks").unwrap();
+        let mut vm = crate::execution::VM::new();
+        vm.run(&program).unwrap();
+        assert_eq!(vm.output(), &["52", "4"]);
+
+        let source = "@n := 7\nif @n > 5 unsigned\nprint @n\nif @n == 6\nprint @n\nend\nelse\n@n := 0\nprint @n\nend\n\
+            if @n != 7\nprint @n\nelse\n@n += 1\nprint @n\nend\nhalt\n";
+        let program = assembler::assemble(source, "blocks").unwrap();
+        let mut vm = crate::execution::VM::new();
+        vm.run(&program).unwrap();
+        assert_eq!(vm.output(), &["7", "8"]);
+
+        for (source, message, line) in [
+            ("@i := 1\nwhile @i > 0\n@i -= 1\n", "'while' without 'end'", 2),
+            ("end\n", "'end' without 'while', 'loop' or 'if'", 1),
+            ("@a := 1\nif @a == 1\nelse\nelse\nend\n", "'if' block already has an 'else'", 4),
+            ("loop\nelse\nend\n", "'else' without 'if'", 2),
+            ("@a := 1\nloop\nif @a == 1\nbreak\nend\nend\nif @a > 0\n", "'if' without 'end'", 7),
+            ("break\n", "'break' outside a loop", 1),
+            ("loop\nproc f()\nendproc\nend\n", "'loop' without 'end'", 1),
+        ] {
+            let error = assembler::assemble(source, "blocks").unwrap_err();
+            assert!(error.to_string().contains(message), "{}", error);
+            assert_eq!(error.line(), Some(line));
+        }
+    }
+}
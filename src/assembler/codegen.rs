@@ -1,20 +1,102 @@
 //! Code generation — converts AST statements into VM instructions.
 //!
 //! Key responsibility: maps named variables (e.g., `counter`, `x`, `r0`)
-//! to physical registers (R0–R15). Uses a simple linear allocator.
+//! to physical registers (R0–R15). Uses a simple linear allocator that
+//! reuses the registers of variables past their last use (see `liveness`);
+//! once the registers run out, further variables are spilled to stack slots in a
+//! frame addressed from BP. A statement that touches spilled variables
+//! borrows registers for them, saving the registers' own values in the
+//! frame first and restoring them afterwards.
 //! Labels are resolved with a two-pass approach:
 //!   Pass 1: Emit all instructions, recording label positions as they appear
 //!   Pass 2: Resolve placeholders (jumps/calls to labels) using recorded positions
+//! `while`, `loop` and `if` blocks are first lowered to labels and jumps (see
+//! `blocks`), and array declarations and computed element accesses to
+//! allocations and plain indexed accesses (see `arrays`).
+//! Binary operations on known values are folded into one `LoadImm` (see
+//! `fold`).
+//! Constants (`const NAME := value`) are substituted as immediates and must
+//! be defined before they are used. Any other name in an immediate position
+//! is a data label, resolved to its data-section address in pass 2, as is
+//! `&name` when `name` is a data label rather than a code label.
+//! `enum Name { A, B, C }` defines the constants A = 0, B = 1 and C = 2.
+//! Identical string literals share one copy in the data section.
+//! `emit` decodes its bytes into instructions and emits them unchanged; the
+//! register allocator does not see which registers they touch.
+//! For an object file (`generate_object`), a label or procedure that is not
+//! defined is left for the linker: its instruction is listed in
+//! `relocations` and targets 0 until then.
+//!
+//! `proc name(a, b) ... endproc` bodies get their own variables and follow
+//! this calling convention:
+//!   - Parameter i (1-based) lives in Ri. The first four are passed in
+//!     R1–R4; the caller pushes the rest in order and the callee pops them
+//!     into R5, R6, ... on entry. At most 15 parameters.
+//!   - The return value is in R0 (`return value`).
+//!   - R0 and the parameter registers are caller-saved: `call name(args)`
+//!     pushes those holding the caller's variables and pops them afterwards.
+//!   - Every other register the body uses is callee-saved: pushed on entry
+//!     and popped before returning. A body that spills also saves BP and
+//!     gets a frame of its own.
+//!
+//! A `proc` can go anywhere outside another proc. Execution that would fall
+//! into its body (a proc before the top-level code, or after a statement
+//! that does not halt or jump) is sent past it with a jump.
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
 use crate::core::Register;
 use crate::instruction::Instruction;
-use crate::error::VmError;
+use crate::error::{ErrorCode, VmError};
 use crate::assembler::parser::ast::*;
+use super::{arrays, blocks, fold, liveness};
+
+/// Output of code generation.
+#[derive(Debug, Clone)]
+pub struct GeneratedCode {
+    /// Resolved instructions
+    pub instructions: Vec<Instruction>,
+    /// Data section contents: read-only data, then `writable_len` bytes of
+    /// writable data
+    pub data: Vec<u8>,
+    /// Length of the writable data at the end of `data`
+    pub writable_len: usize,
+    /// Source line for each instruction
+    pub line_table: Vec<usize>,
+    /// Included file of each instruction's line; `None` for the file being assembled
+    pub file_table: Vec<Option<Arc<str>>>,
+    /// Label name to instruction index
+    pub symbols: BTreeMap<String, usize>,
+    /// Variable name to the register it was given
+    pub var_map: HashMap<String, Register>,
+    /// Variables kept in stack slots, in the order they were spilled
+    pub spilled: Vec<String>,
+    /// Data-section offset of each distinct string, from literals and `.string`
+    pub strings: BTreeMap<usize, String>,
+    /// Data label name to data-section offset
+    pub data_symbols: BTreeMap<String, usize>,
+    /// Instructions that load a data-section address
+    pub data_refs: Vec<usize>,
+    /// Instructions that load a code address (`@f := &label`)
+    pub code_refs: Vec<usize>,
+    /// Instructions referring to a label no statement defines, with the
+    /// label; only object files have any
+    pub relocations: Vec<(usize, String)>,
+    /// Resource name to data-section offset and length
+    pub resources: BTreeMap<String, (usize, usize)>,
+}
 
 /// Generate a list of instructions and debug info from parsed statements.
-pub fn generate(statements: Vec<SpannedStatement>) -> Result<(Vec<Instruction>, Vec<u8>, Vec<usize>), VmError> {
+pub fn generate(statements: Vec<SpannedStatement>) -> Result<GeneratedCode, VmError> {
+    let mut gen = CodeGenerator::new();
+    gen.generate(statements)
+}
+
+/// Like `generate`, leaving undefined labels and procedures to be resolved
+/// by the linker
+pub fn generate_object(statements: Vec<SpannedStatement>) -> Result<GeneratedCode, VmError> {
     let mut gen = CodeGenerator::new();
+    gen.object = true;
     gen.generate(statements)
 }
 
@@ -23,14 +105,119 @@ struct CodeGenerator {
     var_map: HashMap<String, Register>,
     /// Next free general-purpose register index
     next_reg: u8,
+    /// General-purpose registers the allocator may use (`.registers`)
+    gp_count: u8,
+    /// Registers of variables that are no longer live
+    free: Vec<Register>,
+    /// Registers given to variables of the current scope, live or not
+    used: Vec<Register>,
+    /// Top-level variables that are no longer live, with their registers
+    retired: HashMap<String, Register>,
     /// Map from label name to instruction index
     label_map: HashMap<String, usize>,
+    /// Values of the constants defined so far
+    constants: HashMap<String, u64>,
+    /// Map from data label name to offset in the data section
+    data_labels: HashMap<String, usize>,
+    /// Map from resource name to data-section offset and length
+    resources: HashMap<String, (usize, usize)>,
     /// Collected instructions (with possible unresolved label refs)
     instructions: Vec<InstructionSlot>,
     /// Accumulated data strings
     data_section: Vec<u8>,
+    /// Data declared after `.data`, placed after `data_section` at the end
+    writable_section: Vec<u8>,
+    /// Data labels whose offsets are in `writable_section`
+    writable_labels: Vec<String>,
+    /// Whether data declarations go in `writable_section`
+    writable: bool,
+    /// Offset of each string already in the data section, so identical
+    /// literals share one copy
+    strings: HashMap<String, usize>,
     /// Line numbers corresponding to instructions
     line_table: Vec<usize>,
+    /// Files corresponding to instructions
+    file_table: Vec<Option<Arc<str>>>,
+    /// File of the statement being generated
+    current_file: Option<Arc<str>>,
+    /// Stack frame of the top level or of the procedure being generated
+    frame: Frame,
+    /// Registers borrowed for the statement being generated
+    scratch: Vec<Scratch>,
+    /// Every variable spilled so far
+    spilled: Vec<String>,
+    /// Parameter count of each procedure
+    procs: HashMap<String, usize>,
+    /// Procedure calls to check against `procs`: name, argument count, line, file
+    proc_calls: Vec<(String, usize, usize, Option<Arc<str>>)>,
+    /// Procedure being generated
+    proc: Option<ProcScope>,
+    /// Whether undefined labels become relocations instead of errors
+    object: bool,
+}
+
+/// State of the procedure whose body is being generated
+#[derive(Debug)]
+struct ProcScope {
+    name: String,
+    params: usize,
+    /// Where callee-saved pushes go once the body's registers are known
+    body_start: usize,
+    /// Labels defined in the body
+    labels: Vec<String>,
+    /// Placeholder jumps to the epilogue, one per `return`
+    returns: Vec<usize>,
+    /// Jump over the body, when the code before it falls through
+    skip: Option<usize>,
+    /// Caller's variables, restored at `endproc`
+    outer_vars: HashMap<String, Register>,
+    outer_next_reg: u8,
+    outer_free: Vec<Register>,
+    outer_used: Vec<Register>,
+    outer_frame: Frame,
+    line: usize,
+    file: Option<Arc<str>>,
+}
+
+/// Stack slots addressed from BP: slot i is at BP + 8 * i. Slot 0 saves the
+/// first register a statement borrows, since it needs no index register.
+#[derive(Debug, Default)]
+struct Frame {
+    /// Number of slots
+    size: usize,
+    /// Spilled variable name to slot
+    spills: HashMap<String, usize>,
+    /// Slot saving the i-th register a statement borrows
+    saves: Vec<usize>,
+}
+
+impl Frame {
+    fn new_slot(&mut self) -> usize {
+        self.save_slot(0);
+        self.size += 1;
+        self.size - 1
+    }
+
+    fn save_slot(&mut self, index: usize) -> usize {
+        while self.saves.len() <= index {
+            self.saves.push(self.size);
+            self.size += 1;
+        }
+        self.saves[index]
+    }
+}
+
+/// A register borrowed for one statement
+#[derive(Debug)]
+struct Scratch {
+    reg: Register,
+    /// Spilled variable (or `__tmp`) it stands in for; `None` for the
+    /// register that indexes the frame when writing spilled variables back
+    var: Option<String>,
+    /// Slot of the spilled variable
+    slot: Option<usize>,
+    /// Whether the statement writes the variable
+    write: bool,
 }
 
 /// During codegen, some jumps have unknown targets. We use placeholders.
@@ -42,6 +229,19 @@ enum InstructionSlot {
     Call { label: String },
     /// Load address of a string in data section. Value is offset in data_section.
     LoadStringAddress { dest: Register, offset: usize },
+    /// Load the address of a data label, which may be declared later
+    LoadDataAddress { dest: Register, label: String },
+    /// Load the instruction index of a code label
+    LoadCodeAddress { dest: Register, label: String },
+}
+
+/// Instructions with their labels resolved, and the references the linker
+/// has to adjust
+struct Resolved {
+    instructions: Vec<Instruction>,
+    data_refs: Vec<usize>,
+    code_refs: Vec<usize>,
+    relocations: Vec<(usize, String)>,
 }
 
 impl CodeGenerator {
@@ -49,10 +249,30 @@ impl CodeGenerator {
         Self {
             var_map: HashMap::new(),
             next_reg: 0,
+            gp_count: Register::GP_COUNT as u8,
+            free: Vec::new(),
+            used: Vec::new(),
+            retired: HashMap::new(),
             label_map: HashMap::new(),
+            constants: HashMap::new(),
+            data_labels: HashMap::new(),
+            resources: HashMap::new(),
             instructions: Vec::new(),
             data_section: Vec::new(),
+            writable_section: Vec::new(),
+            writable_labels: Vec::new(),
+            writable: false,
+            strings: HashMap::new(),
             line_table: Vec::new(),
+            file_table: Vec::new(),
+            current_file: None,
+            frame: Frame::default(),
+            scratch: Vec::new(),
+            spilled: Vec::new(),
+            procs: HashMap::new(),
+            proc_calls: Vec::new(),
+            proc: None,
+            object: false,
         }
     }
 
@@ -61,6 +281,10 @@ impl CodeGenerator {
         if let Some(&reg) = self.var_map.get(name) {
             return Ok(reg);
         }
+        // Spilled variables and `__tmp` live in borrowed registers
+        if let Some(scratch) = self.scratch.iter().find(|s| s.var.as_deref() == Some(name)) {
+            return Ok(scratch.reg);
+        }
 
         // Check if it's a named register like "r0" ... "r15", "sp", "bp"
         if let Some(reg) = try_parse_register_name(name) {
@@ -68,24 +292,27 @@ impl CodeGenerator {
             return Ok(reg);
         }
 
-        // Special case: if it's our scratch register and all GP are taken,
-        // we "borrow" R15. This is slightly risky but usually fine in this VM.
-        // A better fix would be push/pop, but let's try this first.
-        if name == "__tmp" && self.next_reg >= Register::GP_COUNT as u8 {
-             return Ok(Register::R15);
+        // Reuse the lowest register a dead variable left behind, unless an
+        // explicit register name has claimed it since
+        self.free.retain(|reg| !self.var_map.values().any(|r| r == reg));
+        if let Some(reg) = self.free.iter().min_by_key(|reg| reg.to_u8()).copied() {
+            self.free.retain(|&r| r != reg);
+            self.var_map.insert(name.to_string(), reg);
+            self.used.push(reg);
+            return Ok(reg);
         }
 
         // Allocate the next free register, skipping any already claimed
         loop {
-            if self.next_reg >= Register::GP_COUNT as u8 {
-                return Err(VmError::Assembler(format!(
+            if self.next_reg >= self.gp_count {
+                return Err(VmError::assembler(ErrorCode::OutOfRegisters, format!(
                     "Too many variables: cannot allocate register for '{}' (all {} GP registers in use)",
-                    name, Register::GP_COUNT
+                    name, self.gp_count
                 )));
             }
 
-            let reg = Register::from_u8(self.next_reg)
-                .map_err(|e| VmError::Assembler(format!("{}", e)))?;
+            let reg = Register::general(self.next_reg)
+                .map_err(VmError::from)?;
             self.next_reg += 1;
 
             // Skip if already claimed by an explicit register name
@@ -94,30 +321,480 @@ impl CodeGenerator {
             }
 
             self.var_map.insert(name.to_string(), reg);
+            self.used.push(reg);
             return Ok(reg);
         }
     }
 
-    /// Resolve an Operand to a register, inserting a LoadImm if it's an immediate.
+    /// Free the registers of variables whose live range ended
+    fn retire(&mut self, names: &[String]) {
+        for name in names {
+            if let Some(reg) = self.var_map.remove(name) {
+                self.free.push(reg);
+                if self.proc.is_none() {
+                    self.retired.insert(name.clone(), reg);
+                }
+            }
+        }
+    }
+
+    /// General-purpose registers not yet given to a variable
+    fn free_registers(&self) -> usize {
+        (self.next_reg..self.gp_count)
+            .filter_map(|index| Register::general(index).ok())
+            .chain(self.free.iter().copied())
+            .filter(|reg| !self.var_map.values().any(|r| r == reg))
+            .count()
+    }
+
+    /// Before a statement: give its new variables registers, or stack slots
+    /// once the registers run out, then borrow registers for the spilled
+    /// variables it touches (and for `__tmp` if none is free)
+    fn borrow_registers(&mut self, node: &Statement, line: usize) -> Result<(), VmError> {
+        if matches!(node, Statement::Proc { .. } | Statement::EndProc) {
+            return Ok(());
+        }
+        let (writes, reads) = node.accesses();
+        let mut names: Vec<&str> = Vec::new();
+        for name in writes.iter().chain(&reads) {
+            if !names.contains(name) {
+                names.push(name);
+            }
+        }
+
+        let new: Vec<&str> = names.iter().copied()
+            .filter(|name| !self.var_map.contains_key(*name) && !self.frame.spills.contains_key(*name))
+            .filter(|name| try_parse_register_name(name).is_none())
+            .collect();
+        let wants_tmp = uses_tmp(node) && !self.var_map.contains_key("__tmp");
+        let mut free = self.free_registers();
+        if new.len() + wants_tmp as usize > free {
+            // Variables are allocated eagerly here so `__tmp` cannot take
+            // the last register from them
+            for name in new {
+                if free > 0 {
+                    self.resolve_var(name)?;
+                    free -= 1;
+                } else {
+                    let slot = self.frame.new_slot();
+                    self.frame.spills.insert(name.to_string(), slot);
+                    self.spilled.push(name.to_string());
+                }
+            }
+        }
+        let tmp_borrowed = wants_tmp && free == 0;
+
+        let spilled: Vec<(&str, usize)> = names.iter()
+            .filter_map(|&name| self.frame.spills.get(name).map(|&slot| (name, slot)))
+            .collect();
+        if spilled.is_empty() && !tmp_borrowed {
+            return Ok(());
+        }
+
+        // R0 is left alone so `return` can load the result into it
+        let busy: Vec<Register> = names.iter().chain(&["__tmp"]).filter_map(|name| self.var_map.get(*name)).copied().collect();
+        let mut candidates = (1..self.gp_count)
+            .filter_map(|index| Register::general(index).ok())
+            .filter(|reg| !busy.contains(reg));
+        let mut next = || candidates.next().ok_or_else(|| VmError::assembler(ErrorCode::OutOfRegisters,
+            "No register left to borrow for a spilled variable"));
+
+        let mut scratch = Vec::new();
+        for &(name, slot) in &spilled {
+            scratch.push(Scratch { reg: next()?, var: Some(name.to_string()), slot: Some(slot), write: writes.contains(&name) });
+        }
+        if tmp_borrowed {
+            scratch.push(Scratch { reg: next()?, var: Some("__tmp".to_string()), slot: None, write: false });
+        }
+        if scratch.iter().any(|s| s.write) {
+            scratch.push(Scratch { reg: next()?, var: None, slot: None, write: false });
+        }
+
+        // Save the borrowed registers: the first at [BP], the rest through it
+        let first = scratch[0].reg;
+        self.frame.save_slot(0);
+        self.push_instr(Instruction::Store { src: first, addr_reg: Register::BP }, line);
+        for (index, borrowed) in scratch.iter().enumerate().skip(1) {
+            let save = self.frame.save_slot(index);
+            self.push_instr(Instruction::LoadImm { dest: first, value: save as u64 }, line);
+            self.push_instr(Instruction::StoreIndexed { src: borrowed.reg, base_reg: Register::BP, index_reg: first }, line);
+        }
+        for borrowed in &scratch {
+            if let (Some(var), Some(slot)) = (&borrowed.var, borrowed.slot) {
+                if reads.contains(&var.as_str()) {
+                    self.push_instr(Instruction::LoadImm { dest: borrowed.reg, value: slot as u64 }, line);
+                    self.push_instr(Instruction::LoadIndexed { dest: borrowed.reg, base_reg: Register::BP, index_reg: borrowed.reg }, line);
+                }
+            }
+        }
+        self.scratch = scratch;
+        Ok(())
+    }
+
+    /// After a statement (or before its final jump): write spilled
+    /// variables back to their slots and restore the borrowed registers
+    fn release_registers(&mut self, line: usize) {
+        let scratch = std::mem::take(&mut self.scratch);
+        let Some(first) = scratch.first().map(|s| s.reg) else { return };
+
+        if let Some(index_reg) = scratch.iter().find(|s| s.var.is_none()).map(|s| s.reg) {
+            for borrowed in scratch.iter().filter(|s| s.write) {
+                let slot = borrowed.slot.unwrap_or_default();
+                self.push_instr(Instruction::LoadImm { dest: index_reg, value: slot as u64 }, line);
+                self.push_instr(Instruction::StoreIndexed { src: borrowed.reg, base_reg: Register::BP, index_reg }, line);
+            }
+        }
+        for (index, borrowed) in scratch.iter().enumerate().skip(1) {
+            let save = self.frame.saves[index];
+            self.push_instr(Instruction::LoadImm { dest: first, value: save as u64 }, line);
+            self.push_instr(Instruction::LoadIndexed { dest: borrowed.reg, base_reg: Register::BP, index_reg: first }, line);
+        }
+        self.push_instr(Instruction::Load { dest: first, addr_reg: Register::BP }, line);
+    }
+
+    /// Reserve the top-level frame at the start of the program and point
+    /// BP at it. Everything after moves down, so labels and resolved
+    /// targets shift with it.
+    fn emit_top_frame(&mut self) {
+        if self.frame.size == 0 {
+            return;
+        }
+        let mut prologue = vec![Instruction::Push { src: Register::BP }; self.frame.size];
+        prologue.push(Instruction::Move { dest: Register::BP, src: Register::SP });
+        let count = prologue.len();
+
+        for slot in &mut self.instructions {
+            if let InstructionSlot::Real(instruction) = slot {
+                if let Some(target) = instruction.target_mut() {
+                    *target += count;
+                }
+            }
+        }
+        for index in self.label_map.values_mut() {
+            *index += count;
+        }
+        let line = self.line_table.first().copied().unwrap_or(1);
+        self.instructions.splice(0..0, prologue.into_iter().map(InstructionSlot::Real));
+        self.line_table.splice(0..0, std::iter::repeat_n(line, count));
+        self.file_table.splice(0..0, std::iter::repeat_n(None, count));
+    }
+
+    fn define_const(&mut self, name: String, value: u64) -> Result<(), VmError> {
+        if self.constants.contains_key(&name) {
+            return Err(VmError::assembler(ErrorCode::DuplicateConstant, format!(
+                "Constant '{}' is already defined", name
+            )));
+        }
+        self.constants.insert(name, value);
+        Ok(())
+    }
+
+    /// Value of a constant defined earlier in the program
+    fn resolve_const(&self, name: &str) -> Result<u64, VmError> {
+        self.constants.get(name).copied().ok_or_else(|| {
+            VmError::assembler(ErrorCode::UndefinedConstant, format!("Undefined constant: '{}'", name))
+        })
+    }
+
+    /// Load a constant's value, or else a data label's address, into `dest`
+    fn load_name(&mut self, dest: Register, name: &str, line: usize) {
+        match self.constants.get(name) {
+            Some(&value) => self.push_instr(Instruction::LoadImm { dest, value }, line),
+            None => self.push_slot(InstructionSlot::LoadDataAddress { dest, label: name.to_string() }, line),
+        }
+    }
+
+    /// Resolve an Operand to a register, inserting a LoadImm if it's an immediate or name.
     fn resolve_operand(&mut self, operand: &Operand, line: usize) -> Result<Register, VmError> {
+        if let Operand::Variable(name) = operand {
+            return self.resolve_var(name);
+        }
+        // Reuse the same temporary register name everywhere to avoid exhaustion
+        let temp_name = "__tmp";
+        let reg = self.resolve_var(temp_name)?;
         match operand {
-            Operand::Variable(name) => self.resolve_var(name),
-            Operand::Immediate(value) => {
-                // Reuse the same temporary register name everywhere to avoid exhaustion
-                let temp_name = "__tmp";
-                let reg = self.resolve_var(temp_name)?;
-                self.push_instr(
-                    Instruction::LoadImm { dest: reg, value: *value },
-                    line
-                );
-                Ok(reg)
+            Operand::Constant(name) => self.load_name(reg, name, line),
+            Operand::Immediate(value) => self.push_instr(
+                Instruction::LoadImm { dest: reg, value: *value },
+                line
+            ),
+            Operand::Variable(_) => unreachable!(),
+        }
+        Ok(reg)
+    }
+
+    /// Put the value of `operand` in `dest`
+    fn load_operand(&mut self, dest: Register, operand: &Operand, line: usize) -> Result<(), VmError> {
+        match operand {
+            Operand::Variable(name) => {
+                let src = self.resolve_var(name)?;
+                if src != dest {
+                    self.push_instr(Instruction::Move { dest, src }, line);
+                }
+            }
+            Operand::Immediate(value) => self.push_instr(Instruction::LoadImm { dest, value: *value }, line),
+            Operand::Constant(name) => self.load_name(dest, name, line),
+        }
+        Ok(())
+    }
+
+    /// Start a procedure: give its parameters their registers and pop the
+    /// ones passed on the stack
+    fn begin_proc(&mut self, name: String, params: Vec<String>, line: usize) -> Result<(), VmError> {
+        if let Some(outer) = &self.proc {
+            return Err(VmError::assembler(ErrorCode::Syntax, format!(
+                "Procedure '{}' cannot be nested inside '{}'", name, outer.name
+            )));
+        }
+        if params.len() >= Register::GP_COUNT {
+            return Err(VmError::assembler(ErrorCode::ArgumentCount, format!(
+                "Procedure '{}' has {} parameters; at most {} are supported", name, params.len(), Register::GP_COUNT - 1
+            )));
+        }
+
+        let here = self.instructions.len();
+        let falls_through = !matches!(
+            self.instructions.last(),
+            Some(InstructionSlot::Jump { .. } | InstructionSlot::Real(
+                Instruction::Halt | Instruction::Exit { .. } | Instruction::Jump { .. } | Instruction::Return
+            ))
+        ) || self.label_map.values().any(|&index| index == here);
+        let skip = falls_through.then(|| {
+            self.push_instr(Instruction::Jump { target: 0 }, line);
+            here
+        });
+
+        self.label_map.insert(name.clone(), self.instructions.len());
+        self.procs.insert(name.clone(), params.len());
+        let outer_vars = std::mem::take(&mut self.var_map);
+        let outer_next_reg = std::mem::replace(&mut self.next_reg, 0);
+        let outer_free = std::mem::take(&mut self.free);
+        let outer_used = std::mem::take(&mut self.used);
+        let outer_frame = std::mem::take(&mut self.frame);
+        for (i, param) in params.iter().enumerate() {
+            self.var_map.insert(param.clone(), Register::from_u8(i as u8 + 1).map_err(VmError::from)?);
+        }
+        // Stack parameters were pushed in order, so the last is on top
+        for i in (4..params.len()).rev() {
+            self.push_instr(Instruction::Pop { dest: Register::from_u8(i as u8 + 1).map_err(VmError::from)? }, line);
+        }
+
+        self.proc = Some(ProcScope {
+            name,
+            params: params.len(),
+            body_start: self.instructions.len(),
+            labels: Vec::new(),
+            returns: Vec::new(),
+            skip,
+            outer_vars,
+            outer_next_reg,
+            outer_free,
+            outer_used,
+            outer_frame,
+            line,
+            file: self.current_file.clone(),
+        });
+        Ok(())
+    }
+
+    /// Finish a procedure: save the callee-saved registers its body used,
+    /// emit the epilogue and point every `return` at it
+    fn end_proc(&mut self, line: usize) -> Result<(), VmError> {
+        let Some(mut scope) = self.proc.take() else {
+            return Err(VmError::assembler(ErrorCode::Syntax, "'endproc' without 'proc'"));
+        };
+
+        // A final `return` would only jump to the next instruction
+        let end = self.instructions.len();
+        if scope.returns.last() == Some(&(end.wrapping_sub(1))) {
+            scope.returns.pop();
+            self.instructions.pop();
+            self.line_table.pop();
+            self.file_table.pop();
+            for label in &scope.labels {
+                if let Some(index) = self.label_map.get_mut(label).filter(|index| **index == end) {
+                    *index -= 1;
+                }
+            }
+        }
+
+        let mut saved: Vec<Register> = self.var_map.values().chain(&self.used).copied()
+            .filter(|reg| reg.gp_index().is_some_and(|index| index as usize > scope.params))
+            .collect();
+        saved.sort_by_key(|reg| reg.gp_index());
+        saved.dedup();
+
+        let mut prologue: Vec<Instruction> = saved.iter().map(|&src| Instruction::Push { src }).collect();
+        let frame = self.frame.size;
+        if frame > 0 {
+            prologue.extend(std::iter::repeat_n(Instruction::Push { src: Register::BP }, frame + 1));
+            prologue.push(Instruction::Move { dest: Register::BP, src: Register::SP });
+        }
+
+        // Insert the prologue at the start of the body, shifting what follows
+        let start = scope.body_start;
+        let count = prologue.len();
+        for (offset, instruction) in prologue.into_iter().enumerate() {
+            self.instructions.insert(start + offset, InstructionSlot::Real(instruction));
+            self.line_table.insert(start + offset, scope.line);
+            self.file_table.insert(start + offset, scope.file.clone());
+        }
+        for label in &scope.labels {
+            if let Some(index) = self.label_map.get_mut(label) {
+                *index += count;
+            }
+        }
+        for index in scope.returns.iter_mut().filter(|index| **index >= start) {
+            *index += count;
+        }
+
+        let exit = self.instructions.len();
+        for &index in &scope.returns {
+            self.instructions[index] = InstructionSlot::Real(Instruction::Jump { target: exit });
+        }
+        // Dropping the slots into BP leaves the caller's BP for last
+        for _ in 0..frame + usize::from(frame > 0) {
+            self.push_instr(Instruction::Pop { dest: Register::BP }, line);
+        }
+        for &reg in saved.iter().rev() {
+            self.push_instr(Instruction::Pop { dest: reg }, line);
+        }
+        self.push_instr(Instruction::Return, line);
+        if let Some(index) = scope.skip {
+            self.instructions[index] = InstructionSlot::Real(Instruction::Jump { target: self.instructions.len() });
+        }
+
+        self.var_map = scope.outer_vars;
+        self.next_reg = scope.outer_next_reg;
+        self.free = scope.outer_free;
+        self.used = scope.outer_used;
+        self.frame = scope.outer_frame;
+        Ok(())
+    }
+
+    /// Call a procedure: save the caller-saved registers holding variables,
+    /// pass the arguments, and copy R0 to `dest`
+    fn emit_proc_call(&mut self, name: String, args: Vec<Operand>, dest: Option<String>, line: usize) -> Result<(), VmError> {
+        if args.len() >= Register::GP_COUNT {
+            return Err(VmError::assembler(ErrorCode::ArgumentCount, format!(
+                "Call to '{}' has {} arguments; at most {} are supported", name, args.len(), Register::GP_COUNT - 1
+            )));
+        }
+        let dest = dest.map(|dest| self.resolve_var(&dest)).transpose()?;
+
+        let mut saved = Vec::new();
+        for index in 0..=args.len() as u8 {
+            let reg = Register::from_u8(index).map_err(VmError::from)?;
+            let holds_variable = self.var_map.iter().any(|(var, &r)| r == reg && var != "__tmp");
+            if holds_variable && Some(reg) != dest {
+                saved.push(reg);
             }
         }
+        for &reg in &saved {
+            self.push_instr(Instruction::Push { src: reg }, line);
+        }
+
+        // Push every argument before loading any register, since arguments
+        // may live in the registers being loaded: stack arguments stay
+        // pushed, register arguments are popped into R4..R1
+        for arg in args.iter().skip(4).chain(args.iter().take(4)) {
+            let reg = self.resolve_operand(arg, line)?;
+            self.push_instr(Instruction::Push { src: reg }, line);
+        }
+        for index in (1..=args.len().min(4) as u8).rev() {
+            self.push_instr(Instruction::Pop { dest: Register::from_u8(index).map_err(VmError::from)? }, line);
+        }
+        self.push_slot(InstructionSlot::Call { label: name.clone() }, line);
+
+        if let Some(dest) = dest.filter(|&dest| dest != Register::R0) {
+            self.push_instr(Instruction::Move { dest, src: Register::R0 }, line);
+        }
+        for &reg in saved.iter().rev() {
+            self.push_instr(Instruction::Pop { dest: reg }, line);
+        }
+        self.proc_calls.push((name, args.len(), line, self.current_file.clone()));
+        Ok(())
+    }
+
+    /// Data section that declarations currently go in
+    fn section(&mut self) -> &mut Vec<u8> {
+        if self.writable { &mut self.writable_section } else { &mut self.data_section }
+    }
+
+    /// Append a data declaration to the current data section, aligned to its
+    /// element size
+    fn emit_data(&mut self, label: Option<String>, item: DataItem) -> Result<(), VmError> {
+        let (values, width, text) = match item {
+            DataItem::String(text) => (Vec::new(), 1, Some(text)),
+            DataItem::Byte(values) => (values, 1, None),
+            DataItem::Word(values) => (values, 2, None),
+            DataItem::Qword(values) => (values, 8, None),
+            DataItem::Resource { name, bytes, .. } => return self.emit_resource(label, name, bytes),
+        };
+        while !self.section().len().is_multiple_of(width) {
+            self.section().push(0);
+        }
+
+        if let Some(label) = label {
+            if self.data_labels.contains_key(&label) {
+                return Err(VmError::assembler(ErrorCode::DuplicateLabel, format!(
+                    "Data label '{}' is already defined", label
+                )));
+            }
+            if self.writable {
+                self.writable_labels.push(label.clone());
+            }
+            let offset = self.section().len();
+            self.data_labels.insert(label, offset);
+        }
+        if let Some(text) = text {
+            // Literals may share a read-only copy, but not a writable one
+            if !self.writable {
+                self.strings.entry(text.clone()).or_insert(self.data_section.len());
+            }
+            self.section().extend_from_slice(text.as_bytes());
+            self.section().push(0);
+        }
+        for value in values {
+            let value = match value {
+                Operand::Immediate(value) => value,
+                Operand::Constant(name) => self.resolve_const(&name)?,
+                Operand::Variable(var) => {
+                    return Err(VmError::assembler(ErrorCode::Syntax, format!("Data cannot hold variable @{}", var)));
+                }
+            };
+            // Accept both unsigned values and sign-extended negatives that fit
+            let bits = width as u32 * 8;
+            let fits = bits == 64 || value >> bits == 0 || (value as i64) >> (bits - 1) == -1;
+            if !fits {
+                return Err(VmError::assembler(ErrorCode::Syntax, format!(
+                    "Value {} does not fit in {} byte(s)", value as i64, width
+                )));
+            }
+            self.section().extend_from_slice(&value.to_le_bytes()[..width]);
+        }
+        Ok(())
     }
     
+    /// Append the bytes of a `.resource` file to the read-only data section,
+    /// aligned like a qword
+    fn emit_resource(&mut self, label: Option<String>, name: String, bytes: Vec<u8>) -> Result<(), VmError> {
+        if self.resources.contains_key(&name) {
+            return Err(VmError::assembler(ErrorCode::DuplicateLabel, format!("Resource '{}' is already defined", name)));
+        }
+        let writable = std::mem::replace(&mut self.writable, false);
+        let aligned = self.emit_data(label, DataItem::Qword(Vec::new()));
+        self.writable = writable;
+        aligned?;
+        self.resources.insert(name, (self.data_section.len(), bytes.len()));
+        self.data_section.extend_from_slice(&bytes);
+        Ok(())
+    }
+
     fn push_slot(&mut self, slot: InstructionSlot, line: usize) {
         self.instructions.push(slot);
         self.line_table.push(line);
+        self.file_table.push(self.current_file.clone());
     }
 
     fn push_instr(&mut self, instr: Instruction, line: usize) {
@@ -125,15 +802,73 @@ impl CodeGenerator {
     }
 
     /// Main generation entry point.
-    fn generate(&mut self, statements: Vec<SpannedStatement>) -> Result<(Vec<Instruction>, Vec<u8>, Vec<usize>), VmError> {
+    fn generate(&mut self, statements: Vec<SpannedStatement>) -> Result<GeneratedCode, VmError> {
         // Emit instructions for each statement; labels record positions as they appear.
-        for stmt in statements {
-            self.emit_statement(stmt)?;
+        let statements = arrays::lower_arrays(blocks::lower_blocks(statements)?);
+        let last_uses = liveness::last_uses(&statements);
+        let folded = fold::fold_constants(&statements);
+        for ((mut stmt, dead), value) in statements.into_iter().zip(&last_uses).zip(folded) {
+            if let (Statement::BinOp { dest, .. }, Some(value)) = (&mut stmt.node, value) {
+                stmt.node = Statement::LoadImm { dest: std::mem::take(dest), value };
+            }
+            let line = stmt.line;
+            self.current_file = stmt.file.clone();
+            self.borrow_registers(&stmt.node, line)
+                .and_then(|()| self.emit_statement(stmt))
+                .map_err(|e| in_file(e.at_line(line), &self.current_file))?;
+            self.release_registers(line);
+            self.retire(dead);
+        }
+        if let Some(scope) = &self.proc {
+            let error = VmError::assembler(ErrorCode::Syntax, format!("Procedure '{}' is missing 'endproc'", scope.name));
+            return Err(in_file(error.at_line(scope.line), &scope.file));
+        }
+        for (name, args, line, file) in &self.proc_calls {
+            let error = match self.procs.get(name) {
+                Some(&params) if params == *args => continue,
+                Some(&params) => VmError::assembler(ErrorCode::ArgumentCount, format!(
+                    "Procedure '{}' takes {} argument(s), got {}", name, params, args
+                )),
+                None if self.label_map.contains_key(name) => VmError::assembler(ErrorCode::Syntax, format!(
+                    "'{}' is a label, not a procedure; use 'call {}' without arguments", name, name
+                )),
+                None if self.object => continue,
+                None => VmError::assembler(ErrorCode::UndefinedLabel, format!("Undefined procedure: '{}'", name)),
+            };
+            return Err(in_file(error.at_line(*line), file));
+        }
+
+        self.emit_top_frame();
+
+        // Writable data follows the read-only data, qword aligned
+        let writable_len = self.writable_section.len();
+        if !self.writable_labels.is_empty() || writable_len > 0 {
+            let base = self.data_section.len().next_multiple_of(8);
+            self.data_section.resize(base, 0);
+            self.data_section.append(&mut self.writable_section);
+            for label in &self.writable_labels {
+                *self.data_labels.get_mut(label).unwrap() += base;
+            }
         }
 
         // Resolve all label references
-        let instrs = self.resolve_labels()?;
-        Ok((instrs, self.data_section.clone(), self.line_table.clone()))
+        let Resolved { instructions, data_refs, code_refs, relocations } = self.resolve_labels()?;
+        Ok(GeneratedCode {
+            instructions,
+            data: self.data_section.clone(),
+            writable_len,
+            line_table: self.line_table.clone(),
+            file_table: self.file_table.clone(),
+            symbols: self.label_map.iter().map(|(name, &idx)| (name.clone(), idx)).collect(),
+            var_map: self.retired.iter().chain(&self.var_map).map(|(name, &reg)| (name.clone(), reg)).collect(),
+            spilled: self.spilled.clone(),
+            strings: self.strings.iter().map(|(text, &offset)| (offset, text.clone())).collect(),
+            data_symbols: self.data_labels.iter().map(|(name, &offset)| (name.clone(), offset)).collect(),
+            data_refs,
+            code_refs,
+            relocations,
+            resources: self.resources.iter().map(|(name, &place)| (name.clone(), place)).collect(),
+        })
     }
 
     fn emit_statement(&mut self, spanned: SpannedStatement) -> Result<(), VmError> {
@@ -141,17 +876,81 @@ impl CodeGenerator {
         match spanned.node {
             Statement::Label(name) => {
                 // Record the current instruction index for this label
+                if let Some(scope) = &mut self.proc {
+                    scope.labels.push(name.clone());
+                }
                 self.label_map.insert(name, self.instructions.len());
             }
             Statement::Halt => {
                 self.push_instr(Instruction::Halt, line);
             }
+            Statement::Exit(name) => {
+                let reg = self.resolve_var(&name)?;
+                self.push_instr(Instruction::Exit { src: reg }, line);
+            }
             Statement::Nop => {
                 self.push_instr(Instruction::Nop, line);
             }
-            Statement::Return => {
-                self.push_instr(Instruction::Return, line);
+            Statement::Enter(locals_size) => {
+                self.push_instr(Instruction::Enter { locals_size }, line);
+            }
+            Statement::Leave => {
+                self.push_instr(Instruction::Leave, line);
+            }
+            Statement::LoadLocal { dest, offset } => {
+                let reg = self.resolve_var(&dest)?;
+                self.push_instr(Instruction::LoadLocal { dest: reg, offset }, line);
+            }
+            Statement::StoreLocal { src, offset } => {
+                let reg = self.resolve_var(&src)?;
+                self.push_instr(Instruction::StoreLocal { src: reg, offset }, line);
+            }
+            Statement::Breakpoint => {
+                self.push_instr(Instruction::Breakpoint, line);
+            }
+            Statement::Trace(on) => {
+                self.push_instr(if on { Instruction::TraceOn } else { Instruction::TraceOff }, line);
+            }
+            Statement::Return(value) => {
+                if let Some(value) = value {
+                    self.load_operand(Register::R0, &value, line)?;
+                }
+                self.release_registers(line);
+                match &mut self.proc {
+                    Some(scope) => {
+                        // Jump to the epilogue; the target is patched at `endproc`
+                        scope.returns.push(self.instructions.len());
+                        self.push_instr(Instruction::Jump { target: 0 }, line);
+                    }
+                    None => self.push_instr(Instruction::Return, line),
+                }
+            }
+            Statement::Emit(bytes) => {
+                let mut offset = 0;
+                while offset < bytes.len() {
+                    let (instr, size) = Instruction::decode_at(&bytes[offset..], self.instructions.len()).map_err(|e| VmError::assembler(
+                        ErrorCode::Syntax, format!("Emitted bytes do not encode an instruction at byte {}: {}", offset, e.message())
+                    ))?;
+                    self.push_instr(instr, line);
+                    offset += size;
+                }
+            }
+            Statement::Proc { name, params } => self.begin_proc(name, params, line)?,
+            Statement::EndProc => self.end_proc(line)?,
+            Statement::While { .. }
+            | Statement::Loop
+            | Statement::Break
+            | Statement::Continue
+            | Statement::IfBlock { .. }
+            | Statement::Else
+            | Statement::End
+            | Statement::ArrayAlloc { .. }
+            | Statement::ArrayLiteral { .. }
+            | Statement::LoadElement { .. }
+            | Statement::StoreElement { .. } => {
+                unreachable!("blocks and arrays are lowered before code generation")
             }
+            Statement::CallProc { name, args, dest } => self.emit_proc_call(name, args, dest, line)?,
             Statement::LoadImm { dest, value } => {
                 let reg = self.resolve_var(&dest)?;
                 self.push_instr(
@@ -159,6 +958,42 @@ impl CodeGenerator {
                     line
                 );
             }
+            Statement::LoadConst { dest, name } => {
+                let reg = self.resolve_var(&dest)?;
+                self.load_name(reg, &name, line);
+            }
+            Statement::LabelAddress { dest, label } => {
+                let reg = self.resolve_var(&dest)?;
+                self.push_slot(InstructionSlot::LoadCodeAddress { dest: reg, label }, line);
+            }
+            Statement::Data { label, item } => {
+                self.emit_data(label, item)?;
+            }
+            Statement::Section { writable } => self.writable = writable,
+            Statement::Registers(count) => {
+                if !self.instructions.is_empty() || !self.var_map.is_empty() {
+                    return Err(VmError::assembler(ErrorCode::Syntax,
+                        "'.registers' must come before the first statement that emits code"));
+                }
+                self.gp_count = count as u8;
+            }
+            Statement::Const { name, value } => {
+                let value = match value {
+                    Operand::Constant(other) => self.resolve_const(&other)?,
+                    Operand::Immediate(value) => value,
+                    Operand::Variable(var) => {
+                        return Err(VmError::assembler(ErrorCode::Syntax, format!(
+                            "Constant '{}' cannot take the value of variable @{}", name, var
+                        )));
+                    }
+                };
+                self.define_const(name, value)?;
+            }
+            Statement::Enum { variants, .. } => {
+                for (value, variant) in variants.into_iter().enumerate() {
+                    self.define_const(variant, value as u64)?;
+                }
+            }
             Statement::MoveVar { dest, src } => {
                 let dest_reg = self.resolve_var(&dest)?;
                 let src_reg = self.resolve_var(&src)?;
@@ -191,6 +1026,10 @@ impl CodeGenerator {
                     BinOp::Xor => Instruction::Xor { dest: dest_reg, left: left_reg, right: right_reg },
                     BinOp::Shl => Instruction::Shl { dest: dest_reg, left: left_reg, right: right_reg },
                     BinOp::Shr => Instruction::Shr { dest: dest_reg, left: left_reg, right: right_reg },
+                    BinOp::MulHi => Instruction::MulHi { dest: dest_reg, left: left_reg, right: right_reg },
+                    BinOp::SignedDiv => Instruction::IDiv { dest: dest_reg, left: left_reg, right: right_reg },
+                    BinOp::SignedMod => Instruction::IMod { dest: dest_reg, left: left_reg, right: right_reg },
+                    BinOp::SignedShr => Instruction::ISht { dest: dest_reg, left: left_reg, right: right_reg },
                 };
                 self.push_instr(instr, line);
             }
@@ -235,9 +1074,23 @@ impl CodeGenerator {
                     line
                 );
             }
+            Statement::Rand(name) => {
+                let reg = self.resolve_var(&name)?;
+                self.push_instr(Instruction::Rand { dest: reg }, line);
+            }
+            Statement::FeatQuery { dest, leaf } => {
+                let reg = self.resolve_var(&dest)?;
+                self.push_instr(Instruction::FeatQuery { dest: reg, id: leaf }, line);
+            }
             Statement::Syscall => {
                 self.push_instr(Instruction::Syscall, line);
             }
+            Statement::Int(vector) => {
+                self.push_instr(Instruction::Int { vector }, line);
+            }
+            Statement::IRet => {
+                self.push_instr(Instruction::IRet, line);
+            }
             Statement::Print(name) => {
                 let reg = self.resolve_var(&name)?;
                 
@@ -268,9 +1121,24 @@ impl CodeGenerator {
             Statement::Goto(label) => {
                 self.push_slot(InstructionSlot::Jump { label }, line);
             }
+            Statement::Switch { index, labels } => {
+                let index = self.resolve_var(&index)?;
+                let count = u32::try_from(labels.len()).map_err(|_| VmError::assembler(ErrorCode::Syntax, format!(
+                    "switch has {} labels; at most {} fit in its jump table", labels.len(), u32::MAX
+                )))?;
+                self.push_instr(Instruction::Switch { index, count }, line);
+                self.release_registers(line);
+                for label in labels {
+                    self.push_slot(InstructionSlot::Jump { label }, line);
+                }
+            }
             Statement::Call(label) => {
                 self.push_slot(InstructionSlot::Call { label }, line);
             }
+            Statement::CallReg(target) => {
+                let target_reg = self.resolve_var(&target)?;
+                self.push_instr(Instruction::CallReg { target_reg }, line);
+            }
             Statement::If { left, comparison, right, label } => {
                 let left_reg = self.resolve_var(&left)?;
                 let right_reg = self.resolve_operand(&right, line)?;
@@ -279,27 +1147,50 @@ impl CodeGenerator {
                     Instruction::Compare { left: left_reg, right: right_reg },
                     line
                 );
+                self.release_registers(line);
                 // Emit conditional jump placeholder
                 self.push_slot(InstructionSlot::JumpIf {
                     comparison,
                     label,
                 }, line);
             }
-            Statement::Store { value_var, addr_var } => {
-                let src_reg = self.resolve_var(&value_var)?;
-                let addr_reg = self.resolve_var(&addr_var)?;
+            Statement::Compare { left, right } => {
+                let left_reg = self.resolve_var(&left)?;
+                let right_reg = self.resolve_var(&right)?;
                 self.push_instr(
-                    Instruction::Store { src: src_reg, addr_reg },
+                    Instruction::Compare { left: left_reg, right: right_reg },
                     line
                 );
             }
-            Statement::Load { dest_var, addr_var } => {
-                let dest_reg = self.resolve_var(&dest_var)?;
+            Statement::Test { left, right } => {
+                let left = self.resolve_var(&left)?;
+                let right = self.resolve_var(&right)?;
+                self.push_instr(Instruction::Test { left, right }, line);
+            }
+            Statement::Branch { comparison, label } => {
+                self.push_slot(InstructionSlot::JumpIf { comparison, label }, line);
+            }
+            Statement::Store { value_var, addr_var, size } => {
+                let src = self.resolve_var(&value_var)?;
                 let addr_reg = self.resolve_var(&addr_var)?;
-                self.push_instr(
-                    Instruction::Load { dest: dest_reg, addr_reg },
-                    line
-                );
+                let instr = match size {
+                    AccessSize::Byte => Instruction::StoreByte { src, addr_reg },
+                    AccessSize::Word => Instruction::StoreWord { src, addr_reg },
+                    AccessSize::DWord => Instruction::StoreDWord { src, addr_reg },
+                    AccessSize::QWord => Instruction::Store { src, addr_reg },
+                };
+                self.push_instr(instr, line);
+            }
+            Statement::Load { dest_var, addr_var, size } => {
+                let dest = self.resolve_var(&dest_var)?;
+                let addr_reg = self.resolve_var(&addr_var)?;
+                let instr = match size {
+                    AccessSize::Byte => Instruction::LoadByte { dest, addr_reg },
+                    AccessSize::Word => Instruction::LoadWord { dest, addr_reg },
+                    AccessSize::DWord => Instruction::LoadDWord { dest, addr_reg },
+                    AccessSize::QWord => Instruction::Load { dest, addr_reg },
+                };
+                self.push_instr(instr, line);
             }
             Statement::StoreIndexed { base_var, index_var, value } => {
                 let base_reg = self.resolve_var(&base_var)?;
@@ -321,10 +1212,16 @@ impl CodeGenerator {
             }
             Statement::LoadString { dest, value } => {
                 let reg = self.resolve_var(&dest)?;
-                
-                let offset = self.data_section.len();
-                self.data_section.extend_from_slice(value.as_bytes());
-                self.data_section.push(0);
+                let offset = match self.strings.get(&value) {
+                    Some(&offset) => offset,
+                    None => {
+                        let offset = self.data_section.len();
+                        self.data_section.extend_from_slice(value.as_bytes());
+                        self.data_section.push(0);
+                        self.strings.insert(value, offset);
+                        offset
+                    }
+                };
 
                 self.push_slot(InstructionSlot::LoadStringAddress { dest: reg, offset }, line);
             }
@@ -361,37 +1258,52 @@ impl CodeGenerator {
                     line
                 );
             }
-            Statement::FBinOp { dest, left, op, right } => {
+            Statement::FBinOp { dest, left, op, right, width } => {
                 let dest_reg = self.resolve_var(&dest)?;
                 let left_reg = self.resolve_var(&left)?;
                 let right_reg = self.resolve_var(&right)?;
-                let instr = match op {
-                    FBinOp::Add => Instruction::FAdd { dest: dest_reg, left: left_reg, right: right_reg },
-                    FBinOp::Sub => Instruction::FSub { dest: dest_reg, left: left_reg, right: right_reg },
-                    FBinOp::Mul => Instruction::FMul { dest: dest_reg, left: left_reg, right: right_reg },
-                    FBinOp::Div => Instruction::FDiv { dest: dest_reg, left: left_reg, right: right_reg },
+                let instr = match (op, width) {
+                    (FBinOp::Add, FloatWidth::F64) => Instruction::FAdd { dest: dest_reg, left: left_reg, right: right_reg },
+                    (FBinOp::Sub, FloatWidth::F64) => Instruction::FSub { dest: dest_reg, left: left_reg, right: right_reg },
+                    (FBinOp::Mul, FloatWidth::F64) => Instruction::FMul { dest: dest_reg, left: left_reg, right: right_reg },
+                    (FBinOp::Div, FloatWidth::F64) => Instruction::FDiv { dest: dest_reg, left: left_reg, right: right_reg },
+                    (FBinOp::Add, FloatWidth::F32) => Instruction::FAdd32 { dest: dest_reg, left: left_reg, right: right_reg },
+                    (FBinOp::Sub, FloatWidth::F32) => Instruction::FSub32 { dest: dest_reg, left: left_reg, right: right_reg },
+                    (FBinOp::Mul, FloatWidth::F32) => Instruction::FMul32 { dest: dest_reg, left: left_reg, right: right_reg },
+                    (FBinOp::Div, FloatWidth::F32) => Instruction::FDiv32 { dest: dest_reg, left: left_reg, right: right_reg },
+                    (FBinOp::Min, _) => Instruction::FMin { dest: dest_reg, left: left_reg, right: right_reg },
+                    (FBinOp::Max, _) => Instruction::FMax { dest: dest_reg, left: left_reg, right: right_reg },
                 };
                 self.push_instr(instr, line);
             }
-            Statement::FUnaryOp { dest, op, src } => {
+            Statement::FUnaryOp { dest, op, src, width } => {
                 let dest_reg = self.resolve_var(&dest)?;
                 let src_reg = self.resolve_var(&src)?;
-                let instr = match op {
-                    FUnaryOp::Sqrt => Instruction::FSqrt { dest: dest_reg, src: src_reg },
-                    FUnaryOp::Abs => Instruction::FAbs { dest: dest_reg, src: src_reg },
-                    FUnaryOp::Neg => Instruction::FNeg { dest: dest_reg, src: src_reg },
-                    FUnaryOp::ToInt => Instruction::F2I { dest: dest_reg, src: src_reg },
-                    FUnaryOp::ToFloat => Instruction::I2F { dest: dest_reg, src: src_reg },
+                let instr = match (op, width) {
+                    (FUnaryOp::Sqrt, FloatWidth::F32) => Instruction::FSqrt32 { dest: dest_reg, src: src_reg },
+                    (FUnaryOp::Sqrt, _) => Instruction::FSqrt { dest: dest_reg, src: src_reg },
+                    (FUnaryOp::Abs, _) => Instruction::FAbs { dest: dest_reg, src: src_reg },
+                    (FUnaryOp::Neg, _) => Instruction::FNeg { dest: dest_reg, src: src_reg },
+                    (FUnaryOp::Class, _) => Instruction::FClass { dest: dest_reg, src: src_reg },
+                    (FUnaryOp::Floor, _) => Instruction::FFloor { dest: dest_reg, src: src_reg },
+                    (FUnaryOp::Ceil, _) => Instruction::FCeil { dest: dest_reg, src: src_reg },
+                    (FUnaryOp::Trunc, _) => Instruction::FTrunc { dest: dest_reg, src: src_reg },
+                    (FUnaryOp::Round, _) => Instruction::FRound { dest: dest_reg, src: src_reg },
+                    (FUnaryOp::ToInt, _) => Instruction::F2I { dest: dest_reg, src: src_reg },
+                    (FUnaryOp::ToFloat, _) => Instruction::I2F { dest: dest_reg, src: src_reg },
+                    (FUnaryOp::Demote, _) => Instruction::FDemote { dest: dest_reg, src: src_reg },
+                    (FUnaryOp::Promote, _) => Instruction::FPromote { dest: dest_reg, src: src_reg },
                 };
                 self.push_instr(instr, line);
             }
-            Statement::FCmp { left, right } => {
-                let left_reg = self.resolve_var(&left)?;
-                let right_reg = self.resolve_var(&right)?;
-                self.push_instr(
-                    Instruction::FCmp { left: left_reg, right: right_reg },
-                    line
-                );
+            Statement::FCmp { left, right, width } => {
+                let left = self.resolve_var(&left)?;
+                let right = self.resolve_var(&right)?;
+                let instr = match width {
+                    FloatWidth::F64 => Instruction::FCmp { left, right },
+                    FloatWidth::F32 => Instruction::FCmp32 { left, right },
+                };
+                self.push_instr(instr, line);
             }
             Statement::BitUnaryOp { dest, op, src } => {
                 let dest_reg = self.resolve_var(&dest)?;
@@ -419,42 +1331,88 @@ impl CodeGenerator {
     }
 
     /// Replace all label placeholders with resolved instruction indices.
-    fn resolve_labels(&self) -> Result<Vec<Instruction>, VmError> {
+    /// Also returns the instructions that load data addresses and, for an
+    /// object file, those left for the linker.
+    fn resolve_labels(&self) -> Result<Resolved, VmError> {
         let mut result = Vec::with_capacity(self.instructions.len());
+        let mut data_refs = Vec::new();
+        let mut code_refs = Vec::new();
+        let mut relocations = Vec::new();
 
-        for slot in &self.instructions {
+        for ((slot, &line), file) in self.instructions.iter().zip(&self.line_table).zip(&self.file_table) {
+            let index = result.len();
+            let mut target = |label: &String| match self.label_map.get(label) {
+                Some(&target) => Ok(target),
+                None if self.object => {
+                    relocations.push((index, label.clone()));
+                    Ok(0)
+                }
+                None => {
+                    let error = VmError::assembler(ErrorCode::UndefinedLabel, format!("Undefined label: '{}'", label));
+                    Err(in_file(error.at_line(line), file))
+                }
+            };
             match slot {
                 InstructionSlot::Real(i) => {
-                    result.push(i.clone());
+                    result.push(*i);
                 }
                 InstructionSlot::Jump { label } => {
-                    let target = self.label_map.get(label)
-                        .ok_or_else(|| VmError::Assembler(format!("Undefined label: '{}'", label)))?;
-                    result.push(Instruction::Jump { target: *target });
+                    let target = target(label)?;
+                    result.push(Instruction::Jump { target });
                 }
                 InstructionSlot::Call { label } => {
-                    let target = self.label_map.get(label)
-                        .ok_or_else(|| VmError::Assembler(format!("Undefined label: '{}'", label)))?;
-                    result.push(Instruction::Call { target: *target });
+                    let target = target(label)?;
+                    result.push(Instruction::Call { target });
                 }
                 InstructionSlot::JumpIf { comparison, label } => {
-                    let target = self.label_map.get(label)
-                        .ok_or_else(|| VmError::Assembler(format!("Undefined label: '{}'", label)))?;
+                    let target = target(label)?;
                     let jump = match comparison {
-                        Comparison::Equal => Instruction::JumpIfEq { target: *target },
-                        Comparison::NotEqual => Instruction::JumpIfNe { target: *target },
-                        Comparison::GreaterThan => Instruction::JumpIfGt { target: *target },
-                        Comparison::LessThan => Instruction::JumpIfLt { target: *target },
-                        Comparison::GreaterEqual => Instruction::JumpIfGe { target: *target },
-                        Comparison::LessEqual => Instruction::JumpIfLe { target: *target },
-                        Comparison::UnsignedGreaterThan => Instruction::JumpIfAbove { target: *target },
-                        Comparison::UnsignedLessThan => Instruction::JumpIfBelow { target: *target },
-                        Comparison::UnsignedGreaterEqual => Instruction::JumpIfAe { target: *target },
-                        Comparison::UnsignedLessEqual => Instruction::JumpIfBe { target: *target },
+                        Comparison::Equal => Instruction::JumpIfEq { target },
+                        Comparison::NotEqual => Instruction::JumpIfNe { target },
+                        Comparison::GreaterThan => Instruction::JumpIfGt { target },
+                        Comparison::LessThan => Instruction::JumpIfLt { target },
+                        Comparison::GreaterEqual => Instruction::JumpIfGe { target },
+                        Comparison::LessEqual => Instruction::JumpIfLe { target },
+                        Comparison::UnsignedGreaterThan => Instruction::JumpIfAbove { target },
+                        Comparison::UnsignedLessThan => Instruction::JumpIfBelow { target },
+                        Comparison::UnsignedGreaterEqual => Instruction::JumpIfAe { target },
+                        Comparison::UnsignedLessEqual => Instruction::JumpIfBe { target },
+                        Comparison::Zero => Instruction::JumpIfZero { target },
+                        Comparison::NotZero => Instruction::JumpIfNotZero { target },
                     };
                     result.push(jump);
                 }
+                InstructionSlot::LoadDataAddress { dest, label } => {
+                    let offset = match self.data_labels.get(label) {
+                        Some(&offset) => {
+                            data_refs.push(index);
+                            offset
+                        }
+                        None if self.object => {
+                            relocations.push((index, label.clone()));
+                            0
+                        }
+                        None => {
+                            let error = VmError::assembler(ErrorCode::UndefinedConstant, format!(
+                                "Undefined constant or data label: '{}'", label
+                            ));
+                            return Err(in_file(error.at_line(line), file));
+                        }
+                    };
+                    result.push(Instruction::LoadImm { dest: *dest, value: offset as u64 });
+                }
+                // `&name` of a data label loads its data-section address
+                InstructionSlot::LoadCodeAddress { dest, label } if !self.label_map.contains_key(label) && self.data_labels.contains_key(label) => {
+                    data_refs.push(index);
+                    result.push(Instruction::LoadImm { dest: *dest, value: self.data_labels[label] as u64 });
+                }
+                InstructionSlot::LoadCodeAddress { dest, label } => {
+                    let target = target(label)?;
+                    code_refs.push(index);
+                    result.push(Instruction::LoadImm { dest: *dest, value: target as u64 });
+                }
                 InstructionSlot::LoadStringAddress { dest, offset } => {
+                    data_refs.push(index);
                     // Load the address (offset in memory)
                     // We assume data is loaded at memory address 0
                     result.push(Instruction::LoadImm { 
@@ -465,12 +1423,33 @@ impl CodeGenerator {
             }
         }
 
-        Ok(result)
+        Ok(Resolved { instructions: result, data_refs, code_refs, relocations })
+    }
+}
+
+/// Whether the statement may load an immediate or constant into `__tmp`
+fn uses_tmp(node: &Statement) -> bool {
+    let not_variable = |operand: &Operand| !matches!(operand, Operand::Variable(_));
+    match node {
+        Statement::BinOp { right: operand, .. }
+        | Statement::CompoundAssign { operand, .. }
+        | Statement::StoreIndexed { value: operand, .. }
+        | Statement::If { right: operand, .. } => not_variable(operand),
+        Statement::CallProc { args, .. } => args.iter().any(not_variable),
+        _ => false,
+    }
+}
+
+/// Attribute an error to the included file it came from, if any
+fn in_file(error: VmError, file: &Option<Arc<str>>) -> VmError {
+    match file {
+        Some(file) => error.in_file(file),
+        None => error,
     }
 }
 
 /// Try to parse a register name like "r0", "r1", ..., "r15", "sp", "bp"
-fn try_parse_register_name(name: &str) -> Option<Register> {
+pub(crate) fn try_parse_register_name(name: &str) -> Option<Register> {
     match name {
         "r0" => Some(Register::R0),
         "r1" => Some(Register::R1),
@@ -509,6 +1488,54 @@ fn try_parse_register_name(name: &str) -> Option<Register> {
         "f13" => Some(Register::F13),
         "f14" => Some(Register::F14),
         "f15" => Some(Register::F15),
+        "r16" => Some(Register::R16),
+        "r17" => Some(Register::R17),
+        "r18" => Some(Register::R18),
+        "r19" => Some(Register::R19),
+        "r20" => Some(Register::R20),
+        "r21" => Some(Register::R21),
+        "r22" => Some(Register::R22),
+        "r23" => Some(Register::R23),
+        "r24" => Some(Register::R24),
+        "r25" => Some(Register::R25),
+        "r26" => Some(Register::R26),
+        "r27" => Some(Register::R27),
+        "r28" => Some(Register::R28),
+        "r29" => Some(Register::R29),
+        "r30" => Some(Register::R30),
+        "r31" => Some(Register::R31),
+        "r32" => Some(Register::R32),
+        "r33" => Some(Register::R33),
+        "r34" => Some(Register::R34),
+        "r35" => Some(Register::R35),
+        "r36" => Some(Register::R36),
+        "r37" => Some(Register::R37),
+        "r38" => Some(Register::R38),
+        "r39" => Some(Register::R39),
+        "r40" => Some(Register::R40),
+        "r41" => Some(Register::R41),
+        "r42" => Some(Register::R42),
+        "r43" => Some(Register::R43),
+        "r44" => Some(Register::R44),
+        "r45" => Some(Register::R45),
+        "r46" => Some(Register::R46),
+        "r47" => Some(Register::R47),
+        "r48" => Some(Register::R48),
+        "r49" => Some(Register::R49),
+        "r50" => Some(Register::R50),
+        "r51" => Some(Register::R51),
+        "r52" => Some(Register::R52),
+        "r53" => Some(Register::R53),
+        "r54" => Some(Register::R54),
+        "r55" => Some(Register::R55),
+        "r56" => Some(Register::R56),
+        "r57" => Some(Register::R57),
+        "r58" => Some(Register::R58),
+        "r59" => Some(Register::R59),
+        "r60" => Some(Register::R60),
+        "r61" => Some(Register::R61),
+        "r62" => Some(Register::R62),
+        "r63" => Some(Register::R63),
         _ => None,
     }
 }
@@ -521,7 +1548,7 @@ mod tests {
     #[test]
     fn test_codegen_hello() {
         let stmts = parser::parse("@r0 := 42\nprint @r0\nhalt\n").unwrap();
-        let (instructions, _, _) = generate(stmts).unwrap();
+        let instructions = generate(stmts).unwrap().instructions;
         // 0: LoadImm
         // Print expands to: Push, Push, Move, LoadImm, Syscall, Pop, Pop (7 instrs)
         // Total 1 + 7 + 1 (Halt) = 9
@@ -535,7 +1562,7 @@ mod tests {
     #[test]
     fn test_codegen_jump() {
         let stmts = parser::parse("goto end\n@r0 := 99\nend:\nhalt\n").unwrap();
-        let (instructions, _, _) = generate(stmts).unwrap();
+        let instructions = generate(stmts).unwrap().instructions;
         // goto end -> Jump { target: 2 } (skipping the loadimm)
         // @r0 := 99 -> LoadImm
         // end: -> (no instruction, label points to index 2)
@@ -544,4 +1571,182 @@ mod tests {
         assert!(matches!(&instructions[0], Instruction::Jump { target: 2 }));
         assert!(matches!(&instructions[2], Instruction::Halt));
     }
+
+    #[test]
+    fn test_codegen_symbols() {
+        let stmts = parser::parse("goto main
+helper:
+return
+main:
+call helper
+halt
+").unwrap();
+        let code = generate(stmts).unwrap();
+        assert_eq!(code.symbols.get("helper"), Some(&1));
+        assert_eq!(code.symbols.get("main"), Some(&2));
+    }
+
+    #[test]
+    fn test_codegen_constants() {
+        let stmts = parser::parse("const MAX := 100\nconst LOW := -1\n@r0 := MAX\n@r0 += LOW\nif @r0 < MAX goto done\ndone:\nhalt\n").unwrap();
+        let instructions = generate(stmts).unwrap().instructions;
+        assert!(matches!(&instructions[0], Instruction::LoadImm { dest: Register::R0, value: 100 }));
+        assert!(matches!(&instructions[1], Instruction::LoadImm { value: u64::MAX, .. }));
+        assert!(matches!(&instructions[3], Instruction::LoadImm { value: 100, .. }));
+
+        let err = generate(parser::parse("@r0 := MAX\nconst MAX := 1\n").unwrap()).unwrap_err();
+        assert_eq!((err.code(), err.line()), (ErrorCode::UndefinedConstant, Some(1)));
+        let err = generate(parser::parse("const A := 1\nconst A := 2\n").unwrap()).unwrap_err();
+        assert_eq!((err.code(), err.line()), (ErrorCode::DuplicateConstant, Some(2)));
+
+        let stmts = parser::parse("enum State { Idle, Running, Done }\n@s := Done\nif @s == Running goto end\nend:\n").unwrap();
+        let instructions = generate(stmts).unwrap().instructions;
+        assert!(matches!(&instructions[0], Instruction::LoadImm { value: 2, .. }));
+        assert!(matches!(&instructions[1], Instruction::LoadImm { value: 1, .. }));
+        let err = generate(parser::parse("enum E { A }\nconst A := 3\n").unwrap()).unwrap_err();
+        assert_eq!(err.code(), ErrorCode::DuplicateConstant);
+    }
+
+    #[test]
+    fn test_codegen_procedures() {
+        let source = "@keep := 7\n@f := call fact(5)\nprint @f\n@s := call sum6(1, 2, 3, 4, 5, 6)\nprint @s\nprint @keep\nhalt\n\
+            proc fact(n)\nif @n > 1 goto recurse\nreturn 1\nrecurse:\n@m := @n - 1\n@sub := call fact(@m)\n@res := @n * @sub\nreturn @res\nendproc\n\
+            proc sum6(a, b, c, d, e, f)\n@t := @a + @b\n@t += @c\n@t += @d\n@t += @e\n@t += @f\nreturn @t\nendproc\n";
+        let program = crate::assembler::assemble(source, "procs").unwrap();
+        let mut vm = crate::execution::VM::new();
+        vm.print_immediately = false;
+        vm.run(&program).unwrap();
+        assert_eq!(vm.output(), &["120", "21", "7"]);
+
+        // A proc before the top-level code is jumped over
+        let source = "proc twice(n)\n@t := @n + @n\nreturn @t\nendproc\n@x := call twice(21)\nprint @x\nhalt\n";
+        let program = crate::assembler::assemble(source, "procs").unwrap();
+        let mut vm = crate::execution::VM::new();
+        vm.print_immediately = false;
+        vm.run(&program).unwrap();
+        assert_eq!(vm.output(), &["42"]);
+
+        let err = generate(parser::parse("call f(1)\nhalt\nproc f(a, b)\nendproc\n").unwrap()).unwrap_err();
+        assert_eq!((err.code(), err.line()), (ErrorCode::ArgumentCount, Some(1)));
+        let err = generate(parser::parse("proc f()\nhalt\n").unwrap()).unwrap_err();
+        assert_eq!((err.code(), err.line()), (ErrorCode::Syntax, Some(1)));
+    }
+
+    #[test]
+    fn test_codegen_spilling() {
+        // 21 variables live at once at the top level and 18 in a procedure,
+        // with spilled loop counters, immediates, pops and call results
+        let vars: String = (0..18).map(|i| format!("@v{} := {}\n", i, i)).collect();
+        let total: String = (1..18).map(|i| format!("@v0 += @v{}\n", i)).collect();
+        let body: String = (0..16).map(|i| format!("@a{} := {}\n", i, i)).collect();
+        let result: String = (0..16).map(|i| format!("@a16 += @a{}\n", i)).collect();
+        let source = format!(
+            "{}@i := 0\n@sum := 0\nloop:\n@sum += @v17\n@i += 1\nif @i < 3 goto loop\nprint @sum\n\
+             push @v16\n@v17 := pop\nprint @v17\n@r := call wide(@v17)\nprint @r\n{}print @v0\nhalt\n\
+             proc wide(n)\n{}@a15 += @n\n@a16 := @a15 * 2\n{}return @a16\nendproc\n",
+            vars, total, body, result
+        );
+        let code = generate(parser::parse(&source).unwrap()).unwrap();
+        assert_eq!(code.spilled, ["v16", "v17", "i", "sum", "r", "a15"]);
+
+        let program = crate::assembler::assemble(&source, "spill").unwrap();
+        let mut vm = crate::execution::VM::new();
+        vm.print_immediately = false;
+        vm.run(&program).unwrap();
+        assert_eq!(vm.output(), &["51", "16", "198", "152"]);
+    }
+
+    #[test]
+    fn test_codegen_extended_registers() {
+        let vars: String = (0..40).map(|i| format!("@v{} := {}\n", i, i)).collect();
+        let total: String = (1..40).map(|i| format!("@v0 += @v{}\n", i)).collect();
+        let source = format!(".registers 64\n{}{}print @v0\nhalt\n", vars, total);
+        let code = generate(parser::parse(&source).unwrap()).unwrap();
+        assert!(code.spilled.is_empty());
+
+        let program = crate::assembler::assemble(&source, "wide").unwrap();
+        assert!(program.uses_extended_registers());
+        let mut vm = crate::execution::VM::new();
+        vm.print_immediately = false;
+        vm.run(&program).unwrap();
+        assert_eq!(vm.output(), &["780"]);
+
+        let err = crate::assembler::assemble("@a := 1\n.registers 64\n", "t").unwrap_err();
+        assert!(err.to_string().contains("'.registers' must come before"), "{}", err);
+    }
+
+    #[test]
+    fn test_codegen_data_directives() {
+        let source = "@r0 := table\nhalt\nflags: .byte 1, -1\nmsg: .string \"hi\"\ntable: .qword 7, 0x10\n.word 2\n";
+        let code = generate(parser::parse(source).unwrap()).unwrap();
+        assert!(matches!(&code.instructions[0], Instruction::LoadImm { dest: Register::R0, value: 8 }));
+        assert_eq!(&code.data[..8], &[1, 0xff, b'h', b'i', 0, 0, 0, 0]);
+        assert_eq!(&code.data[8..16], &7u64.to_le_bytes());
+        assert_eq!(&code.data[24..], &[2, 0]);
+
+        let err = generate(parser::parse("bad: .byte 256\n").unwrap()).unwrap_err();
+        assert_eq!((err.code(), err.line()), (ErrorCode::Syntax, Some(1)));
+        let err = generate(parser::parse("@r0 := missing\n").unwrap()).unwrap_err();
+        assert_eq!((err.code(), err.line()), (ErrorCode::UndefinedConstant, Some(1)));
+    }
+
+    #[test]
+    fn test_codegen_emit() {
+        let source = "emit 0x01
+emit.bytes 0x10 0x00 42 0 0 0 0 0 0 0 0x00
+";
+        let instructions = generate(parser::parse(source).unwrap()).unwrap().instructions;
+        assert_eq!(instructions, [Instruction::Nop, Instruction::LoadImm { dest: Register::R0, value: 42 }, Instruction::Halt]);
+
+        let err = generate(parser::parse("emit 0x10 0x00
+").unwrap()).unwrap_err();
+        assert_eq!((err.code(), err.line()), (ErrorCode::Syntax, Some(1)));
+        assert!(parser::parse("emit 256
+").is_err());
+    }
+
+    #[test]
+    fn test_codegen_string_pool() {
+        let source = "msg: .string \"hi\"\n@a := \"hi\"\n@b := \"bye\"\n@c := \"bye\"\nhalt\n";
+        let code = generate(parser::parse(source).unwrap()).unwrap();
+        assert_eq!(code.data, b"hi\0bye\0");
+        assert_eq!(code.strings, BTreeMap::from([(0, "hi".to_string()), (3, "bye".to_string())]));
+    }
+
+    #[test]
+    fn test_codegen_writable_data() {
+        let source = r#"msg: .string "hi"
+.data
+counter: .qword 0
+slot: .qword 0
+.rodata
+bye: .string "bye"
+@p := counter
+@v := 41
+@v := @v + 1
+store @v at @p
+@w := load @p
+print @w
+@m := msg
+@s := slot
+store @m at @s
+@q := load @s
+store @v at @q
+halt
+"#;
+        let code = generate(parser::parse(source).unwrap()).unwrap();
+        assert_eq!((code.data.len(), code.writable_len), (24, 16));
+        assert_eq!(&code.data[..7], b"hi\0bye\0");
+
+        let program = crate::assembler::assemble(source, "t").unwrap();
+        let mut vm = crate::execution::VM::new();
+        vm.print_immediately = false;
+        let err = vm.run(&program).unwrap_err();
+        assert_eq!(vm.output(), ["42"]);
+        assert!(err.to_string().contains("does not have Write permission"), "{}", err);
+
+        let err = crate::assembler::assemble("@p := msg\n@v := 1\nstore @v at @p\nhalt\nmsg: .string \"hi\"\n", "t")
+            .and_then(|program| crate::instruction::validate::validate(&program)).unwrap_err();
+        assert!(err.to_string().contains("outside the writable data section"), "{}", err);
+    }
 }
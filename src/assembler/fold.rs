@@ -0,0 +1,133 @@
+//! Constant folding for binary operations.
+//!
+//! A variable whose only definition in its scope is an immediate (or a
+//! binary operation that folds) has a known value. A `BinOp` whose left
+//! operand is known and whose right operand is an immediate, constant or
+//! known variable is computed at assembly time and emitted as one `LoadImm`.
+//! Division by zero is left for run time, and so is an operation directly
+//! followed by a raw `jz`/`jnz`, since `LoadImm` does not set the flags.
+
+use std::collections::HashMap;
+use super::codegen::try_parse_register_name;
+use super::liveness::scopes;
+use super::parser::ast::*;
+
+/// For each statement, the value it folds to, if it is a foldable `BinOp`
+pub fn fold_constants(statements: &[SpannedStatement]) -> Vec<Option<u64>> {
+    let constants = constant_values(statements);
+    let mut folded = vec![None; statements.len()];
+    for (indices, _) in scopes(statements) {
+        let known = known_variables(statements, &indices, &constants);
+        for (position, &index) in indices.iter().enumerate() {
+            let followed_by_branch = indices.get(position + 1)
+                .is_some_and(|&next| matches!(statements[next].node, Statement::Branch { .. }));
+            if !followed_by_branch {
+                folded[index] = evaluate(&statements[index].node, &known, &constants);
+            }
+        }
+    }
+    folded
+}
+
+/// Values of `const` and `enum` constants, in definition order
+fn constant_values(statements: &[SpannedStatement]) -> HashMap<&str, u64> {
+    let mut constants = HashMap::new();
+    for stmt in statements {
+        match &stmt.node {
+            Statement::Const { name, value: Operand::Immediate(value) } => {
+                constants.insert(name.as_str(), *value);
+            }
+            Statement::Const { name, value: Operand::Constant(other) } => {
+                if let Some(&value) = constants.get(other.as_str()) {
+                    constants.insert(name.as_str(), value);
+                }
+            }
+            Statement::Enum { variants, .. } => {
+                constants.extend(variants.iter().enumerate().map(|(value, variant)| (variant.as_str(), value as u64)));
+            }
+            _ => {}
+        }
+    }
+    constants
+}
+
+/// Variables of one scope whose single definition has a known value
+fn known_variables<'a>(statements: &'a [SpannedStatement], indices: &[usize], constants: &HashMap<&str, u64>) -> HashMap<&'a str, u64> {
+    let mut definitions: HashMap<&str, Vec<&Statement>> = HashMap::new();
+    for &index in indices {
+        let node = &statements[index].node;
+        for name in node.accesses().0 {
+            definitions.entry(name).or_default().push(node);
+        }
+    }
+
+    let mut known = HashMap::new();
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for (&name, defs) in &definitions {
+            if defs.len() != 1 || known.contains_key(name) || try_parse_register_name(name).is_some() {
+                continue;
+            }
+            let value = match defs[0] {
+                Statement::LoadImm { value, .. } => Some(*value),
+                node => evaluate(node, &known, constants),
+            };
+            if let Some(value) = value {
+                known.insert(name, value);
+                changed = true;
+            }
+        }
+    }
+    known
+}
+
+/// Value of a `BinOp` whose operands are all known
+fn evaluate(node: &Statement, known: &HashMap<&str, u64>, constants: &HashMap<&str, u64>) -> Option<u64> {
+    let Statement::BinOp { left, op, right, .. } = node else { return None };
+    let left = *known.get(left.as_str())?;
+    let right = match right {
+        Operand::Immediate(value) => *value,
+        Operand::Constant(name) => *constants.get(name.as_str())?,
+        Operand::Variable(name) => *known.get(name.as_str())?,
+    };
+    // Mirrors the arithmetic and logic handlers
+    Some(match op {
+        BinOp::Add => left.wrapping_add(right),
+        BinOp::Sub => left.wrapping_sub(right),
+        BinOp::Mul => left.wrapping_mul(right),
+        BinOp::Div => left.checked_div(right)?,
+        BinOp::Mod => left.checked_rem(right)?,
+        BinOp::And => left & right,
+        BinOp::Or => left | right,
+        BinOp::Xor => left ^ right,
+        BinOp::Shl => left.wrapping_shl(right as u32),
+        BinOp::Shr => left.wrapping_shr(right as u32),
+        BinOp::MulHi => ((left as u128 * right as u128) >> 64) as u64,
+        BinOp::SignedDiv => (right != 0).then(|| (left as i64).wrapping_div(right as i64) as u64)?,
+        BinOp::SignedMod => (right != 0).then(|| (left as i64).wrapping_rem(right as i64) as u64)?,
+        BinOp::SignedShr => (left as i64).wrapping_shr(right as u32) as u64,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::parser;
+
+    #[test]
+    fn test_fold_constants() {
+        let source = "const SCALE := 4\n@w := 10\n@h := 3\n@area := @w * @h\n@scaled := @area << SCALE\n\
+            @n := 5\n@n -= 1\n@left := @n + 1\n@zero := @h / 0\n@flagged := @w - 10\njz done\ndone:\n";
+        let folded = fold_constants(&parser::parse(source).unwrap());
+        assert_eq!(folded[3], Some(30));
+        assert_eq!(folded[4], Some(480));
+        // @n has two definitions; division by zero and flag users stay
+        assert_eq!(folded[7], None);
+        assert_eq!(folded[8], None);
+        assert_eq!(folded[9], None);
+
+        let program = crate::assembler::assemble("@a := 6\n@b := @a * 7\nprint @b\n", "fold").unwrap();
+        assert!(matches!(program.instructions[1], crate::instruction::Instruction::LoadImm { value: 42, .. }));
+    }
+}
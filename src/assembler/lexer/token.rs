@@ -1,5 +1,7 @@
 //! Token types for the lexer.
 
+use std::ops::Range;
+
 /// Represents a single token from the source.
 #[derive(Debug, Clone, PartialEq)]
 pub enum Token {
@@ -61,8 +63,18 @@ pub enum Token {
     LeftBracket,
     /// ]
     RightBracket,
+    /// (
+    LeftParen,
+    /// )
+    RightParen,
+    /// {
+    LeftBrace,
+    /// }
+    RightBrace,
     /// :
     Colon,
+    /// .name — an assembler directive such as `.include`
+    Directive(String),
     /// Keywords
     Keyword(Keyword),
     /// End of line
@@ -77,7 +89,10 @@ pub enum Keyword {
     Push,
     Pop,
     Peek,
+    Rand,
+    FeatQuery,
     Goto,
+    Switch,
     If,
     Call,
     Return,
@@ -86,8 +101,22 @@ pub enum Keyword {
     At,
     Debug,
     Syscall,
+    Int,
+    IRet,
     Nop,
+    Enter,
+    Leave,
+    Local,
+    Breakpoint,
+    TraceOn,
+    TraceOff,
     Unsigned, // New keyword for unsigned comparisons
+    Signed,
+    Const,
+    Proc,
+    EndProc,
+    Enum,
+    While,
     Alloc,
     Free,
     MemCopy,
@@ -97,8 +126,17 @@ pub enum Keyword {
     FSub,
     FMul,
     FDiv,
+    FMin,
+    FMax,
+    FClass,
     FSqrt,
+    FDemote,
+    FPromote,
     FAbs,
+    FFloor,
+    FCeil,
+    FTrunc,
+    FRound,
     FNeg,
     F2I,
     I2F,
@@ -110,16 +148,47 @@ pub enum Keyword {
     BSwap,
     RotL,
     RotR,
+    MulHi,
+    // Raw flag-based control flow
+    Compare,
+    Test,
+    Jz,
+    Jnz,
+    Jeq,
+    Jne,
+    Jgt,
+    Jlt,
+    Jge,
+    Jle,
+    Ja,
+    Jb,
+    Jae,
+    Jbe,
 }
 
 /// Tokenize a single line of source code.
 pub fn tokenize_line(line: &str) -> Vec<Token> {
+    tokenize_line_spanned(line).into_iter().map(|(token, _)| token).collect()
+}
+
+/// Tokenize a line, pairing each token with its byte range in `line`.
+pub fn tokenize_line_spanned(line: &str) -> Vec<(Token, Range<usize>)> {
     let mut tokens = Vec::new();
     let chars: Vec<char> = line.chars().collect();
     let len = chars.len();
     let mut i = 0;
+    // Byte offset of each char, plus the end of the line
+    let offsets: Vec<usize> = line.char_indices().map(|(offset, _)| offset).chain([line.len()]).collect();
+    let mut spans = Vec::new();
+    let mut token_start = 0;
 
     while i < len {
+        // The previous iteration ended a token
+        if spans.len() < tokens.len() {
+            spans.push(offsets[token_start]..offsets[i]);
+        }
+        token_start = i;
+
         // Skip whitespace
         if chars[i].is_whitespace() {
             i += 1;
@@ -143,38 +212,32 @@ pub fn tokenize_line(line: &str) -> Vec<Token> {
             continue;
         }
 
-        // Number: decimal, 0x hex, 0b binary
-        if chars[i].is_ascii_digit() {
-            let start = i;
-            if chars[i] == '0' && i + 1 < len {
-                if chars[i + 1] == 'x' || chars[i + 1] == 'X' {
-                    i += 2;
-                    let hex_start = i;
-                    while i < len && chars[i].is_ascii_hexdigit() {
-                        i += 1;
-                    }
-                    let hex_str: String = chars[hex_start..i].iter().collect();
-                    let value = u64::from_str_radix(&hex_str, 16).unwrap_or(0);
-                    tokens.push(Token::Number(value));
-                    continue;
-                } else if chars[i + 1] == 'b' || chars[i + 1] == 'B' {
-                    i += 2;
-                    let bin_start = i;
-                    while i < len && (chars[i] == '0' || chars[i] == '1') {
-                        i += 1;
-                    }
-                    let bin_str: String = chars[bin_start..i].iter().collect();
-                    let value = u64::from_str_radix(&bin_str, 2).unwrap_or(0);
-                    tokens.push(Token::Number(value));
-                    continue;
-                }
+        // Number: decimal, 0x hex, 0b binary, with an optional '-'. Right
+        // after a value '-' is subtraction (`@a -1` is `@a - 1`), except
+        // across a comma or in the value list of a data directive.
+        let follows_value = matches!(
+            tokens.last(),
+            Some(Token::Register(_) | Token::Number(_) | Token::Identifier(_) | Token::RightBracket | Token::RightParen)
+        ) && chars[..i].iter().rev().find(|c| !c.is_whitespace()) != Some(&',')
+            && !tokens.iter().any(|token| matches!(token, Token::Directive(_)));
+        let negative = chars[i] == '-' && !follows_value && chars.get(i + 1).is_some_and(char::is_ascii_digit);
+        if chars[i].is_ascii_digit() || negative {
+            if negative {
+                i += 1;
             }
-            while i < len && chars[i].is_ascii_digit() {
+            let (radix, digits_start) = match chars.get(i + 1) {
+                Some('x' | 'X') if chars[i] == '0' => (16, i + 2),
+                Some('b' | 'B') if chars[i] == '0' => (2, i + 2),
+                _ => (10, i),
+            };
+            i = digits_start;
+            while i < len && chars[i].is_digit(radix) {
                 i += 1;
             }
-            let num_str: String = chars[start..i].iter().collect();
-            let value = num_str.parse::<u64>().unwrap_or(0);
-            tokens.push(Token::Number(value));
+            let digits: String = chars[digits_start..i].iter().collect();
+            let value = u64::from_str_radix(&digits, radix).unwrap_or(0);
+            // Negative literals are stored in two's complement
+            tokens.push(Token::Number(if negative { value.wrapping_neg() } else { value }));
             continue;
         }
 
@@ -221,10 +284,25 @@ pub fn tokenize_line(line: &str) -> Vec<Token> {
             '<' => { tokens.push(Token::LessThan); i += 1; continue; }
             '[' => { tokens.push(Token::LeftBracket); i += 1; continue; }
             ']' => { tokens.push(Token::RightBracket); i += 1; continue; }
+            '(' => { tokens.push(Token::LeftParen); i += 1; continue; }
+            ')' => { tokens.push(Token::RightParen); i += 1; continue; }
+            '{' => { tokens.push(Token::LeftBrace); i += 1; continue; }
+            '}' => { tokens.push(Token::RightBrace); i += 1; continue; }
             ':' => { tokens.push(Token::Colon); i += 1; continue; }
             _ => {}
         }
 
+        // Directives: .name
+        if chars[i] == '.' && i + 1 < len && (chars[i + 1].is_alphabetic() || chars[i + 1] == '_') {
+            i += 1;
+            let start = i;
+            while i < len && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            tokens.push(Token::Directive(chars[start..i].iter().collect()));
+            continue;
+        }
+
         // Identifiers and keywords
         if chars[i].is_alphabetic() || chars[i] == '_' {
             let start = i;
@@ -238,7 +316,10 @@ pub fn tokenize_line(line: &str) -> Vec<Token> {
                 "push" => Token::Keyword(Keyword::Push),
                 "pop" => Token::Keyword(Keyword::Pop),
                 "peek" => Token::Keyword(Keyword::Peek),
+                "rand" => Token::Keyword(Keyword::Rand),
+                "featquery" => Token::Keyword(Keyword::FeatQuery),
                 "goto" => Token::Keyword(Keyword::Goto),
+                "switch" => Token::Keyword(Keyword::Switch),
                 "if" => Token::Keyword(Keyword::If),
                 "call" => Token::Keyword(Keyword::Call),
                 "return" => Token::Keyword(Keyword::Return),
@@ -247,15 +328,41 @@ pub fn tokenize_line(line: &str) -> Vec<Token> {
                 "at" => Token::Keyword(Keyword::At),
                 "debug" => Token::Keyword(Keyword::Debug),
                 "syscall" => Token::Keyword(Keyword::Syscall),
+                "int" => Token::Keyword(Keyword::Int),
+                "iret" => Token::Keyword(Keyword::IRet),
                 "nop" => Token::Keyword(Keyword::Nop),
+                "enter" => Token::Keyword(Keyword::Enter),
+                "leave" => Token::Keyword(Keyword::Leave),
+                "local" => Token::Keyword(Keyword::Local),
+                "breakpoint" => Token::Keyword(Keyword::Breakpoint),
+                "trace_on" => Token::Keyword(Keyword::TraceOn),
+                "trace_off" => Token::Keyword(Keyword::TraceOff),
                 "unsigned" => Token::Keyword(Keyword::Unsigned),
+                "signed" => Token::Keyword(Keyword::Signed),
+                "const" => Token::Keyword(Keyword::Const),
+                "proc" => Token::Keyword(Keyword::Proc),
+                "endproc" => Token::Keyword(Keyword::EndProc),
+                "enum" => Token::Keyword(Keyword::Enum),
+                "while" => Token::Keyword(Keyword::While),
+                "alloc" => Token::Keyword(Keyword::Alloc),
+                "free" => Token::Keyword(Keyword::Free),
+                "memcpy" => Token::Keyword(Keyword::MemCopy),
                 "memset" => Token::Keyword(Keyword::MemSet),
                 "fadd" => Token::Keyword(Keyword::FAdd),
                 "fsub" => Token::Keyword(Keyword::FSub),
                 "fmul" => Token::Keyword(Keyword::FMul),
                 "fdiv" => Token::Keyword(Keyword::FDiv),
+                "fmin" => Token::Keyword(Keyword::FMin),
+                "fmax" => Token::Keyword(Keyword::FMax),
+                "fclass" => Token::Keyword(Keyword::FClass),
                 "fsqrt" => Token::Keyword(Keyword::FSqrt),
+                "fdemote" => Token::Keyword(Keyword::FDemote),
+                "fpromote" => Token::Keyword(Keyword::FPromote),
                 "fabs" => Token::Keyword(Keyword::FAbs),
+                "ffloor" => Token::Keyword(Keyword::FFloor),
+                "fceil" => Token::Keyword(Keyword::FCeil),
+                "ftrunc" => Token::Keyword(Keyword::FTrunc),
+                "fround" => Token::Keyword(Keyword::FRound),
                 "fneg" => Token::Keyword(Keyword::FNeg),
                 "f2i" => Token::Keyword(Keyword::F2I),
                 "i2f" => Token::Keyword(Keyword::I2F),
@@ -266,6 +373,21 @@ pub fn tokenize_line(line: &str) -> Vec<Token> {
                 "bswap" => Token::Keyword(Keyword::BSwap),
                 "rotl" => Token::Keyword(Keyword::RotL),
                 "rotr" => Token::Keyword(Keyword::RotR),
+                "mulhi" => Token::Keyword(Keyword::MulHi),
+                "compare" => Token::Keyword(Keyword::Compare),
+                "test" => Token::Keyword(Keyword::Test),
+                "jz" => Token::Keyword(Keyword::Jz),
+                "jnz" => Token::Keyword(Keyword::Jnz),
+                "jeq" => Token::Keyword(Keyword::Jeq),
+                "jne" => Token::Keyword(Keyword::Jne),
+                "jgt" => Token::Keyword(Keyword::Jgt),
+                "jlt" => Token::Keyword(Keyword::Jlt),
+                "jge" => Token::Keyword(Keyword::Jge),
+                "jle" => Token::Keyword(Keyword::Jle),
+                "ja" => Token::Keyword(Keyword::Ja),
+                "jb" => Token::Keyword(Keyword::Jb),
+                "jae" => Token::Keyword(Keyword::Jae),
+                "jbe" => Token::Keyword(Keyword::Jbe),
                 _ => Token::Identifier(word),
             };
             tokens.push(token);
@@ -292,8 +414,11 @@ pub fn tokenize_line(line: &str) -> Vec<Token> {
         // Skip unrecognized characters
         i += 1;
     }
+    if spans.len() < tokens.len() {
+        spans.push(offsets[token_start]..offsets[i]);
+    }
 
-    tokens
+    tokens.into_iter().zip(spans).collect()
 }
 
 #[cfg(test)]
@@ -342,6 +467,15 @@ mod tests {
         ]);
     }
 
+    #[test]
+    fn test_tokenize_negative_literal() {
+        assert_eq!(tokenize_line("if @x > -1 goto l")[3], Token::Number(u64::MAX));
+        assert_eq!(tokenize_line("@r0 := -0x10")[2], Token::Number(16u64.wrapping_neg()));
+        // After a value, '-' is subtraction
+        assert_eq!(tokenize_line("@r0 := @r1 -1")[3..], [Token::Minus, Token::Number(1)]);
+        assert_eq!(tokenize_line(".byte 1 -2")[2], Token::Number(2u64.wrapping_neg()));
+    }
+
     #[test]
     fn test_tokenize_swap() {
         let tokens = tokenize_line("@r2 <=> @r3");
@@ -352,6 +486,12 @@ mod tests {
         ]);
     }
 
+    #[test]
+    fn test_tokenize_spans() {
+        let spans: Vec<_> = tokenize_line_spanned("@r0 := \"é\", 42 ; done").into_iter().map(|(_, span)| span).collect();
+        assert_eq!(spans, vec![0..3, 4..6, 7..11, 13..15]);
+    }
+
     #[test]
     fn test_tokenize_string() {
         let tokens = tokenize_line("@ptr := \"Hello\"");
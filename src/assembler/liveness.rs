@@ -0,0 +1,123 @@
+//! Live ranges of named variables, used to hand a dead variable's register
+//! to a later one.
+//!
+//! A variable is live from its first mention to its last within the top
+//! level or the procedure it belongs to. A jump whose source and target
+//! bracket any part of that range stretches it over the whole jump, so a
+//! variable used inside a loop stays live until the loop's last backward
+//! jump. Scopes that use `call label` subroutines or a bare `return` share
+//! registers with code reached through the call stack, which this pass does
+//! not follow; their variables are never freed.
+
+use std::collections::HashMap;
+use super::codegen::try_parse_register_name;
+use super::parser::ast::*;
+
+/// Split a program into variable scopes: the top level, which carries on
+/// across procedure definitions, and each procedure from `proc` to
+/// `endproc`. Returns each scope's statement indices and whether it is a
+/// procedure.
+pub fn scopes(statements: &[SpannedStatement]) -> Vec<(Vec<usize>, bool)> {
+    let mut top = Vec::new();
+    let mut procs = Vec::new();
+    let mut current: Option<Vec<usize>> = None;
+    for (index, stmt) in statements.iter().enumerate() {
+        match (&stmt.node, &mut current) {
+            (Statement::Proc { .. }, _) => procs.extend(current.replace(vec![index])),
+            (Statement::EndProc, Some(body)) => {
+                body.push(index);
+                procs.extend(current.take());
+            }
+            (_, Some(body)) => body.push(index),
+            (_, None) => top.push(index),
+        }
+    }
+    procs.extend(current);
+    std::iter::once((top, false)).chain(procs.into_iter().map(|body| (body, true))).collect()
+}
+
+/// For each statement, the variables whose live range ends there
+pub fn last_uses(statements: &[SpannedStatement]) -> Vec<Vec<String>> {
+    let mut ends = vec![Vec::new(); statements.len()];
+    for (indices, in_proc) in scopes(statements) {
+        for (index, name) in scope_last_uses(statements, &indices, in_proc) {
+            ends[index].push(name);
+        }
+    }
+    ends
+}
+
+/// Last uses within the scope made of `indices`, as (statement index, variable)
+fn scope_last_uses<'a>(statements: &'a [SpannedStatement], indices: &[usize], in_proc: bool) -> Vec<(usize, String)> {
+    let scope = || indices.iter().map(|&index| (index, &statements[index]));
+    let mut labels = HashMap::new();
+    let mut ranges: HashMap<&'a str, (usize, usize)> = HashMap::new();
+    for (index, stmt) in scope() {
+        match &stmt.node {
+            Statement::Call(_) | Statement::CallReg(_) => return Vec::new(),
+            Statement::Return(_) if !in_proc => return Vec::new(),
+            Statement::Label(name) => {
+                labels.insert(name.as_str(), index);
+            }
+            _ => {}
+        }
+        let (writes, reads) = stmt.node.accesses();
+        for name in writes.into_iter().chain(reads) {
+            if try_parse_register_name(name).is_none() {
+                ranges.entry(name).and_modify(|range| range.1 = index).or_insert((index, index));
+            }
+        }
+    }
+
+    let jumps: Vec<(usize, usize)> = scope()
+        .flat_map(|(index, stmt)| match &stmt.node {
+            Statement::Goto(label) | Statement::If { label, .. } | Statement::Branch { label, .. } => vec![(index, label)],
+            Statement::Switch { labels, .. } => labels.iter().map(|label| (index, label)).collect(),
+            _ => vec![],
+        })
+        .filter_map(|(index, label)| labels.get(label.as_str()).map(|&target| (index.min(target), index.max(target))))
+        .collect();
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for range in ranges.values_mut() {
+            for &(low, high) in &jumps {
+                if range.0 <= high && range.1 >= low && range.1 < high {
+                    range.1 = high;
+                    changed = true;
+                }
+            }
+        }
+    }
+
+    let mut uses: Vec<(usize, String)> = ranges.into_iter().map(|(name, (_, last))| (last, name.to_string())).collect();
+    uses.sort();
+    uses
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::parser;
+    use crate::core::Register;
+
+    #[test]
+    fn test_last_uses() {
+        let source = "@a := 1\nprint @a\n@b := 2\nloop:\n@c := @b\n@b -= 1\nif @b > 0 goto loop\nprint @c\nhalt\n\
+            proc f(x)\nreturn @x\nendproc\n";
+        let ends = last_uses(&parser::parse(source).unwrap());
+        assert_eq!(ends[1], ["a"]);
+        assert_eq!(ends[6], ["b"]);
+        assert_eq!(ends[7], ["c"]);
+        assert_eq!(ends[10], ["x"]);
+        let code = crate::assembler::codegen::generate(parser::parse(source).unwrap()).unwrap();
+        assert_eq!((code.var_map["a"], code.var_map["b"]), (Register::R0, Register::R0));
+
+        // Top-level variables stay live across procedure definitions
+        let ends = last_uses(&parser::parse("@a := 1\nproc f()\n@a := 2\nendproc\nprint @a\n").unwrap());
+        assert_eq!((ends[0].len(), &ends[2], &ends[4]), (0, &vec!["a".to_string()], &vec!["a".to_string()]));
+
+        let ends = last_uses(&parser::parse("@a := 1\ncall sub\nhalt\nsub:\nreturn\n").unwrap());
+        assert!(ends.iter().all(Vec::is_empty));
+    }
+}
@@ -2,24 +2,258 @@
 //!
 //! Converts `.alya` source text into a `Program` of instructions.
 //!
-//! Pipeline: Source → Lexer → Parser → CodeGen → Program
+//! Pipeline: Source → Lexer → Parser → CodeGen → Program, with an optional
+//! warning pass over the generated code.
+//!
+//! To assemble from Rust code instead of text, use `ProgramBuilder`, which
+//! resolves jump and call labels the same way.
 
 pub mod lexer;
 pub mod parser;
+pub mod arrays;
+pub mod blocks;
 pub mod codegen;
+pub mod fold;
+pub mod liveness;
+pub mod warnings;
+
+pub use crate::instruction::ProgramBuilder;
 
-use crate::instruction::Program;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use crate::instruction::{Metadata, Program};
+use crate::instruction::validate::validate;
 use crate::error::VmError;
+use crate::linker::{ObjectFile, Relocation};
+use warnings::{Warning, WarningKind};
 
-/// Assemble source code into a program.
+/// Assemble source code into a program. `name` is the path the source was
+/// read from; `.include` paths are resolved relative to its directory.
 pub fn assemble(source: &str, name: &str) -> Result<Program, VmError> {
-    // Parse the source into AST statements
-    let statements = parser::parse(source)?;
+    build(source, name, false, false).map(|(program, _)| program)
+}
+
+/// Like `assemble`, also returning the warnings found in the source
+pub fn assemble_with_warnings(source: &str, name: &str) -> Result<(Program, Vec<Warning>), VmError> {
+    build(source, name, true, false).map(|(program, built)| (program, built.warnings))
+}
+
+/// Assemble source into an object file for `linker::link`, leaving labels
+/// and procedures it does not define to other objects
+pub fn assemble_object(source: &str, name: &str) -> Result<ObjectFile, VmError> {
+    assemble_object_with_warnings(source, name).map(|(object, _)| object)
+}
+
+/// Like `assemble_object`, also returning the warnings found in the source.
+/// Unused labels are not reported, since other objects may use them.
+pub fn assemble_object_with_warnings(source: &str, name: &str) -> Result<(ObjectFile, Vec<Warning>), VmError> {
+    let (program, built) = build(source, name, true, true)?;
+    let object = ObjectFile {
+        program,
+        data_symbols: built.data_symbols,
+        data_refs: built.data_refs,
+        code_refs: built.code_refs,
+        relocations: built.relocations.into_iter().map(|(index, symbol)| Relocation { index, symbol }).collect(),
+    };
+    let warnings = built.warnings.into_iter().filter(|warning| warning.kind != WarningKind::UnusedLabel).collect();
+    Ok((object, warnings))
+}
+
+/// Assemble source and run the bytecode validator over the result, without
+/// producing output. Every error is reported as an assembler error with the
+/// source line (and span) of the offending statement.
+pub fn check(source: &str, name: &str) -> Result<Program, VmError> {
+    check_with_warnings(source, name).map(|(program, _)| program)
+}
+
+/// Like `check`, also returning the warnings found in the source
+pub fn check_with_warnings(source: &str, name: &str) -> Result<(Program, Vec<Warning>), VmError> {
+    let (program, built) = build(source, name, true, false)?;
+    validate(&program).map_err(|e| {
+        let error = VmError::assembler(e.code(), e.message());
+        let Some(pc) = e.pc().filter(|&pc| pc < program.len()) else { return error };
+        let error = error.at_line(program.line_table[pc]);
+        match program.file_index(pc) {
+            0 => error.locate(source),
+            file => {
+                let file = &program.files[file];
+                error.in_file(file).locate(include_source(&built.includes, file))
+            }
+        }
+    })?;
+    Ok((program, built.warnings))
+}
+
+/// Parse source (with its `.include`s) and describe the statements as JSON;
+/// see `parser::json` for the format
+pub fn parse_to_json(source: &str, name: &str) -> Result<String, VmError> {
+    let parsed = parser::parse_program(source, name).map_err(|e| e.locate(source))?;
+    Ok(parser::json::to_json(name, &parsed.statements))
+}
+
+/// Code generated for a program, with the sources needed to locate errors
+struct Build {
+    includes: Vec<(Arc<str>, String)>,
+    warnings: Vec<Warning>,
+    data_symbols: BTreeMap<String, usize>,
+    data_refs: Vec<usize>,
+    code_refs: Vec<usize>,
+    relocations: Vec<(usize, String)>,
+}
+
+fn build(source: &str, name: &str, warn: bool, object: bool) -> Result<(Program, Build), VmError> {
+    // Parse the source (and any included files) into AST statements
+    let parsed = parser::parse_program(source, name).map_err(|e| e.locate(source))?;
+
+    // Generate instructions, line table, and symbols from AST
+    let includes = parsed.includes;
+    let statements = if warn { parsed.statements.clone() } else { Vec::new() };
+    let generate = if object { codegen::generate_object } else { codegen::generate };
+    let code = generate(parsed.statements).map_err(|e| {
+        let text = e.file().map_or(source, |file| include_source(&includes, file));
+        e.locate(text)
+    })?;
+    let warnings = if warn { warnings::check_warnings(&statements, &code) } else { Vec::new() };
+
+    let mut program = Program::with_data(name, code.instructions, code.data);
+    program.writable_len = code.writable_len;
+    program.line_table = code.line_table;
+    // Objects always name their file, so linked programs can tell them apart
+    if object || code.file_table.iter().any(Option::is_some) {
+        program.files = std::iter::once(name.to_string()).chain(includes.iter().map(|(file, _)| file.to_string())).collect();
+        program.file_table = code.file_table.iter()
+            .map(|file| file.as_ref().map_or(0, |file| 1 + includes.iter().position(|(name, _)| name == file).unwrap_or(0)))
+            .collect();
+    }
+    program.symbols = code.symbols;
+    program.resources = code.resources;
+    let stem = std::path::Path::new(name).file_stem().map_or(name.into(), |stem| stem.to_string_lossy());
+    program.metadata = Some(Metadata::new(stem));
+    program.variables = code.var_map.iter()
+        .filter(|(name, _)| !name.starts_with("__") && codegen::try_parse_register_name(name).is_none())
+        .map(|(name, &reg)| (name.clone(), reg))
+        .collect();
+    Ok((program, Build {
+        includes,
+        warnings,
+        data_symbols: code.data_symbols,
+        data_refs: code.data_refs,
+        code_refs: code.code_refs,
+        relocations: code.relocations,
+    }))
+}
+
+/// Source text of the included file `name`
+fn include_source<'a>(includes: &'a [(Arc<str>, String)], name: &str) -> &'a str {
+    includes.iter().find(|(file, _)| &**file == name).map_or("", |(_, source)| source)
+}
+
+/// Assemble the token text produced by `alya_asm!`, where statements are
+/// separated by `;` instead of newlines.
+pub fn assemble_inline(text: &str) -> Result<Program, VmError> {
+    assemble(&inline_to_source(text), "alya_asm")
+}
+
+/// Turn `stringify!` output back into line-based source. Older compilers put
+/// spaces between every token, so `@ r0 : = 1` is rejoined as `@r0 := 1`.
+fn inline_to_source(text: &str) -> String {
+    let mut source = String::new();
+    let mut chars = text.chars().map(|c| if c == '\n' { ' ' } else { c }).peekable();
+    let mut in_string = false;
+
+    while let Some(c) = chars.next() {
+        if in_string {
+            in_string = c != '"';
+            source.push(c);
+            continue;
+        }
+        match c {
+            '"' => {
+                in_string = true;
+                source.push(c);
+            }
+            ';' => source.push('\n'),
+            '@' => {
+                source.push(c);
+                while chars.next_if_eq(&' ').is_some() {}
+            }
+            ':' => {
+                while chars.next_if_eq(&' ').is_some() {}
+                if chars.next_if_eq(&'=').is_some() {
+                    source.push_str(":=");
+                } else {
+                    // A label ends its statement
+                    source.push_str(":\n");
+                }
+            }
+            '-' => {
+                // `- 1` after an operator is the literal `-1`
+                let after_value = source.trim_end().ends_with(|c: char| c.is_alphanumeric() || c == '_' || c == ']' || c == ')');
+                source.push(c);
+                if !after_value {
+                    while chars.next_if_eq(&' ').is_some() {}
+                }
+            }
+            '>' if source.ends_with("<= ") => {
+                source.pop();
+                source.push(c);
+            }
+            _ => source.push(c),
+        }
+    }
+    source.lines().map(str::trim).filter(|line| !line.is_empty()).collect::<Vec<_>>().join("\n")
+}
+
+/// Assemble Alya statements written inline in Rust, separated by `;`.
+/// The source is checked on first use, panicking with the assembler error if
+/// it is invalid, and each use returns a copy of the cached program.
+///
+/// ```
+/// let program = alya_vm::alya_asm! {
+///     @r0 := 42;
+///     print @r0;
+///     halt
+/// };
+/// let mut vm = alya_vm::VM::new();
+/// vm.print_immediately = false;
+/// vm.run(&program).unwrap();
+/// assert_eq!(vm.output(), &["42"]);
+/// ```
+#[macro_export]
+macro_rules! alya_asm {
+    ($($tokens:tt)*) => {{
+        static PROGRAM: ::std::sync::OnceLock<$crate::instruction::Program> = ::std::sync::OnceLock::new();
+        PROGRAM
+            .get_or_init(|| {
+                $crate::assembler::assemble_inline(stringify!($($tokens)*))
+                    .unwrap_or_else(|e| panic!("alya_asm!: {}", e))
+            })
+            .clone()
+    }};
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::{AssemblerError, ErrorCode};
+
+    #[test]
+    fn test_inline_to_source() {
+        assert_eq!(inline_to_source("@ r0 : = 1 ; loop : @ a <= > @ b"), "@r0 := 1\nloop :\n@a <=> @b");
+        assert_eq!(inline_to_source("@s :=\n\"a;b\"; halt"), "@s := \"a;b\"\nhalt");
+        assert_eq!(inline_to_source("@ a : = - 1 ; @ b : = @ a - 1"), "@a := -1\n@b := @a - 1");
+
+        let program = crate::alya_asm! { @n := 3; top: @n -= 1; if @n > 0 goto top; halt };
+        assert_eq!(program.symbols["top"], 1);
+    }
 
-    // Generate instructions and line table from AST
-    let (instructions, data, line_table) = codegen::generate(statements)?;
+    #[test]
+    fn test_check_reports_validator_errors_at_source_line() {
+        assert!(check("@r0 := 1\npush @r0\n@r0 := pop\nhalt\n", "ok").is_ok());
 
-    let mut program = Program::with_data(name, instructions, data);
-    program.line_table = line_table;
-    Ok(program)
+        let err = check("@r0 := 1\n  @r1 := pop\nhalt\n", "bad").unwrap_err();
+        assert_eq!((err.code(), err.line()), (ErrorCode::StackUnderflow, Some(2)));
+        let VmError::Assembler(AssemblerError { span: Some(span), .. }) = err else { panic!() };
+        assert_eq!((span.offset, span.len), (11, 10));
+    }
 }
@@ -1,10 +1,14 @@
 //! AST node types for the Alya assembler.
 
+use std::sync::Arc;
+
 /// A statement with source location metadata.
 #[derive(Debug, Clone, PartialEq)]
 pub struct SpannedStatement {
     pub node: Statement,
     pub line: usize,
+    /// Included file the statement came from; `None` for the file being assembled
+    pub file: Option<Arc<str>>,
 }
 
 /// A single statement in an Alya program.
@@ -12,6 +16,18 @@ pub struct SpannedStatement {
 pub enum Statement {
     /// Load an immediate value: @dest := value
     LoadImm { dest: String, value: u64 },
+
+    /// Load a named constant or the address of a data label: @dest := NAME
+    LoadConst { dest: String, name: String },
+
+    /// Load the instruction index of a code label: @dest := &label
+    LabelAddress { dest: String, label: String },
+
+    /// Compile-time constant definition: const NAME := value
+    Const { name: String, value: Operand },
+
+    /// Constants numbered from 0: enum Name { A, B, C }
+    Enum { name: String, variants: Vec<String> },
     
     /// Load address of a string literal: @dest := "string"
     LoadString { dest: String, value: String },
@@ -40,6 +56,12 @@ pub enum Statement {
     /// Peek: @dest := peek
     Peek(String),
 
+    /// Rand: @dest := rand
+    Rand(String),
+
+    /// Feature query: @dest := featquery leaf
+    FeatQuery { dest: String, leaf: u8 },
+
     /// Print: print @src
     Print(String),
 
@@ -49,32 +71,119 @@ pub enum Statement {
     /// Halt
     Halt,
 
+    /// Halt with an exit status: halt @src
+    Exit(String),
+
     /// Nop
     Nop,
 
+    /// Set up a BP frame with this many bytes of locals: enter N
+    Enter(u32),
+
+    /// Tear down the frame `enter` set up: leave
+    Leave,
+
+    /// Load a frame slot: @dest := local offset
+    LoadLocal { dest: String, offset: i32 },
+
+    /// Store to a frame slot: local offset := @src
+    StoreLocal { src: String, offset: i32 },
+
+    /// Software breakpoint: breakpoint
+    Breakpoint,
+
+    /// Turn instruction tracing on or off: trace_on / trace_off
+    Trace(bool),
+
     /// Label definition: name:
     Label(String),
 
     /// Unconditional jump: goto label
     Goto(String),
 
+    /// Jump table: switch @index goto first, second, ...
+    Switch { index: String, labels: Vec<String> },
+
     /// Conditional jump: if @left cmp @right goto label
     If { left: String, comparison: Comparison, right: Operand, label: String },
 
+    /// Raw flag comparison: compare @left @right
+    Compare { left: String, right: String },
+
+    /// Set the flags from a bitwise AND: test @left @right
+    Test { left: String, right: String },
+
+    /// Raw conditional jump on the current flags: jlt label
+    Branch { comparison: Comparison, label: String },
+
     /// Function call: call label
     Call(String),
 
+    /// Indirect call to the address in a variable: call @target
+    CallReg(String),
+
+    /// Procedure call: [@dest :=] call name(args)
+    CallProc { name: String, args: Vec<Operand>, dest: Option<String> },
+
+    /// Procedure start: proc name(a, b)
+    Proc { name: String, params: Vec<String> },
+
+    /// Procedure end: endproc
+    EndProc,
+
+    /// Encoded instructions copied into the program as they are: emit 0x01
+    Emit(Vec<u8>),
+
+    /// Load from a computed element: @dest := @base[@index * size + offset]
+    LoadElement { dest: String, base_var: String, element: Element },
+
+    /// Store to a computed element: @base[@index * size + offset] := value
+    StoreElement { base_var: String, element: Element, value: Operand },
+
+    /// Zero-filled heap array of `length` qwords: @arr := array length
+    ArrayAlloc { dest: String, length: Operand },
+
+    /// Heap array holding a copy of the values: @arr := [1, 2, 3]
+    ArrayLiteral { dest: String, values: Vec<Operand> },
+
+    /// Loop that runs while a comparison holds: while @a <cmp> value
+    While { left: String, comparison: Comparison, right: Operand },
+
+    /// Loop with no test, left with `break`: loop
+    Loop,
+
+    /// Jump past the `end` of the innermost loop: break
+    Break,
+
+    /// Jump back to the start of the innermost loop: continue
+    Continue,
+
+    /// Start of a block run only if a comparison holds: if @a <cmp> value
+    IfBlock { left: String, comparison: Comparison, right: Operand },
+
+    /// Start of the block run when the `if` block's comparison fails: else
+    Else,
+
+    /// End of a `while`, `loop` or `if` block: end
+    End,
+
     /// System call (ID in R0, Args in R1...)
     Syscall,
 
-    /// Return
-    Return,
+    /// Software interrupt: int vector
+    Int(u8),
 
-    /// Store value at address: store @value at @addr
-    Store { value_var: String, addr_var: String },
+    /// Return from an interrupt or trap handler: iret
+    IRet,
 
-    /// Load from address: @dest := load @addr
-    Load { dest_var: String, addr_var: String },
+    /// Return, optionally with a value for R0: return [value]
+    Return(Option<Operand>),
+
+    /// Store value at address: store[.size] @value at @addr
+    Store { value_var: String, addr_var: String, size: AccessSize },
+
+    /// Load from address: @dest := load[.size] @addr
+    Load { dest_var: String, addr_var: String, size: AccessSize },
 
     /// Indexed store: @base[@index] := @value
     StoreIndexed { base_var: String, index_var: String, value: Operand },
@@ -95,19 +204,178 @@ pub enum Statement {
     MemSet { dest_var: String, value_var: String, size_var: String },
 
     /// Floating point binary op: @dest := @left fop @right
-    FBinOp { dest: String, left: String, op: FBinOp, right: String },
+    FBinOp { dest: String, left: String, op: FBinOp, right: String, width: FloatWidth },
 
     /// Floating point unary op: @dest := fop @src
-    FUnaryOp { dest: String, op: FUnaryOp, src: String },
+    FUnaryOp { dest: String, op: FUnaryOp, src: String, width: FloatWidth },
 
     /// Floating point comparison: fcmp @left, @right
-    FCmp { left: String, right: String },
+    FCmp { left: String, right: String, width: FloatWidth },
 
     /// Bitwise extension unary op: @dest := bop @src
     BitUnaryOp { dest: String, op: BitUnaryOp, src: String },
 
     /// Bitwise rotation: @dest := @left rot @right
     BitRotOp { dest: String, left: String, op: BitRotOp, right: String },
+
+    /// Data declaration: [label:] .qword 1, 2, 3
+    Data { label: Option<String>, item: DataItem },
+    /// `.data` or `.rodata`: whether the data declarations that follow may
+    /// be written at runtime
+    Section { writable: bool },
+    /// `.registers N`: how many general-purpose registers the allocator
+    /// may use, 16 or 64
+    Registers(usize),
+}
+
+impl Statement {
+    /// Variables the statement writes and reads
+    pub fn accesses(&self) -> (Vec<&str>, Vec<&str>) {
+            match self {
+            Statement::LoadImm { dest, .. }
+            | Statement::LoadConst { dest, .. }
+            | Statement::LabelAddress { dest, .. }
+            | Statement::LoadString { dest, .. }
+            | Statement::ArrayLiteral { dest, .. }
+            | Statement::Pop(dest)
+            | Statement::Peek(dest)
+            | Statement::Rand(dest)
+            | Statement::LoadLocal { dest, .. }
+            | Statement::FeatQuery { dest, .. } => (vec![dest], vec![]),
+            Statement::MoveVar { dest, src }
+            | Statement::UnaryOp { dest, operand: src, .. }
+            | Statement::FUnaryOp { dest, src, .. }
+            | Statement::BitUnaryOp { dest, src, .. }
+            | Statement::Alloc { dest, size_var: src }
+            | Statement::Load { dest_var: dest, addr_var: src, .. } => (vec![dest], vec![src]),
+            Statement::LoadElement { dest, base_var, element } => {
+                (vec![dest], std::iter::once(base_var.as_str()).chain(element.variables()).collect())
+            }
+            Statement::StoreElement { base_var, element, value } => {
+                (vec![], std::iter::once(base_var.as_str()).chain(element.variables()).chain(variable_name(value)).collect())
+            }
+            Statement::ArrayAlloc { dest, length } => (vec![dest], variable_name(length).into_iter().collect()),
+            Statement::BinOp { dest, left, right, .. } => (vec![dest], [Some(left.as_str()), variable_name(right)].into_iter().flatten().collect()),
+            Statement::FBinOp { dest, left, right, .. } | Statement::BitRotOp { dest, left, right, .. } => (vec![dest], vec![left, right]),
+            Statement::LoadIndexed { dest, base_var, index_var } => (vec![dest], vec![base_var, index_var]),
+            // Compound assignment and swap read what they overwrite
+            Statement::CompoundAssign { dest, operand, .. } => (vec![dest], [Some(dest.as_str()), variable_name(operand)].into_iter().flatten().collect()),
+            Statement::Swap { left, right } => (vec![left, right], vec![left, right]),
+            Statement::Switch { index, .. } | Statement::CallReg(index) => (vec![], vec![index]),
+            Statement::Push(src) | Statement::Exit(src) | Statement::Print(src) | Statement::Debug(src) | Statement::Free { ptr_var: src } => (vec![], vec![src]),
+            Statement::CallProc { args, dest, .. } => (dest.iter().map(String::as_str).collect(), args.iter().filter_map(variable_name).collect()),
+            Statement::Return(Some(value)) => (vec![], variable_name(value).into_iter().collect()),
+            // Parameters are written by the caller
+            Statement::Proc { params, .. } => (params.iter().map(String::as_str).collect(), vec![]),
+            Statement::If { left, right, .. } | Statement::IfBlock { left, right, .. } | Statement::While { left, right, .. } => (vec![], [Some(left.as_str()), variable_name(right)].into_iter().flatten().collect()),
+            Statement::Compare { left, right } | Statement::Test { left, right } | Statement::FCmp { left, right, .. } => (vec![], vec![left, right]),
+            Statement::Store { value_var, addr_var, .. } => (vec![], vec![value_var, addr_var]),
+            Statement::StoreIndexed { base_var, index_var, value } => {
+                (vec![], [Some(base_var.as_str()), Some(index_var.as_str()), variable_name(value)].into_iter().flatten().collect())
+            }
+            Statement::StoreLocal { src, .. } => (vec![], vec![src]),
+            Statement::MemCopy { dest_var, src_var, size_var } => (vec![], vec![dest_var, src_var, size_var]),
+            Statement::MemSet { dest_var, value_var, size_var } => (vec![], vec![dest_var, value_var, size_var]),
+            Statement::Const { .. }
+            | Statement::Enum { .. }
+            | Statement::Halt
+            | Statement::Nop
+            | Statement::Enter(_)
+            | Statement::Leave
+            | Statement::Breakpoint
+            | Statement::Trace(_)
+            | Statement::Label(_)
+            | Statement::Goto(_)
+            | Statement::Branch { .. }
+            | Statement::Call(_)
+            | Statement::Syscall
+            | Statement::Int(_)
+            | Statement::IRet
+            | Statement::Emit(_)
+            | Statement::Return(None)
+            | Statement::EndProc
+            | Statement::Loop
+            | Statement::Break
+            | Statement::Continue
+            | Statement::Else
+            | Statement::End
+            | Statement::Data { .. }
+            | Statement::Section { .. }
+            | Statement::Registers(_) => (vec![], vec![]),
+        }
+    }
+}
+
+fn variable_name(operand: &Operand) -> Option<&str> {
+    match operand {
+        Operand::Variable(name) => Some(name),
+        _ => None,
+    }
+}
+
+/// Qword position inside `[...]`: `index * scale + offset` qwords past the
+/// base address
+#[derive(Debug, Clone, PartialEq)]
+pub struct Element {
+    pub index: Operand,
+    pub scale: Operand,
+    pub offset: Operand,
+}
+
+impl Element {
+    /// The index variable, if the element is a plain `[@index]`
+    pub fn as_index_var(&self) -> Option<String> {
+        match (&self.index, &self.scale, &self.offset) {
+            (Operand::Variable(index), Operand::Immediate(1), Operand::Immediate(0)) => Some(index.clone()),
+            _ => None,
+        }
+    }
+
+    /// Variables the position reads
+    fn variables(&self) -> impl Iterator<Item = &str> {
+        [&self.index, &self.scale, &self.offset].into_iter().filter_map(variable_name)
+    }
+}
+
+/// Contents of a data declaration
+#[derive(Debug, Clone, PartialEq)]
+pub enum DataItem {
+    /// `.byte`: one byte per value
+    Byte(Vec<Operand>),
+    /// `.word`: two little-endian bytes per value
+    Word(Vec<Operand>),
+    /// `.qword`: eight little-endian bytes per value
+    Qword(Vec<Operand>),
+    /// `.string`: the text followed by a NUL byte
+    String(String),
+    /// `.resource "name" "path"`: the bytes of a host file, read by the
+    /// parser relative to the source file
+    Resource { name: String, path: String, bytes: Vec<u8> },
+}
+
+/// Width of a `load` or `store`, named by its suffix
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessSize {
+    /// `.byte`: 8 bits
+    Byte,
+    /// `.word`: 16 bits
+    Word,
+    /// `.dword`: 32 bits
+    DWord,
+    /// No suffix: 64 bits
+    QWord,
+}
+
+impl AccessSize {
+    /// Size named by a `load`/`store` suffix, such as `byte`
+    pub fn from_suffix(suffix: &str) -> Option<Self> {
+        match suffix {
+            "byte" => Some(AccessSize::Byte),
+            "word" => Some(AccessSize::Word),
+            "dword" => Some(AccessSize::DWord),
+            _ => None,
+        }
+    }
 }
 
 /// Binary operators
@@ -123,6 +391,12 @@ pub enum BinOp {
     Xor,
     Shl,
     Shr,
+    /// `mulhi`: upper 64 bits of the product
+    MulHi,
+    /// `/`, `%` and `>>` with the `signed` suffix
+    SignedDiv,
+    SignedMod,
+    SignedShr,
 }
 
 /// Unary operators
@@ -153,13 +427,38 @@ pub enum Comparison {
     UnsignedLessThan,
     UnsignedGreaterEqual,
     UnsignedLessEqual,
+    /// Only reachable through the raw `jz`/`jnz` branches
+    Zero,
+    NotZero,
+}
+
+impl Comparison {
+    /// The comparison that holds exactly when this one does not
+    pub fn negate(self) -> Comparison {
+        match self {
+            Comparison::Equal => Comparison::NotEqual,
+            Comparison::NotEqual => Comparison::Equal,
+            Comparison::GreaterThan => Comparison::LessEqual,
+            Comparison::LessThan => Comparison::GreaterEqual,
+            Comparison::GreaterEqual => Comparison::LessThan,
+            Comparison::LessEqual => Comparison::GreaterThan,
+            Comparison::UnsignedGreaterThan => Comparison::UnsignedLessEqual,
+            Comparison::UnsignedLessThan => Comparison::UnsignedGreaterEqual,
+            Comparison::UnsignedGreaterEqual => Comparison::UnsignedLessThan,
+            Comparison::UnsignedLessEqual => Comparison::UnsignedGreaterThan,
+            Comparison::Zero => Comparison::NotZero,
+            Comparison::NotZero => Comparison::Zero,
+        }
+    }
 }
 
-/// An operand that can be either a variable name or immediate value
+/// An operand that can be a variable name, an immediate value, or a named
+/// constant or data label
 #[derive(Debug, Clone, PartialEq)]
 pub enum Operand {
     Variable(String),
     Immediate(u64),
+    Constant(String),
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -168,6 +467,8 @@ pub enum FBinOp {
     Sub,
     Mul,
     Div,
+    Min,
+    Max,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -175,8 +476,24 @@ pub enum FUnaryOp {
     Sqrt,
     Abs,
     Neg,
+    Floor,
+    Ceil,
+    Trunc,
+    Round, // ties to even
+    Class,
     ToFloat, // i2f
     ToInt,   // f2i
+    Demote,  // f64 -> f32
+    Promote, // f32 -> f64
+}
+
+/// Precision of a floating-point statement
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FloatWidth {
+    /// No suffix: f64 bits
+    F64,
+    /// `.f32`: an f32 in the low 32 bits
+    F32,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
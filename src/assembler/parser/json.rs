@@ -0,0 +1,207 @@
+//! JSON export of parsed statements, for editors and visualizers.
+//!
+//! The document lists one record per statement with its source line, the
+//! included file it came from (or `null`), a snake_case `kind` and that
+//! kind's fields:
+//!
+//! ```text
+//! {"line":3,"file":null,"kind":"bin_op","dest":"sum","left":"sum","op":"add","right":{"variable":"x"}}
+//! ```
+//!
+//! Operands are `{"variable":…}`, `{"immediate":…}` or `{"constant":…}`.
+
+use crate::instruction::disassembler::json_string;
+use super::ast::*;
+
+/// Turn a Debug variant name such as `GreaterEqual` into `greater_equal`
+fn snake_case(name: impl std::fmt::Debug) -> String {
+    let mut out = String::new();
+    for (i, c) in format!("{:?}", name).chars().enumerate() {
+        if c.is_ascii_uppercase() && i > 0 {
+            out.push('_');
+        }
+        out.push(c.to_ascii_lowercase());
+    }
+    json_string(&out)
+}
+
+fn operand(operand: &Operand) -> String {
+    match operand {
+        Operand::Variable(name) => format!("{{\"variable\":{}}}", json_string(name)),
+        Operand::Immediate(value) => format!("{{\"immediate\":{}}}", value),
+        Operand::Constant(name) => format!("{{\"constant\":{}}}", json_string(name)),
+    }
+}
+
+fn element_json(element: &Element) -> String {
+    format!(
+        "{{\"index\":{},\"scale\":{},\"offset\":{}}}",
+        operand(&element.index), operand(&element.scale), operand(&element.offset)
+    )
+}
+
+fn list<T>(items: &[T], item: impl Fn(&T) -> String) -> String {
+    format!("[{}]", items.iter().map(item).collect::<Vec<_>>().join(","))
+}
+
+fn optional(name: &Option<String>) -> String {
+    name.as_deref().map_or("null".to_string(), json_string)
+}
+
+/// Kind and fields of one statement
+fn fields(node: &Statement) -> (&'static str, Vec<(&'static str, String)>) {
+    let s = |text: &String| json_string(text);
+    match node {
+        Statement::LoadImm { dest, value } => ("load_imm", vec![("dest", s(dest)), ("value", value.to_string())]),
+        Statement::LoadConst { dest, name } => ("load_const", vec![("dest", s(dest)), ("name", s(name))]),
+        Statement::LabelAddress { dest, label } => ("label_address", vec![("dest", s(dest)), ("label", s(label))]),
+        Statement::Const { name, value } => ("const", vec![("name", s(name)), ("value", operand(value))]),
+        Statement::Enum { name, variants } => ("enum", vec![("name", s(name)), ("variants", list(variants, s))]),
+        Statement::LoadString { dest, value } => ("load_string", vec![("dest", s(dest)), ("value", s(value))]),
+        Statement::MoveVar { dest, src } => ("move", vec![("dest", s(dest)), ("src", s(src))]),
+        Statement::Swap { left, right } => ("swap", vec![("left", s(left)), ("right", s(right))]),
+        Statement::BinOp { dest, left, op, right } => {
+            ("bin_op", vec![("dest", s(dest)), ("left", s(left)), ("op", snake_case(op)), ("right", operand(right))])
+        }
+        Statement::UnaryOp { dest, op, operand } => ("unary_op", vec![("dest", s(dest)), ("op", snake_case(op)), ("operand", s(operand))]),
+        Statement::CompoundAssign { dest, op, operand: value } => {
+            ("compound_assign", vec![("dest", s(dest)), ("op", snake_case(op)), ("operand", operand(value))])
+        }
+        Statement::Push(src) => ("push", vec![("src", s(src))]),
+        Statement::Pop(dest) => ("pop", vec![("dest", s(dest))]),
+        Statement::Peek(dest) => ("peek", vec![("dest", s(dest))]),
+        Statement::Rand(dest) => ("rand", vec![("dest", s(dest))]),
+        Statement::FeatQuery { dest, leaf } => ("feature_query", vec![("dest", s(dest)), ("leaf", leaf.to_string())]),
+        Statement::Print(src) => ("print", vec![("src", s(src))]),
+        Statement::Debug(src) => ("debug", vec![("src", s(src))]),
+        Statement::Halt => ("halt", vec![]),
+        Statement::Exit(src) => ("exit", vec![("src", s(src))]),
+        Statement::Nop => ("nop", vec![]),
+        Statement::Enter(locals_size) => ("enter", vec![("locals_size", locals_size.to_string())]),
+        Statement::Leave => ("leave", vec![]),
+        Statement::LoadLocal { dest, offset } => ("load_local", vec![("dest", s(dest)), ("offset", offset.to_string())]),
+        Statement::StoreLocal { src, offset } => ("store_local", vec![("src", s(src)), ("offset", offset.to_string())]),
+        Statement::Breakpoint => ("breakpoint", vec![]),
+        Statement::Trace(on) => ("trace", vec![("on", on.to_string())]),
+        Statement::Label(name) => ("label", vec![("name", s(name))]),
+        Statement::Goto(label) => ("goto", vec![("label", s(label))]),
+        Statement::Switch { index, labels } => ("switch", vec![("index", s(index)), ("labels", list(labels, s))]),
+        Statement::If { left, comparison, right, label } => ("if", vec![
+            ("left", s(left)), ("comparison", snake_case(comparison)), ("right", operand(right)), ("label", s(label)),
+        ]),
+        Statement::Compare { left, right } => ("compare", vec![("left", s(left)), ("right", s(right))]),
+        Statement::Test { left, right } => ("test", vec![("left", s(left)), ("right", s(right))]),
+        Statement::Branch { comparison, label } => ("branch", vec![("comparison", snake_case(comparison)), ("label", s(label))]),
+        Statement::Call(label) => ("call", vec![("label", s(label))]),
+        Statement::CallReg(target) => ("call_reg", vec![("target", s(target))]),
+        Statement::CallProc { name, args, dest } => {
+            ("call_proc", vec![("name", s(name)), ("args", list(args, operand)), ("dest", optional(dest))])
+        }
+        Statement::Proc { name, params } => ("proc", vec![("name", s(name)), ("params", list(params, s))]),
+        Statement::EndProc => ("end_proc", vec![]),
+        Statement::Emit(bytes) => ("emit", vec![("bytes", list(bytes, u8::to_string))]),
+        Statement::LoadElement { dest, base_var, element } => {
+            ("load_element", vec![("dest", s(dest)), ("base", s(base_var)), ("element", element_json(element))])
+        }
+        Statement::StoreElement { base_var, element, value } => {
+            ("store_element", vec![("base", s(base_var)), ("element", element_json(element)), ("value", operand(value))])
+        }
+        Statement::ArrayAlloc { dest, length } => ("array_alloc", vec![("dest", s(dest)), ("length", operand(length))]),
+        Statement::ArrayLiteral { dest, values } => ("array_literal", vec![("dest", s(dest)), ("values", list(values, operand))]),
+        Statement::While { left, comparison, right } => {
+            ("while", vec![("left", s(left)), ("comparison", snake_case(comparison)), ("right", operand(right))])
+        }
+        Statement::Loop => ("loop", vec![]),
+        Statement::Break => ("break", vec![]),
+        Statement::Continue => ("continue", vec![]),
+        Statement::IfBlock { left, comparison, right } => {
+            ("if_block", vec![("left", s(left)), ("comparison", snake_case(comparison)), ("right", operand(right))])
+        }
+        Statement::Else => ("else", vec![]),
+        Statement::End => ("end", vec![]),
+        Statement::Syscall => ("syscall", vec![]),
+        Statement::Int(vector) => ("int", vec![("vector", vector.to_string())]),
+        Statement::IRet => ("iret", vec![]),
+        Statement::Return(value) => ("return", vec![("value", value.as_ref().map_or("null".to_string(), operand))]),
+        Statement::Store { value_var, addr_var, size } => {
+            ("store", vec![("value", s(value_var)), ("addr", s(addr_var)), ("size", snake_case(size))])
+        }
+        Statement::Load { dest_var, addr_var, size } => {
+            ("load", vec![("dest", s(dest_var)), ("addr", s(addr_var)), ("size", snake_case(size))])
+        }
+        Statement::StoreIndexed { base_var, index_var, value } => {
+            ("store_indexed", vec![("base", s(base_var)), ("index", s(index_var)), ("value", operand(value))])
+        }
+        Statement::LoadIndexed { dest, base_var, index_var } => {
+            ("load_indexed", vec![("dest", s(dest)), ("base", s(base_var)), ("index", s(index_var))])
+        }
+        Statement::Alloc { dest, size_var } => ("alloc", vec![("dest", s(dest)), ("size", s(size_var))]),
+        Statement::Free { ptr_var } => ("free", vec![("ptr", s(ptr_var))]),
+        Statement::MemCopy { dest_var, src_var, size_var } => {
+            ("memcpy", vec![("dest", s(dest_var)), ("src", s(src_var)), ("size", s(size_var))])
+        }
+        Statement::MemSet { dest_var, value_var, size_var } => {
+            ("memset", vec![("dest", s(dest_var)), ("value", s(value_var)), ("size", s(size_var))])
+        }
+        Statement::FBinOp { dest, left, op, right, width } => ("float_bin_op", vec![
+            ("dest", s(dest)), ("left", s(left)), ("op", snake_case(op)), ("right", s(right)), ("width", snake_case(width)),
+        ]),
+        Statement::FUnaryOp { dest, op, src, width } => {
+            ("float_unary_op", vec![("dest", s(dest)), ("op", snake_case(op)), ("src", s(src)), ("width", snake_case(width))])
+        }
+        Statement::FCmp { left, right, width } => ("float_compare", vec![("left", s(left)), ("right", s(right)), ("width", snake_case(width))]),
+        Statement::BitUnaryOp { dest, op, src } => ("bit_unary_op", vec![("dest", s(dest)), ("op", snake_case(op)), ("src", s(src))]),
+        Statement::BitRotOp { dest, left, op, right } => {
+            ("bit_rot_op", vec![("dest", s(dest)), ("left", s(left)), ("op", snake_case(op)), ("right", s(right))])
+        }
+        Statement::Data { label, item } => {
+            let (directive, values) = match item {
+                DataItem::Byte(values) => ("byte", list(values, operand)),
+                DataItem::Word(values) => ("word", list(values, operand)),
+                DataItem::Qword(values) => ("qword", list(values, operand)),
+                DataItem::String(text) => ("string", json_string(text)),
+                DataItem::Resource { name, path, bytes } => ("resource", format!(
+                    "{{\"name\":{},\"path\":{},\"size\":{}}}", json_string(name), json_string(path), bytes.len()
+                )),
+            };
+            ("data", vec![("label", optional(label)), ("directive", json_string(directive)), ("values", values)])
+        }
+        Statement::Section { writable } => ("section", vec![("writable", writable.to_string())]),
+        Statement::Registers(count) => ("registers", vec![("count", count.to_string())]),
+    }
+}
+
+/// JSON document with one record per statement of the program `name`
+pub fn to_json(name: &str, statements: &[SpannedStatement]) -> String {
+    let records: Vec<String> = statements.iter().map(|stmt| {
+        let (kind, fields) = fields(&stmt.node);
+        let mut record = format!(
+            "{{\"line\":{},\"file\":{},\"kind\":{}",
+            stmt.line, stmt.file.as_deref().map_or("null".to_string(), json_string), json_string(kind)
+        );
+        for (key, value) in fields {
+            record.push_str(&format!(",{}:{}", json_string(key), value));
+        }
+        record.push('}');
+        record
+    }).collect();
+
+    format!("{{\"name\":{},\"statements\":[\n  {}\n]}}\n", json_string(name), records.join(",\n  "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::parser::parse;
+
+    #[test]
+    fn test_statements_to_json() {
+        let json = to_json("a.alya", &parse("@sum := @sum + @x\nif @sum >= 10 goto done\nmsg: .string \"hi\"\ndone:\n").unwrap());
+        assert!(json.starts_with("{\"name\":\"a.alya\",\"statements\":[\n"));
+        assert!(json.contains(
+            "{\"line\":1,\"file\":null,\"kind\":\"bin_op\",\"dest\":\"sum\",\"left\":\"sum\",\"op\":\"add\",\"right\":{\"variable\":\"x\"}}"
+        ));
+        assert!(json.contains("\"kind\":\"if\",\"left\":\"sum\",\"comparison\":\"greater_equal\",\"right\":{\"immediate\":10}"));
+        assert!(json.contains("\"kind\":\"data\",\"label\":\"msg\",\"directive\":\"string\",\"values\":\"hi\"}"));
+    }
+}
@@ -1,7 +1,8 @@
 //! Parser module.
 
 pub mod ast;
+pub mod json;
 pub mod parse;
 
-pub use parse::parse;
+pub use parse::{parse, parse_program, ParsedProgram};
 pub use ast::*;
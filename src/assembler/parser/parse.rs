@@ -1,57 +1,272 @@
 //! Parser — converts source lines into AST statements.
-
-use crate::assembler::lexer::token::{Token, Keyword, tokenize_line};
-use crate::error::VmError;
+//!
+//! `.include "file.alya"` splices another source file in place. Paths are
+//! relative to the including file, and a file that (indirectly) includes
+//! itself is an error.
+//!
+//! Data declarations go in the read-only data section until `.data`
+//! switches to the writable one; `.rodata` switches back.
+//!
+//! `.resource "name" "path"` embeds the bytes of a host file in the data
+//! section, with the path resolved the same way.
+//!
+//! `/`, `%` and `>>` treat their operands as unsigned; a trailing `signed`,
+//! as in `@q := @a / @b signed`, makes them signed, the way `unsigned`
+//! does for comparisons.
+//!
+//! `load` and `store` move 64 bits; the `.byte`, `.word` (16-bit) and
+//! `.dword` (32-bit) suffixes, as in `@c := load.byte @p`, move less, with
+//! loads zero-extending.
+//!
+//! `test @a @b` sets the flags from `@a & @b` for a following `jz`/`jnz`,
+//! the way `compare` does from `@a - @b`.
+//!
+//! `switch @i goto a, b, c` jumps to the `@i`-th label through a jump table,
+//! falling through to the next statement when `@i` is past the last one.
+//!
+//! `@f := &label` loads the address of a code label, and `call @f` calls
+//! whatever address a register holds.
+//!
+//! `enter N` pushes BP, points BP at the saved value and reserves N bytes
+//! of zeroed locals below it (`@bp - 8` is the first qword); `leave` drops
+//! them and restores BP. The register allocator does not use these frames.
+//!
+//! `.f32` after `fadd`, `fsub`, `fmul`, `fdiv`, `fsqrt` or `fcmp` works on
+//! single-precision values in the low 32 bits of each register. `fdemote`
+//! and `fpromote` convert between them and the usual f64.
+//!
+//! `fmin` and `fmax` return the other operand when one is NaN. `fclass @d @x`
+//! sets one of the NaN, infinite, zero, subnormal or normal bits in `@d`
+//! (1, 2, 4, 8 and 16), plus 32 when `@x` is negative.
+//!
+//! `@x := local -8` and `local -8 := @x` load and store the qword at a
+//! signed byte offset from BP, such as a local reserved by `enter`.
+//!
+//! `@x := rand` loads the next value of the VM's seeded random generator.
+//!
+//! `@f := featquery 0` loads the bitmask of optional extensions the VM
+//! implements (see `core::features`); other leaves read as 0.
+//!
+//! `breakpoint` stops the debugger's `continue` as if a breakpoint were set
+//! there, and runs as a `nop` outside it. `trace_on` and `trace_off` switch
+//! instruction tracing.
+//!
+//! `int 40` calls the handler syscall 9 registered for vector 40, and
+//! `iret` returns from it, restoring the flags. Faults such as division
+//! by zero trap to the handlers for vectors 0-3 in the same way. Timer
+//! handlers (syscall 8) are called without pushing the flags and end with
+//! `return` instead.
+//!
+//! `halt @x` stops the program with `@x` as its exit status, which `run`
+//! returns and the CLI exits with (255 if it is outside 0-255). A plain
+//! `halt` exits with 0.
+//!
+//! `.registers 64`, before the first statement that emits code, lets the
+//! register allocator use the extended bank R16-R63 as well as R0-R15.
+//! Procedure arguments still go in R1-R4 and on the stack.
+//!
+//! `.repeat N` ... `.endrepeat` emits the statements between them N times.
+//! Blocks nest but cannot span files, and a label inside one is defined
+//! once per copy, so it is a duplicate unless N is at most 1.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use crate::assembler::lexer::token::{Token, Keyword, tokenize_line_spanned};
+use crate::core::Register;
+use crate::error::{ErrorCode, VmError};
 use super::ast::*;
 
-/// Parse source code into a list of statements.
+/// Statements of a program with every file `.include` pulled in
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParsedProgram {
+    pub statements: Vec<SpannedStatement>,
+    /// Name and source of each included file, in the order first included
+    pub includes: Vec<(Arc<str>, String)>,
+}
+
+/// Parse source code into a list of statements. Included paths are
+/// resolved relative to the current directory.
 pub fn parse(source: &str) -> Result<Vec<SpannedStatement>, VmError> {
-    let mut statements = Vec::new();
+    parse_program(source, "").map(|parsed| parsed.statements)
+}
 
-    for (line_num, line) in source.lines().enumerate() {
-        let trimmed = line.trim();
-        if trimmed.is_empty() || trimmed.starts_with(';') {
-            continue;
-        }
+/// Parse the program in `source`, read from `path`, expanding `.include`
+/// directives relative to the including file's directory. Errors inside an
+/// included file name it and are already located in its source.
+pub fn parse_program(source: &str, path: &str) -> Result<ParsedProgram, VmError> {
+    let mut parser = IncludeParser {
+        program: ParsedProgram { statements: Vec::new(), includes: Vec::new() },
+        stack: fs::canonicalize(path).into_iter().collect(),
+    };
+    let dir = Path::new(path).parent().unwrap_or(Path::new("")).to_path_buf();
+    parser.parse_source(source, None, &dir)?;
+    Ok(parser.program)
+}
 
-        let tokens = tokenize_line(trimmed);
-        if tokens.is_empty() {
-            continue;
-        }
+struct IncludeParser {
+    program: ParsedProgram,
+    /// Canonical paths of the files currently being parsed, outermost first
+    stack: Vec<PathBuf>,
+}
 
-        let actual_line = line_num + 1;
-        let stmt_node = parse_line(&tokens, actual_line)
-            .map_err(|e| VmError::Assembler(format!("Line {}: {}", actual_line, e)))?;
+impl IncludeParser {
+    fn parse_source(&mut self, source: &str, file: Option<Arc<str>>, dir: &Path) -> Result<(), VmError> {
+        // Open `.repeat` blocks: count, index of the first statement, line
+        let mut repeats: Vec<(u64, usize, usize)> = Vec::new();
+        for (line_num, line) in source.lines().enumerate() {
+            let trimmed = line.trim();
+            if trimmed.is_empty() || trimmed.starts_with(';') {
+                continue;
+            }
 
-        if let Some(node) = stmt_node {
-            statements.push(SpannedStatement {
-                node,
-                line: actual_line,
-            });
+            let spanned = tokenize_line_spanned(trimmed);
+            if spanned.is_empty() {
+                continue;
+            }
+            let tokens: Vec<Token> = spanned.iter().map(|(token, _)| token.clone()).collect();
+
+            let actual_line = line_num + 1;
+            let syntax_error = |e: LineError| {
+                // Point at the offending token, or just past the end of the line
+                let indent = line.len() - line.trim_start().len();
+                let (offset, len) = match spanned.get(e.token) {
+                    Some((_, span)) => (span.start, span.len()),
+                    None => (trimmed.len(), 0),
+                };
+                VmError::assembler(ErrorCode::Syntax, e.message).at_line(actual_line).at_token(indent + offset, len)
+            };
+
+            if tokens[0] == Token::Directive("include".to_string()) {
+                let Some(Token::StringLiteral(include)) = tokens.get(1) else {
+                    return Err(syntax_error(LineError::at(1, "Expected \"path\" after '.include'")));
+                };
+                self.include(&dir.join(include)).map_err(|e| e.at_line(actual_line))?;
+                continue;
+            }
+            if tokens[0] == Token::Directive("repeat".to_string()) {
+                let [_, Token::Number(count)] = tokens[..] else {
+                    return Err(syntax_error(LineError::at(1, "Expected a repeat count after '.repeat'")));
+                };
+                repeats.push((count, self.program.statements.len(), actual_line));
+                continue;
+            }
+            if tokens[0] == Token::Directive("endrepeat".to_string()) {
+                let Some((count, start, _)) = repeats.pop() else {
+                    return Err(syntax_error(LineError::at(0, "'.endrepeat' without '.repeat'")));
+                };
+                let body = self.program.statements.split_off(start);
+                for _ in 0..count {
+                    self.program.statements.extend(body.iter().cloned());
+                }
+                continue;
+            }
+
+            let mut stmt_node = parse_line(&tokens).map_err(syntax_error)?;
+            if let Some(Statement::Data { item: DataItem::Resource { path, bytes, .. }, .. }) = &mut stmt_node {
+                *path = dir.join(&*path).display().to_string();
+                *bytes = fs::read(&*path).map_err(|e| {
+                    VmError::assembler(ErrorCode::InvalidSource, format!("Cannot read resource '{}': {}", path, e)).at_line(actual_line)
+                })?;
+            }
+
+            if let Some(node) = stmt_node {
+                self.program.statements.push(SpannedStatement {
+                    node,
+                    line: actual_line,
+                    file: file.clone(),
+                });
+            }
         }
+        if let Some(&(_, _, line)) = repeats.last() {
+            return Err(VmError::assembler(ErrorCode::Syntax, "'.repeat' without '.endrepeat'").at_line(line));
+        }
+        Ok(())
     }
 
-    Ok(statements)
+    /// Parse the file at `path` in place of an `.include` line
+    fn include(&mut self, path: &Path) -> Result<(), VmError> {
+        let name = path.display().to_string();
+        let canonical = fs::canonicalize(path).map_err(|e| {
+            VmError::assembler(ErrorCode::InvalidSource, format!("Cannot include '{}': {}", name, e))
+        })?;
+        if let Some(start) = self.stack.iter().position(|p| *p == canonical) {
+            let cycle: Vec<String> = self.stack[start..].iter().chain([&canonical]).map(|p| p.display().to_string()).collect();
+            return Err(VmError::assembler(ErrorCode::InvalidSource, format!("Include cycle: {}", cycle.join(" -> "))));
+        }
+        let source = fs::read_to_string(path).map_err(|e| {
+            VmError::assembler(ErrorCode::InvalidSource, format!("Cannot include '{}': {}", name, e))
+        })?;
+
+        let file: Arc<str> = Arc::from(name.as_str());
+        if !self.program.includes.iter().any(|(included, _)| *included == file) {
+            self.program.includes.push((file.clone(), source.clone()));
+        }
+        self.stack.push(canonical);
+        let dir = path.parent().unwrap_or(Path::new("")).to_path_buf();
+        self.parse_source(&source, Some(file), &dir)
+            .map_err(|e| if e.file().is_some() { e } else { e.locate(&source).in_file(&name) })?;
+        self.stack.pop();
+        Ok(())
+    }
 }
 
 /// Parse a single line of tokens into a statement.
-fn parse_line(tokens: &[Token], _line_num: usize) -> Result<Option<Statement>, String> {
+fn parse_line(tokens: &[Token]) -> Result<Option<Statement>, LineError> {
     if tokens.is_empty() {
         return Ok(None);
     }
 
-
-
     // Check for label definition: identifier followed by ':'
     if let Token::Identifier(name) = &tokens[0] {
         if tokens.len() > 1 && tokens[1] == Token::Colon {
+            // table: .qword 1, 2, 3
+            if let Some(Token::Directive(directive)) = tokens.get(2) {
+                let item = parse_data(directive, tokens, 3)?;
+                return Ok(Some(Statement::Data { label: Some(name.clone()), item }));
+            }
             return Ok(Some(Statement::Label(name.clone())));
         }
     }
 
-    // halt
+    if let Token::Directive(name) = &tokens[0] {
+        if name == "data" || name == "rodata" {
+            if tokens.len() > 1 {
+                return Err(LineError::at(1, format!("'.{}' takes no operands", name)));
+            }
+            return Ok(Some(Statement::Section { writable: name == "data" }));
+        }
+        if name == "registers" {
+            return match tokens {
+                [_, Token::Number(count)] if [Register::GP_COUNT, Register::EXTENDED_GP_COUNT].contains(&(*count as usize)) => {
+                    Ok(Some(Statement::Registers(*count as usize)))
+                }
+                _ => Err(LineError::at(1, format!(
+                    "Expected '.registers {}' or '.registers {}'", Register::GP_COUNT, Register::EXTENDED_GP_COUNT
+                ))),
+            };
+        }
+        let item = parse_data(name, tokens, 1)?;
+        return Ok(Some(Statement::Data { label: None, item }));
+    }
+
+    // const NAME := value
+    if matches!(&tokens[0], Token::Keyword(Keyword::Const)) {
+        return parse_const(tokens);
+    }
+
+    // enum Name { A, B, C }
+    if matches!(&tokens[0], Token::Keyword(Keyword::Enum)) {
+        return parse_enum(tokens);
+    }
+
+    // halt / halt @status
     if matches!(&tokens[0], Token::Keyword(Keyword::Halt)) {
-        return Ok(Some(Statement::Halt));
+        return match tokens.get(1) {
+            None => Ok(Some(Statement::Halt)),
+            Some(Token::Register(name)) => Ok(Some(Statement::Exit(name.clone()))),
+            Some(_) => Err(LineError::at(1, "Expected a register holding the exit status after 'halt'")),
+        };
     }
 
     // nop
@@ -59,9 +274,54 @@ fn parse_line(tokens: &[Token], _line_num: usize) -> Result<Option<Statement>, S
         return Ok(Some(Statement::Nop));
     }
 
-    // return
+    // enter size / leave
+    if matches!(&tokens[0], Token::Keyword(Keyword::Enter)) {
+        return match tokens {
+            [_, Token::Number(size)] if *size <= u32::MAX as u64 => Ok(Some(Statement::Enter(*size as u32))),
+            _ => Err(LineError::at(1, "Expected the size of the locals in bytes after 'enter'")),
+        };
+    }
+    if matches!(&tokens[0], Token::Keyword(Keyword::Leave)) {
+        return Ok(Some(Statement::Leave));
+    }
+
+    // local offset := @src
+    if matches!(&tokens[0], Token::Keyword(Keyword::Local)) {
+        let offset = tokens.get(1).and_then(frame_offset);
+        return match (offset, tokens.get(2), tokens.get(3)) {
+            (Some(offset), Some(Token::Assign), Some(Token::Register(src))) => {
+                Ok(Some(Statement::StoreLocal { src: src.clone(), offset }))
+            }
+            (None, ..) => Err(LineError::at(1, "Expected a 32-bit signed offset from BP after 'local'")),
+            _ => Err(LineError::at(3, "Expected 'local offset := @src'")),
+        };
+    }
+
+    // breakpoint / trace_on / trace_off
+    match &tokens[0] {
+        Token::Keyword(Keyword::Breakpoint) => return Ok(Some(Statement::Breakpoint)),
+        Token::Keyword(Keyword::TraceOn) => return Ok(Some(Statement::Trace(true))),
+        Token::Keyword(Keyword::TraceOff) => return Ok(Some(Statement::Trace(false))),
+        _ => {}
+    }
+
+    // return [value]
     if matches!(&tokens[0], Token::Keyword(Keyword::Return)) {
-        return Ok(Some(Statement::Return));
+        if tokens.len() == 1 {
+            return Ok(Some(Statement::Return(None)));
+        }
+        return match parse_operand(tokens, 1) {
+            Some((value, _)) => Ok(Some(Statement::Return(Some(value)))),
+            None => Err(LineError::at(1, "Expected register, number or constant after 'return'")),
+        };
+    }
+
+    // proc name(a, b) / endproc
+    if matches!(&tokens[0], Token::Keyword(Keyword::Proc)) {
+        return parse_proc(tokens);
+    }
+    if matches!(&tokens[0], Token::Keyword(Keyword::EndProc)) {
+        return Ok(Some(Statement::EndProc));
     }
 
     // syscall
@@ -69,6 +329,17 @@ fn parse_line(tokens: &[Token], _line_num: usize) -> Result<Option<Statement>, S
         return Ok(Some(Statement::Syscall));
     }
 
+    // int vector / iret
+    if matches!(&tokens[0], Token::Keyword(Keyword::Int)) {
+        return match tokens.get(1) {
+            Some(Token::Number(vector)) if *vector <= 0xff => Ok(Some(Statement::Int(*vector as u8))),
+            _ => Err(LineError::at(1, "Expected an interrupt vector from 0 to 255 after 'int'")),
+        };
+    }
+    if matches!(&tokens[0], Token::Keyword(Keyword::IRet)) {
+        return Ok(Some(Statement::IRet));
+    }
+
     // print @reg
     if matches!(&tokens[0], Token::Keyword(Keyword::Print)) {
         if tokens.len() >= 2 {
@@ -76,7 +347,7 @@ fn parse_line(tokens: &[Token], _line_num: usize) -> Result<Option<Statement>, S
                 return Ok(Some(Statement::Print(name.clone())));
             }
         }
-        return Err("Expected register after 'print'".to_string());
+        return Err(LineError::at(1, "Expected register after 'print'"));
     }
 
     // debug @reg
@@ -86,7 +357,7 @@ fn parse_line(tokens: &[Token], _line_num: usize) -> Result<Option<Statement>, S
                 return Ok(Some(Statement::Debug(name.clone())));
             }
         }
-        return Err("Expected register after 'debug'".to_string());
+        return Err(LineError::at(1, "Expected register after 'debug'"));
     }
 
     // push @reg
@@ -96,7 +367,7 @@ fn parse_line(tokens: &[Token], _line_num: usize) -> Result<Option<Statement>, S
                 return Ok(Some(Statement::Push(name.clone())));
             }
         }
-        return Err("Expected register after 'push'".to_string());
+        return Err(LineError::at(1, "Expected register after 'push'"));
     }
 
     // goto label
@@ -106,17 +377,76 @@ fn parse_line(tokens: &[Token], _line_num: usize) -> Result<Option<Statement>, S
                 return Ok(Some(Statement::Goto(name.clone())));
             }
         }
-        return Err("Expected label after 'goto'".to_string());
+        return Err(LineError::at(1, "Expected label after 'goto'"));
     }
 
-    // call label
+    // switch @index goto first, second, ...
+    if matches!(&tokens[0], Token::Keyword(Keyword::Switch)) {
+        let Some(Token::Register(index)) = tokens.get(1) else {
+            return Err(LineError::at(1, "Expected register after 'switch'"));
+        };
+        if !matches!(tokens.get(2), Some(Token::Keyword(Keyword::Goto))) {
+            return Err(LineError::at(2, "Expected 'goto' after the switch index"));
+        }
+        let mut labels = Vec::new();
+        for (i, token) in tokens.iter().enumerate().skip(3) {
+            match token {
+                Token::Identifier(label) => labels.push(label.clone()),
+                _ => return Err(LineError::at(i, "Expected label in switch table")),
+            }
+        }
+        if labels.is_empty() {
+            return Err(LineError::at(3, "Expected at least one label after 'switch ... goto'"));
+        }
+        return Ok(Some(Statement::Switch { index: index.clone(), labels }));
+    }
+
+    // call label / call name(args)
     if matches!(&tokens[0], Token::Keyword(Keyword::Call)) {
+        if tokens.get(2) == Some(&Token::LeftParen) {
+            return parse_call(tokens, 1, None);
+        }
         if tokens.len() >= 2 {
-            if let Token::Identifier(name) = &tokens[1] {
-                return Ok(Some(Statement::Call(name.clone())));
+            match &tokens[1] {
+                Token::Identifier(name) => return Ok(Some(Statement::Call(name.clone()))),
+                Token::Register(target) => return Ok(Some(Statement::CallReg(target.clone()))),
+                _ => {}
             }
         }
-        return Err("Expected label after 'call'".to_string());
+        return Err(LineError::at(1, "Expected label or register after 'call'"));
+    }
+
+    // compare @left @right
+    if matches!(&tokens[0], Token::Keyword(Keyword::Compare)) {
+        if tokens.len() >= 3 {
+            if let (Token::Register(left), Token::Register(right)) = (&tokens[1], &tokens[2]) {
+                return Ok(Some(Statement::Compare {
+                    left: left.clone(),
+                    right: right.clone(),
+                }));
+            }
+        }
+        return Err(LineError::at(bad_register(tokens, 2), "Expected 'compare @left @right'"));
+    }
+
+    // test @left @right
+    if matches!(&tokens[0], Token::Keyword(Keyword::Test)) {
+        if let [_, Token::Register(left), Token::Register(right), ..] = tokens {
+            return Ok(Some(Statement::Test { left: left.clone(), right: right.clone() }));
+        }
+        return Err(LineError::at(bad_register(tokens, 2), "Expected 'test @left @right'"));
+    }
+
+    // jz/jnz/jeq/... label
+    if let Token::Keyword(keyword) = &tokens[0] {
+        if let Some(comparison) = branch_comparison(keyword) {
+            if tokens.len() >= 2 {
+                if let Token::Identifier(name) = &tokens[1] {
+                    return Ok(Some(Statement::Branch { comparison, label: name.clone() }));
+                }
+            }
+            return Err(LineError::at(1, "Expected label after conditional jump"));
+        }
     }
 
     // free @ptr
@@ -126,7 +456,7 @@ fn parse_line(tokens: &[Token], _line_num: usize) -> Result<Option<Statement>, S
                 return Ok(Some(Statement::Free { ptr_var: ptr.clone() }));
             }
         }
-        return Err("Expected register after 'free'".to_string());
+        return Err(LineError::at(1, "Expected register after 'free'"));
     }
 
     // memcpy @dest @src @size
@@ -142,69 +472,80 @@ fn parse_line(tokens: &[Token], _line_num: usize) -> Result<Option<Statement>, S
                 }));
             }
         }
-        return Err("Expected 'memcpy @dest @src @size'".to_string());
+        return Err(LineError::at(bad_register(tokens, 3), "Expected 'memcpy @dest @src @size'"));
     }
 
-    // FP Binary: fadd @dest @left @right
+    // FP Binary: fadd[.f32] @dest @left @right
     if matches!(&tokens[0], Token::Keyword(Keyword::FAdd) | Token::Keyword(Keyword::FSub) | 
-                           Token::Keyword(Keyword::FMul) | Token::Keyword(Keyword::FDiv)) {
-        if tokens.len() >= 4 {
-            if let (Token::Register(dest), Token::Register(left), Token::Register(right)) =
-                (&tokens[1], &tokens[2], &tokens[3])
-            {
-                let op = match &tokens[0] {
-                    Token::Keyword(Keyword::FAdd) => FBinOp::Add,
-                    Token::Keyword(Keyword::FSub) => FBinOp::Sub,
-                    Token::Keyword(Keyword::FMul) => FBinOp::Mul,
-                    Token::Keyword(Keyword::FDiv) => FBinOp::Div,
-                    _ => unreachable!(),
-                };
-                return Ok(Some(Statement::FBinOp {
-                    dest: dest.clone(),
-                    left: left.clone(),
-                    op,
-                    right: right.clone(),
-                }));
-            }
+                           Token::Keyword(Keyword::FMul) | Token::Keyword(Keyword::FDiv) |
+                           Token::Keyword(Keyword::FMin) | Token::Keyword(Keyword::FMax)) {
+        let (width, at) = parse_float_width(tokens)?;
+        if let [Token::Register(dest), Token::Register(left), Token::Register(right), ..] = &tokens[at..] {
+            let op = match &tokens[0] {
+                Token::Keyword(Keyword::FAdd) => FBinOp::Add,
+                Token::Keyword(Keyword::FSub) => FBinOp::Sub,
+                Token::Keyword(Keyword::FMul) => FBinOp::Mul,
+                Token::Keyword(Keyword::FDiv) => FBinOp::Div,
+                Token::Keyword(Keyword::FMin) => FBinOp::Min,
+                Token::Keyword(Keyword::FMax) => FBinOp::Max,
+                _ => unreachable!(),
+            };
+            return Ok(Some(Statement::FBinOp {
+                dest: dest.clone(),
+                left: left.clone(),
+                op,
+                right: right.clone(),
+                width,
+            }));
         }
-        return Err(format!("Expected '{:?} @dest @left @right'", tokens[0]));
+        return Err(LineError::at(at - 1 + bad_register(&tokens[at - 1..], 3), format!("Expected '{:?} @dest @left @right'", tokens[0])));
     }
 
-    // FP Unary: fsqrt @dest @src
+    // FP Unary: fsqrt[.f32] @dest @src
     if matches!(&tokens[0], Token::Keyword(Keyword::FSqrt) | Token::Keyword(Keyword::FAbs) | 
                            Token::Keyword(Keyword::FNeg) | Token::Keyword(Keyword::F2I) | 
-                           Token::Keyword(Keyword::I2F)) {
-        if tokens.len() >= 3 {
-            if let (Token::Register(dest), Token::Register(src)) = (&tokens[1], &tokens[2]) {
-                let op = match &tokens[0] {
-                    Token::Keyword(Keyword::FSqrt) => FUnaryOp::Sqrt,
-                    Token::Keyword(Keyword::FAbs) => FUnaryOp::Abs,
-                    Token::Keyword(Keyword::FNeg) => FUnaryOp::Neg,
-                    Token::Keyword(Keyword::F2I) => FUnaryOp::ToInt,
-                    Token::Keyword(Keyword::I2F) => FUnaryOp::ToFloat,
-                    _ => unreachable!(),
-                };
-                return Ok(Some(Statement::FUnaryOp {
-                    dest: dest.clone(),
-                    op,
-                    src: src.clone(),
-                }));
-            }
+                           Token::Keyword(Keyword::I2F) | Token::Keyword(Keyword::FDemote) |
+                           Token::Keyword(Keyword::FPromote) | Token::Keyword(Keyword::FFloor) |
+                           Token::Keyword(Keyword::FCeil) | Token::Keyword(Keyword::FTrunc) |
+                           Token::Keyword(Keyword::FRound) | Token::Keyword(Keyword::FClass)) {
+        let (width, at) = parse_float_width(tokens)?;
+        if let [Token::Register(dest), Token::Register(src), ..] = &tokens[at..] {
+            let op = match &tokens[0] {
+                Token::Keyword(Keyword::FSqrt) => FUnaryOp::Sqrt,
+                Token::Keyword(Keyword::FAbs) => FUnaryOp::Abs,
+                Token::Keyword(Keyword::FNeg) => FUnaryOp::Neg,
+                Token::Keyword(Keyword::FClass) => FUnaryOp::Class,
+                Token::Keyword(Keyword::FFloor) => FUnaryOp::Floor,
+                Token::Keyword(Keyword::FCeil) => FUnaryOp::Ceil,
+                Token::Keyword(Keyword::FTrunc) => FUnaryOp::Trunc,
+                Token::Keyword(Keyword::FRound) => FUnaryOp::Round,
+                Token::Keyword(Keyword::F2I) => FUnaryOp::ToInt,
+                Token::Keyword(Keyword::I2F) => FUnaryOp::ToFloat,
+                Token::Keyword(Keyword::FDemote) => FUnaryOp::Demote,
+                Token::Keyword(Keyword::FPromote) => FUnaryOp::Promote,
+                _ => unreachable!(),
+            };
+            return Ok(Some(Statement::FUnaryOp {
+                dest: dest.clone(),
+                op,
+                src: src.clone(),
+                width,
+            }));
         }
-        return Err(format!("Expected '{:?} @dest @src'", tokens[0]));
+        return Err(LineError::at(at - 1 + bad_register(&tokens[at - 1..], 2), format!("Expected '{:?} @dest @src'", tokens[0])));
     }
 
-    // FCmp: fcmp @left @right
+    // FCmp: fcmp[.f32] @left @right
     if matches!(&tokens[0], Token::Keyword(Keyword::FCmp)) {
-        if tokens.len() >= 3 {
-            if let (Token::Register(left), Token::Register(right)) = (&tokens[1], &tokens[2]) {
-                return Ok(Some(Statement::FCmp {
-                    left: left.clone(),
-                    right: right.clone(),
-                }));
-            }
+        let (width, at) = parse_float_width(tokens)?;
+        if let [Token::Register(left), Token::Register(right), ..] = &tokens[at..] {
+            return Ok(Some(Statement::FCmp {
+                left: left.clone(),
+                right: right.clone(),
+                width,
+            }));
         }
-        return Err("Expected 'fcmp @left @right'".to_string());
+        return Err(LineError::at(at - 1 + bad_register(&tokens[at - 1..], 2), "Expected 'fcmp @left @right'"));
     }
 
     // Bit Unary: popcnt @dest @src
@@ -226,7 +567,7 @@ fn parse_line(tokens: &[Token], _line_num: usize) -> Result<Option<Statement>, S
                 }));
             }
         }
-        return Err(format!("Expected '{:?} @dest @src'", tokens[0]));
+        return Err(LineError::at(bad_register(tokens, 2), format!("Expected '{:?} @dest @src'", tokens[0])));
     }
 
     // Bit Rot: rotl @dest @left @right
@@ -248,7 +589,20 @@ fn parse_line(tokens: &[Token], _line_num: usize) -> Result<Option<Statement>, S
                 }));
             }
         }
-        return Err(format!("Expected '{:?} @dest @left @right'", tokens[0]));
+        return Err(LineError::at(bad_register(tokens, 3), format!("Expected '{:?} @dest @left @right'", tokens[0])));
+    }
+
+    // mulhi @dest @left @right
+    if matches!(&tokens[0], Token::Keyword(Keyword::MulHi)) {
+        if let [_, Token::Register(dest), Token::Register(left), Token::Register(right), ..] = tokens {
+            return Ok(Some(Statement::BinOp {
+                dest: dest.clone(),
+                left: left.clone(),
+                op: BinOp::MulHi,
+                right: Operand::Variable(right.clone()),
+            }));
+        }
+        return Err(LineError::at(bad_register(tokens, 3), "Expected 'mulhi @dest @left @right'"));
     }
 
     // memset @dest @value @size
@@ -264,54 +618,150 @@ fn parse_line(tokens: &[Token], _line_num: usize) -> Result<Option<Statement>, S
                 }));
             }
         }
-        return Err("Expected 'memset @dest @value @size'".to_string());
+        return Err(LineError::at(bad_register(tokens, 3), "Expected 'memset @dest @value @size'"));
     }
 
-    // store @value at @addr
+    // store[.byte|.word|.dword] @value at @addr
     if matches!(&tokens[0], Token::Keyword(Keyword::Store)) {
-        if tokens.len() >= 4 {
-            if let (Token::Register(value), Token::Keyword(Keyword::At), Token::Register(addr)) =
-                (&tokens[1], &tokens[2], &tokens[3])
-            {
-                return Ok(Some(Statement::Store {
-                    value_var: value.clone(),
-                    addr_var: addr.clone(),
-                }));
-            }
+        let (size, at) = parse_access_size(tokens, 1)?;
+        if let (Some(Token::Register(value)), Some(Token::Keyword(Keyword::At)), Some(Token::Register(addr))) =
+            (tokens.get(at), tokens.get(at + 1), tokens.get(at + 2))
+        {
+            return Ok(Some(Statement::Store {
+                value_var: value.clone(),
+                addr_var: addr.clone(),
+                size,
+            }));
         }
-        return Err("Expected 'store @value at @addr'".to_string());
+        let bad = if !matches!(tokens.get(at), Some(Token::Register(_))) {
+            at
+        } else if tokens.get(at + 1) != Some(&Token::Keyword(Keyword::At)) {
+            at + 1
+        } else {
+            at + 2
+        };
+        return Err(LineError::at(bad, "Expected 'store @value at @addr'"));
     }
 
-    // if @a <cmp> @b goto label
+    // if @a <cmp> @b [goto label]
     if matches!(&tokens[0], Token::Keyword(Keyword::If)) {
         return parse_if(tokens);
     }
 
+    // emit 0x01 / emit.bytes 0x10 0x00 0x2a ...
+    if matches!(&tokens[0], Token::Identifier(word) if word == "emit") {
+        return parse_emit(tokens);
+    }
+
+    // while @a <cmp> @b / loop / break / continue / else / end
+    if matches!(&tokens[0], Token::Keyword(Keyword::While)) {
+        return parse_while(tokens);
+    }
+    // These words stay usable as label names, as in `goto end`
+    if let [Token::Identifier(word)] = tokens {
+        match word.as_str() {
+            "loop" => return Ok(Some(Statement::Loop)),
+            "break" => return Ok(Some(Statement::Break)),
+            "continue" => return Ok(Some(Statement::Continue)),
+            "else" => return Ok(Some(Statement::Else)),
+            "end" => return Ok(Some(Statement::End)),
+            _ => {}
+        }
+    }
+
     // @reg ... (assignment or compound)
     if let Token::Register(name) = &tokens[0] {
         return parse_register_statement(tokens, name);
     }
 
-    Err(format!("Unexpected token: {:?}", tokens[0]))
+    Err(LineError::at(0, format!("Unexpected token: {:?}", tokens[0])))
+}
+
+/// A syntax error in one line, and the index of the token it points at
+/// (`tokens.len()` when something is missing at the end of the line)
+#[derive(Debug)]
+struct LineError {
+    message: String,
+    token: usize,
+}
+
+impl LineError {
+    fn at(token: usize, message: impl Into<String>) -> Self {
+        LineError { message: message.into(), token }
+    }
+}
+
+/// Index of the first of the `count` operands after `tokens[0]` that is
+/// missing or not a register
+fn bad_register(tokens: &[Token], count: usize) -> usize {
+    (1..=count).find(|&i| !matches!(tokens.get(i), Some(Token::Register(_)))).unwrap_or(1)
 }
 
-/// Parse an if-conditional: if @a <cmp> @b goto label
-fn parse_if(tokens: &[Token]) -> Result<Option<Statement>, String> {
-    // if @a <cmp> @b goto label
-    // tokens[0] = if
-    // tokens[1] = @a
-    // tokens[2] = comparison
-    // tokens[3] = @b or number
-    // tokens[4] = goto
-    // tokens[5] = label
+/// Comparison tested by a raw branch keyword
+fn branch_comparison(keyword: &Keyword) -> Option<Comparison> {
+    Some(match keyword {
+        Keyword::Jz => Comparison::Zero,
+        Keyword::Jnz => Comparison::NotZero,
+        Keyword::Jeq => Comparison::Equal,
+        Keyword::Jne => Comparison::NotEqual,
+        Keyword::Jgt => Comparison::GreaterThan,
+        Keyword::Jlt => Comparison::LessThan,
+        Keyword::Jge => Comparison::GreaterEqual,
+        Keyword::Jle => Comparison::LessEqual,
+        Keyword::Ja => Comparison::UnsignedGreaterThan,
+        Keyword::Jb => Comparison::UnsignedLessThan,
+        Keyword::Jae => Comparison::UnsignedGreaterEqual,
+        Keyword::Jbe => Comparison::UnsignedLessEqual,
+        _ => return None,
+    })
+}
+
+/// Parse an if-conditional: `if @a <cmp> @b goto label`, or the start of an
+/// if block when the line stops after the comparison
+fn parse_if(tokens: &[Token]) -> Result<Option<Statement>, LineError> {
+    // if @a <cmp> @b [unsigned] [goto label]
+    if tokens.len() < 4 {
+        return Err(LineError::at(tokens.len(), "Incomplete if statement"));
+    }
+    let (left, comparison, right, goto_idx) = parse_condition(tokens, "if")?;
+    if goto_idx == tokens.len() {
+        return Ok(Some(Statement::IfBlock { left, comparison, right }));
+    }
+
+    if tokens.len() < goto_idx + 2 {
+        return Err(LineError::at(tokens.len(), "Incomplete if statement"));
+    }
+
+    if !matches!(&tokens[goto_idx], Token::Keyword(Keyword::Goto)) {
+        return Err(LineError::at(goto_idx, "Expected 'goto' in if statement"));
+    }
+
+    let label = match &tokens[goto_idx + 1] {
+        Token::Identifier(name) => name.clone(),
+        _ => return Err(LineError::at(goto_idx + 1, "Expected label after 'goto'")),
+    };
+
+    Ok(Some(Statement::If { left, comparison, right, label }))
+}
 
-    if tokens.len() < 6 {
-        return Err("Incomplete if statement".to_string());
+/// Parse a loop header: while @a <cmp> @b [unsigned]
+fn parse_while(tokens: &[Token]) -> Result<Option<Statement>, LineError> {
+    if tokens.len() < 4 {
+        return Err(LineError::at(tokens.len(), "Incomplete while statement"));
+    }
+    let (left, comparison, right, end) = parse_condition(tokens, "while")?;
+    if end < tokens.len() {
+        return Err(LineError::at(end, "Expected end of line after while condition"));
     }
+    Ok(Some(Statement::While { left, comparison, right }))
+}
 
+/// Parse the `@a <cmp> @b [unsigned]` after `tokens[0]`, returning the index
+/// of the token that follows it
+fn parse_condition(tokens: &[Token], keyword: &str) -> Result<(String, Comparison, Operand, usize), LineError> {
     let left = match &tokens[1] {
         Token::Register(name) => name.clone(),
-        _ => return Err("Expected register after 'if'".to_string()),
+        _ => return Err(LineError::at(1, format!("Expected register after '{}'", keyword))),
     };
 
     let comparison = match &tokens[2] {
@@ -321,63 +771,36 @@ fn parse_if(tokens: &[Token]) -> Result<Option<Statement>, String> {
         Token::LessThan => Comparison::LessThan,
         Token::GreaterEqual => Comparison::GreaterEqual,
         Token::LessEqual => Comparison::LessEqual,
-        _ => return Err(format!("Expected comparison operator, got {:?}", tokens[2])),
+        _ => return Err(LineError::at(2, format!("Expected comparison operator, got {:?}", tokens[2]))),
     };
 
     let right = match &tokens[3] {
         Token::Register(name) => Operand::Variable(name.clone()),
         Token::Number(n) => Operand::Immediate(*n),
-        _ => return Err("Expected register or number after comparison".to_string()),
+        Token::Identifier(name) => Operand::Constant(name.clone()),
+        _ => return Err(LineError::at(3, "Expected register, number or constant after comparison")),
     };
 
-    let mut is_unsigned = false;
-    let mut goto_idx = 4;
-
     // Check for "unsigned" keyword
-    if tokens.len() > 4 && matches!(&tokens[4], Token::Keyword(Keyword::Unsigned)) {
-        is_unsigned = true;
-        goto_idx = 5;
-    }
-
-    if tokens.len() < goto_idx + 2 {
-        return Err("Incomplete if statement".to_string());
+    if !matches!(tokens.get(4), Some(Token::Keyword(Keyword::Unsigned))) {
+        return Ok((left, comparison, right, 4));
     }
-
-    if !matches!(&tokens[goto_idx], Token::Keyword(Keyword::Goto)) {
-        return Err("Expected 'goto' in if statement".to_string());
-    }
-
-    let label = match &tokens[goto_idx + 1] {
-        Token::Identifier(name) => name.clone(),
-        _ => return Err("Expected label after 'goto'".to_string()),
+    let comparison = match comparison {
+        Comparison::GreaterThan => Comparison::UnsignedGreaterThan,
+        Comparison::LessThan => Comparison::UnsignedLessThan,
+        Comparison::GreaterEqual => Comparison::UnsignedGreaterEqual,
+        Comparison::LessEqual => Comparison::UnsignedLessEqual,
+        Comparison::Equal => Comparison::Equal, // Equal is same for signed/unsigned
+        Comparison::NotEqual => Comparison::NotEqual, // NotEqual is same for signed/unsigned
+        _ => return Err(LineError::at(2, "Invalid comparison for unsigned")),
     };
-
-    let final_comparison = if is_unsigned {
-        match comparison {
-            Comparison::GreaterThan => Comparison::UnsignedGreaterThan,
-            Comparison::LessThan => Comparison::UnsignedLessThan,
-            Comparison::GreaterEqual => Comparison::UnsignedGreaterEqual,
-            Comparison::LessEqual => Comparison::UnsignedLessEqual,
-            Comparison::Equal => Comparison::Equal, // Equal is same for signed/unsigned
-            Comparison::NotEqual => Comparison::NotEqual, // NotEqual is same for signed/unsigned
-            _ => return Err("Invalid comparison for unsigned".to_string()),
-        }
-    } else {
-        comparison
-    };
-
-    Ok(Some(Statement::If {
-        left,
-        comparison: final_comparison,
-        right,
-        label,
-    }))
+    Ok((left, comparison, right, 5))
 }
 
 /// Parse a statement starting with @register
-fn parse_register_statement(tokens: &[Token], name: &str) -> Result<Option<Statement>, String> {
+fn parse_register_statement(tokens: &[Token], name: &str) -> Result<Option<Statement>, LineError> {
     if tokens.len() < 2 {
-        return Err(format!("Incomplete statement for @{}", name));
+        return Err(LineError::at(1, format!("Incomplete statement for @{}", name)));
     }
 
     // Check for indexed store: @base[@index] := @value
@@ -394,7 +817,7 @@ fn parse_register_statement(tokens: &[Token], name: &str) -> Result<Option<State
                 right: other.clone(),
             }));
         }
-        return Err("Expected register after '<=>'".to_string());
+        return Err(LineError::at(2, "Expected register after '<=>'"));
     }
 
     // Compound assignment: @reg += value/@reg
@@ -408,11 +831,11 @@ fn parse_register_statement(tokens: &[Token], name: &str) -> Result<Option<State
 
     // Assignment: @reg := ...
     if tokens[1] != Token::Assign {
-        return Err(format!("Expected ':=' or compound assignment after @{}", name));
+        return Err(LineError::at(1, format!("Expected ':=' or compound assignment after @{}", name)));
     }
 
     if tokens.len() < 3 {
-        return Err(format!("Expected value after ':=' for @{}", name));
+        return Err(LineError::at(2, format!("Expected value after ':=' for @{}", name)));
     }
 
     // @reg := pop
@@ -420,11 +843,40 @@ fn parse_register_statement(tokens: &[Token], name: &str) -> Result<Option<State
         return Ok(Some(Statement::Pop(name.to_string())));
     }
 
+    // @reg := rand
+    if matches!(&tokens[2], Token::Keyword(Keyword::Rand)) {
+        return Ok(Some(Statement::Rand(name.to_string())));
+    }
+
+    // @reg := local offset
+    if matches!(&tokens[2], Token::Keyword(Keyword::Local)) {
+        return match tokens.get(3).and_then(frame_offset) {
+            Some(offset) => Ok(Some(Statement::LoadLocal { dest: name.to_string(), offset })),
+            None => Err(LineError::at(3, "Expected a 32-bit signed offset from BP after 'local'")),
+        };
+    }
+
+    // @reg := featquery leaf
+    if matches!(&tokens[2], Token::Keyword(Keyword::FeatQuery)) {
+        return match tokens.get(3) {
+            Some(Token::Number(leaf)) if *leaf <= 0xff => Ok(Some(Statement::FeatQuery { dest: name.to_string(), leaf: *leaf as u8 })),
+            _ => Err(LineError::at(3, "Expected a feature leaf from 0 to 255 after 'featquery'")),
+        };
+    }
+
     // @reg := peek
     if matches!(&tokens[2], Token::Keyword(Keyword::Peek)) {
         return Ok(Some(Statement::Peek(name.to_string())));
     }
 
+    // @reg := call name(args)
+    if matches!(&tokens[2], Token::Keyword(Keyword::Call)) {
+        if tokens.get(4) != Some(&Token::LeftParen) {
+            return Err(LineError::at(4, "Expected '(' after procedure name"));
+        }
+        return parse_call(tokens, 3, Some(name.to_string()));
+    }
+
     // @reg := alloc @size
     if matches!(&tokens[2], Token::Keyword(Keyword::Alloc)) {
         if tokens.len() >= 4 {
@@ -435,20 +887,20 @@ fn parse_register_statement(tokens: &[Token], name: &str) -> Result<Option<State
                 }));
             }
         }
-        return Err("Expected register after 'alloc'".to_string());
+        return Err(LineError::at(3, "Expected register after 'alloc'"));
     }
 
-    // @reg := load @addr
+    // @reg := load[.byte|.word|.dword] @addr
     if matches!(&tokens[2], Token::Keyword(Keyword::Load)) {
-        if tokens.len() >= 4 {
-            if let Token::Register(addr) = &tokens[3] {
-                return Ok(Some(Statement::Load {
-                    dest_var: name.to_string(),
-                    addr_var: addr.clone(),
-                }));
-            }
+        let (size, at) = parse_access_size(tokens, 3)?;
+        if let Some(Token::Register(addr)) = tokens.get(at) {
+            return Ok(Some(Statement::Load {
+                dest_var: name.to_string(),
+                addr_var: addr.clone(),
+                size,
+            }));
         }
-        return Err("Expected register after 'load'".to_string());
+        return Err(LineError::at(at, "Expected register after 'load'"));
     }
 
     // @reg := ~@src (bitwise NOT)
@@ -462,7 +914,49 @@ fn parse_register_statement(tokens: &[Token], name: &str) -> Result<Option<State
                 }));
             }
         }
-        return Err("Expected register after '~'".to_string());
+        return Err(LineError::at(3, "Expected register after '~'"));
+    }
+
+    // @reg := &label (address of a code or data label)
+    if tokens[2] == Token::Ampersand {
+        if let [_, _, _, Token::Identifier(label)] = tokens {
+            return Ok(Some(Statement::LabelAddress { dest: name.to_string(), label: label.clone() }));
+        }
+        return Err(LineError::at(3, "Expected label after '&'"));
+    }
+
+    // @arr := array length
+    if let [_, _, Token::Identifier(word), ..] = tokens {
+        if word == "array" && tokens.len() > 3 {
+            return match parse_operand(tokens, 3) {
+                Some((length, len)) if 3 + len == tokens.len() => Ok(Some(Statement::ArrayAlloc { dest: name.to_string(), length })),
+                Some((_, len)) => Err(LineError::at(3 + len, "Expected end of line after array length")),
+                None => Err(LineError::at(3, "Expected register, number or constant after 'array'")),
+            };
+        }
+    }
+
+    // @arr := [1, 2, 3]
+    if tokens[2] == Token::LeftBracket {
+        let mut values = Vec::new();
+        let mut at = 3;
+        while tokens.get(at) != Some(&Token::RightBracket) {
+            match parse_operand(tokens, at) {
+                Some((Operand::Variable(_), _)) => return Err(LineError::at(at, "Array elements must be numbers or constants")),
+                Some((value, len)) => {
+                    values.push(value);
+                    at += len;
+                }
+                None => return Err(LineError::at(at, "Expected number, constant or ']' in array")),
+            }
+        }
+        if values.is_empty() {
+            return Err(LineError::at(at, "Expected at least one array element"));
+        }
+        if at + 1 < tokens.len() {
+            return Err(LineError::at(at + 1, "Expected end of line after ']'"));
+        }
+        return Ok(Some(Statement::ArrayLiteral { dest: name.to_string(), values }));
     }
 
     // @dest := @src  (simple move or binary op)
@@ -483,34 +977,32 @@ fn parse_register_statement(tokens: &[Token], name: &str) -> Result<Option<State
                 value: *value,
             }));
         }
-        Token::Minus => {
-            // Handle negative immediate: @dest := -number
-            if tokens.len() >= 4 {
-                if let Token::Number(val) = &tokens[3] {
-                    // Convert -val to u64 (two's complement)
-                    let neg_val = (-(*val as i64)) as u64;
-                    return Ok(Some(Statement::LoadImm {
-                        dest: name.to_string(),
-                        value: neg_val,
-                    }));
-                }
+        // `- 5` with a space is lexed as a minus and a number
+        Token::Minus if matches!(tokens.get(3), Some(Token::Number(_))) => {
+            if let Some((Operand::Immediate(value), _)) = parse_operand(tokens, 2) {
+                return Ok(Some(Statement::LoadImm { dest: name.to_string(), value }));
             }
         }
+        Token::Identifier(constant) => {
+            return Ok(Some(Statement::LoadConst {
+                dest: name.to_string(),
+                name: constant.clone(),
+            }));
+        }
         Token::Register(src_name) => {
             // Check if it's a binary operation: @dest := @src op @right
             if tokens.len() >= 5 {
-                // Check for indexed load: @dest := @base[@index]
+                // Check for indexed load: @dest := @base[index]
                 if tokens[3] == Token::LeftBracket {
-                    if let Token::Register(index) = &tokens[4] {
-                        if tokens.len() >= 6 && tokens[5] == Token::RightBracket {
-                            return Ok(Some(Statement::LoadIndexed {
-                                dest: name.to_string(),
-                                base_var: src_name.clone(),
-                                index_var: index.clone(),
-                            }));
-                        }
+                    let (element, next) = parse_element(tokens, 3)?;
+                    if next < tokens.len() {
+                        return Err(LineError::at(next, "Expected end of line after indexed load"));
                     }
-                    return Err("Expected @index] in indexed load".to_string());
+                    let (dest, base_var) = (name.to_string(), src_name.clone());
+                    return Ok(Some(match element.as_index_var() {
+                        Some(index_var) => Statement::LoadIndexed { dest, base_var, index_var },
+                        None => Statement::LoadElement { dest, base_var, element },
+                    }));
                 }
 
                 let op = match &tokens[3] {
@@ -533,20 +1025,21 @@ fn parse_register_statement(tokens: &[Token], name: &str) -> Result<Option<State
                     }
                 };
 
-                // Handle right operand which could be negative number
-                let right = if tokens[4] == Token::Minus && tokens.len() >= 6 {
-                   if let Token::Number(n) = &tokens[5] {
-                        let neg_val = (-(*n as i64)) as u64;
-                        Operand::Immediate(neg_val)
-                   } else {
-                       return Err("Expected number after '-' in right operand".to_string());
-                   }
-                } else {
-                    match &tokens[4] {
-                        Token::Register(r) => Operand::Variable(r.clone()),
-                        Token::Number(n) => Operand::Immediate(*n),
-                        _ => return Err("Expected register or number as right operand".to_string()),
+                let (right, next) = match parse_operand(tokens, 4) {
+                    Some((right, len)) => (right, 4 + len),
+                    None => return Err(LineError::at(4, "Expected register, number or constant as right operand")),
+                };
+                let op = match (op, tokens.get(next)) {
+                    (_, Some(Token::Keyword(Keyword::Signed))) if next + 1 < tokens.len() => {
+                        return Err(LineError::at(next + 1, "Expected end of line after 'signed'"));
+                    }
+                    (BinOp::Div, Some(Token::Keyword(Keyword::Signed))) => BinOp::SignedDiv,
+                    (BinOp::Mod, Some(Token::Keyword(Keyword::Signed))) => BinOp::SignedMod,
+                    (BinOp::Shr, Some(Token::Keyword(Keyword::Signed))) => BinOp::SignedShr,
+                    (_, Some(Token::Keyword(Keyword::Signed))) => {
+                        return Err(LineError::at(next, "'signed' only applies to '/', '%' and '>>'"));
                     }
+                    (op, _) => op,
                 };
 
                 return Ok(Some(Statement::BinOp {
@@ -566,19 +1059,207 @@ fn parse_register_statement(tokens: &[Token], name: &str) -> Result<Option<State
         _ => {}
     }
 
-    Err(format!("Unexpected token after ':=' : {:?}", tokens[2]))
+    Err(LineError::at(2, format!("Unexpected token after ':=' : {:?}", tokens[2])))
+}
+
+/// Parse an enum declaration on one line: enum Name { A, B, C }
+fn parse_enum(tokens: &[Token]) -> Result<Option<Statement>, LineError> {
+    let Some(Token::Identifier(name)) = tokens.get(1) else {
+        return Err(LineError::at(1, "Expected name after 'enum'"));
+    };
+    if tokens.get(2) != Some(&Token::LeftBrace) {
+        return Err(LineError::at(2, format!("Expected '{{' after 'enum {}'", name)));
+    }
+    let mut variants = Vec::new();
+    let mut i = 3;
+    loop {
+        match tokens.get(i) {
+            Some(Token::RightBrace) => break,
+            Some(Token::Identifier(variant)) => variants.push(variant.clone()),
+            _ => return Err(LineError::at(i, "Expected variant name or '}'")),
+        }
+        i += 1;
+    }
+    Ok(Some(Statement::Enum { name: name.clone(), variants }))
+}
+
+/// Parse the optional size suffix of `load` or `store` at `tokens[at]`,
+/// returning the size and the index of the next token
+fn parse_access_size(tokens: &[Token], at: usize) -> Result<(AccessSize, usize), LineError> {
+    match tokens.get(at) {
+        Some(Token::Directive(suffix)) => match AccessSize::from_suffix(suffix) {
+            Some(size) => Ok((size, at + 1)),
+            None => Err(LineError::at(at, format!("Unknown access size '.{}'; expected .byte, .word or .dword", suffix))),
+        },
+        _ => Ok((AccessSize::QWord, at)),
+    }
+}
+
+/// A BP-relative offset for `local`: a number that fits in an i32
+fn frame_offset(token: &Token) -> Option<i32> {
+    match token {
+        Token::Number(n) => i32::try_from(*n as i64).ok(),
+        _ => None,
+    }
+}
+
+/// Precision named by an optional `.f32` suffix on the keyword in
+/// `tokens[0]`, and the index of the first operand after it
+fn parse_float_width(tokens: &[Token]) -> Result<(FloatWidth, usize), LineError> {
+    match tokens.get(1) {
+        Some(Token::Directive(suffix)) if suffix == "f32" => match &tokens[0] {
+            Token::Keyword(Keyword::FAdd | Keyword::FSub | Keyword::FMul | Keyword::FDiv | Keyword::FSqrt | Keyword::FCmp) => {
+                Ok((FloatWidth::F32, 2))
+            }
+            _ => Err(LineError::at(1, "'.f32' only applies to fadd, fsub, fmul, fdiv, fsqrt and fcmp")),
+        },
+        Some(Token::Directive(suffix)) => Err(LineError::at(1, format!("Unknown float width '.{}'; expected .f32", suffix))),
+        _ => Ok((FloatWidth::F64, 1)),
+    }
+}
+
+/// Parse raw instruction bytes: `emit b...` or `emit.bytes b...`
+fn parse_emit(tokens: &[Token]) -> Result<Option<Statement>, LineError> {
+    let first = match tokens.get(1) {
+        Some(Token::Directive(form)) if form == "bytes" => 2,
+        Some(Token::Directive(form)) => return Err(LineError::at(1, format!("Unknown emit form 'emit.{}'", form))),
+        _ => 1,
+    };
+    let mut bytes = Vec::new();
+    for (at, token) in tokens.iter().enumerate().skip(first) {
+        match token {
+            Token::Number(n) if *n <= 0xff => bytes.push(*n as u8),
+            _ => return Err(LineError::at(at, "Expected a byte value from 0 to 255 after 'emit'")),
+        }
+    }
+    if bytes.is_empty() {
+        return Err(LineError::at(first, "Expected at least one byte after 'emit'"));
+    }
+    Ok(Some(Statement::Emit(bytes)))
+}
+
+/// Parse the operand at `tokens[at]`: a register, number or constant.
+/// A minus followed by a number, as lexed from `- 5`, is a negative number.
+/// Returns it with the number of tokens it spans.
+fn parse_operand(tokens: &[Token], at: usize) -> Option<(Operand, usize)> {
+    match tokens.get(at..)? {
+        [Token::Register(name), ..] => Some((Operand::Variable(name.clone()), 1)),
+        [Token::Number(n), ..] => Some((Operand::Immediate(*n), 1)),
+        [Token::Minus, Token::Number(n), ..] => Some((Operand::Immediate(n.wrapping_neg()), 2)),
+        [Token::Identifier(name), ..] => Some((Operand::Constant(name.clone()), 1)),
+        _ => None,
+    }
+}
+
+/// Parse `name(args)` at `tokens[at]`, the target of a procedure call
+fn parse_call(tokens: &[Token], at: usize, dest: Option<String>) -> Result<Option<Statement>, LineError> {
+    let Some(Token::Identifier(name)) = tokens.get(at) else {
+        return Err(LineError::at(at, "Expected procedure name after 'call'"));
+    };
+    let mut args = Vec::new();
+    let mut i = at + 2;
+    while tokens.get(i) != Some(&Token::RightParen) {
+        let Some((arg, len)) = parse_operand(tokens, i) else {
+            return Err(LineError::at(i, "Expected register, number, constant or ')' in argument list"));
+        };
+        args.push(arg);
+        i += len;
+    }
+    Ok(Some(Statement::CallProc { name: name.clone(), args, dest }))
+}
+
+/// Parse a procedure header: proc name(a, b)
+fn parse_proc(tokens: &[Token]) -> Result<Option<Statement>, LineError> {
+    let Some(Token::Identifier(name)) = tokens.get(1) else {
+        return Err(LineError::at(1, "Expected procedure name after 'proc'"));
+    };
+    if tokens.get(2) != Some(&Token::LeftParen) {
+        return Err(LineError::at(2, format!("Expected '(' after 'proc {}'", name)));
+    }
+    let mut params = Vec::new();
+    let mut i = 3;
+    loop {
+        match tokens.get(i) {
+            Some(Token::RightParen) => break,
+            Some(Token::Identifier(param) | Token::Register(param)) => params.push(param.clone()),
+            _ => return Err(LineError::at(i, "Expected parameter name or ')'")),
+        }
+        i += 1;
+    }
+    Ok(Some(Statement::Proc { name: name.clone(), params }))
+}
+
+/// Parse a constant definition: const NAME := value, where value is a
+/// number or an earlier constant
+fn parse_const(tokens: &[Token]) -> Result<Option<Statement>, LineError> {
+    let Some(Token::Identifier(name)) = tokens.get(1) else {
+        return Err(LineError::at(1, "Expected name after 'const'"));
+    };
+    if tokens.get(2) != Some(&Token::Assign) {
+        return Err(LineError::at(2, format!("Expected ':=' after 'const {}'", name)));
+    }
+    let value = match parse_operand(tokens, 3) {
+        Some((value @ (Operand::Immediate(_) | Operand::Constant(_)), _)) => value,
+        _ => return Err(LineError::at(3, format!("Expected number or constant as the value of '{}'", name))),
+    };
+    Ok(Some(Statement::Const { name: name.clone(), value }))
+}
+
+/// Parse the operands of a data directive, which start at `tokens[first]`.
+/// Commas between values are optional.
+fn parse_data(directive: &str, tokens: &[Token], first: usize) -> Result<DataItem, LineError> {
+    if directive == "string" {
+        return match &tokens[first..] {
+            [Token::StringLiteral(text)] => Ok(DataItem::String(text.clone())),
+            _ => Err(LineError::at(first, "Expected a single \"string\" after '.string'")),
+        };
+    }
+    if directive == "resource" {
+        // The parser fills in the bytes once it knows the source directory
+        return match &tokens[first..] {
+            [Token::StringLiteral(name), Token::StringLiteral(path)] => {
+                Ok(DataItem::Resource { name: name.clone(), path: path.clone(), bytes: Vec::new() })
+            }
+            _ => Err(LineError::at(first, "Expected \"name\" \"path\" after '.resource'")),
+        };
+    }
+    let item: fn(Vec<Operand>) -> DataItem = match directive {
+        "byte" => DataItem::Byte,
+        "word" => DataItem::Word,
+        "qword" => DataItem::Qword,
+        _ => return Err(LineError::at(first - 1, format!("Unknown directive '.{}'", directive))),
+    };
+
+    let mut values = Vec::new();
+    let mut consumed = 0;
+    while first + consumed < tokens.len() {
+        let rest = &tokens[first + consumed..];
+        let (value, len) = match rest {
+            [Token::Number(n), ..] => (Operand::Immediate(*n), 1),
+            [Token::Identifier(name), ..] => (Operand::Constant(name.clone()), 1),
+            [other, ..] => return Err(LineError::at(first + consumed, format!("Expected number or constant in '.{}', got {:?}", directive, other))),
+            [] => unreachable!(),
+        };
+        values.push(value);
+        consumed += len;
+    }
+    if values.is_empty() {
+        return Err(LineError::at(first, format!("Expected at least one value after '.{}'", directive)));
+    }
+    Ok(item(values))
 }
 
 /// Parse compound assignment: @reg += operand
-fn parse_compound_assign(tokens: &[Token], name: &str, op: CompoundOp) -> Result<Option<Statement>, String> {
+fn parse_compound_assign(tokens: &[Token], name: &str, op: CompoundOp) -> Result<Option<Statement>, LineError> {
     if tokens.len() < 3 {
-        return Err(format!("Expected value after compound assignment for @{}", name));
+        return Err(LineError::at(2, format!("Expected value after compound assignment for @{}", name)));
     }
 
     let operand = match &tokens[2] {
         Token::Register(r) => Operand::Variable(r.clone()),
         Token::Number(n) => Operand::Immediate(*n),
-        _ => return Err("Expected register or number for compound assignment".to_string()),
+        Token::Identifier(c) => Operand::Constant(c.clone()),
+        _ => return Err(LineError::at(2, "Expected register, number or constant for compound assignment")),
     };
 
     Ok(Some(Statement::CompoundAssign {
@@ -588,52 +1269,73 @@ fn parse_compound_assign(tokens: &[Token], name: &str, op: CompoundOp) -> Result
     }))
 }
 
-/// Parse indexed store: @base[@index] := @value
+/// Parse indexed store: @base[index] := value
 /// This is called from the main parse_line when we detect @base [ ...
-fn parse_indexed_store(tokens: &[Token]) -> Result<Option<Statement>, String> {
-    // @base [ @index ] := @value
-    // tokens[0] = @base
-    // tokens[1] = [
-    // tokens[2] = @index
-    // tokens[3] = ]
-    // tokens[4] = :=
-    // tokens[5] = @value
-
-    if tokens.len() < 6 {
-        return Err("Incomplete indexed store statement".to_string());
-    }
-
+fn parse_indexed_store(tokens: &[Token]) -> Result<Option<Statement>, LineError> {
+    // @base [ index ] := value
     let base = match &tokens[0] {
         Token::Register(name) => name.clone(),
-        _ => return Err("Expected register for indexed store base".to_string()),
+        _ => return Err(LineError::at(0, "Expected register for indexed store base")),
     };
+    let (element, next) = parse_element(tokens, 1)?;
 
-    let index = match &tokens[2] {
-        Token::Register(name) => name.clone(),
-        _ => return Err("Expected register for index".to_string()),
-    };
-
-    if tokens[3] != Token::RightBracket {
-        return Err("Expected ']'".to_string());
-    }
-
-    if tokens[4] != Token::Assign {
-        return Err("Expected ':=' in indexed store".to_string());
+    if tokens.get(next) != Some(&Token::Assign) {
+        return Err(LineError::at(next, "Expected ':=' in indexed store"));
     }
 
-    let value = match &tokens[5] {
-        Token::Register(name) => Operand::Variable(name.clone()),
-        Token::Number(n) => Operand::Immediate(*n),
-        _ => return Err("Expected register or number for indexed store value".to_string()),
+    let value = match parse_operand(tokens, next + 1) {
+        Some((value, _)) => value,
+        None => return Err(LineError::at(next + 1, "Expected register, number or constant for indexed store value")),
     };
 
-    Ok(Some(Statement::StoreIndexed {
-        base_var: base,
-        index_var: index,
-        value,
+    Ok(Some(match element.as_index_var() {
+        Some(index) => Statement::StoreIndexed { base_var: base, index_var: index, value },
+        None => Statement::StoreElement { base_var: base, element, value },
     }))
 }
 
+/// Parse `[@index]`, `[N]`, `[@index + N]`, `[@index * S]` or
+/// `[@index * S + N]` starting at the `[` in `tokens[at]`. Returns it with
+/// the index of the token after `]`.
+fn parse_element(tokens: &[Token], at: usize) -> Result<(Element, usize), LineError> {
+    let expected = "Expected @index, @index * size + offset or a number inside '[...]'";
+    let Some((index, len)) = parse_operand(tokens, at + 1) else {
+        return Err(LineError::at(at + 1, expected));
+    };
+    let mut next = at + 1 + len;
+    let mut element = Element { index, scale: Operand::Immediate(1), offset: Operand::Immediate(0) };
+
+    if tokens.get(next) == Some(&Token::Star) && matches!(element.index, Operand::Variable(_)) {
+        match parse_operand(tokens, next + 1) {
+            Some((scale @ (Operand::Immediate(_) | Operand::Constant(_)), len)) => {
+                element.scale = scale;
+                next += 1 + len;
+            }
+            _ => return Err(LineError::at(next + 1, "Expected number or constant as the element size")),
+        }
+    }
+    let is_variable = matches!(element.index, Operand::Variable(_));
+    match (tokens.get(next), tokens.get(next + 1)) {
+        (Some(Token::Plus), _) if is_variable => {
+            let Some((offset, len)) = parse_operand(tokens, next + 1) else {
+                return Err(LineError::at(next + 1, "Expected register, number or constant as the offset"));
+            };
+            element.offset = offset;
+            next += 1 + len;
+        }
+        (Some(Token::Minus), Some(Token::Number(n))) if is_variable => {
+            element.offset = Operand::Immediate(n.wrapping_neg());
+            next += 2;
+        }
+        _ => {}
+    }
+
+    if tokens.get(next) != Some(&Token::RightBracket) {
+        return Err(LineError::at(next, if next == at + 1 + len { expected } else { "Expected ']'" }));
+    }
+    Ok((element, next + 1))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -664,6 +1366,15 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_parse_signed() {
+        let stmts = parse("@q := @a / 2 signed\n@s := @a >> @b signed\n").unwrap();
+        assert!(matches!(&stmts[0].node, Statement::BinOp { op: BinOp::SignedDiv, right: Operand::Immediate(2), .. }));
+        assert!(matches!(&stmts[1].node, Statement::BinOp { op: BinOp::SignedShr, .. }));
+        let err = parse("@q := @a + @b signed\n").unwrap_err();
+        assert!(err.to_string().contains("'signed' only applies to"), "{}", err);
+    }
+
     #[test]
     fn test_parse_label() {
         let stmts = parse("loop_start:\n").unwrap();
@@ -683,5 +1394,91 @@ mod tests {
         } else {
             panic!("Expected If");
         }
+
+        let stmts = parse("if @x > -1 goto l\n@y := @x - -2\n").unwrap();
+        assert!(matches!(&stmts[0].node, Statement::If { right: Operand::Immediate(u64::MAX), .. }));
+        assert!(matches!(&stmts[1].node, Statement::BinOp { op: BinOp::Sub, right: Operand::Immediate(n), .. } if *n == 2u64.wrapping_neg()));
+
+        // A space after the minus still gives a negative number
+        let stmts = parse("@r0 := - 5\n@y := @x + - 2\nconst N := - 3\n").unwrap();
+        assert!(matches!(&stmts[0].node, Statement::LoadImm { value, .. } if *value == 5u64.wrapping_neg()));
+        assert!(matches!(&stmts[1].node, Statement::BinOp { op: BinOp::Add, right: Operand::Immediate(n), .. } if *n == 2u64.wrapping_neg()));
+        assert!(matches!(&stmts[2].node, Statement::Const { value: Operand::Immediate(n), .. } if *n == 3u64.wrapping_neg()));
+    }
+
+    #[test]
+    fn test_parse_error_points_at_token() {
+        let source = "halt\n  memcpy @a 5 @c\n";
+        let err = parse(source).unwrap_err().locate(source);
+        assert_eq!((err.code(), err.line(), err.column(source)), (ErrorCode::Syntax, Some(2), Some(13)));
+        assert_eq!(err.snippet(source).unwrap(), "  |\n2 |   memcpy @a 5 @c\n  |             ^");
+
+        let err = parse("goto\n").unwrap_err().locate("goto\n");
+        assert_eq!(err.column("goto\n"), Some(5));
+    }
+
+    #[test]
+    fn test_parse_repeat() {
+        let stmts = parse(".repeat 2\n@r0 += 1\n.repeat 3\nnop\n.endrepeat\n.endrepeat\nhalt\n").unwrap();
+        assert_eq!(stmts.len(), 9);
+        assert_eq!(stmts.iter().filter(|s| s.node == Statement::Nop).count(), 6);
+        assert_eq!((stmts[1].line, stmts[4].line), (4, 2));
+
+        let err = parse(".repeat 2\nnop\n").unwrap_err();
+        assert_eq!((err.code(), err.line()), (ErrorCode::Syntax, Some(1)));
+        assert!(parse(".endrepeat\n").is_err());
+    }
+
+    #[test]
+    fn test_parse_include_and_cycle() {
+        let dir = std::env::temp_dir().join(format!("alya_include_{}", std::process::id()));
+        fs::create_dir_all(dir.join("lib")).unwrap();
+        fs::write(dir.join("lib/util.alya"), "util:\n  return\n").unwrap();
+        let root = dir.join("main.alya");
+        let source = "call util\nhalt\n.include \"lib/util.alya\"\n";
+
+        let parsed = parse_program(source, root.to_str().unwrap()).unwrap();
+        assert_eq!(parsed.statements.len(), 4);
+        let util = &parsed.statements[2];
+        assert!(matches!(&util.node, Statement::Label(name) if name == "util"));
+        assert_eq!(util.line, 1);
+        assert!(util.file.as_deref().unwrap().ends_with("util.alya"));
+        assert_eq!(parsed.includes.len(), 1);
+        let program = crate::assembler::assemble(source, root.to_str().unwrap()).unwrap();
+        assert_eq!(program.file_table, [0, 0, 1]);
+        assert_eq!(program.location(2).map(|(file, line)| (file.ends_with("util.alya"), line)), Some((true, 2)));
+
+        fs::write(dir.join("lib/util.alya"), ".include \"../main.alya\"\n").unwrap();
+        fs::write(&root, source).unwrap();
+        let err = parse_program(source, root.to_str().unwrap()).unwrap_err();
+        assert_eq!((err.code(), err.line()), (ErrorCode::InvalidSource, Some(1)));
+        assert!(err.file().unwrap().ends_with("util.alya"));
+        assert!(err.to_string().contains("Include cycle"));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_resource() {
+        let dir = std::env::temp_dir().join(format!("alya_resource_{}", std::process::id()));
+        fs::create_dir_all(dir.join("assets")).unwrap();
+        fs::write(dir.join("assets/levels.dat"), [3, 1, 4]).unwrap();
+        let root = dir.join("main.alya");
+        let source = "tag: .byte 9\nlevels: .resource \"levels\" \"assets/levels.dat\"\n@r1 := \"levels\"\n\
+            @r0 := 7\nsyscall\n@addr := @r0\n@len := @r1\n@b := load @addr\n@b := @b & 255\nprint @len\nprint @b\n\
+            @r0 := 7\n@r1 := \"missing\"\nsyscall\n@addr := @r0\nprint @addr\nhalt\n";
+
+        let program = crate::assembler::assemble(source, root.to_str().unwrap()).unwrap();
+        assert_eq!(program.resources["levels"], (8, 3));
+        assert_eq!(&program.data[8..11], [3, 1, 4]);
+        let mut vm = crate::execution::VM::new();
+        vm.print_immediately = false;
+        vm.run(&crate::loader::load(&crate::loader::encode(&program)).unwrap()).unwrap();
+        assert_eq!(vm.output(), ["3", "3", &u64::MAX.to_string()]);
+
+        let err = parse_program(".resource \"x\" \"nope.dat\"\n", root.to_str().unwrap()).unwrap_err();
+        assert!(err.to_string().contains("Cannot read resource"), "{}", err);
+        let err = crate::assembler::assemble(".resource \"x\" \"assets/levels.dat\"\n.resource \"x\" \"assets/levels.dat\"\n", root.to_str().unwrap()).unwrap_err();
+        assert!(err.to_string().contains("Resource 'x' is already defined"), "{}", err);
+        fs::remove_dir_all(dir).unwrap();
     }
 }
@@ -0,0 +1,183 @@
+//! Warning pass run after code generation.
+//!
+//! Warnings never stop assembly. `check_warnings` flags labels that nothing
+//! jumps to or loads, named variables that are written but never read, and
+//! variables kept on the stack because every register was taken, which
+//! makes each access several instructions long. Explicit register names
+//! such as `@r0` are never reported as unread, since syscalls read them.
+
+use std::collections::HashSet;
+use std::fmt;
+use std::sync::Arc;
+use crate::instruction::disassembler::json_string;
+use super::codegen::{try_parse_register_name, GeneratedCode};
+use super::parser::ast::*;
+
+/// What a warning is about
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum WarningKind {
+    /// A label no jump, call or load refers to
+    UnusedLabel,
+    /// A named variable that is written but never read
+    UnusedVariable,
+    /// A variable spilled to the stack because the registers ran out
+    Spill,
+}
+
+impl WarningKind {
+    /// Name used by `-W` and in diagnostics
+    pub fn as_str(self) -> &'static str {
+        match self {
+            WarningKind::UnusedLabel => "unused-label",
+            WarningKind::UnusedVariable => "unused-variable",
+            WarningKind::Spill => "spill",
+        }
+    }
+}
+
+impl fmt::Display for WarningKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// A suspicious construct in otherwise valid source
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Warning {
+    pub kind: WarningKind,
+    pub message: String,
+    /// 1-based source line
+    pub line: usize,
+    /// Included file the warning is in; `None` for the file being assembled
+    pub file: Option<String>,
+}
+
+impl Warning {
+    fn new(kind: WarningKind, message: String, line: usize, file: &Option<Arc<str>>) -> Self {
+        Warning { kind, message, line, file: file.as_deref().map(str::to_string) }
+    }
+
+    /// One-line JSON diagnostic, shaped like `VmError::to_json`
+    pub fn to_json(&self, file: &str) -> String {
+        format!(
+            "{{\"level\":\"warning\",\"code\":\"{}\",\"message\":{},\"file\":{},\"line\":{}}}",
+            self.kind, json_string(&self.message), json_string(self.file.as_deref().unwrap_or(file)), self.line
+        )
+    }
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.file {
+            Some(file) => write!(f, "{}:{}: {}", file, self.line, self.message),
+            None => write!(f, "Line {}: {}", self.line, self.message),
+        }
+    }
+}
+
+/// Look for unused labels, unread variables and spilled variables
+pub fn check_warnings(statements: &[SpannedStatement], code: &GeneratedCode) -> Vec<Warning> {
+    let mut warnings = Vec::new();
+
+    let mut labels = Vec::new();
+    let mut referenced = HashSet::new();
+    let mut first_write: Vec<(&str, &SpannedStatement)> = Vec::new();
+    let mut read = HashSet::new();
+    for stmt in statements {
+        match &stmt.node {
+            Statement::Label(name) | Statement::Proc { name, .. } | Statement::Data { label: Some(name), .. } => {
+                labels.push((name.as_str(), stmt))
+            }
+            _ => {}
+        }
+        referenced.extend(references(&stmt.node));
+
+        let (writes, reads) = stmt.node.accesses();
+        read.extend(reads);
+        for var in writes {
+            if !first_write.iter().any(|(name, _)| *name == var) {
+                first_write.push((var, stmt));
+            }
+        }
+    }
+
+    for (label, stmt) in labels {
+        if !referenced.contains(label) {
+            let message = format!("Label '{}' is never used", label);
+            warnings.push(Warning::new(WarningKind::UnusedLabel, message, stmt.line, &stmt.file));
+        }
+    }
+    for &(var, stmt) in &first_write {
+        if !read.contains(var) && try_parse_register_name(var).is_none() {
+            let message = format!("Variable @{} is written but never read", var);
+            warnings.push(Warning::new(WarningKind::UnusedVariable, message, stmt.line, &stmt.file));
+        }
+    }
+    for (var, stmt) in first_write {
+        if code.spilled.iter().any(|spilled| spilled == var) {
+            let message = format!("Variable @{} is kept on the stack because all registers are in use", var);
+            warnings.push(Warning::new(WarningKind::Spill, message, stmt.line, &stmt.file));
+        }
+    }
+    warnings
+}
+
+fn constant_name(operand: &Operand) -> Option<&str> {
+    match operand {
+        Operand::Constant(name) => Some(name),
+        _ => None,
+    }
+}
+
+fn element_constants(element: &Element) -> impl Iterator<Item = &str> {
+    [&element.index, &element.scale, &element.offset].into_iter().filter_map(constant_name)
+}
+
+/// Labels, data labels and constants a statement refers to by name
+fn references(node: &Statement) -> Vec<&str> {
+    match node {
+        Statement::Goto(label) | Statement::Call(label) | Statement::LabelAddress { label, .. } => vec![label],
+        Statement::If { right, label, .. } => [Some(label.as_str()), constant_name(right)].into_iter().flatten().collect(),
+        Statement::Branch { label, .. } => vec![label],
+        Statement::Switch { labels, .. } => labels.iter().map(String::as_str).collect(),
+        Statement::LoadConst { name, .. } => vec![name],
+        Statement::CallProc { name, args, .. } => std::iter::once(name.as_str()).chain(args.iter().filter_map(constant_name)).collect(),
+        Statement::Return(Some(operand)) => constant_name(operand).into_iter().collect(),
+        Statement::BinOp { right: operand, .. }
+        | Statement::CompoundAssign { operand, .. }
+        | Statement::StoreIndexed { value: operand, .. }
+        | Statement::ArrayAlloc { length: operand, .. }
+        | Statement::Const { value: operand, .. } => constant_name(operand).into_iter().collect(),
+        Statement::LoadElement { element, .. } => element_constants(element).collect(),
+        Statement::StoreElement { element, value, .. } => element_constants(element).chain(constant_name(value)).collect(),
+        Statement::Data { item: DataItem::Byte(values) | DataItem::Word(values) | DataItem::Qword(values), .. }
+        | Statement::ArrayLiteral { values, .. } => {
+            values.iter().filter_map(constant_name).collect()
+        }
+        _ => vec![],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler;
+
+    #[test]
+    fn test_warnings() {
+        let source = "@count := 1\n@unused := 2\nprint @count\nunused_label:\nhalt\n";
+        let (_, warnings) = assembler::assemble_with_warnings(source, "w").unwrap();
+        let found: Vec<(WarningKind, usize)> = warnings.iter().map(|w| (w.kind, w.line)).collect();
+        assert_eq!(found, vec![(WarningKind::UnusedLabel, 4), (WarningKind::UnusedVariable, 2)]);
+        assert!(warnings[1].to_json("w.alya").contains("\"code\":\"unused-variable\""));
+
+        let vars: String = (0..17).map(|i| format!("@v{} := {}\n", i, i)).collect();
+        let prints: String = (0..17).map(|i| format!("print @v{}\n", i)).collect();
+        let (_, warnings) = assembler::assemble_with_warnings(&(vars + &prints), "t").unwrap();
+        assert_eq!(warnings.len(), 1);
+        assert_eq!((warnings[0].kind, warnings[0].line), (WarningKind::Spill, 17));
+        assert!(warnings[0].message.contains("@v16"));
+    }
+}
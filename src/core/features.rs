@@ -0,0 +1,27 @@
+//! Optional ISA extensions, as reported by `featquery`.
+//!
+//! Leaf 0 is a bitmask of the `FEAT_*` extensions the running VM implements.
+//! Other leaves are reserved and read as 0, so programs built for a later
+//! VM can still ask.
+
+/// f64 arithmetic, comparison, rounding and conversion
+pub const FEAT_FLOAT: u64 = 1;
+/// Single-precision (`.f32`) float operations
+pub const FEAT_FLOAT32: u64 = 1 << 1;
+/// Vector operations
+pub const FEAT_SIMD: u64 = 1 << 2;
+/// Atomic memory operations
+pub const FEAT_ATOMICS: u64 = 1 << 3;
+/// General-purpose registers R16-R63
+pub const FEAT_EXTENDED_REGISTERS: u64 = 1 << 4;
+
+/// Extensions this VM implements
+pub const SUPPORTED_FEATURES: u64 = FEAT_FLOAT | FEAT_FLOAT32 | FEAT_EXTENDED_REGISTERS;
+
+/// Value `featquery` returns for `leaf`
+pub fn query(leaf: u8) -> u64 {
+    match leaf {
+        0 => SUPPORTED_FEATURES,
+        _ => 0,
+    }
+}
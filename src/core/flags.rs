@@ -13,6 +13,7 @@ pub enum Flag {
 
 /// Flags register state
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Flags {
     bits: u64,
 }
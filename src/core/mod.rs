@@ -4,16 +4,21 @@
 //! - Registers (general-purpose and special)
 //! - Opcodes (instruction identifiers)
 //! - Flags (CPU status flags)
+//! - Rng (seedable pseudo-random generator)
+//! - Features (optional ISA extensions)
 //!
 //! These types have NO dependencies on other modules.
 
 mod register;
 mod opcode;
 mod flags;
+mod rng;
+pub mod features;
 
 pub use register::{Register, RegisterError};
 pub use opcode::{Opcode, OpcodeError};
 pub use flags::{Flags, Flag};
+pub use rng::Rng;
 
 /// Re-export commonly used items
 pub mod prelude {
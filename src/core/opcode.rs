@@ -6,16 +6,22 @@ use std::fmt;
 ///
 /// Organized by function for easy reference and future expansion.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[repr(u8)]
 pub enum Opcode {
     // Control (0x00-0x0F)
     Halt = 0x00,
     Nop = 0x01,
+    Exit = 0x02,
 
     // Data Movement (0x10-0x1F)
     LoadImm = 0x10,
     Move = 0x11,
     Swap = 0x12,
+    /// LoadImm with a sign-extended 1-byte immediate
+    LoadImm8 = 0x13,
+    /// LoadImm with a sign-extended 4-byte immediate
+    LoadImm32 = 0x14,
 
     // Arithmetic (0x20-0x2F)
     Add = 0x20,
@@ -23,6 +29,9 @@ pub enum Opcode {
     Mul = 0x22,
     Div = 0x23,
     Mod = 0x24,
+    IDiv = 0x25,
+    IMod = 0x26,
+    MulHi = 0x27,
 
     // Compound Assignment (0x30-0x3F)
     AddAssign = 0x30,
@@ -37,11 +46,16 @@ pub enum Opcode {
     Not = 0x43,
     Shl = 0x44,
     Shr = 0x45,
+    ISht = 0x46,
 
     // Stack (0x50-0x5F)
     Push = 0x50,
     Pop = 0x51,
     Peek = 0x52,
+    Enter = 0x53,
+    Leave = 0x54,
+    LoadLocal = 0x55,
+    StoreLocal = 0x56,
 
     // Memory (0x60-0x6F)
     Load = 0x60,
@@ -52,6 +66,12 @@ pub enum Opcode {
     Free = 0x65,
     MemCopy = 0x66,
     MemSet = 0x67,
+    LoadByte = 0x68,
+    StoreByte = 0x69,
+    LoadWord = 0x6A,
+    StoreWord = 0x6B,
+    LoadDWord = 0x6C,
+    StoreDWord = 0x6D,
 
     // Control Flow (0x70-0x7F)
     Jump = 0x70,
@@ -70,13 +90,23 @@ pub enum Opcode {
 
     // Compare (used before conditional jumps)
     Compare = 0x79,
+    Test = 0x7C,
+    /// Prefix: the jump or call opcode that follows takes an i32 target
+    /// relative to its own instruction index
+    Relative = 0x7A,
+    Switch = 0x7B,
 
     // Functions (0x80-0x8F)
     Call = 0x80,
     Return = 0x81,
+    CallReg = 0x82,
 
     // System (0x90-0x9F)
     Syscall = 0x99,
+    Rand = 0x9A,
+    FeatQuery = 0x9B,
+    Int = 0x9C,
+    IRet = 0x9D,
 
     // Floating Point (0xA0-0xAF)
     FAdd = 0xA0,
@@ -89,6 +119,12 @@ pub enum Opcode {
     F2I = 0xA7,
     I2F = 0xA8,
     FCmp = 0xA9,
+    FFloor = 0xAA,
+    FCeil = 0xAB,
+    FTrunc = 0xAC,
+    FRound = 0xAD,
+    FMin = 0xAE,
+    FMax = 0xAF,
 
     // Bit Manipulation (0xB0-0xBF)
     PopCnt = 0xB0,
@@ -98,6 +134,19 @@ pub enum Opcode {
     RotL = 0xB4,
     RotR = 0xB5,
 
+    // Single-precision Floating Point (0xC0-0xCF)
+    FAdd32 = 0xC0,
+    FSub32 = 0xC1,
+    FMul32 = 0xC2,
+    FDiv32 = 0xC3,
+    FSqrt32 = 0xC4,
+    FCmp32 = 0xC5,
+    FDemote = 0xC6,
+    FPromote = 0xC7,
+
+    // Floating Point, continued (0xD0-0xDF)
+    FClass = 0xD0,
+
     // Debug (0xF0-0xFF)
     Breakpoint = 0xF1,
     TraceOn = 0xF2,
@@ -110,7 +159,10 @@ impl Opcode {
         match value {
             0x00 => Ok(Opcode::Halt),
             0x01 => Ok(Opcode::Nop),
+            0x02 => Ok(Opcode::Exit),
             0x10 => Ok(Opcode::LoadImm),
+            0x13 => Ok(Opcode::LoadImm8),
+            0x14 => Ok(Opcode::LoadImm32),
             0x11 => Ok(Opcode::Move),
             0x12 => Ok(Opcode::Swap),
             0x20 => Ok(Opcode::Add),
@@ -118,6 +170,9 @@ impl Opcode {
             0x22 => Ok(Opcode::Mul),
             0x23 => Ok(Opcode::Div),
             0x24 => Ok(Opcode::Mod),
+            0x25 => Ok(Opcode::IDiv),
+            0x26 => Ok(Opcode::IMod),
+            0x27 => Ok(Opcode::MulHi),
             0x30 => Ok(Opcode::AddAssign),
             0x31 => Ok(Opcode::SubAssign),
             0x32 => Ok(Opcode::MulAssign),
@@ -128,9 +183,14 @@ impl Opcode {
             0x43 => Ok(Opcode::Not),
             0x44 => Ok(Opcode::Shl),
             0x45 => Ok(Opcode::Shr),
+            0x46 => Ok(Opcode::ISht),
             0x50 => Ok(Opcode::Push),
             0x51 => Ok(Opcode::Pop),
             0x52 => Ok(Opcode::Peek),
+            0x53 => Ok(Opcode::Enter),
+            0x54 => Ok(Opcode::Leave),
+            0x55 => Ok(Opcode::LoadLocal),
+            0x56 => Ok(Opcode::StoreLocal),
             0x60 => Ok(Opcode::Load),
             0x61 => Ok(Opcode::Store),
             0x62 => Ok(Opcode::LoadIndexed),
@@ -139,6 +199,12 @@ impl Opcode {
             0x65 => Ok(Opcode::Free),
             0x66 => Ok(Opcode::MemCopy),
             0x67 => Ok(Opcode::MemSet),
+            0x68 => Ok(Opcode::LoadByte),
+            0x69 => Ok(Opcode::StoreByte),
+            0x6A => Ok(Opcode::LoadWord),
+            0x6B => Ok(Opcode::StoreWord),
+            0x6C => Ok(Opcode::LoadDWord),
+            0x6D => Ok(Opcode::StoreDWord),
             0x70 => Ok(Opcode::Jump),
             0x71 => Ok(Opcode::JumpIfZero),
             0x72 => Ok(Opcode::JumpIfNotZero),
@@ -153,9 +219,17 @@ impl Opcode {
             0x4B => Ok(Opcode::JumpIfAe),
             0x4C => Ok(Opcode::JumpIfBe),
             0x79 => Ok(Opcode::Compare),
+            0x7C => Ok(Opcode::Test),
+            0x7A => Ok(Opcode::Relative),
+            0x7B => Ok(Opcode::Switch),
             0x80 => Ok(Opcode::Call),
+            0x82 => Ok(Opcode::CallReg),
             0x81 => Ok(Opcode::Return),
             0x99 => Ok(Opcode::Syscall),
+            0x9A => Ok(Opcode::Rand),
+            0x9B => Ok(Opcode::FeatQuery),
+            0x9C => Ok(Opcode::Int),
+            0x9D => Ok(Opcode::IRet),
             0xA0 => Ok(Opcode::FAdd),
             0xA1 => Ok(Opcode::FSub),
             0xA2 => Ok(Opcode::FMul),
@@ -166,12 +240,27 @@ impl Opcode {
             0xA7 => Ok(Opcode::F2I),
             0xA8 => Ok(Opcode::I2F),
             0xA9 => Ok(Opcode::FCmp),
+            0xAA => Ok(Opcode::FFloor),
+            0xAB => Ok(Opcode::FCeil),
+            0xAC => Ok(Opcode::FTrunc),
+            0xAD => Ok(Opcode::FRound),
+            0xAE => Ok(Opcode::FMin),
+            0xAF => Ok(Opcode::FMax),
             0xB0 => Ok(Opcode::PopCnt),
             0xB1 => Ok(Opcode::Clz),
             0xB2 => Ok(Opcode::Ctz),
             0xB3 => Ok(Opcode::BSwap),
             0xB4 => Ok(Opcode::RotL),
             0xB5 => Ok(Opcode::RotR),
+            0xC0 => Ok(Opcode::FAdd32),
+            0xC1 => Ok(Opcode::FSub32),
+            0xC2 => Ok(Opcode::FMul32),
+            0xC3 => Ok(Opcode::FDiv32),
+            0xC4 => Ok(Opcode::FSqrt32),
+            0xC5 => Ok(Opcode::FCmp32),
+            0xC6 => Ok(Opcode::FDemote),
+            0xC7 => Ok(Opcode::FPromote),
+            0xD0 => Ok(Opcode::FClass),
             0xF1 => Ok(Opcode::Breakpoint),
             0xF2 => Ok(Opcode::TraceOn),
             0xF3 => Ok(Opcode::TraceOff),
@@ -189,7 +278,10 @@ impl Opcode {
         match self {
             Opcode::Halt => "halt",
             Opcode::Nop => "nop",
+            Opcode::Exit => "exit",
             Opcode::LoadImm => "loadimm",
+            Opcode::LoadImm8 => "loadimm8",
+            Opcode::LoadImm32 => "loadimm32",
             Opcode::Move => "move",
             Opcode::Swap => "swap",
             Opcode::Add => "add",
@@ -197,6 +289,9 @@ impl Opcode {
             Opcode::Mul => "mul",
             Opcode::Div => "div",
             Opcode::Mod => "mod",
+            Opcode::IDiv => "idiv",
+            Opcode::IMod => "imod",
+            Opcode::MulHi => "mulhi",
             Opcode::AddAssign => "add_assign",
             Opcode::SubAssign => "sub_assign",
             Opcode::MulAssign => "mul_assign",
@@ -207,9 +302,14 @@ impl Opcode {
             Opcode::Not => "not",
             Opcode::Shl => "shl",
             Opcode::Shr => "shr",
+            Opcode::ISht => "isht",
             Opcode::Push => "push",
             Opcode::Pop => "pop",
             Opcode::Peek => "peek",
+            Opcode::Enter => "enter",
+            Opcode::Leave => "leave",
+            Opcode::LoadLocal => "loadlocal",
+            Opcode::StoreLocal => "storelocal",
             Opcode::Load => "load",
             Opcode::Store => "store",
             Opcode::LoadIndexed => "load_indexed",
@@ -218,6 +318,12 @@ impl Opcode {
             Opcode::Free => "free",
             Opcode::MemCopy => "memcpy",
             Opcode::MemSet => "memset",
+            Opcode::LoadByte => "load_byte",
+            Opcode::StoreByte => "store_byte",
+            Opcode::LoadWord => "load_word",
+            Opcode::StoreWord => "store_word",
+            Opcode::LoadDWord => "load_dword",
+            Opcode::StoreDWord => "store_dword",
             Opcode::Jump => "jump",
             Opcode::JumpIfZero => "jump_if_zero",
             Opcode::JumpIfNotZero => "jump_if_not_zero",
@@ -232,15 +338,30 @@ impl Opcode {
             Opcode::JumpIfAe => "jump_if_ae",
             Opcode::JumpIfBe => "jump_if_be",
             Opcode::Compare => "compare",
+            Opcode::Test => "test",
+            Opcode::Relative => "relative",
+            Opcode::Switch => "switch",
             Opcode::Call => "call",
+            Opcode::CallReg => "callreg",
             Opcode::Return => "return",
             Opcode::Syscall => "syscall",
+            Opcode::Rand => "rand",
+            Opcode::Int => "int",
+            Opcode::IRet => "iret",
+            Opcode::FeatQuery => "featquery",
             Opcode::FAdd => "fadd",
             Opcode::FSub => "fsub",
             Opcode::FMul => "fmul",
             Opcode::FDiv => "fdiv",
+            Opcode::FMin => "fmin",
+            Opcode::FMax => "fmax",
+            Opcode::FClass => "fclass",
             Opcode::FSqrt => "fsqrt",
             Opcode::FAbs => "fabs",
+            Opcode::FFloor => "ffloor",
+            Opcode::FCeil => "fceil",
+            Opcode::FTrunc => "ftrunc",
+            Opcode::FRound => "fround",
             Opcode::FNeg => "fneg",
             Opcode::F2I => "f2i",
             Opcode::I2F => "i2f",
@@ -251,6 +372,14 @@ impl Opcode {
             Opcode::BSwap => "bswap",
             Opcode::RotL => "rotl",
             Opcode::RotR => "rotr",
+            Opcode::FAdd32 => "fadd32",
+            Opcode::FSub32 => "fsub32",
+            Opcode::FMul32 => "fmul32",
+            Opcode::FDiv32 => "fdiv32",
+            Opcode::FSqrt32 => "fsqrt32",
+            Opcode::FCmp32 => "fcmp32",
+            Opcode::FDemote => "fdemote",
+            Opcode::FPromote => "fpromote",
             Opcode::Breakpoint => "breakpoint",
             Opcode::TraceOn => "trace_on",
             Opcode::TraceOff => "trace_off",
@@ -266,6 +395,7 @@ impl fmt::Display for Opcode {
 
 /// Errors related to opcode operations
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum OpcodeError {
     Unknown(u8),
 }
@@ -4,6 +4,8 @@ use std::fmt;
 
 /// All registers available in the Alya VM.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 #[repr(u8)]
 pub enum Register {
     // General-purpose registers (0-15)
@@ -48,15 +50,72 @@ pub enum Register {
     F13 = 34,
     F14 = 35,
     F15 = 36,
+
+    // Extended general-purpose registers (37-84), for `.registers 64`
+    R16 = 37,
+    R17 = 38,
+    R18 = 39,
+    R19 = 40,
+    R20 = 41,
+    R21 = 42,
+    R22 = 43,
+    R23 = 44,
+    R24 = 45,
+    R25 = 46,
+    R26 = 47,
+    R27 = 48,
+    R28 = 49,
+    R29 = 50,
+    R30 = 51,
+    R31 = 52,
+    R32 = 53,
+    R33 = 54,
+    R34 = 55,
+    R35 = 56,
+    R36 = 57,
+    R37 = 58,
+    R38 = 59,
+    R39 = 60,
+    R40 = 61,
+    R41 = 62,
+    R42 = 63,
+    R43 = 64,
+    R44 = 65,
+    R45 = 66,
+    R46 = 67,
+    R47 = 68,
+    R48 = 69,
+    R49 = 70,
+    R50 = 71,
+    R51 = 72,
+    R52 = 73,
+    R53 = 74,
+    R54 = 75,
+    R55 = 76,
+    R56 = 77,
+    R57 = 78,
+    R58 = 79,
+    R59 = 80,
+    R60 = 81,
+    R61 = 82,
+    R62 = 83,
+    R63 = 84,
 }
 
 impl Register {
     /// Total number of registers
-    pub const COUNT: usize = 37;
+    pub const COUNT: usize = 85;
+
+    /// Registers before the extended bank: R0-R15, the special registers
+    /// and F0-F15
+    pub const BASE_COUNT: usize = 37;
 
     /// Number of general-purpose registers
     pub const GP_COUNT: usize = 16;
 
+    /// Number of general-purpose registers with the extended bank
+    pub const EXTENDED_GP_COUNT: usize = 64;
+
     /// Convert from byte representation
     pub fn from_u8(value: u8) -> Result<Self, RegisterError> {
         match value {
@@ -97,10 +156,86 @@ impl Register {
             34 => Ok(Register::F13),
             35 => Ok(Register::F14),
             36 => Ok(Register::F15),
+            37 => Ok(Register::R16),
+            38 => Ok(Register::R17),
+            39 => Ok(Register::R18),
+            40 => Ok(Register::R19),
+            41 => Ok(Register::R20),
+            42 => Ok(Register::R21),
+            43 => Ok(Register::R22),
+            44 => Ok(Register::R23),
+            45 => Ok(Register::R24),
+            46 => Ok(Register::R25),
+            47 => Ok(Register::R26),
+            48 => Ok(Register::R27),
+            49 => Ok(Register::R28),
+            50 => Ok(Register::R29),
+            51 => Ok(Register::R30),
+            52 => Ok(Register::R31),
+            53 => Ok(Register::R32),
+            54 => Ok(Register::R33),
+            55 => Ok(Register::R34),
+            56 => Ok(Register::R35),
+            57 => Ok(Register::R36),
+            58 => Ok(Register::R37),
+            59 => Ok(Register::R38),
+            60 => Ok(Register::R39),
+            61 => Ok(Register::R40),
+            62 => Ok(Register::R41),
+            63 => Ok(Register::R42),
+            64 => Ok(Register::R43),
+            65 => Ok(Register::R44),
+            66 => Ok(Register::R45),
+            67 => Ok(Register::R46),
+            68 => Ok(Register::R47),
+            69 => Ok(Register::R48),
+            70 => Ok(Register::R49),
+            71 => Ok(Register::R50),
+            72 => Ok(Register::R51),
+            73 => Ok(Register::R52),
+            74 => Ok(Register::R53),
+            75 => Ok(Register::R54),
+            76 => Ok(Register::R55),
+            77 => Ok(Register::R56),
+            78 => Ok(Register::R57),
+            79 => Ok(Register::R58),
+            80 => Ok(Register::R59),
+            81 => Ok(Register::R60),
+            82 => Ok(Register::R61),
+            83 => Ok(Register::R62),
+            84 => Ok(Register::R63),
             _ => Err(RegisterError::InvalidCode(value)),
         }
     }
 
+    /// Look up a register by its name (e.g. "r0", "sp", "f3"), with or without a leading '@'
+    pub fn from_name(name: &str) -> Result<Self, RegisterError> {
+        let lower = name.trim_start_matches('@').to_ascii_lowercase();
+        (0..Self::COUNT as u8)
+            .filter_map(|code| Self::from_u8(code).ok())
+            .find(|reg| reg.name() == lower)
+            .ok_or_else(|| RegisterError::InvalidName(name.to_string()))
+    }
+
+    /// The general-purpose register numbered `index`: R0-R15, then the
+    /// extended R16-R63
+    pub fn general(index: u8) -> Result<Self, RegisterError> {
+        match index as usize {
+            i if i < Self::GP_COUNT => Self::from_u8(index),
+            i if i < Self::EXTENDED_GP_COUNT => Self::from_u8(index + (Self::BASE_COUNT - Self::GP_COUNT) as u8),
+            _ => Err(RegisterError::InvalidCode(index)),
+        }
+    }
+
+    /// Position among the general-purpose registers, as taken by `general`
+    pub const fn gp_index(self) -> Option<u8> {
+        match self as u8 {
+            code if code < Self::GP_COUNT as u8 => Some(code),
+            code if code >= Self::BASE_COUNT as u8 => Some(code - (Self::BASE_COUNT - Self::GP_COUNT) as u8),
+            _ => None,
+        }
+    }
+
     /// Convert to byte representation
     pub const fn to_u8(self) -> u8 {
         self as u8
@@ -108,7 +243,12 @@ impl Register {
 
     /// Check if this is a general-purpose register
     pub const fn is_general_purpose(self) -> bool {
-        (self as u8) < 16
+        self.gp_index().is_some()
+    }
+
+    /// Check if this is one of R16-R63
+    pub const fn is_extended(self) -> bool {
+        (self as u8) >= Self::BASE_COUNT as u8
     }
 
     /// Check if this is a special register
@@ -156,6 +296,54 @@ impl Register {
             Register::F13 => "f13",
             Register::F14 => "f14",
             Register::F15 => "f15",
+            Register::R16 => "r16",
+            Register::R17 => "r17",
+            Register::R18 => "r18",
+            Register::R19 => "r19",
+            Register::R20 => "r20",
+            Register::R21 => "r21",
+            Register::R22 => "r22",
+            Register::R23 => "r23",
+            Register::R24 => "r24",
+            Register::R25 => "r25",
+            Register::R26 => "r26",
+            Register::R27 => "r27",
+            Register::R28 => "r28",
+            Register::R29 => "r29",
+            Register::R30 => "r30",
+            Register::R31 => "r31",
+            Register::R32 => "r32",
+            Register::R33 => "r33",
+            Register::R34 => "r34",
+            Register::R35 => "r35",
+            Register::R36 => "r36",
+            Register::R37 => "r37",
+            Register::R38 => "r38",
+            Register::R39 => "r39",
+            Register::R40 => "r40",
+            Register::R41 => "r41",
+            Register::R42 => "r42",
+            Register::R43 => "r43",
+            Register::R44 => "r44",
+            Register::R45 => "r45",
+            Register::R46 => "r46",
+            Register::R47 => "r47",
+            Register::R48 => "r48",
+            Register::R49 => "r49",
+            Register::R50 => "r50",
+            Register::R51 => "r51",
+            Register::R52 => "r52",
+            Register::R53 => "r53",
+            Register::R54 => "r54",
+            Register::R55 => "r55",
+            Register::R56 => "r56",
+            Register::R57 => "r57",
+            Register::R58 => "r58",
+            Register::R59 => "r59",
+            Register::R60 => "r60",
+            Register::R61 => "r61",
+            Register::R62 => "r62",
+            Register::R63 => "r63",
         }
     }
 }
@@ -168,6 +356,7 @@ impl fmt::Display for Register {
 
 /// Errors related to register operations
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum RegisterError {
     InvalidCode(u8),
     InvalidName(String),
@@ -200,9 +389,20 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_general_registers() {
+        for index in 0..Register::EXTENDED_GP_COUNT as u8 {
+            let reg = Register::general(index).unwrap();
+            assert_eq!(reg.gp_index(), Some(index));
+            assert_eq!(reg.name(), format!("r{}", index));
+        }
+        assert_eq!(Register::HP.gp_index(), None);
+    }
+
     #[test]
     fn test_invalid_register() {
-        assert!(Register::from_u8(37).is_err());
+        assert!(Register::from_u8(Register::COUNT as u8).is_err());
+        assert!(Register::general(64).is_err());
         assert!(Register::from_u8(255).is_err());
     }
 
@@ -211,11 +411,22 @@ mod tests {
         assert!(Register::R0.is_general_purpose());
         assert!(Register::R15.is_general_purpose());
         assert!(!Register::SP.is_general_purpose());
+        assert!(Register::R63.is_general_purpose() && Register::R63.is_extended());
+        assert!(!Register::F15.is_extended());
 
         assert!(Register::SP.is_special());
         assert!(!Register::R5.is_special());
     }
 
+    #[test]
+    fn test_register_from_name() {
+        assert_eq!(Register::from_name("r3").unwrap(), Register::R3);
+        assert_eq!(Register::from_name("@SP").unwrap(), Register::SP);
+        assert_eq!(Register::from_name("f15").unwrap(), Register::F15);
+        assert_eq!(Register::from_name("r16").unwrap(), Register::R16);
+        assert!(Register::from_name("r64").is_err());
+    }
+
     #[test]
     fn test_register_display() {
         assert_eq!(format!("{}", Register::R0), "@r0");
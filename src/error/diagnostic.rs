@@ -0,0 +1,37 @@
+//! miette integration (enabled with the `miette` feature).
+//!
+//! `VmError` implements `Diagnostic`, so a front-end can render an assembler
+//! error against its source:
+//!
+//! ```ignore
+//! let report = miette::Report::new(err).with_source_code(miette::NamedSource::new(path, source));
+//! eprintln!("{:?}", report);
+//! ```
+
+use std::fmt::Display;
+use miette::{Diagnostic, LabeledSpan};
+use super::types::{AssemblerError, VmError};
+
+impl Diagnostic for VmError {
+    fn code<'a>(&'a self) -> Option<Box<dyn Display + 'a>> {
+        Some(Box::new(VmError::code(self)))
+    }
+
+    fn help<'a>(&'a self) -> Option<Box<dyn Display + 'a>> {
+        let help = match (self.pc(), self.address()) {
+            (Some(pc), Some(addr)) => format!("at instruction {}, address {:#x}", pc, addr),
+            (Some(pc), None) => format!("at instruction {}", pc),
+            (None, Some(addr)) => format!("at address {:#x}", addr),
+            (None, None) => return None,
+        };
+        Some(Box::new(help))
+    }
+
+    fn labels(&self) -> Option<Box<dyn Iterator<Item = LabeledSpan> + '_>> {
+        let VmError::Assembler(AssemblerError { span: Some(span), message, .. }) = self else {
+            return None;
+        };
+        let label = LabeledSpan::new(Some(message.clone()), span.offset, span.len);
+        Some(Box::new(std::iter::once(label)))
+    }
+}
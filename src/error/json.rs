@@ -0,0 +1,66 @@
+//! JSON diagnostics for editors and CI.
+//!
+//! Each error becomes one single-line JSON object, so a stream of them can
+//! be read line by line:
+//!
+//! ```text
+//! {"level":"error","code":"E302","message":"Undefined label: 'end'","file":"a.alya",
+//!  "line":2,"column":3,"span":{"offset":7,"len":8},"pc":null,"address":null}
+//! ```
+
+use std::fmt::Write;
+use crate::instruction::disassembler::json_string;
+use super::types::{AssemblerError, VmError};
+
+/// Write `value` as a JSON number, or `null` when absent
+fn json_number(value: Option<usize>) -> String {
+    value.map_or_else(|| "null".to_string(), |n| n.to_string())
+}
+
+impl VmError {
+    /// One-line JSON diagnostic for this error in `file`, or in the included
+    /// file it names. `source` (of that file) resolves columns of assembler
+    /// errors; `line_table` maps the faulting pc of a runtime error back to
+    /// its source line.
+    pub fn to_json(&self, file: &str, source: Option<&str>, line_table: &[usize]) -> String {
+        let line = self.line().or_else(|| self.pc().and_then(|pc| line_table.get(pc).copied()));
+        let column = source.and_then(|source| self.column(source));
+        let span = match self {
+            VmError::Assembler(AssemblerError { span: Some(span), .. }) => {
+                format!("{{\"offset\":{},\"len\":{}}}", span.offset, span.len)
+            }
+            _ => "null".to_string(),
+        };
+
+        let mut out = String::new();
+        let _ = write!(
+            out,
+            "{{\"level\":\"error\",\"code\":\"{}\",\"message\":{},\"file\":{},\"line\":{},\"column\":{},\"span\":{},\"pc\":{},\"address\":{}}}",
+            self.code(), json_string(&self.message()), json_string(self.file().unwrap_or(file)),
+            json_number(line), json_number(column), span,
+            json_number(self.pc()), json_number(self.address()),
+        );
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::{ErrorCode, VmError};
+
+    #[test]
+    fn test_error_json() {
+        let source = "halt\n  goto end\n";
+        let err = VmError::assembler(ErrorCode::UndefinedLabel, "Undefined label: 'end'").at_line(2).locate(source);
+        assert_eq!(err.column(source), Some(3));
+        assert_eq!(
+            err.to_json("a.alya", Some(source), &[]),
+            "{\"level\":\"error\",\"code\":\"E302\",\"message\":\"Undefined label: 'end'\",\"file\":\"a.alya\",\
+             \"line\":2,\"column\":3,\"span\":{\"offset\":7,\"len\":8},\"pc\":null,\"address\":null}"
+        );
+
+        let fault = VmError::execution(ErrorCode::CallDepth, "Call stack overflow").with_pc(1);
+        assert!(fault.to_json("p.bin", None, &[4, 9]).contains("\"line\":9,\"column\":null,\"span\":null,\"pc\":1,"));
+        assert!(VmError::DivisionByZero.to_json("p.bin", None, &[]).contains("\"message\":\"Division by zero\""));
+    }
+}
@@ -2,6 +2,9 @@
 
 mod types;
 mod result;
+mod json;
+#[cfg(feature = "miette")]
+mod diagnostic;
 
-pub use types::VmError;
+pub use types::{AssemblerError, ErrorCode, ExecutionError, SourceSpan, VmError};
 pub use result::VmResult;
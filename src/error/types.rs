@@ -2,8 +2,148 @@ use std::fmt;
 use crate::core::{RegisterError, OpcodeError};
 use crate::memory::{MemoryError, StackError};
 
+/// Stable identifier for each kind of failure, so tools can match on the
+/// kind without parsing messages. Codes never change meaning once released.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ErrorCode {
+    Register,
+    Opcode,
+    Memory,
+    Stack,
+    Io,
+    DivisionByZero,
+    Halted,
+    /// `run` hit its instruction limit
+    InstructionLimit,
+    /// The program counter points outside the program
+    InvalidPc,
+    /// Data or heap setup failed before the first instruction
+    LoadFailed,
+    /// Call nesting too deep
+    CallDepth,
+    /// `ret` with an empty call stack
+    ReturnWithoutCall,
+    /// Execution was stopped from outside
+    Cancelled,
+    /// An embedding API was asked to run without a program
+    NoProgram,
+    /// `int` raised a vector with no handler registered
+    UnhandledInterrupt,
+    /// Every unfinished isolate is waiting for a message
+    Deadlock,
+    /// Bytecode ended in the middle of an instruction
+    TruncatedBytecode,
+    /// Bytecode holds an opcode or register that cannot be decoded
+    InvalidEncoding,
+    /// A jump or call target lies outside the program
+    InvalidTarget,
+    /// A constant address misses the data section or writes to it
+    DataReference,
+    /// A pop or peek runs on a stack that is certainly empty
+    StackUnderflow,
+    /// A binary lacks the ALYA header, has a newer version or is cut short
+    InvalidBinary,
+    /// Source could not be parsed
+    Syntax,
+    UndefinedLabel,
+    DuplicateLabel,
+    /// More variables than general-purpose registers
+    OutOfRegisters,
+    /// Source text is missing or not UTF-8
+    InvalidSource,
+    /// A constant is used before (or without) its `const` definition
+    UndefinedConstant,
+    /// A constant is defined twice
+    DuplicateConstant,
+    /// A procedure is called with the wrong number of arguments
+    ArgumentCount,
+}
+
+impl ErrorCode {
+    /// Short code such as `E302`, as shown in diagnostics
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ErrorCode::Register => "E001",
+            ErrorCode::Opcode => "E002",
+            ErrorCode::Memory => "E003",
+            ErrorCode::Stack => "E004",
+            ErrorCode::Io => "E005",
+            ErrorCode::DivisionByZero => "E006",
+            ErrorCode::Halted => "E007",
+            ErrorCode::InstructionLimit => "E101",
+            ErrorCode::InvalidPc => "E102",
+            ErrorCode::LoadFailed => "E103",
+            ErrorCode::CallDepth => "E104",
+            ErrorCode::ReturnWithoutCall => "E105",
+            ErrorCode::Cancelled => "E106",
+            ErrorCode::NoProgram => "E107",
+            ErrorCode::UnhandledInterrupt => "E108",
+            ErrorCode::Deadlock => "E109",
+            ErrorCode::TruncatedBytecode => "E201",
+            ErrorCode::InvalidEncoding => "E202",
+            ErrorCode::InvalidTarget => "E203",
+            ErrorCode::DataReference => "E204",
+            ErrorCode::StackUnderflow => "E205",
+            ErrorCode::InvalidBinary => "E206",
+            ErrorCode::Syntax => "E301",
+            ErrorCode::UndefinedLabel => "E302",
+            ErrorCode::DuplicateLabel => "E303",
+            ErrorCode::OutOfRegisters => "E304",
+            ErrorCode::InvalidSource => "E305",
+            ErrorCode::UndefinedConstant => "E306",
+            ErrorCode::DuplicateConstant => "E307",
+            ErrorCode::ArgumentCount => "E308",
+        }
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Byte range in assembler source
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SourceSpan {
+    pub offset: usize,
+    pub len: usize,
+}
+
+/// A failure while decoding or executing a program
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ExecutionError {
+    pub code: ErrorCode,
+    pub message: String,
+    /// Index of the faulting instruction, when raised while stepping
+    pub pc: Option<usize>,
+    /// Memory address involved, if any
+    pub address: Option<usize>,
+}
+
+/// A failure while assembling source
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AssemblerError {
+    pub code: ErrorCode,
+    pub message: String,
+    /// 1-based source line
+    pub line: Option<usize>,
+    /// Location of the offending token (or else `line`) in the source,
+    /// filled in by `assembler::assemble`
+    pub span: Option<SourceSpan>,
+    /// Offending token as a byte range within `line`, when the parser knows it
+    pub token: Option<SourceSpan>,
+    /// Included file the error is in; `None` for the file being assembled
+    pub file: Option<String>,
+}
+
 /// Unified error type for the entire VM.
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum VmError {
     /// Register-related errors
     Register(RegisterError),
@@ -14,9 +154,9 @@ pub enum VmError {
     /// Stack errors
     Stack(StackError),
     /// Execution errors
-    Execution(String),
+    Execution(ExecutionError),
     /// Assembler errors
-    Assembler(String),
+    Assembler(AssemblerError),
     /// I/O errors
     Io(String),
     /// Division by zero
@@ -25,6 +165,163 @@ pub enum VmError {
     Halted,
 }
 
+impl VmError {
+    /// Execution error of the given kind
+    pub fn execution(code: ErrorCode, message: impl Into<String>) -> Self {
+        VmError::Execution(ExecutionError { code, message: message.into(), pc: None, address: None })
+    }
+
+    /// Assembler error of the given kind
+    pub fn assembler(code: ErrorCode, message: impl Into<String>) -> Self {
+        VmError::Assembler(AssemblerError { code, message: message.into(), line: None, span: None, token: None, file: None })
+    }
+
+    /// Record the faulting instruction, unless one is already known
+    pub fn with_pc(mut self, pc: usize) -> Self {
+        if let VmError::Execution(e) = &mut self {
+            e.pc.get_or_insert(pc);
+        }
+        self
+    }
+
+    /// Record the memory address involved, unless one is already known
+    pub fn with_address(mut self, address: usize) -> Self {
+        if let VmError::Execution(e) = &mut self {
+            e.address.get_or_insert(address);
+        }
+        self
+    }
+
+    /// Record the source line of an assembler error, unless one is already known
+    pub fn at_line(mut self, line: usize) -> Self {
+        if let VmError::Assembler(e) = &mut self {
+            e.line.get_or_insert(line);
+        }
+        self
+    }
+
+    /// Record the offending token's byte range within the error's line, unless one is already known
+    pub fn at_token(mut self, offset: usize, len: usize) -> Self {
+        if let VmError::Assembler(e) = &mut self {
+            e.token.get_or_insert(SourceSpan { offset, len });
+        }
+        self
+    }
+
+    /// Record the included file an assembler error is in, unless one is already known
+    pub fn in_file(mut self, file: &str) -> Self {
+        if let VmError::Assembler(e) = &mut self {
+            e.file.get_or_insert_with(|| file.to_string());
+        }
+        self
+    }
+
+    /// Resolve the line (and token) of an assembler error to a span of `source`
+    pub fn locate(mut self, source: &str) -> Self {
+        if let VmError::Assembler(AssemblerError { line: Some(line), span: span @ None, token, .. }) = &mut self {
+            let mut offset = 0;
+            for (index, text) in source.split_inclusive('\n').enumerate() {
+                if index + 1 == *line {
+                    let trimmed = text.trim_end();
+                    let indent = trimmed.len() - trimmed.trim_start().len();
+                    *span = Some(match token {
+                        Some(token) => SourceSpan { offset: offset + token.offset, len: token.len },
+                        None => SourceSpan { offset: offset + indent, len: trimmed.len() - indent },
+                    });
+                    break;
+                }
+                offset += text.len();
+            }
+        }
+        self
+    }
+
+    /// Kind of failure
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            VmError::Register(_) => ErrorCode::Register,
+            VmError::Opcode(_) => ErrorCode::Opcode,
+            VmError::Memory(_) => ErrorCode::Memory,
+            VmError::Stack(_) => ErrorCode::Stack,
+            VmError::Execution(e) => e.code,
+            VmError::Assembler(e) => e.code,
+            VmError::Io(_) => ErrorCode::Io,
+            VmError::DivisionByZero => ErrorCode::DivisionByZero,
+            VmError::Halted => ErrorCode::Halted,
+        }
+    }
+
+    /// Faulting instruction index, if known
+    pub fn pc(&self) -> Option<usize> {
+        match self {
+            VmError::Execution(e) => e.pc,
+            _ => None,
+        }
+    }
+
+    /// Memory address involved in the failure, if any
+    pub fn address(&self) -> Option<usize> {
+        match self {
+            VmError::Execution(e) => e.address,
+            VmError::Memory(MemoryError::OutOfBounds { address, .. })
+            | VmError::Memory(MemoryError::Unaligned { address, .. })
+            | VmError::Memory(MemoryError::SegmentationFault { address, .. }) => Some(*address),
+            _ => None,
+        }
+    }
+
+    /// Source line of an assembler error, if known
+    pub fn line(&self) -> Option<usize> {
+        match self {
+            VmError::Assembler(e) => e.line,
+            _ => None,
+        }
+    }
+
+    /// Included file of an assembler error, if it is not in the file being assembled
+    pub fn file(&self) -> Option<&str> {
+        match self {
+            VmError::Assembler(e) => e.file.as_deref(),
+            _ => None,
+        }
+    }
+
+    /// 1-based column of an assembler error's span within its line of `source`
+    pub fn column(&self, source: &str) -> Option<usize> {
+        let VmError::Assembler(AssemblerError { span: Some(span), .. }) = self else { return None };
+        let before = source.get(..span.offset)?;
+        let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+        Some(before[line_start..].chars().count() + 1)
+    }
+
+    /// The offending line of `source` with a caret under the error's span:
+    ///
+    /// ```text
+    ///   |
+    /// 2 |   goto end
+    ///   |   ^^^^^^^^
+    /// ```
+    pub fn snippet(&self, source: &str) -> Option<String> {
+        let VmError::Assembler(AssemblerError { line: Some(line), span: Some(span), .. }) = self else { return None };
+        let text = source.lines().nth(line.checked_sub(1)?)?;
+        let column = self.column(source)?;
+        // Keep tabs so the caret lines up with the text above it
+        let pad: String = text.chars().take(column - 1).map(|c| if c == '\t' { '\t' } else { ' ' }).collect();
+        let width = source.get(span.offset..span.offset + span.len).map_or(0, |s| s.chars().count()).max(1);
+        let gutter = " ".repeat(line.to_string().len());
+        Some(format!("{} |\n{} | {}\n{} | {}{}", gutter, line, text, gutter, pad, "^".repeat(width)))
+    }
+
+    /// The message without the kind prefix that `Display` adds
+    pub fn message(&self) -> String {
+        match self {
+            VmError::Assembler(e) => e.message.clone(),
+            VmError::Execution(e) => e.message.clone(),
+            other => other.to_string(),
+        }
+    }
+}
+
 impl fmt::Display for VmError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -32,8 +329,14 @@ impl fmt::Display for VmError {
             VmError::Opcode(e) => write!(f, "Opcode error: {}", e),
             VmError::Memory(e) => write!(f, "Memory error: {}", e),
             VmError::Stack(e) => write!(f, "Stack error: {}", e),
-            VmError::Execution(msg) => write!(f, "Execution error: {}", msg),
-            VmError::Assembler(msg) => write!(f, "Assembler error: {}", msg),
+            VmError::Execution(e) => write!(f, "Execution error: {}", e.message),
+            VmError::Assembler(AssemblerError { file: Some(file), line: Some(line), message, .. }) => {
+                write!(f, "Assembler error: {}:{}: {}", file, line, message)
+            }
+            VmError::Assembler(AssemblerError { line: Some(line), message, .. }) => {
+                write!(f, "Assembler error: Line {}: {}", line, message)
+            }
+            VmError::Assembler(e) => write!(f, "Assembler error: {}", e.message),
             VmError::Io(msg) => write!(f, "I/O error: {}", msg),
             VmError::DivisionByZero => write!(f, "Division by zero"),
             VmError::Halted => write!(f, "VM halted"),
@@ -66,3 +369,26 @@ impl From<StackError> for VmError {
         VmError::Stack(e)
     }
 }
+
+impl From<std::io::Error> for VmError {
+    fn from(e: std::io::Error) -> Self {
+        VmError::Io(e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_assembler_error_location() {
+        let err = VmError::assembler(ErrorCode::UndefinedLabel, "Undefined label: 'end'")
+            .at_line(2)
+            .locate("halt\n  goto end\n");
+        assert_eq!(err.code().as_str(), "E302");
+        assert_eq!(err.line(), Some(2));
+        assert_eq!(err.to_string(), "Assembler error: Line 2: Undefined label: 'end'");
+        let VmError::Assembler(AssemblerError { span: Some(span), .. }) = err else { panic!() };
+        assert_eq!(span, SourceSpan { offset: 7, len: 8 });
+    }
+}
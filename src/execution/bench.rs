@@ -0,0 +1,106 @@
+//! Benchmark harness for comparing ISA and dispatcher changes.
+//!
+//! `bench` runs a program repeatedly on one VM and reports wall-clock time,
+//! throughput, cycles from the VM's cost model and the opcodes that
+//! dominate execution.
+
+use std::fmt::Write;
+use std::time::{Duration, Instant};
+use crate::core::Opcode;
+use crate::error::VmResult;
+use crate::instruction::Program;
+use super::VM;
+
+/// Timing and profile of repeated runs of one program
+#[derive(Debug, Clone, PartialEq)]
+pub struct BenchReport {
+    pub iterations: u32,
+    /// Instructions executed by each run
+    pub instructions_per_run: u64,
+    /// Virtual cycles of each run under the default cost model
+    pub cycles_per_run: u64,
+    pub total: Duration,
+    pub fastest: Duration,
+    pub slowest: Duration,
+    /// Per-opcode counts for one run, most frequent first
+    pub hot_spots: Vec<(Opcode, u64)>,
+}
+
+impl BenchReport {
+    /// Mean wall-clock time of one run
+    pub fn mean(&self) -> Duration {
+        self.total / self.iterations.max(1)
+    }
+
+    /// Instructions executed per second across all runs
+    pub fn instructions_per_second(&self) -> f64 {
+        let seconds = self.total.as_secs_f64();
+        if seconds == 0.0 {
+            return 0.0;
+        }
+        (self.instructions_per_run * self.iterations as u64) as f64 / seconds
+    }
+
+    /// Multi-line summary with the top `hot` opcodes
+    pub fn summary(&self, hot: usize) -> String {
+        let mut out = String::new();
+        let _ = writeln!(out, "Iterations:       {}", self.iterations);
+        let _ = writeln!(out, "Instructions/run: {}", self.instructions_per_run);
+        let _ = writeln!(out, "Cycles/run:       {}", self.cycles_per_run);
+        let _ = writeln!(out, "Mean time/run:    {:?} (fastest {:?}, slowest {:?})", self.mean(), self.fastest, self.slowest);
+        let _ = writeln!(out, "Instructions/sec: {:.0}", self.instructions_per_second());
+        let _ = writeln!(out, "Hot spots:");
+        for (opcode, count) in self.hot_spots.iter().take(hot) {
+            let percentage = *count as f64 / self.instructions_per_run.max(1) as f64 * 100.0;
+            let _ = writeln!(out, "  {:<15} : {:>10} ({:>5.1}%)", opcode.name(), count, percentage);
+        }
+        out
+    }
+}
+
+/// Run `program` to completion `iterations` times (at least once) on a
+/// fresh VM with output captured
+pub fn bench(program: &Program, iterations: u32) -> VmResult<BenchReport> {
+    let mut vm = VM::new();
+    vm.print_immediately = false;
+
+    let mut report = BenchReport {
+        iterations: iterations.max(1),
+        instructions_per_run: 0,
+        cycles_per_run: 0,
+        total: Duration::ZERO,
+        fastest: Duration::MAX,
+        slowest: Duration::ZERO,
+        hot_spots: Vec::new(),
+    };
+    for _ in 0..report.iterations {
+        let start = Instant::now();
+        vm.run(program)?;
+        let elapsed = start.elapsed();
+        report.total += elapsed;
+        report.fastest = report.fastest.min(elapsed);
+        report.slowest = report.slowest.max(elapsed);
+    }
+    report.instructions_per_run = vm.instruction_count;
+    report.cycles_per_run = vm.cycles();
+    report.hot_spots = vm.opcode_profile();
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler;
+
+    #[test]
+    fn test_bench_reports_profile() {
+        let program = assembler::assemble("@r0 := 0\nloop:\n@r0 += 1\nif @r0 < 1000 goto loop\nhalt\n", "bench").unwrap();
+        let report = bench(&program, 3).unwrap();
+        assert_eq!(report.iterations, 3);
+        assert!(report.instructions_per_run > 3000);
+        assert!(report.cycles_per_run >= report.instructions_per_run);
+        assert!(report.fastest <= report.slowest);
+        assert_eq!(report.hot_spots.iter().map(|(_, n)| n).sum::<u64>(), report.instructions_per_run);
+        assert!(report.summary(3).contains("Hot spots:"));
+    }
+}
@@ -1,10 +1,74 @@
 //! Execution context — register file and flags state.
 
+use std::collections::BTreeMap;
 use crate::core::{Register, Flags};
+use crate::error::ErrorCode;
+
+/// Serde support for the register array, which is longer than serde's
+/// built-in array impls cover
+#[cfg(feature = "serde")]
+mod register_file {
+    use serde::{de::Error, Deserialize, Deserializer, Serialize, Serializer};
+    use crate::core::Register;
+
+    pub fn serialize<S: Serializer>(registers: &[u64; Register::COUNT], serializer: S) -> Result<S::Ok, S::Error> {
+        registers.as_slice().serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<[u64; Register::COUNT], D::Error> {
+        let values = Vec::<u64>::deserialize(deserializer)?;
+        let len = values.len();
+        values.try_into().map_err(|_| D::Error::invalid_length(len, &"one value per register"))
+    }
+}
+
+/// Virtual timer armed by syscall 8: after every `interval` instructions
+/// the VM calls `handler`, which ends with `return` rather than `iret`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Timer {
+    pub interval: u64,
+    /// Instruction index of the interrupt handler
+    pub handler: usize,
+    /// Instructions left before the next interrupt
+    pub remaining: u64,
+}
+
+/// An interrupt handler in progress: the call depth inside it and the
+/// flags to restore when it returns
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Interrupt {
+    pub depth: usize,
+    pub flags: Flags,
+}
+
+/// Trap vector for division by zero
+pub const TRAP_DIVISION_BY_ZERO: u8 = 0;
+/// Trap vector for reads and writes the memory does not allow
+pub const TRAP_MEMORY_FAULT: u8 = 1;
+/// Trap vector for instructions that cannot be decoded or fetched
+pub const TRAP_INVALID_INSTRUCTION: u8 = 2;
+/// Trap vector for stack overflow and underflow
+pub const TRAP_STACK_FAULT: u8 = 3;
+
+/// The trap vector a fault with this error code raises, if any
+pub fn trap_vector(code: ErrorCode) -> Option<u8> {
+    match code {
+        ErrorCode::DivisionByZero => Some(TRAP_DIVISION_BY_ZERO),
+        ErrorCode::Memory => Some(TRAP_MEMORY_FAULT),
+        ErrorCode::Opcode | ErrorCode::InvalidEncoding | ErrorCode::InvalidPc => Some(TRAP_INVALID_INSTRUCTION),
+        ErrorCode::Stack => Some(TRAP_STACK_FAULT),
+        _ => None,
+    }
+}
 
 /// Holds the mutable state of the VM during execution.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ExecutionContext {
     /// Register values (indexed by Register::to_u8())
+    #[cfg_attr(feature = "serde", serde(with = "register_file"))]
     pub registers: [u64; Register::COUNT],
     /// CPU flags
     pub flags: Flags,
@@ -12,10 +76,18 @@ pub struct ExecutionContext {
     pub pc: usize,
     /// Whether the VM is halted
     pub halted: bool,
+    /// Status `halt @x` exited with; 0 for a plain `halt`
+    pub exit_code: i32,
     /// Call stack for return addresses
     pub call_stack: Vec<usize>,
     /// Whether tracing is enabled
     pub trace: bool,
+    /// Timer interrupt, if armed
+    pub timer: Option<Timer>,
+    /// Interrupt handler running, if any. The timer waits until it returns.
+    pub interrupt: Option<Interrupt>,
+    /// Handler instruction for each trap vector, registered by syscall 9
+    pub traps: BTreeMap<u8, usize>,
 }
 
 impl ExecutionContext {
@@ -26,8 +98,12 @@ impl ExecutionContext {
             flags: Flags::new(),
             pc: 0,
             halted: false,
+            exit_code: 0,
             call_stack: Vec::new(),
             trace: false,
+            timer: None,
+            interrupt: None,
+            traps: BTreeMap::new(),
         }
     }
 
@@ -47,8 +123,12 @@ impl ExecutionContext {
         self.flags = Flags::new();
         self.pc = 0;
         self.halted = false;
+        self.exit_code = 0;
         self.call_stack.clear();
         self.trace = false;
+        self.timer = None;
+        self.interrupt = None;
+        self.traps.clear();
     }
 }
 
@@ -0,0 +1,87 @@
+//! Cycle costs per opcode, for a virtual clock that weighs a division
+//! more than an addition.
+//!
+//! The default costs loosely follow a simple in-order core: one cycle for
+//! moves, logic and jumps, a few for multiplies, memory and floating point,
+//! and many for division, allocation and syscalls. Instruction counts stay
+//! available as `VM::instruction_count`.
+
+use crate::core::Opcode;
+
+/// Cycles charged for each opcode
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CostModel {
+    costs: [u64; 256],
+}
+
+impl CostModel {
+    /// Every opcode costs one cycle, so cycles equal instructions
+    pub fn uniform() -> Self {
+        CostModel { costs: [1; 256] }
+    }
+
+    /// Cycles one `opcode` takes
+    pub fn cost(&self, opcode: Opcode) -> u64 {
+        self.costs[opcode.to_u8() as usize]
+    }
+
+    /// Charge `cycles` for `opcode`
+    pub fn set(&mut self, opcode: Opcode, cycles: u64) {
+        self.costs[opcode.to_u8() as usize] = cycles;
+    }
+
+    /// Builder form of `set`
+    pub fn with(mut self, opcode: Opcode, cycles: u64) -> Self {
+        self.set(opcode, cycles);
+        self
+    }
+}
+
+impl Default for CostModel {
+    fn default() -> Self {
+        use Opcode::*;
+        let table: &[(u64, &[Opcode])] = &[
+            (2, &[
+                Load, Store, LoadIndexed, StoreIndexed, LoadByte, StoreByte, LoadWord, StoreWord,
+                LoadDWord, StoreDWord, LoadLocal, StoreLocal, Push, Pop, Peek, Enter, Leave, Switch,
+            ]),
+            (3, &[Mul, MulHi, MulAssign, Call, CallReg, Return, Int, IRet]),
+            (4, &[
+                FAdd, FSub, FMul, FMin, FMax, FAbs, FNeg, FCmp, F2I, I2F, FFloor, FCeil, FTrunc, FRound, FClass,
+                FAdd32, FSub32, FMul32, FCmp32, FDemote, FPromote, Rand,
+            ]),
+            (10, &[MemCopy, MemSet]),
+            (15, &[FDiv, FDiv32]),
+            (20, &[Div, Mod, IDiv, IMod, DivAssign, FSqrt, FSqrt32, Alloc, Free]),
+            (50, &[Syscall]),
+        ];
+        let mut model = CostModel::uniform();
+        for &(cycles, opcodes) in table {
+            for &opcode in opcodes {
+                model.set(opcode, cycles);
+            }
+        }
+        model
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler;
+    use crate::execution::VM;
+
+    #[test]
+    fn test_cycles_follow_cost_model() {
+        let program = assembler::assemble("@a := 100\npush @a\n@b := pop\n@c := @a / @b\nhalt\n", "c").unwrap();
+        let mut vm = VM::new();
+        vm.run(&program).unwrap();
+        let expected: u64 = program.instructions.iter().map(|i| CostModel::default().cost(i.opcode())).sum();
+        assert_eq!(vm.cycles(), expected);
+        assert!(vm.cycles() > vm.instruction_count);
+
+        vm.cost_model = CostModel::uniform().with(Opcode::Div, 100);
+        vm.run(&program).unwrap();
+        assert_eq!(vm.cycles(), vm.instruction_count - 1 + 100);
+    }
+}
@@ -1,207 +1,1235 @@
-use std::collections::HashSet;
+use std::cell::RefCell;
+use std::fs;
 use std::io::{self, Write};
-use crate::instruction::Program;
-use crate::execution::VM;
-use crate::error::VmResult;
-use crate::core::Register;
+use std::path::{Path, PathBuf};
+use crate::instruction::{disasm, Program};
+use crate::execution::{Checkpoint, History, VM};
+use crate::execution::expr::{Expr, Template};
+use crate::execution::prompt::Prompt;
+use crate::error::{VmError, VmResult};
+use crate::memory::MemoryAccess;
+use crate::core::{Opcode, Register};
+use crate::instruction::Instruction;
+
+/// Write a line of debugger output to the session's output stream
+macro_rules! outln {
+    ($dbg:expr) => {{
+        let _ = writeln!($dbg.out.borrow_mut());
+    }};
+    ($dbg:expr, $($arg:tt)*) => {{
+        let _ = writeln!($dbg.out.borrow_mut(), $($arg)*);
+    }};
+}
+
+/// Something the debugger watches for changes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Watchpoint {
+    /// A register value
+    Register(Register),
+    /// The qword at a memory address
+    Memory(usize),
+}
+
+impl std::fmt::Display for Watchpoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Watchpoint::Register(reg) => write!(f, "{}", reg),
+            Watchpoint::Memory(addr) => write!(f, "mem {:#x}", addr),
+        }
+    }
+}
+
+/// Instructions the debugger pauses on before they execute
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Catchpoint {
+    /// A syscall, optionally only when r0 holds the given number
+    Syscall(Option<u64>),
+    /// Any opcode in a class such as `store`, or a single opcode by name
+    Opcode { name: String, opcodes: Vec<Opcode> },
+}
+
+impl Catchpoint {
+    /// Build an opcode catchpoint from a class or opcode name
+    pub fn opcode(name: &str) -> Result<Self, String> {
+        let name = name.to_lowercase();
+        let opcodes = opcode_class(&name)
+            .map(<[Opcode]>::to_vec)
+            .or_else(|| {
+                (0..=u8::MAX)
+                    .filter_map(|b| Opcode::from_u8(b).ok())
+                    .find(|op| op.name() == name)
+                    .map(|op| vec![op])
+            })
+            .ok_or_else(|| format!("Unknown opcode or class '{}'", name))?;
+        Ok(Catchpoint::Opcode { name, opcodes })
+    }
+
+    /// Whether the instruction about to execute should pause the program
+    pub fn matches(&self, instr: &Instruction, vm: &VM) -> bool {
+        match self {
+            Catchpoint::Syscall(number) => {
+                matches!(instr, Instruction::Syscall)
+                    && number.is_none_or(|n| vm.ctx.get_reg(Register::R0) == n)
+            }
+            Catchpoint::Opcode { opcodes, .. } => opcodes.contains(&instr.opcode()),
+        }
+    }
+}
+
+impl std::fmt::Display for Catchpoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Catchpoint::Syscall(Some(n)) => write!(f, "syscall {}", n),
+            Catchpoint::Syscall(None) => write!(f, "syscall"),
+            Catchpoint::Opcode { name, .. } => write!(f, "opcode {}", name),
+        }
+    }
+}
+
+/// Opcodes grouped for `catch opcode <class>`
+fn opcode_class(name: &str) -> Option<&'static [Opcode]> {
+    use Opcode::*;
+    Some(match name {
+        "store" | "write" => &[Store, StoreByte, StoreWord, StoreDWord, StoreIndexed, StoreLocal, MemCopy, MemSet, Push],
+        "load" | "read" => &[Load, LoadByte, LoadWord, LoadDWord, LoadIndexed, LoadLocal, Pop, Peek],
+        "memory" => &[
+            Load, Store, LoadByte, StoreByte, LoadWord, StoreWord, LoadDWord, StoreDWord,
+            LoadIndexed, StoreIndexed, LoadLocal, StoreLocal, Alloc, Free, MemCopy, MemSet,
+        ],
+        "stack" => &[Push, Pop, Peek, Enter, Leave],
+        "jump" | "branch" => &[
+            Jump, JumpIfZero, JumpIfNotZero, JumpIfGt, JumpIfLt, JumpIfGe, JumpIfLe,
+            JumpIfEq, JumpIfNe, JumpIfAbove, JumpIfBelow, JumpIfAe, JumpIfBe, Switch,
+        ],
+        "call" => &[Call, CallReg, Return, Int, IRet],
+        "arith" => &[Add, Sub, Mul, MulHi, Div, Mod, IDiv, IMod, AddAssign, SubAssign, MulAssign, DivAssign],
+        "bitwise" => &[And, Or, Xor, Not, Shl, Shr, ISht, Test],
+        "float" => &[
+            FAdd, FSub, FMul, FDiv, FSqrt, FAbs, FNeg, F2I, I2F, FCmp,
+            FFloor, FCeil, FTrunc, FRound, FMin, FMax, FClass,
+            FAdd32, FSub32, FMul32, FDiv32, FSqrt32, FCmp32, FDemote, FPromote,
+        ],
+        _ => return None,
+    })
+}
+
+/// A numbered breakpoint and its hit bookkeeping
+#[derive(Debug, Clone)]
+pub struct Breakpoint {
+    pub id: usize,
+    pub pc: usize,
+    /// Only stop when this evaluates nonzero
+    pub condition: Option<Expr>,
+    /// Delete after the first stop (`tbreak`)
+    pub temporary: bool,
+    /// Remaining hits to pass over before stopping
+    pub ignore_count: u64,
+    /// Times the breakpoint was reached with its condition true
+    pub hits: u64,
+}
+
+/// Logs a message each time execution reaches `pc`, without stopping
+#[derive(Debug, Clone)]
+pub struct Tracepoint {
+    pub pc: usize,
+    pub message: Template,
+    pub hits: u64,
+}
+
+/// Instructions a `call` may run before it is abandoned
+const CALL_STEP_LIMIT: u64 = 10_000_000;
 
 pub struct Debugger {
     vm: VM,
-    breakpoints: HashSet<usize>,
+    breakpoints: Vec<Breakpoint>,
+    next_breakpoint_id: usize,
+    watchpoints: Vec<(Watchpoint, Option<Expr>)>,
+    catchpoints: Vec<Catchpoint>,
+    tracepoints: Vec<Tracepoint>,
+    displays: Vec<Expr>,
+    session_path: Option<PathBuf>,
+    history: History,
+    /// Where command output goes (stdout unless redirected)
+    out: RefCell<Box<dyn Write + Send>>,
 }
 
 impl Debugger {
     pub fn new(vm: VM) -> Self {
         Self {
             vm,
-            breakpoints: HashSet::new(),
+            breakpoints: Vec::new(),
+            next_breakpoint_id: 1,
+            watchpoints: Vec::new(),
+            catchpoints: Vec::new(),
+            tracepoints: Vec::new(),
+            displays: Vec::new(),
+            session_path: None,
+            history: History::default(),
+            out: RefCell::new(Box::new(io::stdout())),
         }
     }
 
+    /// Send debugger output to a writer instead of stdout
+    pub fn with_output(mut self, out: impl Write + Send + 'static) -> Self {
+        self.out = RefCell::new(Box::new(out));
+        self
+    }
+
+    /// Redirect debugger output, returning the previous writer
+    pub fn set_output(&mut self, out: Box<dyn Write + Send>) -> Box<dyn Write + Send> {
+        self.out.replace(out)
+    }
+
+    /// Use a `.alyadbg` session file: loaded on start, and the default target of `save`
+    pub fn with_session_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.session_path = Some(path.into());
+        self
+    }
+
+    /// Debugger commands that recreate the current breakpoints, watchpoints,
+    /// catchpoints, tracepoints and displays
+    pub fn session_commands(&self, program: &Program) -> Vec<String> {
+        let mut commands = Vec::new();
+
+        for bp in &self.breakpoints {
+            let command = if bp.temporary { "tbreak" } else { "break" };
+            let location = program.symbol_at(bp.pc)
+                .map(str::to_string)
+                .unwrap_or_else(|| format!("{:x}", bp.pc));
+            commands.push(match &bp.condition {
+                Some(expr) => format!("{} {} if {}", command, location, expr),
+                None => format!("{} {}", command, location),
+            });
+        }
+        for (wp, condition) in &self.watchpoints {
+            commands.push(match condition {
+                Some(expr) => format!("watch {} if {}", wp, expr),
+                None => format!("watch {}", wp),
+            });
+        }
+        for cp in &self.catchpoints {
+            commands.push(format!("catch {}", cp));
+        }
+        for tp in &self.tracepoints {
+            let location = program.symbol_at(tp.pc)
+                .map(str::to_string)
+                .unwrap_or_else(|| format!("{:x}", tp.pc));
+            commands.push(format!("trace {} \"{}\"", location, tp.message));
+        }
+        for expr in &self.displays {
+            commands.push(format!("display {}", expr));
+        }
+
+        commands
+    }
+
+    /// Write the session commands to a file
+    pub fn save_session(&self, program: &Program, path: &Path) -> io::Result<usize> {
+        let commands = self.session_commands(program);
+        let mut text = String::from("# Alya debugger session\n");
+        for command in &commands {
+            text.push_str(command);
+            text.push('\n');
+        }
+        fs::write(path, text)?;
+        Ok(commands.len())
+    }
+
+    /// Run every command in a file, skipping blank lines and `#` comments.
+    /// Returns false if one of the commands ended the session.
+    pub fn source(&mut self, program: &Program, path: &Path) -> VmResult<bool> {
+        let text = fs::read_to_string(path).map_err(|e| VmError::Io(format!("{}: {}", path.display(), e)))?;
+        self.execute_lines(program, &text, false)
+    }
+
+    /// Run a script of debugger commands non-interactively, echoing each
+    /// command after the prompt so the output reads like a recorded session.
+    pub fn run_script(&mut self, program: &Program, script: &str) -> VmResult<()> {
+        self.reset(program)?;
+        self.execute_lines(program, script, true)?;
+        Ok(())
+    }
+
+    fn execute_lines(&mut self, program: &Program, text: &str, echo: bool) -> VmResult<bool> {
+        for line in text.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if echo {
+                outln!(self, "(debug) {}", line);
+            }
+            if !self.execute(program, line)? {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Load the program into a fresh VM state, keeping breakpoints and other settings
+    pub fn reset(&mut self, program: &Program) -> VmResult<()> {
+        self.vm.init(program)?;
+        self.vm.memory.take_watch_hits();
+        self.history.clear();
+        Ok(())
+    }
+
+    /// Current VM state, for inspecting a session after a script
+    pub fn vm(&self) -> &VM {
+        &self.vm
+    }
+
+    /// Mutable VM access, e.g. to change output settings before a session
+    pub fn vm_mut(&mut self) -> &mut VM {
+        &mut self.vm
+    }
+
+    /// Print every display expression, as after each stop
+    fn show_displays(&self) {
+        for (i, expr) in self.displays.iter().enumerate() {
+            match expr.eval(&self.vm) {
+                Ok(val) => outln!(self, "{}: {} = {} (0x{:x})", i, expr, val, val),
+                Err(e) => outln!(self, "{}: {} = <error: {}>", i, expr, e),
+            }
+        }
+    }
+
+    /// Add a watchpoint, registering a memory watch hook if needed.
+    /// A condition restricts it to changes after which the expression is nonzero.
+    pub fn add_watchpoint(&mut self, wp: Watchpoint, condition: Option<Expr>) {
+        if let Some(entry) = self.watchpoints.iter_mut().find(|(w, _)| *w == wp) {
+            entry.1 = condition;
+            return;
+        }
+        if let Watchpoint::Memory(addr) = wp {
+            self.vm.memory.add_watch(addr, 8);
+        }
+        self.watchpoints.push((wp, condition));
+    }
+
+    /// Remove a watchpoint by its list index
+    pub fn remove_watchpoint(&mut self, index: usize) -> Option<Watchpoint> {
+        if index >= self.watchpoints.len() {
+            return None;
+        }
+        let (wp, _) = self.watchpoints.remove(index);
+        if let Watchpoint::Memory(addr) = wp {
+            self.vm.memory.remove_watch(addr);
+        }
+        Some(wp)
+    }
+
+    /// Add a breakpoint, replacing any existing one at the same pc. Returns its number.
+    pub fn add_breakpoint(&mut self, pc: usize, condition: Option<Expr>, temporary: bool) -> usize {
+        self.breakpoints.retain(|bp| bp.pc != pc);
+        let id = self.next_breakpoint_id;
+        self.next_breakpoint_id += 1;
+        self.breakpoints.push(Breakpoint { id, pc, condition, temporary, ignore_count: 0, hits: 0 });
+        id
+    }
+
+    /// Decide whether the breakpoint at the current pc stops execution,
+    /// updating hit and ignore counts and deleting temporary breakpoints.
+    fn breakpoint_hit(&mut self) -> Option<usize> {
+        let pc = self.vm.ctx.pc;
+        let index = self.breakpoints.iter().position(|bp| bp.pc == pc)?;
+        if !self.condition_holds(self.breakpoints[index].condition.as_ref()) {
+            return None;
+        }
+
+        let bp = &mut self.breakpoints[index];
+        bp.hits += 1;
+        if bp.ignore_count > 0 {
+            bp.ignore_count -= 1;
+            return None;
+        }
+        let id = bp.id;
+        if bp.temporary {
+            self.breakpoints.remove(index);
+        }
+        Some(id)
+    }
+
+    /// Print the message of every tracepoint at the current pc
+    fn log_tracepoints(&mut self) {
+        let pc = self.vm.ctx.pc;
+        for tp in self.tracepoints.iter_mut().filter(|tp| tp.pc == pc) {
+            tp.hits += 1;
+            outln!(self, "[trace {:04x}] {}", pc, tp.message.render(&self.vm));
+        }
+    }
+
+    /// Evaluate an optional condition; evaluation errors count as true so the user sees them
+    fn condition_holds(&self, condition: Option<&Expr>) -> bool {
+        match condition {
+            None => true,
+            Some(expr) => match expr.eval(&self.vm) {
+                Ok(v) => v != 0,
+                Err(e) => {
+                    outln!(self, "Error evaluating condition '{}': {}", expr, e);
+                    true
+                }
+            },
+        }
+    }
+
+    /// Run a guest function such as `fib(10)` and return its result, leaving
+    /// the program state untouched. Arguments are passed the way `proc`
+    /// expects them: the first four in r1-r4 and the rest pushed in order.
+    /// The result comes back in r0.
+    pub fn call_function(&mut self, program: &Program, text: &str) -> Result<u64, String> {
+        let (name, args) = text.split_once('(').ok_or("Usage: call <function>(<args>)")?;
+        let name = name.trim();
+        let args = args.trim_end().strip_suffix(')').ok_or("Expected ')' after arguments")?;
+        let target = *program.symbols.get(name).ok_or_else(|| format!("Unknown function '{}'", name))?;
+
+        let mut values = Vec::new();
+        for arg in split_args(args) {
+            values.push(Expr::parse(arg)?.eval(&self.vm)?);
+        }
+        if values.len() >= Register::GP_COUNT {
+            return Err(format!("Too many arguments ({}, max {})", values.len(), Register::GP_COUNT - 1));
+        }
+
+        let checkpoint = Checkpoint::capture(&mut self.vm);
+        let result = self.run_call(program, target, &values);
+        checkpoint.restore(&mut self.vm);
+        self.vm.memory.take_watch_hits();
+        result
+    }
+
+    /// Enter `target` as if called from the current pc and run until it returns
+    fn run_call(&mut self, program: &Program, target: usize, args: &[u64]) -> Result<u64, String> {
+        for (i, value) in args.iter().take(4).enumerate() {
+            self.vm.ctx.set_reg(Register::from_u8(i as u8 + 1).unwrap(), *value);
+        }
+        for &value in args.iter().skip(4) {
+            self.vm.stack.push(&mut self.vm.memory, value).map_err(|e| e.to_string())?;
+        }
+        self.vm.ctx.set_reg(Register::SP, self.vm.stack.pointer() as u64);
+        let depth = self.vm.ctx.call_stack.len();
+        self.vm.ctx.call_stack.push(self.vm.ctx.pc);
+        self.vm.ctx.pc = target;
+        self.vm.ctx.halted = false;
+
+        for _ in 0..CALL_STEP_LIMIT {
+            if self.vm.ctx.call_stack.len() == depth {
+                return Ok(self.vm.ctx.get_reg(Register::R0));
+            }
+            if self.vm.ctx.halted || self.vm.ctx.pc >= program.len() {
+                return Err("Function halted before returning".to_string());
+            }
+            self.vm.step(program).map_err(|e| e.to_string())?;
+        }
+        Err(format!("Function did not return within {} instructions", CALL_STEP_LIMIT))
+    }
+
+    /// Apply a `set` command such as `@r0 = 42` or `mem 0x8000 = 0xff`
+    fn set_value(&mut self, assignment: &str) -> Result<String, String> {
+        let (target, value) = assignment.split_once('=')
+            .ok_or("Usage: set @reg = <value> | set mem <addr> = <byte>")?;
+        let value = value.trim();
+        let value = parse_number(value).ok_or_else(|| format!("Invalid value '{}'", value))?;
+        let target: Vec<&str> = target.split_whitespace().collect();
+
+        match target.as_slice() {
+            ["mem", addr] => {
+                let addr = parse_number(addr).ok_or_else(|| format!("Invalid address '{}'", addr))? as usize;
+                let byte = u8::try_from(value).map_err(|_| format!("Value {:#x} does not fit in a byte", value))?;
+                self.vm.memory.write_byte(addr, byte).map_err(|e| e.to_string())?;
+                self.vm.memory.take_watch_hits();
+                Ok(format!("mem {:#x} = {} (0x{:x})", addr, byte, byte))
+            }
+            [reg] => {
+                let reg = Register::from_name(reg).map_err(|e| e.to_string())?;
+                // The stack keeps its own pointer, which SP mirrors
+                if reg == Register::SP {
+                    self.vm.stack.set_pointer(value as usize);
+                }
+                self.vm.ctx.set_reg(reg, value);
+                Ok(format!("{} = {} (0x{:x})", reg, value, value))
+            }
+            _ => Err("Usage: set @reg = <value> | set mem <addr> = <byte>".to_string()),
+        }
+    }
+
+    /// Execute one instruction and report any watchpoint it triggered.
+    /// Returns true if execution should stop.
+    fn step_watched(&mut self, program: &Program) -> VmResult<bool> {
+        let pc = self.vm.ctx.pc;
+        let reg_before: Vec<(usize, Register, u64)> = self.watchpoints.iter()
+            .enumerate()
+            .filter_map(|(i, (wp, _))| match wp {
+                Watchpoint::Register(reg) => Some((i, *reg, self.vm.ctx.get_reg(*reg))),
+                Watchpoint::Memory(_) => None,
+            })
+            .collect();
+
+        let output_len = self.vm.output.len();
+        self.history.step(&mut self.vm, program)?;
+        if !self.vm.print_immediately {
+            // Program output isn't going to stdout, so show it with ours
+            for line in self.vm.output.iter().skip(output_len) {
+                outln!(self, "{}", line);
+            }
+        }
+
+        let writer = program.get(pc).map(|i| i.to_assembly()).unwrap_or_default();
+        let mut triggered = false;
+
+        for (i, reg, old) in reg_before {
+            let new = self.vm.ctx.get_reg(reg);
+            if new != old && self.condition_holds(self.watchpoints[i].1.as_ref()) {
+                outln!(self, "Watchpoint {}: {} -> {} (0x{:x} -> 0x{:x})", reg, old, new, old, new);
+                triggered = true;
+            }
+        }
+        for hit in self.vm.memory.take_watch_hits() {
+            let condition = self.watchpoints.iter()
+                .find(|(wp, _)| *wp == Watchpoint::Memory(hit.address))
+                .and_then(|(_, c)| c.as_ref());
+            if !self.condition_holds(condition) {
+                continue;
+            }
+            outln!(self, "Watchpoint mem {:#x}: {} -> {} (0x{:x} -> 0x{:x})", hit.address, hit.old, hit.new, hit.old, hit.new);
+            triggered = true;
+        }
+        if triggered {
+            outln!(self, "  written by {:04x}: {}", pc, writer);
+        }
+        if self.vm.take_break_request() {
+            outln!(self, "Software breakpoint at {:04x}", pc);
+            triggered = true;
+        }
+
+        Ok(triggered)
+    }
+
     pub fn run(&mut self, program: &Program) -> VmResult<()> {
-        println!("Alya Debugger (v0.5)");
-        println!("Type 'help' for commands.");
+        outln!(self, "Alya Debugger (v0.5)");
+        outln!(self, "Type 'help' for commands.");
         
-        self.vm.init(program)?;
+        self.reset(program)?;
+
+        if let Some(path) = self.session_path.clone() {
+            if path.exists() {
+                outln!(self, "Loading session from {}", path.display());
+                if !self.source(program, &path)? {
+                    return Ok(());
+                }
+            }
+        }
 
+        let mut prompt = Prompt::new();
         loop {
             if self.vm.ctx.halted {
-                println!("Program halted.");
+                outln!(self, "Program halted.");
             } else if self.vm.ctx.pc >= program.len() {
-                println!("Program reached end.");
+                outln!(self, "Program reached end.");
             }
 
-            print!("(debug) ");
-            io::stdout().flush().unwrap();
+            let input = match prompt.read_line("(debug) ") {
+                Some(input) => input,
+                None => break,
+            };
 
-            let mut input = String::new();
-            if io::stdin().read_line(&mut input).is_err() {
+            if !self.execute(program, &input)? {
                 break;
             }
+        }
 
-            let parts: Vec<&str> = input.trim().split_whitespace().collect();
-            if parts.is_empty() {
-                continue;
-            }
+        Ok(())
+    }
 
-            match parts[0] {
-                "step" | "s" => {
-                    if self.vm.ctx.halted {
-                        println!("Error: Program is halted.");
+    /// Execute a single debugger command line.
+    /// Returns false when the command ends the session.
+    pub fn execute(&mut self, program: &Program, input: &str) -> VmResult<bool> {
+        let parts: Vec<&str> = input.split_whitespace().collect();
+        if parts.is_empty() {
+            return Ok(true);
+        }
+        let resumes = matches!(parts[0], "step" | "s" | "next" | "n" | "continue" | "c" | "rstep" | "rs" | "rnext" | "rn");
+
+        match parts[0] {
+            "step" | "s" => {
+                if self.vm.ctx.halted {
+                    outln!(self, "Error: Program is halted.");
+                } else {
+                    let pc = self.vm.ctx.pc;
+                    if let Some(instr) = program.get(pc) {
+                        outln!(self, "Step {:04x}: {}", pc, instr.to_assembly());
+                        self.step_watched(program)?;
+                        outln!(self);
+                    }
+                }
+            }
+            "next" | "n" => {
+                if self.vm.ctx.halted {
+                    outln!(self, "Error: Program is halted.");
+                } else {
+                    let current = program.location(self.vm.ctx.pc);
+                    if let Some((_, line)) = current {
+                         outln!(self, "Stepping line {}...", line);
+                         // Step until we reach a different line OR it's a call
+                         while !self.vm.ctx.halted && self.vm.ctx.pc < program.len() && 
+                               program.location(self.vm.ctx.pc) == current {
+                             if self.step_watched(program)? {
+                                 break;
+                             }
+                         }
                     } else {
-                        let pc = self.vm.ctx.pc;
-                        if let Some(instr) = program.get(pc) {
-                            println!("Step {:04x}: {}", pc, instr.to_assembly());
-                            self.vm.step(program)?;
-                            println!();
+                         self.step_watched(program)?;
+                    }
+                    outln!(self);
+                }
+            }
+            "rstep" | "rs" => {
+                if self.history.undo(&mut self.vm) {
+                    let pc = self.vm.ctx.pc;
+                    let asm = program.get(pc).map(|i| i.to_assembly()).unwrap_or_default();
+                    outln!(self, "Reversed to {:04x}: {}", pc, asm);
+                } else {
+                    outln!(self, "Error: No execution history to reverse.");
+                }
+                outln!(self);
+            }
+            "rnext" | "rn" => {
+                if self.history.is_empty() {
+                    outln!(self, "Error: No execution history to reverse.");
+                } else {
+                    // Back out of the current line, then to the first instruction of the previous one
+                    let line_of = |pc: usize| program.location(pc);
+                    let current = line_of(self.vm.ctx.pc);
+                    while line_of(self.vm.ctx.pc) == current && self.history.undo(&mut self.vm) {}
+                    let previous = line_of(self.vm.ctx.pc);
+                    while self.history.previous_pc().map(line_of) == Some(previous) {
+                        self.history.undo(&mut self.vm);
+                    }
+                    match previous {
+                        Some((_, line)) => outln!(self, "Reversed to line {} at {:04x}", line, self.vm.ctx.pc),
+                        None => outln!(self, "Reversed to {:04x}", self.vm.ctx.pc),
+                    }
+                }
+                outln!(self);
+            }
+            "continue" | "c" => {
+                if self.vm.ctx.halted {
+                    outln!(self, "Error: Program is halted.");
+                } else {
+                    outln!(self, "Continuing...");
+                    let start_pc = self.vm.ctx.pc;
+                    let mut first = true;
+                    while !self.vm.ctx.halted && self.vm.ctx.pc < program.len() {
+                        // Don't stop again on the breakpoint we're resuming from
+                        let resuming = first && self.vm.ctx.pc == start_pc;
+                        first = false;
+                        if !resuming {
+                            self.log_tracepoints();
+                            if let Some(id) = self.breakpoint_hit() {
+                                outln!(self, "Breakpoint {} reached at {:04x}", id, self.vm.ctx.pc);
+                                break;
+                            }
+                        }
+                        if !resuming {
+                            if let Some(instr) = program.get(self.vm.ctx.pc) {
+                                if let Some(i) = self.catchpoints.iter().position(|cp| cp.matches(instr, &self.vm)) {
+                                    outln!(self, "Catchpoint {} ({}) at {:04x}: {}", i, self.catchpoints[i], self.vm.ctx.pc, instr.to_assembly());
+                                    break;
+                                }
+                            }
+                        }
+                        if self.step_watched(program)? {
+                            break;
                         }
                     }
+                    outln!(self);
                 }
-                "next" | "n" => {
-                    if self.vm.ctx.halted {
-                        println!("Error: Program is halted.");
-                    } else {
-                        let current_line = program.line_table.get(self.vm.ctx.pc).copied();
-                        if let Some(line) = current_line {
-                             println!("Stepping line {}...", line);
-                             // Step until we reach a different line OR it's a call
-                             while !self.vm.ctx.halted && self.vm.ctx.pc < program.len() && 
-                                   program.line_table.get(self.vm.ctx.pc) == Some(&line) {
-                                 self.vm.step(program)?;
-                             }
-                        } else {
-                             self.vm.step(program)?;
+            }
+            "call" => {
+                let text = parts[1..].join(" ");
+                match self.call_function(program, &text) {
+                    Ok(val) => outln!(self, "{} = {} (0x{:x})", text, val, val),
+                    Err(e) => outln!(self, "Error: {}", e),
+                }
+            }
+            "prof" => {
+                outln!(self, "--- Performance Profile ---");
+                outln!(self, "Total Instructions: {}", self.vm.instruction_count);
+                outln!(self, "Top Opcodes:");
+                for (opcode, count) in self.vm.opcode_profile().into_iter().take(8) {
+                    let percentage = (count as f64 / self.vm.instruction_count as f64) * 100.0;
+                    outln!(self, "  {:<15} : {:>8} ({:>5.1}%)", opcode.name(), count, percentage);
+                }
+                outln!(self);
+            }
+            "break" | "b" | "tbreak" => {
+                let temporary = parts[0] == "tbreak";
+                if parts.len() < 2 {
+                    outln!(self, "Usage: {} <pc|symbol|file:line> [if <expr>]", parts[0]);
+                } else {
+                    let condition = match split_condition(&parts[2..]) {
+                        Ok(condition) => condition,
+                        Err(e) => {
+                            outln!(self, "Error: {}", e);
+                            return Ok(true);
+                        }
+                    };
+                    match resolve_location(program, parts[1]) {
+                        Ok(pc) => {
+                            let kind = if temporary { "Temporary breakpoint" } else { "Breakpoint" };
+                            let suffix = condition.as_ref().map(|e| format!(" if {}", e)).unwrap_or_default();
+                            let id = self.add_breakpoint(pc, condition, temporary);
+                            outln!(self, "{} {} set at {:04x}{}{}", kind, id, pc, describe_location(program, pc), suffix);
                         }
-                        println!();
+                        Err(e) => outln!(self, "Error: {}", e),
                     }
                 }
-                "continue" | "c" => {
-                    if self.vm.ctx.halted {
-                        println!("Error: Program is halted.");
-                    } else {
-                        println!("Continuing...");
-                        while !self.vm.ctx.halted && self.vm.ctx.pc < program.len() {
-                            if self.breakpoints.contains(&self.vm.ctx.pc) {
-                                println!("Breakpoint reached at {:04x}", self.vm.ctx.pc);
-                                break;
+            }
+            "trace" | "tp" => {
+                // Keep the message's own spacing rather than the split words
+                let mut rest = input.trim().splitn(3, char::is_whitespace).skip(1);
+                match (rest.next(), rest.next()) {
+                    (None, _) => {
+                        if self.tracepoints.is_empty() {
+                            outln!(self, "No tracepoints.");
+                        }
+                        for (i, tp) in self.tracepoints.iter().enumerate() {
+                            outln!(self, "  {}: {:04x} hits {} \"{}\"", i, tp.pc, tp.hits, tp.message);
+                        }
+                    }
+                    (Some(_), None) => outln!(self, "Usage: trace <loc> <message with {{expr}}>"),
+                    (Some(loc), Some(message)) => {
+                        let message = message.trim();
+                        let message = message.strip_prefix('"').and_then(|m| m.strip_suffix('"')).unwrap_or(message);
+                        match resolve_location(program, loc).and_then(|pc| Template::parse(message).map(|t| (pc, t))) {
+                            Ok((pc, message)) => {
+                                outln!(self, "Tracepoint {} set at {:04x}{}", self.tracepoints.len(), pc, describe_location(program, pc));
+                                self.tracepoints.push(Tracepoint { pc, message, hits: 0 });
                             }
-                            self.vm.step(program)?;
+                            Err(e) => outln!(self, "Error: {}", e),
                         }
-                        println!();
                     }
                 }
-                "prof" => {
-                    println!("--- Performance Profile ---");
-                    println!("Total Instructions: {}", self.vm.instruction_count);
-                    println!("Top Opcodes:");
-                    let mut freq: Vec<_> = self.vm.instr_freq.iter().collect();
-                    freq.sort_by(|a, b| b.1.cmp(a.1));
-                    
-                    use crate::core::Opcode;
-                    for (&op_u8, count) in freq.iter().take(8) {
-                        let name = Opcode::from_u8(op_u8).map(|o| o.name()).unwrap_or("unknown");
-                        let percentage = (**count as f64 / self.vm.instruction_count as f64) * 100.0;
-                        println!("  {:<15} : {:>8} ({:>5.1}%)", name, *count, percentage);
+            }
+            "untrace" => {
+                match parts.get(1).and_then(|n| n.parse::<usize>().ok()) {
+                    Some(index) if index < self.tracepoints.len() => {
+                        let tp = self.tracepoints.remove(index);
+                        outln!(self, "Removed tracepoint at {:04x}", tp.pc);
                     }
-                    println!();
+                    Some(index) => outln!(self, "Error: No tracepoint {}", index),
+                    None => outln!(self, "Usage: untrace <n>"),
+                }
+            }
+            "delete" | "d" => {
+                match parts.get(1).and_then(|n| n.parse::<usize>().ok()) {
+                    Some(id) => match self.breakpoints.iter().position(|bp| bp.id == id) {
+                        Some(index) => {
+                            self.breakpoints.remove(index);
+                            outln!(self, "Deleted breakpoint {}", id);
+                        }
+                        None => outln!(self, "Error: No breakpoint {}", id),
+                    },
+                    None => outln!(self, "Usage: delete <n>"),
+                }
+            }
+            "ignore" => {
+                let args = (
+                    parts.get(1).and_then(|n| n.parse::<usize>().ok()),
+                    parts.get(2).and_then(|n| n.parse::<u64>().ok()),
+                );
+                match args {
+                    (Some(id), Some(count)) => match self.breakpoints.iter_mut().find(|bp| bp.id == id) {
+                        Some(bp) => {
+                            bp.ignore_count = count;
+                            outln!(self, "Will ignore next {} crossings of breakpoint {}", count, id);
+                        }
+                        None => outln!(self, "Error: No breakpoint {}", id),
+                    },
+                    _ => outln!(self, "Usage: ignore <bp> <count>"),
                 }
-                "break" | "b" => {
-                    if parts.len() < 2 {
-                        println!("Usage: break <pc>");
+            }
+            "watch" | "w" => {
+                if parts.len() < 2 {
+                    if self.watchpoints.is_empty() {
+                        outln!(self, "No watchpoints.");
+                    }
+                    for (i, (wp, condition)) in self.watchpoints.iter().enumerate() {
+                        match condition {
+                            Some(expr) => outln!(self, "  {}: {} if {}", i, wp, expr),
+                            None => outln!(self, "  {}: {}", i, wp),
+                        }
+                    }
+                } else {
+                    let (wp, rest) = if parts[1] == "mem" {
+                        match parts.get(2).and_then(|a| parse_number(a)) {
+                            Some(addr) => (Ok(Watchpoint::Memory(addr as usize)), &parts[3..]),
+                            None => (Err("Usage: watch mem <addr> [if <expr>]".to_string()), &parts[2..]),
+                        }
                     } else {
-                        if let Ok(pc) = usize::from_str_radix(parts[1].trim_start_matches("0x"), 16) {
-                            self.breakpoints.insert(pc);
-                            println!("Breakpoint set at {:04x}", pc);
-                        } else if let Ok(pc) = parts[1].parse::<usize>() {
-                            self.breakpoints.insert(pc);
-                            println!("Breakpoint set at {:04x}", pc);
-                        } else {
-                            println!("Error: Invalid PC");
+                        (Register::from_name(parts[1]).map(Watchpoint::Register).map_err(|e| e.to_string()), &parts[2..])
+                    };
+                    match wp.and_then(|wp| split_condition(rest).map(|c| (wp, c))) {
+                        Ok((wp, condition)) => {
+                            match &condition {
+                                Some(expr) => outln!(self, "Watching {} if {}", wp, expr),
+                                None => outln!(self, "Watching {}", wp),
+                            }
+                            self.add_watchpoint(wp, condition);
                         }
+                        Err(e) => outln!(self, "Error: {}", e),
                     }
                 }
-                "list" | "l" => {
-                    let start = self.vm.ctx.pc.saturating_sub(5);
-                    let end = (self.vm.ctx.pc + 5).min(program.len());
-                    for i in start..end {
-                        let prefix = if i == self.vm.ctx.pc { "=>" } else { "  " };
-                        let bp = if self.breakpoints.contains(&i) { "B" } else { " " };
-                        if let Some(instr) = program.get(i) {
-                            println!("{} {} {:04x}: {}", prefix, bp, i, instr.to_assembly());
+            }
+            "catch" => {
+                let cp = match &parts[1..] {
+                    [] => {
+                        if self.catchpoints.is_empty() {
+                            outln!(self, "No catchpoints.");
+                        }
+                        for (i, cp) in self.catchpoints.iter().enumerate() {
+                            outln!(self, "  {}: {}", i, cp);
                         }
+                        return Ok(true);
                     }
-                    println!();
+                    ["syscall"] => Ok(Catchpoint::Syscall(None)),
+                    ["syscall", n] => parse_number(n)
+                        .map(|n| Catchpoint::Syscall(Some(n)))
+                        .ok_or_else(|| format!("Invalid syscall number '{}'", n)),
+                    ["opcode", name] => Catchpoint::opcode(name),
+                    _ => Err("Usage: catch syscall [n] | catch opcode <class|name>".to_string()),
+                };
+                match cp {
+                    Ok(cp) => {
+                        outln!(self, "Catchpoint {}: {}", self.catchpoints.len(), cp);
+                        self.catchpoints.push(cp);
+                    }
+                    Err(e) => outln!(self, "Error: {}", e),
                 }
-                "print" | "p" => {
-                    if parts.len() < 2 {
-                        println!("Usage: print <reg>");
-                    } else {
-                        let reg_name = parts[1].trim_start_matches('@').to_lowercase();
-                        if let Some(reg) = self.try_resolve_register(&reg_name) {
-                            let val = self.vm.ctx.get_reg(reg);
-                            println!("{} = {} (0x{:x})", parts[1], val, val);
-                        } else {
-                            println!("Error: Unknown register '{}'", parts[1]);
+            }
+            "uncatch" => {
+                match parts.get(1).and_then(|n| n.parse::<usize>().ok()) {
+                    Some(index) if index < self.catchpoints.len() => {
+                        let cp = self.catchpoints.remove(index);
+                        outln!(self, "Removed catchpoint {}", cp);
+                    }
+                    Some(index) => outln!(self, "Error: No catchpoint {}", index),
+                    None => outln!(self, "Usage: uncatch <n>"),
+                }
+            }
+            "unwatch" => {
+                match parts.get(1).and_then(|n| n.parse::<usize>().ok()) {
+                    Some(index) => match self.remove_watchpoint(index) {
+                        Some(wp) => outln!(self, "Removed watchpoint {}", wp),
+                        None => outln!(self, "Error: No watchpoint {}", index),
+                    },
+                    None => outln!(self, "Usage: unwatch <n>"),
+                }
+            }
+            "set" => {
+                match self.set_value(&parts[1..].join(" ")) {
+                    Ok(msg) => outln!(self, "{}", msg),
+                    Err(e) => outln!(self, "Error: {}", e),
+                }
+            }
+            "display" => {
+                if parts.len() < 2 {
+                    self.show_displays();
+                } else {
+                    match Expr::parse(&parts[1..].join(" ")) {
+                        Ok(expr) => {
+                            self.displays.push(expr);
+                            self.show_displays();
                         }
+                        Err(e) => outln!(self, "Error: {}", e),
+                    }
+                }
+            }
+            "undisplay" => {
+                match parts.get(1).and_then(|n| n.parse::<usize>().ok()) {
+                    Some(index) if index < self.displays.len() => {
+                        let expr = self.displays.remove(index);
+                        outln!(self, "Removed display {}", expr);
+                    }
+                    Some(index) => outln!(self, "Error: No display {}", index),
+                    None => outln!(self, "Usage: undisplay <n>"),
+                }
+            }
+            "save" => {
+                let path = parts.get(1).map(PathBuf::from).or_else(|| self.session_path.clone());
+                match path {
+                    Some(path) => match self.save_session(program, &path) {
+                        Ok(count) => outln!(self, "Saved {} commands to {}", count, path.display()),
+                        Err(e) => outln!(self, "Error: {}: {}", path.display(), e),
+                    },
+                    None => outln!(self, "Usage: save <file>"),
+                }
+            }
+            "source" => {
+                match parts.get(1) {
+                    Some(path) => match self.source(program, Path::new(path)) {
+                        Ok(keep_going) => return Ok(keep_going),
+                        Err(e) => outln!(self, "Error: {}", e),
+                    },
+                    None => outln!(self, "Usage: source <file>"),
+                }
+            }
+            "list" | "l" => {
+                let start = self.vm.ctx.pc.saturating_sub(5);
+                let end = (self.vm.ctx.pc + 5).min(program.len());
+                for line in disasm::disassemble(program).get(start..end).unwrap_or_default() {
+                    let prefix = if line.index == self.vm.ctx.pc { "=>" } else { "  " };
+                    let bp = if self.breakpoints.iter().any(|bp| bp.pc == line.index) { "B" } else { " " };
+                    if let Some(name) = &line.label {
+                        outln!(self, "     {}:", name);
                     }
+                    outln!(self, "{} {} {:04x}: {}", prefix, bp, line.index, line.text);
                 }
-                "info" => {
-                    if parts.len() < 2 || parts[1] != "registers" {
-                        println!("Usage: info registers");
+                outln!(self);
+            }
+            "print" | "p" => {
+                if parts.len() < 2 {
+                    outln!(self, "Usage: print <expr>");
+                } else {
+                    let text = parts[1..].join(" ");
+                    match Expr::parse(&text).and_then(|expr| expr.eval(&self.vm)) {
+                        Ok(val) => outln!(self, "{} = {} (0x{:x})", text, val, val),
+                        Err(e) => outln!(self, "Error: {}", e),
+                    }
+                }
+            }
+            "info" => {
+                if parts.get(1).is_some_and(|p| ["breakpoints", "break", "b"].contains(p)) {
+                    if self.breakpoints.is_empty() {
+                        outln!(self, "No breakpoints.");
                     } else {
-                        for i in 0..16 {
-                            let reg = Register::from_u8(i).unwrap();
-                            let val = self.vm.ctx.get_reg(reg);
-                            println!("{:<4} = {:<12} (0x{:x})", reg.name(), val, val);
+                        outln!(self, "{:<4} {:<6} {:<8} {:<6} Where", "Num", "Type", "Address", "Hits");
+                    }
+                    for bp in &self.breakpoints {
+                        let kind = if bp.temporary { "tbreak" } else { "break" };
+                        outln!(self, "{:<4} {:<6} {:<8} {:<6}{}", bp.id, kind, format!("{:04x}", bp.pc), bp.hits, describe_location(program, bp.pc));
+                        if let Some(expr) = &bp.condition {
+                            outln!(self, "        stop only if {}", expr);
+                        }
+                        if bp.ignore_count > 0 {
+                            outln!(self, "        ignore next {} hits", bp.ignore_count);
                         }
-                        println!("{:<4} = {:<12} (0x{:x})", "IP", self.vm.ctx.pc, self.vm.ctx.pc);
                     }
+                } else if parts.get(1).is_some_and(|p| ["variables", "vars"].contains(p)) {
+                    if program.variables.is_empty() {
+                        outln!(self, "No variable info (assembled with --no-debug-info?).");
+                    }
+                    for (name, &reg) in &program.variables {
+                        let val = self.vm.ctx.get_reg(reg);
+                        outln!(self, "{} ({}) = {} (0x{:x})", name, reg.name(), val, val);
+                    }
+                } else if parts.len() < 2 || parts[1] != "registers" {
+                    outln!(self, "Usage: info registers | info variables | info breakpoints");
+                } else {
+                    let count = if program.uses_extended_registers() { Register::EXTENDED_GP_COUNT } else { Register::GP_COUNT };
+                    for i in 0..count as u8 {
+                        let reg = Register::general(i).unwrap();
+                        let val = self.vm.ctx.get_reg(reg);
+                        let names = program.variables_in(reg).join(", ");
+                        outln!(self, "{}", format!("{:<4} = {:<12} (0x{:x})  {}", reg.name(), val, val, names).trim_end());
+                    }
+                    outln!(self, "{:<4} = {:<12} (0x{:x})", "IP", self.vm.ctx.pc, self.vm.ctx.pc);
                 }
-                "help" | "?" => {
-                    println!("Commands:");
-                    println!("  step (s)        Execute one instruction");
-                    println!("  next (n)        Execute until next source line");
-                    println!("  continue (c)    Run until breakpoint or end");
-                    println!("  prof            Show instruction profiling data");
-                    println!("  break (b) <pc>  Set breakpoint at instruction index");
-                    println!("  list (l)        Show surrounding assembly");
-                    println!("  print (p) <reg> Display register value");
-                    println!("  info registers  Show all GP registers");
-                    println!("  quit (q)        Exit debugger");
-                }
-                "quit" | "q" => break,
-                _ => println!("Unknown command: '{}'. Type 'help' for info.", parts[0]),
             }
+            "help" | "?" => {
+                outln!(self, "Commands:");
+                outln!(self, "  step (s)        Execute one instruction");
+                outln!(self, "  next (n)        Execute until next source line");
+                outln!(self, "  continue (c)    Run until breakpoint or end");
+                outln!(self, "  rstep (rs)      Undo the last executed instruction");
+                outln!(self, "  rnext (rn)      Reverse to the start of the previous source line");
+                outln!(self, "  prof            Show instruction profiling data");
+                outln!(self, "  break (b) <loc> Set breakpoint at an instruction index,");
+                outln!(self, "                  symbol (main), or source line (file.alya:17)");
+                outln!(self, "  break <loc> if <expr>  Only stop when expr is nonzero");
+                outln!(self, "  tbreak <loc>    Breakpoint deleted after its first stop");
+                outln!(self, "  delete (d) <n>  Delete breakpoint n");
+                outln!(self, "  ignore <n> <c>  Pass over breakpoint n the next c times");
+                outln!(self, "  watch (w) @reg  Stop when a register changes");
+                outln!(self, "  watch mem <addr> Stop when the qword at addr changes");
+                outln!(self, "  watch ... if <expr>  Only report changes where expr is nonzero");
+                outln!(self, "  watch           List watchpoints");
+                outln!(self, "  unwatch <n>     Remove watchpoint n");
+                outln!(self, "  trace <loc> <msg>  Log msg and keep going when loc is reached;");
+                outln!(self, "                  {{expr}} or {{expr:x}} in msg is replaced by its value");
+                outln!(self, "  trace           List tracepoints");
+                outln!(self, "  untrace <n>     Remove tracepoint n");
+                outln!(self, "  catch syscall [n]  Stop before any syscall (or syscall n)");
+                outln!(self, "  catch opcode <c>   Stop before an opcode or class: store, load,");
+                outln!(self, "                     memory, stack, jump, call, arith, bitwise, float");
+                outln!(self, "  catch           List catchpoints");
+                outln!(self, "  uncatch <n>     Remove catchpoint n");
+                outln!(self, "  set @reg = <v>  Overwrite a register");
+                outln!(self, "  set mem <addr> = <byte>  Overwrite a byte of memory");
+                outln!(self, "  list (l)        Show surrounding assembly");
+                outln!(self, "  call f(<args>)  Run a proc with args in r1-r4, the rest pushed,");
+                outln!(self, "                  and print r0; program state is restored afterwards");
+                outln!(self, "  print (p) <expr> Evaluate e.g. @r0 + @r1*8 or *(@sp)");
+                outln!(self, "  info registers  Show all GP registers and the variables in them");
+                outln!(self, "  info variables  Show each variable's register and value");
+                outln!(self, "  info breakpoints  List breakpoints with hit counts");
+                outln!(self, "  display <expr>  Show expr after every stop (no arg: show all)");
+                outln!(self, "  undisplay <n>   Remove display n");
+                outln!(self, "  save [file]     Save breakpoints, watchpoints and displays");
+                outln!(self, "  source <file>   Run debugger commands from a file");
+                outln!(self, "  quit (q)        Exit debugger");
+            }
+            "quit" | "q" => return Ok(false),
+            _ => outln!(self, "Unknown command: '{}'. Type 'help' for info.", parts[0]),
         }
 
-        Ok(())
+        if resumes {
+            self.show_displays();
+        }
+        Ok(true)
+    }
+}
+
+/// Split a call's argument list on top-level commas
+fn split_args(text: &str) -> Vec<&str> {
+    if text.trim().is_empty() {
+        return Vec::new();
+    }
+    let mut args = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, c) in text.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ',' if depth == 0 => {
+                args.push(&text[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    args.push(&text[start..]);
+    args
+}
+
+/// Parse a trailing `if <expr>` clause, if present
+fn split_condition(rest: &[&str]) -> Result<Option<Expr>, String> {
+    match rest.split_first() {
+        None => Ok(None),
+        Some((&"if", expr)) if !expr.is_empty() => Expr::parse(&expr.join(" ")).map(Some),
+        Some(_) => Err(format!("Expected 'if <expr>', found '{}'", rest.join(" "))),
+    }
+}
+
+/// Parse a decimal or 0x-prefixed hex number
+fn parse_number(text: &str) -> Option<u64> {
+    match text.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => text.parse::<u64>().ok(),
+    }
+}
+
+/// Resolve a breakpoint location: a symbol name, `file:line`, or an instruction index.
+/// Symbols win over numbers so labels such as `add` or `face` aren't read as hex.
+fn resolve_location(program: &Program, location: &str) -> Result<usize, String> {
+    if let Some(&index) = program.symbols.get(location) {
+        return Ok(index);
+    }
+
+    if let Some((file, line)) = location.rsplit_once(':') {
+        // Any file the binary does not list is taken to be the assembled one
+        let line: usize = line.parse().map_err(|_| format!("Invalid line number '{}'", line))?;
+        return program.index_for_file_line(program.find_file(file).unwrap_or(0), line)
+            .ok_or_else(|| format!("No code at or after line {}", line));
+    }
+
+    // Instruction indices are shown in hex everywhere, so bare numbers are hex
+    if let Ok(pc) = usize::from_str_radix(location.trim_start_matches("0x"), 16) {
+        return Ok(pc);
+    }
+
+    Err(format!("Unknown symbol or location '{}'", location))
+}
+
+/// Human-readable suffix naming the symbol and line of an instruction
+fn describe_location(program: &Program, pc: usize) -> String {
+    let mut text = String::new();
+    if let Some(name) = program.symbol_at(pc) {
+        text.push_str(&format!(" <{}>", name));
     }
+    match program.location(pc) {
+        Some((file, line)) if program.file_index(pc) != 0 => text.push_str(&format!(" ({}:{})", file, line)),
+        Some((_, line)) => text.push_str(&format!(" (line {})", line)),
+        None => {}
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::instruction::Instruction;
+
+    #[test]
+    fn test_session_commands_round_trip() {
+        let mut program = Program::from_instructions("t", vec![Instruction::Nop; 4]);
+        program.symbols.insert("main".to_string(), 1);
 
-    fn try_resolve_register(&self, name: &str) -> Option<Register> {
-         match name {
-            "r0" => Some(Register::R0),
-            "r1" => Some(Register::R1),
-            "r2" => Some(Register::R2),
-            "r3" => Some(Register::R3),
-            "r4" => Some(Register::R4),
-            "r5" => Some(Register::R5),
-            "r6" => Some(Register::R6),
-            "r7" => Some(Register::R7),
-            "r8" => Some(Register::R8),
-            "r9" => Some(Register::R9),
-            "r10" => Some(Register::R10),
-            "r11" => Some(Register::R11),
-            "r12" => Some(Register::R12),
-            "r13" => Some(Register::R13),
-            "r14" => Some(Register::R14),
-            "r15" => Some(Register::R15),
-            "sp" => Some(Register::SP),
-            "bp" => Some(Register::BP),
-            "hp" => Some(Register::HP),
-            "ip" => Some(Register::IP),
-            "f0" => Some(Register::F0),
-            // ... add more if needed
-            _ => None,
+        let mut dbg = Debugger::new(VM::new());
+        dbg.vm.init(&program).unwrap();
+        for cmd in ["break main", "break 3 if @r0 > 2", "watch mem 0x8000", "catch syscall 2", "catch opcode STORE", "display @r1 + 1"] {
+            assert!(dbg.execute(&program, cmd).unwrap());
         }
+        let saved = dbg.session_commands(&program);
+        assert_eq!(saved, vec![
+            "break main",
+            "break 3 if (@r0 > 2)",
+            "watch mem 0x8000",
+            "catch syscall 2",
+            "catch opcode store",
+            "display (@r1 + 1)",
+        ]);
+
+        let mut restored = Debugger::new(VM::new());
+        restored.vm.init(&program).unwrap();
+        for cmd in &saved {
+            restored.execute(&program, cmd).unwrap();
+        }
+        assert_eq!(restored.session_commands(&program), saved);
+    }
+
+    #[test]
+    fn test_run_script() {
+        let program = Program::from_instructions("t", vec![
+            Instruction::LoadImm { dest: Register::R0, value: 1 },
+            Instruction::LoadImm { dest: Register::R0, value: 2 },
+            Instruction::Halt,
+        ]);
+        let mut dbg = Debugger::new(VM::new());
+        dbg.run_script(&program, "# comment\nbreak 1\ncontinue\nset @r1 = 7\n").unwrap();
+        assert_eq!(dbg.vm().ctx.pc, 1);
+        assert_eq!(dbg.vm().ctx.get_reg(Register::R0), 1);
+        assert_eq!(dbg.vm().ctx.get_reg(Register::R1), 7);
+
+        dbg.run_script(&program, "continue\ncontinue\nquit\nset @r0 = 9").unwrap();
+        assert!(dbg.vm().ctx.halted);
+        assert_eq!(dbg.vm().ctx.get_reg(Register::R0), 2);
+    }
+
+    #[test]
+    fn test_software_breakpoint() {
+        let program = Program::from_instructions("t", vec![
+            Instruction::TraceOn,
+            Instruction::Breakpoint,
+            Instruction::TraceOff,
+            Instruction::Halt,
+        ]);
+        let mut dbg = Debugger::new(VM::new()).with_output(std::io::sink());
+        dbg.run_script(&program, "continue").unwrap();
+        assert_eq!(dbg.vm().ctx.pc, 2);
+        assert!(dbg.vm().ctx.trace);
+
+        dbg.execute(&program, "continue").unwrap();
+        assert!(dbg.vm().ctx.halted);
+        assert!(!dbg.vm().ctx.trace);
+    }
+
+    #[test]
+    fn test_catchpoint_matches() {
+        let mut vm = VM::new();
+        vm.ctx.set_reg(Register::R0, 2);
+        let store = Instruction::Store { addr_reg: Register::R1, src: Register::R2 };
+
+        assert!(Catchpoint::Syscall(None).matches(&Instruction::Syscall, &vm));
+        assert!(Catchpoint::Syscall(Some(2)).matches(&Instruction::Syscall, &vm));
+        assert!(!Catchpoint::Syscall(Some(1)).matches(&Instruction::Syscall, &vm));
+        assert!(Catchpoint::opcode("store").unwrap().matches(&store, &vm));
+        assert!(Catchpoint::opcode("push").unwrap().matches(&Instruction::Push { src: Register::R0 }, &vm));
+        assert!(!Catchpoint::opcode("jump").unwrap().matches(&store, &vm));
+        assert!(Catchpoint::opcode("bogus").is_err());
+    }
+
+    #[test]
+    fn test_ignore_counts_and_temporary_breakpoints() {
+        // 0: r0 += 1 (loop body), 1: jump 0
+        let program = Program::from_instructions("t", vec![
+            Instruction::AddAssign { dest: Register::R0, src: Register::R1 },
+            Instruction::Jump { target: 0 },
+        ]);
+        let mut dbg = Debugger::new(VM::new());
+        dbg.run_script(&program, "set @r1 = 1\nbreak 1\nignore 1 3\ncontinue").unwrap();
+        assert_eq!(dbg.vm().ctx.get_reg(Register::R0), 4);
+        assert_eq!(dbg.breakpoints[0].hits, 4);
+
+        dbg.run_script(&program, "set @r1 = 1\ndelete 1\ntbreak 0\ncontinue").unwrap();
+        assert_eq!(dbg.vm().ctx.get_reg(Register::R0), 1);
+        assert!(dbg.breakpoints.is_empty());
+    }
+
+    #[test]
+    fn test_call_function_restores_state() {
+        // double: r0 = r1 + r1, stored to the heap as a side effect
+        let mut program = Program::from_instructions("t", vec![
+            Instruction::Halt,
+            Instruction::Add { dest: Register::R0, left: Register::R1, right: Register::R1 },
+            Instruction::LoadImm { dest: Register::R5, value: 0x8000 },
+            Instruction::Store { src: Register::R0, addr_reg: Register::R5 },
+            Instruction::Return,
+        ]);
+        program.symbols.insert("double".to_string(), 1);
+
+        let mut dbg = Debugger::new(VM::new());
+        dbg.vm.init(&program).unwrap();
+        dbg.vm.ctx.set_reg(Register::R0, 7);
+        let heap_before = dbg.vm.memory.read_qword(0x8000).unwrap();
+
+        assert_eq!(dbg.call_function(&program, "double(21)"), Ok(42));
+        assert_eq!(dbg.call_function(&program, "double(@r0 + 1)"), Ok(16));
+        assert_eq!(dbg.vm.ctx.get_reg(Register::R0), 7);
+        assert_eq!(dbg.vm.ctx.pc, 0);
+        assert_eq!(dbg.vm.memory.read_qword(0x8000).unwrap(), heap_before);
+        assert!(dbg.call_function(&program, "missing()").is_err());
+    }
+
+    #[test]
+    fn test_set_value() {
+        let program = Program::from_instructions("t", vec![
+            Instruction::LoadImm { dest: Register::R0, value: 5 },
+            Instruction::Push { src: Register::R0 },
+            Instruction::Pop { dest: Register::R1 },
+            Instruction::Halt,
+        ]);
+        let mut dbg = Debugger::new(VM::new());
+        dbg.vm.init(&program).unwrap();
+
+        assert_eq!(dbg.set_value("@r2 = 42"), Ok("@r2 = 42 (0x2a)".to_string()));
+        assert_eq!(dbg.vm.ctx.get_reg(Register::R2), 42);
+        dbg.set_value("mem 0x8000 = 0xff").unwrap();
+        assert_eq!(dbg.vm.memory.read_byte(0x8000).unwrap(), 0xff);
+        assert!(dbg.set_value("mem 0x8000 = 0x100").is_err());
+
+        // The next push lands below the new SP
+        let sp = dbg.vm.stack.pointer() - 64;
+        dbg.set_value(&format!("@sp = {}", sp)).unwrap();
+        assert_eq!(dbg.vm.stack.pointer(), sp);
+        dbg.vm.step(&program).unwrap();
+        dbg.vm.step(&program).unwrap();
+        assert_eq!(dbg.vm.memory.read_qword(sp - 8).unwrap(), 5);
+        dbg.vm.step(&program).unwrap();
+        assert_eq!(dbg.vm.ctx.get_reg(Register::SP), sp as u64);
+    }
+
+    #[test]
+    fn test_call_function_proc() {
+        let source = "halt\nproc add2(a, b)\n@c := @a + @b\nreturn @c\nendproc\n\
+                      proc sum6(a, b, c, d, e, f)\n@s := @a + @b\n@s += @c\n@s += @d\n@s += @e\n@s += @f\nreturn @s\nendproc\n";
+        let program = crate::assembler::assemble(source, "t").unwrap();
+        let mut dbg = Debugger::new(VM::new());
+        dbg.vm.init(&program).unwrap();
+        let sp = dbg.vm.stack.pointer();
+
+        assert_eq!(dbg.call_function(&program, "add2(3, 4)"), Ok(7));
+        assert_eq!(dbg.call_function(&program, "sum6(1, 2, 3, 4, 5, 6)"), Ok(21));
+        assert_eq!(dbg.vm.stack.pointer(), sp);
     }
 }
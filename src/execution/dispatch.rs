@@ -0,0 +1,173 @@
+//! Pre-decoded instructions for the interpreter loop.
+//!
+//! `compile` turns an `Instruction` into an `Op`: a handler function
+//! pointer with the operands in fixed slots. `VM::init` compiles the whole
+//! program once, so each step is an indexed load and an indirect call
+//! rather than a match over the instruction enum.
+
+use crate::core::{Opcode, Register};
+use crate::error::VmResult;
+use crate::instruction::{Instruction, Program};
+use super::handlers::{arithmetic, logic, data_move, control, io, stack as stack_handler, memory as memory_handler, memory_ext, float, bitwise_ext, trap};
+use super::VM;
+
+/// Executes one `Op` against the VM
+pub(crate) type Handler = fn(&mut VM, &Op) -> VmResult<()>;
+
+/// A compiled instruction
+#[derive(Clone, Copy)]
+pub(crate) struct Op {
+    pub handler: Handler,
+    pub opcode: Opcode,
+    /// Register operands in the order the instruction declares them
+    pub r: [Register; 3],
+    /// The immediate, target, count or offset, if the instruction has one
+    pub imm: u64,
+}
+
+/// Compile every instruction of `program`
+pub(crate) fn compile_program(program: &Program) -> Vec<Op> {
+    program.instructions.iter().map(compile).collect()
+}
+
+fn compile(instruction: &Instruction) -> Op {
+    let opcode = instruction.opcode();
+    let make = |regs: &[Register], imm: u64, handler: Handler| {
+        let mut r = [Register::R0; 3];
+        r[..regs.len()].copy_from_slice(regs);
+        Op { handler, opcode, r, imm }
+    };
+    match instruction {
+        // Control
+        Instruction::Halt => make(&[], 0, |vm, _| { vm.ctx.halted = true; Ok(()) }),
+        Instruction::Nop => make(&[], 0, |_, _| Ok(())),
+        Instruction::Exit { src } => make(&[*src], 0, |vm, op| { control::handle_exit(&mut vm.ctx, op.r[0]); Ok(()) }),
+
+        // Data Movement
+        Instruction::LoadImm { dest, value } => make(&[*dest], *value, |vm, op| { data_move::handle_load_imm(&mut vm.ctx, op.r[0], op.imm); Ok(()) }),
+        Instruction::Move { dest, src } => make(&[*dest, *src], 0, |vm, op| { data_move::handle_move(&mut vm.ctx, op.r[0], op.r[1]); Ok(()) }),
+        Instruction::Swap { r1, r2 } => make(&[*r1, *r2], 0, |vm, op| { data_move::handle_swap(&mut vm.ctx, op.r[0], op.r[1]); Ok(()) }),
+
+        // Arithmetic
+        Instruction::Add { dest, left, right } => make(&[*dest, *left, *right], 0, |vm, op| { arithmetic::handle_add(&mut vm.ctx, op.r[0], op.r[1], op.r[2]); Ok(()) }),
+        Instruction::Sub { dest, left, right } => make(&[*dest, *left, *right], 0, |vm, op| { arithmetic::handle_sub(&mut vm.ctx, op.r[0], op.r[1], op.r[2]); Ok(()) }),
+        Instruction::Mul { dest, left, right } => make(&[*dest, *left, *right], 0, |vm, op| { arithmetic::handle_mul(&mut vm.ctx, op.r[0], op.r[1], op.r[2]); Ok(()) }),
+        Instruction::Div { dest, left, right } => make(&[*dest, *left, *right], 0, |vm, op| arithmetic::handle_div(&mut vm.ctx, op.r[0], op.r[1], op.r[2])),
+        Instruction::Mod { dest, left, right } => make(&[*dest, *left, *right], 0, |vm, op| arithmetic::handle_mod(&mut vm.ctx, op.r[0], op.r[1], op.r[2])),
+        Instruction::MulHi { dest, left, right } => make(&[*dest, *left, *right], 0, |vm, op| { arithmetic::handle_mulhi(&mut vm.ctx, op.r[0], op.r[1], op.r[2]); Ok(()) }),
+        Instruction::IDiv { dest, left, right } => make(&[*dest, *left, *right], 0, |vm, op| arithmetic::handle_idiv(&mut vm.ctx, op.r[0], op.r[1], op.r[2])),
+        Instruction::IMod { dest, left, right } => make(&[*dest, *left, *right], 0, |vm, op| arithmetic::handle_imod(&mut vm.ctx, op.r[0], op.r[1], op.r[2])),
+
+        // Compound Assignment
+        Instruction::AddAssign { dest, src } => make(&[*dest, *src], 0, |vm, op| { arithmetic::handle_add_assign(&mut vm.ctx, op.r[0], op.r[1]); Ok(()) }),
+        Instruction::SubAssign { dest, src } => make(&[*dest, *src], 0, |vm, op| { arithmetic::handle_sub_assign(&mut vm.ctx, op.r[0], op.r[1]); Ok(()) }),
+        Instruction::MulAssign { dest, src } => make(&[*dest, *src], 0, |vm, op| { arithmetic::handle_mul_assign(&mut vm.ctx, op.r[0], op.r[1]); Ok(()) }),
+        Instruction::DivAssign { dest, src } => make(&[*dest, *src], 0, |vm, op| arithmetic::handle_div_assign(&mut vm.ctx, op.r[0], op.r[1])),
+
+        // Bitwise
+        Instruction::And { dest, left, right } => make(&[*dest, *left, *right], 0, |vm, op| { logic::handle_and(&mut vm.ctx, op.r[0], op.r[1], op.r[2]); Ok(()) }),
+        Instruction::Or { dest, left, right } => make(&[*dest, *left, *right], 0, |vm, op| { logic::handle_or(&mut vm.ctx, op.r[0], op.r[1], op.r[2]); Ok(()) }),
+        Instruction::Xor { dest, left, right } => make(&[*dest, *left, *right], 0, |vm, op| { logic::handle_xor(&mut vm.ctx, op.r[0], op.r[1], op.r[2]); Ok(()) }),
+        Instruction::Not { dest, src } => make(&[*dest, *src], 0, |vm, op| { logic::handle_not(&mut vm.ctx, op.r[0], op.r[1]); Ok(()) }),
+        Instruction::Shl { dest, left, right } => make(&[*dest, *left, *right], 0, |vm, op| { logic::handle_shl(&mut vm.ctx, op.r[0], op.r[1], op.r[2]); Ok(()) }),
+        Instruction::Shr { dest, left, right } => make(&[*dest, *left, *right], 0, |vm, op| { logic::handle_shr(&mut vm.ctx, op.r[0], op.r[1], op.r[2]); Ok(()) }),
+        Instruction::ISht { dest, left, right } => make(&[*dest, *left, *right], 0, |vm, op| { logic::handle_isht(&mut vm.ctx, op.r[0], op.r[1], op.r[2]); Ok(()) }),
+
+        // Stack
+        Instruction::Push { src } => make(&[*src], 0, |vm, op| stack_handler::handle_push(&mut vm.ctx, &mut vm.stack, &mut vm.memory, op.r[0])),
+        Instruction::Pop { dest } => make(&[*dest], 0, |vm, op| stack_handler::handle_pop(&mut vm.ctx, &mut vm.stack, &vm.memory, op.r[0])),
+        Instruction::Peek { dest } => make(&[*dest], 0, |vm, op| stack_handler::handle_peek(&mut vm.ctx, &vm.stack, &vm.memory, op.r[0])),
+        Instruction::Enter { locals_size } => make(&[], *locals_size as u64, |vm, op| stack_handler::handle_enter(&mut vm.ctx, &mut vm.stack, &mut vm.memory, op.imm as usize)),
+        Instruction::Leave => make(&[], 0, |vm, _| stack_handler::handle_leave(&mut vm.ctx, &mut vm.stack, &vm.memory)),
+
+        // Memory
+        Instruction::LoadLocal { dest, offset } => make(&[*dest], *offset as u64, |vm, op| memory_handler::handle_load_local(&mut vm.ctx, &vm.memory, op.r[0], op.imm as i32)),
+        Instruction::StoreLocal { src, offset } => make(&[*src], *offset as u64, |vm, op| memory_handler::handle_store_local(&mut vm.ctx, &mut vm.memory, op.r[0], op.imm as i32)),
+        Instruction::Load { dest, addr_reg } => make(&[*dest, *addr_reg], 0, |vm, op| memory_handler::handle_load(&mut vm.ctx, &vm.memory, op.r[0], op.r[1])),
+        Instruction::Store { src, addr_reg } => make(&[*src, *addr_reg], 0, |vm, op| memory_handler::handle_store(&mut vm.ctx, &mut vm.memory, op.r[0], op.r[1])),
+        Instruction::LoadByte { dest, addr_reg } => make(&[*dest, *addr_reg], 0, |vm, op| memory_handler::handle_load_sized(&mut vm.ctx, &vm.memory, op.r[0], op.r[1], 1)),
+        Instruction::LoadWord { dest, addr_reg } => make(&[*dest, *addr_reg], 0, |vm, op| memory_handler::handle_load_sized(&mut vm.ctx, &vm.memory, op.r[0], op.r[1], 2)),
+        Instruction::LoadDWord { dest, addr_reg } => make(&[*dest, *addr_reg], 0, |vm, op| memory_handler::handle_load_sized(&mut vm.ctx, &vm.memory, op.r[0], op.r[1], 4)),
+        Instruction::StoreByte { src, addr_reg } => make(&[*src, *addr_reg], 0, |vm, op| memory_handler::handle_store_sized(&mut vm.ctx, &mut vm.memory, op.r[0], op.r[1], 1)),
+        Instruction::StoreWord { src, addr_reg } => make(&[*src, *addr_reg], 0, |vm, op| memory_handler::handle_store_sized(&mut vm.ctx, &mut vm.memory, op.r[0], op.r[1], 2)),
+        Instruction::StoreDWord { src, addr_reg } => make(&[*src, *addr_reg], 0, |vm, op| memory_handler::handle_store_sized(&mut vm.ctx, &mut vm.memory, op.r[0], op.r[1], 4)),
+        Instruction::LoadIndexed { dest, base_reg, index_reg } => make(&[*dest, *base_reg, *index_reg], 0, |vm, op| memory_handler::handle_load_indexed(&mut vm.ctx, &vm.memory, op.r[0], op.r[1], op.r[2])),
+        Instruction::StoreIndexed { src, base_reg, index_reg } => make(&[*src, *base_reg, *index_reg], 0, |vm, op| memory_handler::handle_store_indexed(&mut vm.ctx, &mut vm.memory, op.r[0], op.r[1], op.r[2])),
+
+        // Memory Extensions
+        Instruction::Alloc { dest, size } => make(&[*dest, *size], 0, |vm, op| memory_ext::handle_alloc(&mut vm.ctx, &vm.heap, &mut vm.memory, op.r[0], op.r[1])),
+        Instruction::Free { ptr } => make(&[*ptr], 0, |vm, op| memory_ext::handle_free(&mut vm.ctx, &vm.heap, &mut vm.memory, op.r[0])),
+        Instruction::MemCopy { dest, src, size } => make(&[*dest, *src, *size], 0, |vm, op| memory_ext::handle_memcpy(&mut vm.ctx, &mut vm.memory, op.r[0], op.r[1], op.r[2])),
+        Instruction::MemSet { dest, value, size } => make(&[*dest, *value, *size], 0, |vm, op| memory_ext::handle_memset(&mut vm.ctx, &mut vm.memory, op.r[0], op.r[1], op.r[2])),
+
+        // Control Flow
+        Instruction::Jump { target } => make(&[], *target as u64, |vm, op| { control::handle_jump(&mut vm.ctx, op.imm as usize); Ok(()) }),
+        Instruction::Compare { left, right } => make(&[*left, *right], 0, |vm, op| { control::handle_compare(&mut vm.ctx, op.r[0], op.r[1]); Ok(()) }),
+        Instruction::Test { left, right } => make(&[*left, *right], 0, |vm, op| { control::handle_test(&mut vm.ctx, op.r[0], op.r[1]); Ok(()) }),
+        Instruction::Switch { index, count } => make(&[*index], *count as u64, |vm, op| { control::handle_switch(&mut vm.ctx, op.r[0], op.imm as usize); Ok(()) }),
+        Instruction::JumpIfZero { target } => make(&[], *target as u64, |vm, op| { control::handle_jump_if_zero(&mut vm.ctx, op.imm as usize); Ok(()) }),
+        Instruction::JumpIfNotZero { target } => make(&[], *target as u64, |vm, op| { control::handle_jump_if_not_zero(&mut vm.ctx, op.imm as usize); Ok(()) }),
+        Instruction::JumpIfGt { target } => make(&[], *target as u64, |vm, op| { control::handle_jump_if_gt(&mut vm.ctx, op.imm as usize); Ok(()) }),
+        Instruction::JumpIfLt { target } => make(&[], *target as u64, |vm, op| { control::handle_jump_if_lt(&mut vm.ctx, op.imm as usize); Ok(()) }),
+        Instruction::JumpIfGe { target } => make(&[], *target as u64, |vm, op| { control::handle_jump_if_ge(&mut vm.ctx, op.imm as usize); Ok(()) }),
+        Instruction::JumpIfLe { target } => make(&[], *target as u64, |vm, op| { control::handle_jump_if_le(&mut vm.ctx, op.imm as usize); Ok(()) }),
+        Instruction::JumpIfEq { target } => make(&[], *target as u64, |vm, op| { control::handle_jump_if_eq(&mut vm.ctx, op.imm as usize); Ok(()) }),
+        Instruction::JumpIfNe { target } => make(&[], *target as u64, |vm, op| { control::handle_jump_if_ne(&mut vm.ctx, op.imm as usize); Ok(()) }),
+        Instruction::JumpIfAbove { target } => make(&[], *target as u64, |vm, op| { control::handle_jump_if_above(&mut vm.ctx, op.imm as usize); Ok(()) }),
+        Instruction::JumpIfBelow { target } => make(&[], *target as u64, |vm, op| { control::handle_jump_if_below(&mut vm.ctx, op.imm as usize); Ok(()) }),
+        Instruction::JumpIfAe { target } => make(&[], *target as u64, |vm, op| { control::handle_jump_if_ae(&mut vm.ctx, op.imm as usize); Ok(()) }),
+        Instruction::JumpIfBe { target } => make(&[], *target as u64, |vm, op| { control::handle_jump_if_be(&mut vm.ctx, op.imm as usize); Ok(()) }),
+
+        // Functions
+        Instruction::Call { target } => make(&[], *target as u64, |vm, op| control::handle_call(&mut vm.ctx, op.imm as usize)),
+        Instruction::CallReg { target_reg } => make(&[*target_reg], 0, |vm, op| control::handle_call_reg(&mut vm.ctx, op.r[0], vm.ops.len())),
+        Instruction::Return => make(&[], 0, |vm, _| control::handle_return(&mut vm.ctx)),
+
+        // Floating Point
+        Instruction::FAdd { dest, left, right } => make(&[*dest, *left, *right], 0, |vm, op| { float::handle_fadd(&mut vm.ctx, op.r[0], op.r[1], op.r[2]); Ok(()) }),
+        Instruction::FSub { dest, left, right } => make(&[*dest, *left, *right], 0, |vm, op| { float::handle_fsub(&mut vm.ctx, op.r[0], op.r[1], op.r[2]); Ok(()) }),
+        Instruction::FMul { dest, left, right } => make(&[*dest, *left, *right], 0, |vm, op| { float::handle_fmul(&mut vm.ctx, op.r[0], op.r[1], op.r[2]); Ok(()) }),
+        Instruction::FDiv { dest, left, right } => make(&[*dest, *left, *right], 0, |vm, op| { float::handle_fdiv(&mut vm.ctx, op.r[0], op.r[1], op.r[2]); Ok(()) }),
+        Instruction::FMin { dest, left, right } => make(&[*dest, *left, *right], 0, |vm, op| { float::handle_fmin(&mut vm.ctx, op.r[0], op.r[1], op.r[2]); Ok(()) }),
+        Instruction::FMax { dest, left, right } => make(&[*dest, *left, *right], 0, |vm, op| { float::handle_fmax(&mut vm.ctx, op.r[0], op.r[1], op.r[2]); Ok(()) }),
+        Instruction::FClass { dest, src } => make(&[*dest, *src], 0, |vm, op| { float::handle_fclass(&mut vm.ctx, op.r[0], op.r[1]); Ok(()) }),
+        Instruction::FSqrt { dest, src } => make(&[*dest, *src], 0, |vm, op| { float::handle_fsqrt(&mut vm.ctx, op.r[0], op.r[1]); Ok(()) }),
+        Instruction::FAbs { dest, src } => make(&[*dest, *src], 0, |vm, op| { float::handle_fabs(&mut vm.ctx, op.r[0], op.r[1]); Ok(()) }),
+        Instruction::FFloor { dest, src } => make(&[*dest, *src], 0, |vm, op| { float::handle_ffloor(&mut vm.ctx, op.r[0], op.r[1]); Ok(()) }),
+        Instruction::FCeil { dest, src } => make(&[*dest, *src], 0, |vm, op| { float::handle_fceil(&mut vm.ctx, op.r[0], op.r[1]); Ok(()) }),
+        Instruction::FTrunc { dest, src } => make(&[*dest, *src], 0, |vm, op| { float::handle_ftrunc(&mut vm.ctx, op.r[0], op.r[1]); Ok(()) }),
+        Instruction::FRound { dest, src } => make(&[*dest, *src], 0, |vm, op| { float::handle_fround(&mut vm.ctx, op.r[0], op.r[1]); Ok(()) }),
+        Instruction::FNeg { dest, src } => make(&[*dest, *src], 0, |vm, op| { float::handle_fneg(&mut vm.ctx, op.r[0], op.r[1]); Ok(()) }),
+        Instruction::F2I { dest, src } => make(&[*dest, *src], 0, |vm, op| { float::handle_f2i(&mut vm.ctx, op.r[0], op.r[1]); Ok(()) }),
+        Instruction::I2F { dest, src } => make(&[*dest, *src], 0, |vm, op| { float::handle_i2f(&mut vm.ctx, op.r[0], op.r[1]); Ok(()) }),
+        Instruction::FCmp { left, right } => make(&[*left, *right], 0, |vm, op| { float::handle_fcmp(&mut vm.ctx, op.r[0], op.r[1]); Ok(()) }),
+        Instruction::FAdd32 { dest, left, right } => make(&[*dest, *left, *right], 0, |vm, op| { float::handle_fadd32(&mut vm.ctx, op.r[0], op.r[1], op.r[2]); Ok(()) }),
+        Instruction::FSub32 { dest, left, right } => make(&[*dest, *left, *right], 0, |vm, op| { float::handle_fsub32(&mut vm.ctx, op.r[0], op.r[1], op.r[2]); Ok(()) }),
+        Instruction::FMul32 { dest, left, right } => make(&[*dest, *left, *right], 0, |vm, op| { float::handle_fmul32(&mut vm.ctx, op.r[0], op.r[1], op.r[2]); Ok(()) }),
+        Instruction::FDiv32 { dest, left, right } => make(&[*dest, *left, *right], 0, |vm, op| { float::handle_fdiv32(&mut vm.ctx, op.r[0], op.r[1], op.r[2]); Ok(()) }),
+        Instruction::FSqrt32 { dest, src } => make(&[*dest, *src], 0, |vm, op| { float::handle_fsqrt32(&mut vm.ctx, op.r[0], op.r[1]); Ok(()) }),
+        Instruction::FCmp32 { left, right } => make(&[*left, *right], 0, |vm, op| { float::handle_fcmp32(&mut vm.ctx, op.r[0], op.r[1]); Ok(()) }),
+        Instruction::FDemote { dest, src } => make(&[*dest, *src], 0, |vm, op| { float::handle_fdemote(&mut vm.ctx, op.r[0], op.r[1]); Ok(()) }),
+        Instruction::FPromote { dest, src } => make(&[*dest, *src], 0, |vm, op| { float::handle_fpromote(&mut vm.ctx, op.r[0], op.r[1]); Ok(()) }),
+
+        // Bitwise Extension
+        Instruction::PopCnt { dest, src } => make(&[*dest, *src], 0, |vm, op| { bitwise_ext::handle_popcnt(&mut vm.ctx, op.r[0], op.r[1]); Ok(()) }),
+        Instruction::Clz { dest, src } => make(&[*dest, *src], 0, |vm, op| { bitwise_ext::handle_clz(&mut vm.ctx, op.r[0], op.r[1]); Ok(()) }),
+        Instruction::Ctz { dest, src } => make(&[*dest, *src], 0, |vm, op| { bitwise_ext::handle_ctz(&mut vm.ctx, op.r[0], op.r[1]); Ok(()) }),
+        Instruction::BSwap { dest, src } => make(&[*dest, *src], 0, |vm, op| { bitwise_ext::handle_bswap(&mut vm.ctx, op.r[0], op.r[1]); Ok(()) }),
+        Instruction::RotL { dest, left, right } => make(&[*dest, *left, *right], 0, |vm, op| { bitwise_ext::handle_rotl(&mut vm.ctx, op.r[0], op.r[1], op.r[2]); Ok(()) }),
+        Instruction::RotR { dest, left, right } => make(&[*dest, *left, *right], 0, |vm, op| { bitwise_ext::handle_rotr(&mut vm.ctx, op.r[0], op.r[1], op.r[2]); Ok(()) }),
+
+        // System
+        Instruction::Rand { dest } => make(&[*dest], 0, |vm, op| { data_move::handle_rand(&mut vm.ctx, &mut vm.rng, op.r[0]); Ok(()) }),
+        Instruction::FeatQuery { dest, id } => make(&[*dest], *id as u64, |vm, op| { data_move::handle_feat_query(&mut vm.ctx, op.r[0], op.imm as u8); Ok(()) }),
+        Instruction::Int { vector } => make(&[], *vector as u64, |vm, op| trap::handle_int(&mut vm.ctx, &mut vm.stack, &mut vm.memory, op.imm as u8)),
+        Instruction::IRet => make(&[], 0, |vm, _| trap::handle_iret(&mut vm.ctx, &mut vm.stack, &vm.memory)),
+        Instruction::Breakpoint => make(&[], 0, |vm, _| { vm.break_requested = true; Ok(()) }),
+        Instruction::TraceOn => make(&[], 0, |vm, _| { vm.ctx.trace = true; Ok(()) }),
+        Instruction::TraceOff => make(&[], 0, |vm, _| { vm.ctx.trace = false; Ok(()) }),
+        Instruction::Syscall => make(&[], 0, |vm, _| io::handle_syscall(
+            &mut vm.ctx, &vm.heap, &mut vm.memory, &vm.resources, &mut vm.host_fns, &mut vm.output, vm.print_immediately,
+        )),
+    }
+}
@@ -0,0 +1,57 @@
+//! Execution events for front-ends.
+//!
+//! Observers registered with `VM::add_observer` receive a `VmEvent` for each
+//! effect of every instruction, so a visualizer can animate execution without
+//! diffing the whole VM state after each step. With no observers attached
+//! the VM does no extra work.
+
+use crate::core::Register;
+use crate::instruction::Instruction;
+
+/// Something that happened while executing an instruction
+#[derive(Debug, Clone, PartialEq)]
+pub enum VmEvent {
+    /// An instruction finished executing (successfully or not)
+    InstructionExecuted { pc: usize, instruction: Instruction },
+    /// A register holds a new value
+    RegisterChanged { reg: Register, old: u64, new: u64 },
+    /// A contiguous run of bytes was written; `bytes` holds the new contents
+    MemoryWritten { addr: usize, bytes: Vec<u8> },
+    /// The program printed a line
+    Output(String),
+    /// A syscall is about to run with this id (from R0)
+    SyscallEntered { id: u64 },
+    /// The VM executed `halt`
+    Halted,
+}
+
+/// Receives execution events from a VM
+pub trait VmObserver: Send {
+    fn on_event(&mut self, event: &VmEvent);
+}
+
+impl<F: FnMut(&VmEvent) + Send> VmObserver for F {
+    fn on_event(&mut self, event: &VmEvent) {
+        self(event)
+    }
+}
+
+/// Handle returned by `VM::add_observer`, used to remove the observer again
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ObserverId(pub(crate) usize);
+
+/// Merge per-byte journal entries into runs of adjacent addresses
+pub(crate) fn write_ranges(entries: &[(usize, u8)]) -> Vec<(usize, usize)> {
+    let mut addrs: Vec<usize> = entries.iter().map(|&(addr, _)| addr).collect();
+    addrs.sort_unstable();
+    addrs.dedup();
+
+    let mut ranges: Vec<(usize, usize)> = Vec::new();
+    for addr in addrs {
+        match ranges.last_mut() {
+            Some((start, len)) if *start + *len == addr => *len += 1,
+            _ => ranges.push((addr, 1)),
+        }
+    }
+    ranges
+}
@@ -0,0 +1,450 @@
+//! Debugger expression evaluator.
+//!
+//! Expressions combine registers (`@r0`, `sp`), numbers, memory
+//! dereference (`*(@sp + 8)` reads a qword) and C-style operators.
+//! They back `print`, conditional breakpoints and watch conditions.
+//! A `Template` embeds expressions in text for tracepoint messages.
+
+use std::fmt;
+use crate::core::Register;
+use crate::execution::VM;
+use crate::memory::MemoryAccess;
+
+/// Unary operators
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnaryOp {
+    Neg,
+    Not,
+    BitNot,
+    Deref,
+}
+
+/// Binary operators, in no particular order
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOp {
+    Add, Sub, Mul, Div, Rem,
+    And, Or, Xor, Shl, Shr,
+    Eq, Ne, Lt, Le, Gt, Ge,
+    LogicalAnd, LogicalOr,
+}
+
+impl BinaryOp {
+    fn symbol(self) -> &'static str {
+        match self {
+            BinaryOp::Add => "+",
+            BinaryOp::Sub => "-",
+            BinaryOp::Mul => "*",
+            BinaryOp::Div => "/",
+            BinaryOp::Rem => "%",
+            BinaryOp::And => "&",
+            BinaryOp::Or => "|",
+            BinaryOp::Xor => "^",
+            BinaryOp::Shl => "<<",
+            BinaryOp::Shr => ">>",
+            BinaryOp::Eq => "==",
+            BinaryOp::Ne => "!=",
+            BinaryOp::Lt => "<",
+            BinaryOp::Le => "<=",
+            BinaryOp::Gt => ">",
+            BinaryOp::Ge => ">=",
+            BinaryOp::LogicalAnd => "&&",
+            BinaryOp::LogicalOr => "||",
+        }
+    }
+
+    /// Binding strength; higher binds tighter
+    fn precedence(self) -> u8 {
+        match self {
+            BinaryOp::LogicalOr => 1,
+            BinaryOp::LogicalAnd => 2,
+            BinaryOp::Or => 3,
+            BinaryOp::Xor => 4,
+            BinaryOp::And => 5,
+            BinaryOp::Eq | BinaryOp::Ne => 6,
+            BinaryOp::Lt | BinaryOp::Le | BinaryOp::Gt | BinaryOp::Ge => 7,
+            BinaryOp::Shl | BinaryOp::Shr => 8,
+            BinaryOp::Add | BinaryOp::Sub => 9,
+            BinaryOp::Mul | BinaryOp::Div | BinaryOp::Rem => 10,
+        }
+    }
+}
+
+/// A parsed debugger expression
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Number(u64),
+    Register(Register),
+    Unary(UnaryOp, Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Tok {
+    Number(u64),
+    Ident(String),
+    Op(&'static str),
+    LParen,
+    RParen,
+}
+
+/// Operators, longest first so `<<` wins over `<`
+const OPERATORS: [&str; 21] = [
+    "<<", ">>", "<=", ">=", "==", "!=", "&&", "||",
+    "+", "-", "*", "/", "%", "&", "|", "^", "<", ">", "!", "~", "=",
+];
+
+fn tokenize(text: &str) -> Result<Vec<Tok>, String> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+        } else if c == '(' {
+            tokens.push(Tok::LParen);
+            i += 1;
+        } else if c == ')' {
+            tokens.push(Tok::RParen);
+            i += 1;
+        } else if c.is_ascii_digit() {
+            let start = i;
+            while i < chars.len() && chars[i].is_ascii_alphanumeric() {
+                i += 1;
+            }
+            let word: String = chars[start..i].iter().collect();
+            let value = match word.strip_prefix("0x") {
+                Some(hex) => u64::from_str_radix(hex, 16),
+                None => word.parse::<u64>(),
+            };
+            tokens.push(Tok::Number(value.map_err(|_| format!("Invalid number '{}'", word))?));
+        } else if c == '@' || c.is_ascii_alphabetic() || c == '_' {
+            let start = i;
+            i += 1;
+            while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            tokens.push(Tok::Ident(chars[start..i].iter().collect()));
+        } else {
+            let rest: String = chars[i..].iter().take(2).collect();
+            let op = OPERATORS.iter()
+                .find(|op| rest.starts_with(**op))
+                .ok_or_else(|| format!("Unexpected character '{}'", c))?;
+            // A lone '=' is only a typo for '=='
+            if *op == "=" {
+                return Err("Unexpected '=' (did you mean '=='?)".to_string());
+            }
+            tokens.push(Tok::Op(op));
+            i += op.len();
+        }
+    }
+
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Tok>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Tok> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Tok> {
+        let tok = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        tok
+    }
+
+    fn peek_binary(&self) -> Option<BinaryOp> {
+        let op = match self.peek()? {
+            Tok::Op(op) => *op,
+            _ => return None,
+        };
+        Some(match op {
+            "+" => BinaryOp::Add,
+            "-" => BinaryOp::Sub,
+            "*" => BinaryOp::Mul,
+            "/" => BinaryOp::Div,
+            "%" => BinaryOp::Rem,
+            "&" => BinaryOp::And,
+            "|" => BinaryOp::Or,
+            "^" => BinaryOp::Xor,
+            "<<" => BinaryOp::Shl,
+            ">>" => BinaryOp::Shr,
+            "==" => BinaryOp::Eq,
+            "!=" => BinaryOp::Ne,
+            "<" => BinaryOp::Lt,
+            "<=" => BinaryOp::Le,
+            ">" => BinaryOp::Gt,
+            ">=" => BinaryOp::Ge,
+            "&&" => BinaryOp::LogicalAnd,
+            "||" => BinaryOp::LogicalOr,
+            _ => return None,
+        })
+    }
+
+    /// Precedence climbing over left-associative binary operators
+    fn binary(&mut self, min_prec: u8) -> Result<Expr, String> {
+        let mut lhs = self.unary()?;
+        while let Some(op) = self.peek_binary() {
+            if op.precedence() < min_prec {
+                break;
+            }
+            self.pos += 1;
+            let rhs = self.binary(op.precedence() + 1)?;
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        let op = match self.peek() {
+            Some(Tok::Op("-")) => Some(UnaryOp::Neg),
+            Some(Tok::Op("!")) => Some(UnaryOp::Not),
+            Some(Tok::Op("~")) => Some(UnaryOp::BitNot),
+            Some(Tok::Op("*")) => Some(UnaryOp::Deref),
+            _ => None,
+        };
+        match op {
+            Some(op) => {
+                self.pos += 1;
+                Ok(Expr::Unary(op, Box::new(self.unary()?)))
+            }
+            None => self.primary(),
+        }
+    }
+
+    fn primary(&mut self) -> Result<Expr, String> {
+        match self.next() {
+            Some(Tok::Number(n)) => Ok(Expr::Number(n)),
+            Some(Tok::Ident(name)) => Register::from_name(&name)
+                .map(Expr::Register)
+                .map_err(|_| format!("Unknown register '{}'", name)),
+            Some(Tok::LParen) => {
+                let inner = self.binary(0)?;
+                match self.next() {
+                    Some(Tok::RParen) => Ok(inner),
+                    _ => Err("Expected ')'".to_string()),
+                }
+            }
+            Some(tok) => Err(format!("Unexpected {:?}", tok)),
+            None => Err("Unexpected end of expression".to_string()),
+        }
+    }
+}
+
+impl Expr {
+    /// Parse an expression such as `@r0 + @r1*8` or `*(@sp) == 3`
+    pub fn parse(text: &str) -> Result<Expr, String> {
+        let mut parser = Parser { tokens: tokenize(text)?, pos: 0 };
+        let expr = parser.binary(0)?;
+        if let Some(tok) = parser.peek() {
+            return Err(format!("Unexpected {:?} after expression", tok));
+        }
+        Ok(expr)
+    }
+
+    /// Evaluate against the current VM state using wrapping 64-bit arithmetic
+    pub fn eval(&self, vm: &VM) -> Result<u64, String> {
+        match self {
+            Expr::Number(n) => Ok(*n),
+            Expr::Register(Register::IP) => Ok(vm.ctx.pc as u64),
+            // The stack pointer lives in the stack manager, not the register file
+            Expr::Register(Register::SP) => Ok(vm.stack.pointer() as u64),
+            Expr::Register(reg) => Ok(vm.ctx.get_reg(*reg)),
+            Expr::Unary(op, inner) => {
+                let v = inner.eval(vm)?;
+                match op {
+                    UnaryOp::Neg => Ok(v.wrapping_neg()),
+                    UnaryOp::Not => Ok((v == 0) as u64),
+                    UnaryOp::BitNot => Ok(!v),
+                    UnaryOp::Deref => vm.memory.read_qword(v as usize).map_err(|e| e.to_string()),
+                }
+            }
+            Expr::Binary(BinaryOp::LogicalAnd, lhs, rhs) => {
+                Ok((lhs.eval(vm)? != 0 && rhs.eval(vm)? != 0) as u64)
+            }
+            Expr::Binary(BinaryOp::LogicalOr, lhs, rhs) => {
+                Ok((lhs.eval(vm)? != 0 || rhs.eval(vm)? != 0) as u64)
+            }
+            Expr::Binary(op, lhs, rhs) => {
+                let a = lhs.eval(vm)?;
+                let b = rhs.eval(vm)?;
+                Ok(match op {
+                    BinaryOp::Add => a.wrapping_add(b),
+                    BinaryOp::Sub => a.wrapping_sub(b),
+                    BinaryOp::Mul => a.wrapping_mul(b),
+                    BinaryOp::Div => a.checked_div(b).ok_or("Division by zero")?,
+                    BinaryOp::Rem => a.checked_rem(b).ok_or("Division by zero")?,
+                    BinaryOp::And => a & b,
+                    BinaryOp::Or => a | b,
+                    BinaryOp::Xor => a ^ b,
+                    BinaryOp::Shl => a.wrapping_shl(b as u32),
+                    BinaryOp::Shr => a.wrapping_shr(b as u32),
+                    BinaryOp::Eq => (a == b) as u64,
+                    BinaryOp::Ne => (a != b) as u64,
+                    BinaryOp::Lt => (a < b) as u64,
+                    BinaryOp::Le => (a <= b) as u64,
+                    BinaryOp::Gt => (a > b) as u64,
+                    BinaryOp::Ge => (a >= b) as u64,
+                    BinaryOp::LogicalAnd | BinaryOp::LogicalOr => unreachable!(),
+                })
+            }
+        }
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Expr::Number(n) => write!(f, "{}", n),
+            Expr::Register(reg) => write!(f, "{}", reg),
+            Expr::Unary(op, inner) => {
+                let sym = match op {
+                    UnaryOp::Neg => "-",
+                    UnaryOp::Not => "!",
+                    UnaryOp::BitNot => "~",
+                    UnaryOp::Deref => "*",
+                };
+                write!(f, "{}({})", sym, inner)
+            }
+            Expr::Binary(op, lhs, rhs) => write!(f, "({} {} {})", lhs, op.symbol(), rhs),
+        }
+    }
+}
+
+/// Message text with `{expr}` (decimal) or `{expr:x}` (hex) placeholders
+#[derive(Debug, Clone, PartialEq)]
+pub struct Template {
+    source: String,
+    parts: Vec<TemplatePart>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum TemplatePart {
+    Text(String),
+    Value { expr: Expr, hex: bool },
+}
+
+impl Template {
+    /// Parse a message such as `i={@r0} top={*(@sp):x}`; `{{` and `}}` are literal braces
+    pub fn parse(text: &str) -> Result<Template, String> {
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut chars = text.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let mut inner = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => inner.push(c),
+                            None => return Err("Unclosed '{' in message".to_string()),
+                        }
+                    }
+                    let (inner, hex) = match inner.strip_suffix(":x") {
+                        Some(inner) => (inner.to_string(), true),
+                        None => (inner, false),
+                    };
+                    if !literal.is_empty() {
+                        parts.push(TemplatePart::Text(std::mem::take(&mut literal)));
+                    }
+                    parts.push(TemplatePart::Value { expr: Expr::parse(&inner)?, hex });
+                }
+                '}' => return Err("Unmatched '}' in message".to_string()),
+                c => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            parts.push(TemplatePart::Text(literal));
+        }
+
+        Ok(Template { source: text.to_string(), parts })
+    }
+
+    /// Substitute current values; failed evaluations render as `<error>`
+    pub fn render(&self, vm: &VM) -> String {
+        let mut out = String::new();
+        for part in &self.parts {
+            match part {
+                TemplatePart::Text(text) => out.push_str(text),
+                TemplatePart::Value { expr, hex } => match expr.eval(vm) {
+                    Ok(v) if *hex => out.push_str(&format!("{:#x}", v)),
+                    Ok(v) => out.push_str(&v.to_string()),
+                    Err(e) => out.push_str(&format!("<{}>", e)),
+                },
+            }
+        }
+        out
+    }
+}
+
+impl fmt::Display for Template {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(text: &str, vm: &VM) -> Result<u64, String> {
+        Expr::parse(text)?.eval(vm)
+    }
+
+    #[test]
+    fn test_arithmetic_precedence() {
+        let mut vm = VM::new();
+        vm.ctx.set_reg(Register::R0, 2);
+        vm.ctx.set_reg(Register::R1, 3);
+        assert_eq!(eval("@r0 + @r1*8", &vm), Ok(26));
+        assert_eq!(eval("(@r0 + @r1) * 8", &vm), Ok(40));
+        assert_eq!(eval("r1 - 1 == r0 && !0", &vm), Ok(1));
+        assert_eq!(eval("1 << 4 | 0x1", &vm), Ok(17));
+        assert_eq!(eval("-1", &vm), Ok(u64::MAX));
+        assert!(eval("@r0 / 0", &vm).is_err());
+    }
+
+    #[test]
+    fn test_deref() {
+        let mut vm = VM::new();
+        vm.memory.write_qword(0x8010, 99).unwrap();
+        vm.stack.set_pointer(0x8010);
+        assert_eq!(eval("*(@sp)", &vm), Ok(99));
+        assert_eq!(eval("*(@sp - 0x10 + 16) + 1", &vm), Ok(100));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(Expr::parse("@r0 +").is_err());
+        assert!(Expr::parse("@bogus").is_err());
+        assert!(Expr::parse("@r0 = 1").is_err());
+        assert!(Expr::parse("(1 + 2").is_err());
+        assert!(Expr::parse("1 2").is_err());
+    }
+
+    #[test]
+    fn test_template_render() {
+        let mut vm = VM::new();
+        vm.ctx.set_reg(Register::R0, 255);
+        let t = Template::parse("r0={@r0} hex={@r0:x} {{lit}} sum={@r0 + 1}").unwrap();
+        assert_eq!(t.render(&vm), "r0=255 hex=0xff {lit} sum=256");
+        assert!(Template::parse("bad {@r0").is_err());
+        assert!(Template::parse("bad {@nope}").is_err());
+    }
+}
@@ -0,0 +1,171 @@
+//! Grading runs against expected output and exit status.
+//!
+//! `grade_run` compares what a finished run printed and how it ended with
+//! an instructor's expectations, and produces a report that serializes to
+//! a single JSON object. Exit statuses follow `execution_digest`: the
+//! status `halt @x` stops with (0 for a plain `halt` or running off the
+//! end), or 1 when the program fails.
+
+use std::fmt::Write;
+use crate::error::{ErrorCode, VmError, VmResult};
+use crate::instruction::disassembler::json_string;
+use super::VM;
+
+/// What a correct submission does; unset fields are not checked
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Expectations {
+    /// Expected output text. Line endings and a final newline are ignored.
+    pub output: Option<String>,
+    pub exit_code: Option<i32>,
+}
+
+/// Outcome of grading one run
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    Pass,
+    /// Output or exit status differ from the expectations
+    Fail,
+    /// The run hit its instruction limit
+    LimitExceeded,
+}
+
+impl Verdict {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Verdict::Pass => "pass",
+            Verdict::Fail => "fail",
+            Verdict::LimitExceeded => "limit_exceeded",
+        }
+    }
+
+    /// Process exit status for the CLI, distinct from usage (2) and tool (1) errors
+    pub fn exit_code(self) -> i32 {
+        match self {
+            Verdict::Pass => 0,
+            Verdict::Fail => 3,
+            Verdict::LimitExceeded => 4,
+        }
+    }
+}
+
+/// Everything a grader needs to know about one run
+#[derive(Debug, Clone, PartialEq)]
+pub struct GradeReport {
+    pub verdict: Verdict,
+    pub exit_code: i32,
+    pub expected_exit_code: Option<i32>,
+    /// Error that ended the run, if any
+    pub error: Option<VmError>,
+    pub instruction_count: u64,
+    /// Whether the output matched, when an output was expected
+    pub output_matches: Option<bool>,
+    /// First differing output line (1-based) with the expected and actual
+    /// text; `None` past the end of either side
+    pub first_mismatch: Option<(usize, Option<String>, Option<String>)>,
+}
+
+/// Output text split into comparable lines
+fn output_lines(text: &str) -> Vec<&str> {
+    text.lines().map(|line| line.trim_end_matches('\r')).collect()
+}
+
+/// Grade a run of `vm` that ended with `result`
+pub fn grade_run(vm: &VM, result: VmResult<i32>, expectations: &Expectations) -> GradeReport {
+    let (exit_code, error) = match result {
+        Ok(code) => (code, None),
+        Err(e) => (1, Some(e)),
+    };
+
+    let actual = vm.output.join("\n");
+    let actual = output_lines(&actual);
+    let first_mismatch = expectations.output.as_deref().and_then(|expected| {
+        let expected = output_lines(expected);
+        (0..expected.len().max(actual.len()))
+            .find(|&i| expected.get(i) != actual.get(i))
+            .map(|i| (i + 1, expected.get(i).map(|s| s.to_string()), actual.get(i).map(|s| s.to_string())))
+    });
+    let output_matches = expectations.output.as_ref().map(|_| first_mismatch.is_none());
+    let exit_matches = expectations.exit_code.is_none_or(|code| code == exit_code);
+
+    let verdict = if error.as_ref().is_some_and(|e| e.code() == ErrorCode::InstructionLimit) {
+        Verdict::LimitExceeded
+    } else if output_matches == Some(false) || !exit_matches {
+        Verdict::Fail
+    } else {
+        Verdict::Pass
+    };
+
+    GradeReport {
+        verdict,
+        exit_code,
+        expected_exit_code: expectations.exit_code,
+        error,
+        instruction_count: vm.instruction_count,
+        output_matches,
+        first_mismatch,
+    }
+}
+
+impl GradeReport {
+    /// Single-line JSON object
+    pub fn to_json(&self) -> String {
+        let optional = |value: Option<String>| value.unwrap_or_else(|| "null".to_string());
+        let mismatch = self.first_mismatch.as_ref().map(|(line, expected, actual)| format!(
+            "{{\"line\":{},\"expected\":{},\"actual\":{}}}",
+            line,
+            optional(expected.as_deref().map(json_string)),
+            optional(actual.as_deref().map(json_string)),
+        ));
+        let error = self.error.as_ref().map(|e| format!(
+            "{{\"code\":\"{}\",\"message\":{},\"pc\":{}}}",
+            e.code(), json_string(&e.message()), optional(e.pc().map(|pc| pc.to_string())),
+        ));
+
+        let mut out = String::new();
+        let _ = write!(
+            out,
+            "{{\"verdict\":\"{}\",\"exit_code\":{},\"expected_exit_code\":{},\"output_matches\":{},\"first_mismatch\":{},\"instructions\":{},\"error\":{}}}",
+            self.verdict.as_str(),
+            self.exit_code,
+            optional(self.expected_exit_code.map(|code| code.to_string())),
+            optional(self.output_matches.map(|matches| matches.to_string())),
+            optional(mismatch),
+            self.instruction_count,
+            optional(error),
+        );
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler;
+
+    #[test]
+    fn test_grade_run_verdicts() {
+        let program = assembler::assemble("@r0 := 1\nprint @r0\n@r0 := 2\nprint @r0\nhalt\n", "g").unwrap();
+        let mut vm = VM::new();
+        vm.print_immediately = false;
+        let grade = |vm: &mut VM, expectations: Expectations| {
+            let result = vm.run(&program);
+            grade_run(vm, result, &expectations)
+        };
+
+        let pass = grade(&mut vm, Expectations { output: Some("1\r\n2\n".into()), exit_code: Some(0) });
+        assert_eq!(pass.verdict, Verdict::Pass);
+
+        let wrong = grade(&mut vm, Expectations { output: Some("1\n3\n".into()), exit_code: None });
+        assert_eq!(wrong.verdict, Verdict::Fail);
+        assert_eq!(wrong.first_mismatch, Some((2, Some("3".into()), Some("2".into()))));
+        assert!(wrong.to_json().starts_with(
+            "{\"verdict\":\"fail\",\"exit_code\":0,\"expected_exit_code\":null,\"output_matches\":false,\
+             \"first_mismatch\":{\"line\":2,\"expected\":\"3\",\"actual\":\"2\"},"
+        ));
+
+        vm.max_instructions = 2;
+        let limited = grade(&mut vm, Expectations { output: None, exit_code: Some(0) });
+        assert_eq!((limited.verdict, limited.exit_code), (Verdict::LimitExceeded, 1));
+        assert_eq!(limited.verdict.exit_code(), 4);
+    }
+}
@@ -57,6 +57,42 @@ pub fn handle_mod(ctx: &mut ExecutionContext, dest: Register, left: Register, ri
     Ok(())
 }
 
+/// Execute MulHi: dest = the upper 64 bits of left * right
+pub fn handle_mulhi(ctx: &mut ExecutionContext, dest: Register, left: Register, right: Register) {
+    let product = ctx.get_reg(left) as u128 * ctx.get_reg(right) as u128;
+    let result = (product >> 64) as u64;
+    ctx.set_reg(dest, result);
+    ctx.flags.update_from_result(result, false);
+}
+
+/// Execute IDiv: dest = left / right, as signed values
+pub fn handle_idiv(ctx: &mut ExecutionContext, dest: Register, left: Register, right: Register) -> Result<(), VmError> {
+    let a = ctx.get_reg(left) as i64;
+    let b = ctx.get_reg(right) as i64;
+    if b == 0 {
+        return Err(VmError::DivisionByZero);
+    }
+    // i64::MIN / -1 overflows
+    let (result, overflow) = a.overflowing_div(b);
+    ctx.set_reg(dest, result as u64);
+    ctx.flags.update_from_result(result as u64, overflow);
+    Ok(())
+}
+
+/// Execute IMod: dest = left % right, as signed values; the result takes
+/// the sign of `left`
+pub fn handle_imod(ctx: &mut ExecutionContext, dest: Register, left: Register, right: Register) -> Result<(), VmError> {
+    let a = ctx.get_reg(left) as i64;
+    let b = ctx.get_reg(right) as i64;
+    if b == 0 {
+        return Err(VmError::DivisionByZero);
+    }
+    let result = a.wrapping_rem(b) as u64;
+    ctx.set_reg(dest, result);
+    ctx.flags.update_from_result(result, false);
+    Ok(())
+}
+
 /// Execute AddAssign: dest += src
 pub fn handle_add_assign(ctx: &mut ExecutionContext, dest: Register, src: Register) {
     let a = ctx.get_reg(dest);
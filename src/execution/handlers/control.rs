@@ -1,8 +1,8 @@
 //! Control flow instruction handlers.
 
 use crate::core::Register;
-use crate::execution::context::ExecutionContext;
-use crate::error::VmError;
+use crate::execution::context::{ExecutionContext, Interrupt};
+use crate::error::{ErrorCode, VmError};
 
 /// Execute Compare: set flags based on left - right (SUB behavior)
 pub fn handle_compare(ctx: &mut ExecutionContext, left: Register, right: Register) {
@@ -23,11 +23,34 @@ pub fn handle_compare(ctx: &mut ExecutionContext, left: Register, right: Registe
     ctx.flags.set_overflow(overflow);
 }
 
+/// Execute Test: set flags from left & right (AND behavior), leaving
+/// carry and overflow clear
+pub fn handle_test(ctx: &mut ExecutionContext, left: Register, right: Register) {
+    let result = ctx.get_reg(left) & ctx.get_reg(right);
+    ctx.flags.set_zero(result == 0);
+    ctx.flags.set_negative((result as i64) < 0);
+    ctx.flags.set_carry(false);
+    ctx.flags.set_overflow(false);
+}
+
+/// Execute Exit: halt, keeping the low 32 bits of `src` as the exit status
+pub fn handle_exit(ctx: &mut ExecutionContext, src: Register) {
+    ctx.exit_code = ctx.get_reg(src) as i32;
+    ctx.halted = true;
+}
+
 /// Execute Jump: unconditional jump
 pub fn handle_jump(ctx: &mut ExecutionContext, target: usize) {
     ctx.pc = target;
 }
 
+/// Execute Switch: step into the jump table after the instruction, or past
+/// it when the index is out of range
+pub fn handle_switch(ctx: &mut ExecutionContext, index: Register, count: usize) {
+    let index = ctx.get_reg(index);
+    ctx.pc += if index < count as u64 { index as usize } else { count };
+}
+
 /// Execute JumpIfZero
 pub fn handle_jump_if_zero(ctx: &mut ExecutionContext, target: usize) {
     if ctx.flags.zero() {
@@ -137,17 +160,51 @@ const MAX_STACK_DEPTH: usize = 1024;
 /// Execute Call: push return address, jump to target
 pub fn handle_call(ctx: &mut ExecutionContext, target: usize) -> Result<(), VmError> {
     if ctx.call_stack.len() >= MAX_STACK_DEPTH {
-        return Err(VmError::Execution("Stack overflow: maximum recursion depth exceeded".to_string()));
+        return Err(VmError::execution(ErrorCode::CallDepth, "Stack overflow: maximum recursion depth exceeded"));
     }
     ctx.call_stack.push(ctx.pc);
     ctx.pc = target;
     Ok(())
 }
 
+/// Execute CallReg: call the instruction index held in `target_reg`, which
+/// must be inside the program's `len` instructions
+pub fn handle_call_reg(ctx: &mut ExecutionContext, target_reg: Register, len: usize) -> Result<(), VmError> {
+    let target = ctx.get_reg(target_reg);
+    if target >= len as u64 {
+        return Err(VmError::execution(ErrorCode::InvalidPc, format!("Call target {} is outside the program", target)));
+    }
+    handle_call(ctx, target as usize)
+}
+
+/// Count down the timer after an instruction, calling its handler when it
+/// expires. Once the handler returns, restores the flags it interrupted.
+/// Instructions in the handler do not count.
+pub fn handle_timer(ctx: &mut ExecutionContext) -> Result<(), VmError> {
+    if let Some(interrupt) = ctx.interrupt {
+        if ctx.call_stack.len() >= interrupt.depth {
+            return Ok(());
+        }
+        ctx.flags = interrupt.flags;
+        ctx.interrupt = None;
+        return Ok(());
+    }
+    let Some(timer) = ctx.timer.as_mut() else { return Ok(()) };
+    timer.remaining -= 1;
+    if timer.remaining > 0 {
+        return Ok(());
+    }
+    timer.remaining = timer.interval;
+    let (handler, flags) = (timer.handler, ctx.flags);
+    handle_call(ctx, handler)?;
+    ctx.interrupt = Some(Interrupt { depth: ctx.call_stack.len(), flags });
+    Ok(())
+}
+
 /// Execute Return: pop return address, jump back
 pub fn handle_return(ctx: &mut ExecutionContext) -> Result<(), VmError> {
     let return_addr = ctx.call_stack.pop()
-        .ok_or_else(|| VmError::Execution("Return without matching call".to_string()))?;
+        .ok_or_else(|| VmError::execution(ErrorCode::ReturnWithoutCall, "Return without matching call"))?;
     ctx.pc = return_addr;
     Ok(())
 }
@@ -1,6 +1,6 @@
 //! Data movement instruction handlers.
 
-use crate::core::Register;
+use crate::core::{features, Register, Rng};
 use crate::execution::context::ExecutionContext;
 
 /// Execute LoadImm: dest = immediate value
@@ -14,6 +14,16 @@ pub fn handle_move(ctx: &mut ExecutionContext, dest: Register, src: Register) {
     ctx.set_reg(dest, value);
 }
 
+/// Execute Rand: dest = next value of the VM's generator
+pub fn handle_rand(ctx: &mut ExecutionContext, rng: &mut Rng, dest: Register) {
+    ctx.set_reg(dest, rng.next_u64());
+}
+
+/// Execute FeatQuery: dest = feature word `id`
+pub fn handle_feat_query(ctx: &mut ExecutionContext, dest: Register, id: u8) {
+    ctx.set_reg(dest, features::query(id));
+}
+
 /// Execute Swap: swap(r1, r2)
 pub fn handle_swap(ctx: &mut ExecutionContext, r1: Register, r2: Register) {
     let v1 = ctx.get_reg(r1);
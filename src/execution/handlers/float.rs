@@ -1,8 +1,22 @@
 //! Floating-point instruction handlers.
 
+use std::num::FpCategory;
 use crate::core::Register;
 use crate::execution::context::ExecutionContext;
 
+/// `fclass` bit for NaN
+pub const FCLASS_NAN: u64 = 1;
+/// `fclass` bit for positive or negative infinity
+pub const FCLASS_INFINITE: u64 = 1 << 1;
+/// `fclass` bit for positive or negative zero
+pub const FCLASS_ZERO: u64 = 1 << 2;
+/// `fclass` bit for subnormal values
+pub const FCLASS_SUBNORMAL: u64 = 1 << 3;
+/// `fclass` bit for normal values
+pub const FCLASS_NORMAL: u64 = 1 << 4;
+/// `fclass` bit set alongside the others when the sign bit is set
+pub const FCLASS_NEGATIVE: u64 = 1 << 5;
+
 /// Execute FAdd: dest = left + right
 pub fn handle_fadd(ctx: &mut ExecutionContext, dest: Register, left: Register, right: Register) {
     let a = f64::from_bits(ctx.get_reg(left));
@@ -31,6 +45,38 @@ pub fn handle_fdiv(ctx: &mut ExecutionContext, dest: Register, left: Register, r
     ctx.set_reg(dest, (a / b).to_bits());
 }
 
+/// Execute FMin: dest = the smaller of left and right, or the other
+/// operand when one is NaN
+pub fn handle_fmin(ctx: &mut ExecutionContext, dest: Register, left: Register, right: Register) {
+    let a = f64::from_bits(ctx.get_reg(left));
+    let b = f64::from_bits(ctx.get_reg(right));
+    ctx.set_reg(dest, a.min(b).to_bits());
+}
+
+/// Execute FMax: dest = the larger of left and right, or the other
+/// operand when one is NaN
+pub fn handle_fmax(ctx: &mut ExecutionContext, dest: Register, left: Register, right: Register) {
+    let a = f64::from_bits(ctx.get_reg(left));
+    let b = f64::from_bits(ctx.get_reg(right));
+    ctx.set_reg(dest, a.max(b).to_bits());
+}
+
+/// Execute FClass: dest = the `FCLASS_*` bits describing src
+pub fn handle_fclass(ctx: &mut ExecutionContext, dest: Register, src: Register) {
+    let a = f64::from_bits(ctx.get_reg(src));
+    let mut class = match a.classify() {
+        FpCategory::Nan => FCLASS_NAN,
+        FpCategory::Infinite => FCLASS_INFINITE,
+        FpCategory::Zero => FCLASS_ZERO,
+        FpCategory::Subnormal => FCLASS_SUBNORMAL,
+        FpCategory::Normal => FCLASS_NORMAL,
+    };
+    if a.is_sign_negative() {
+        class |= FCLASS_NEGATIVE;
+    }
+    ctx.set_reg(dest, class);
+}
+
 /// Execute FSqrt: dest = sqrt(src)
 pub fn handle_fsqrt(ctx: &mut ExecutionContext, dest: Register, src: Register) {
     let a = f64::from_bits(ctx.get_reg(src));
@@ -43,6 +89,30 @@ pub fn handle_fabs(ctx: &mut ExecutionContext, dest: Register, src: Register) {
     ctx.set_reg(dest, a.abs().to_bits());
 }
 
+/// Execute FFloor: dest = src rounded toward negative infinity
+pub fn handle_ffloor(ctx: &mut ExecutionContext, dest: Register, src: Register) {
+    let a = f64::from_bits(ctx.get_reg(src));
+    ctx.set_reg(dest, a.floor().to_bits());
+}
+
+/// Execute FCeil: dest = src rounded toward positive infinity
+pub fn handle_fceil(ctx: &mut ExecutionContext, dest: Register, src: Register) {
+    let a = f64::from_bits(ctx.get_reg(src));
+    ctx.set_reg(dest, a.ceil().to_bits());
+}
+
+/// Execute FTrunc: dest = src rounded toward zero
+pub fn handle_ftrunc(ctx: &mut ExecutionContext, dest: Register, src: Register) {
+    let a = f64::from_bits(ctx.get_reg(src));
+    ctx.set_reg(dest, a.trunc().to_bits());
+}
+
+/// Execute FRound: dest = src rounded to the nearest integer, ties to even
+pub fn handle_fround(ctx: &mut ExecutionContext, dest: Register, src: Register) {
+    let a = f64::from_bits(ctx.get_reg(src));
+    ctx.set_reg(dest, a.round_ties_even().to_bits());
+}
+
 /// Execute FNeg: dest = -src
 pub fn handle_fneg(ctx: &mut ExecutionContext, dest: Register, src: Register) {
     let a = f64::from_bits(ctx.get_reg(src));
@@ -65,7 +135,71 @@ pub fn handle_i2f(ctx: &mut ExecutionContext, dest: Register, src: Register) {
 pub fn handle_fcmp(ctx: &mut ExecutionContext, left: Register, right: Register) {
     let a = f64::from_bits(ctx.get_reg(left));
     let b = f64::from_bits(ctx.get_reg(right));
+    set_compare_flags(ctx, a, b);
+}
+
+/// The f32 in the low 32 bits of a register
+fn get_f32(ctx: &ExecutionContext, reg: Register) -> f32 {
+    f32::from_bits(ctx.get_reg(reg) as u32)
+}
+
+/// Store an f32 in the low 32 bits of a register, clearing the rest
+fn set_f32(ctx: &mut ExecutionContext, reg: Register, value: f32) {
+    ctx.set_reg(reg, value.to_bits() as u64);
+}
+
+/// Execute FAdd32: dest = left + right in f32
+pub fn handle_fadd32(ctx: &mut ExecutionContext, dest: Register, left: Register, right: Register) {
+    let value = get_f32(ctx, left) + get_f32(ctx, right);
+    set_f32(ctx, dest, value);
+}
+
+/// Execute FSub32: dest = left - right in f32
+pub fn handle_fsub32(ctx: &mut ExecutionContext, dest: Register, left: Register, right: Register) {
+    let value = get_f32(ctx, left) - get_f32(ctx, right);
+    set_f32(ctx, dest, value);
+}
+
+/// Execute FMul32: dest = left * right in f32
+pub fn handle_fmul32(ctx: &mut ExecutionContext, dest: Register, left: Register, right: Register) {
+    let value = get_f32(ctx, left) * get_f32(ctx, right);
+    set_f32(ctx, dest, value);
+}
+
+/// Execute FDiv32: dest = left / right in f32
+pub fn handle_fdiv32(ctx: &mut ExecutionContext, dest: Register, left: Register, right: Register) {
+    let value = get_f32(ctx, left) / get_f32(ctx, right);
+    set_f32(ctx, dest, value);
+}
+
+/// Execute FSqrt32: dest = sqrt(src) in f32
+pub fn handle_fsqrt32(ctx: &mut ExecutionContext, dest: Register, src: Register) {
+    let value = get_f32(ctx, src).sqrt();
+    set_f32(ctx, dest, value);
+}
+
+/// Execute FCmp32: set flags based on left vs right as f32
+pub fn handle_fcmp32(ctx: &mut ExecutionContext, left: Register, right: Register) {
+    // Widening is exact, so the f64 comparison gives the same answer
+    let a = get_f32(ctx, left) as f64;
+    let b = get_f32(ctx, right) as f64;
+    set_compare_flags(ctx, a, b);
+}
+
+/// Execute FDemote: dest = (f32)src, rounded to nearest
+pub fn handle_fdemote(ctx: &mut ExecutionContext, dest: Register, src: Register) {
+    let a = f64::from_bits(ctx.get_reg(src));
+    set_f32(ctx, dest, a as f32);
+}
+
+/// Execute FPromote: dest = (f64)src
+pub fn handle_fpromote(ctx: &mut ExecutionContext, dest: Register, src: Register) {
+    let a = get_f32(ctx, src) as f64;
+    ctx.set_reg(dest, a.to_bits());
+}
 
+/// Flags of an FCmp: zero if equal, negative if less, carry if unordered
+fn set_compare_flags(ctx: &mut ExecutionContext, a: f64, b: f64) {
     // Reset flags
     ctx.flags.set_zero(false);
     ctx.flags.set_negative(false);
@@ -1,14 +1,30 @@
+use std::collections::BTreeMap;
 use crate::memory::{MemoryAccess};
 use crate::memory::heap::Heap;
 use crate::core::Register;
-use crate::execution::context::ExecutionContext;
+use crate::error::VmResult;
+use crate::execution::context::{ExecutionContext, Timer};
+use crate::execution::vm::HostFn;
 
 /// Execute Syscall
 /// R0 = Syscall ID
 /// R1... = Arguments
-pub fn handle_syscall(ctx: &mut ExecutionContext, heap: &Heap, memory: &mut dyn MemoryAccess, output: &mut Vec<String>, print_immediately: bool) {
+/// `resources` maps each embedded resource to its address and length.
+/// A host function registered for the ID runs instead of the built-in.
+pub fn handle_syscall(
+    ctx: &mut ExecutionContext,
+    heap: &Heap,
+    memory: &mut dyn MemoryAccess,
+    resources: &BTreeMap<String, (usize, usize)>,
+    host_fns: &mut BTreeMap<u64, HostFn>,
+    output: &mut Vec<String>,
+    print_immediately: bool,
+) -> VmResult<()> {
     let id = ctx.get_reg(Register::R0);
-    
+    if let Some(host_fn) = host_fns.get_mut(&id) {
+        return host_fn(ctx, memory);
+    }
+
     match id {
         1 => {
             // Print Integer (Arg: R1)
@@ -20,24 +36,7 @@ pub fn handle_syscall(ctx: &mut ExecutionContext, heap: &Heap, memory: &mut dyn
         }
         2 => {
             // Print String (Arg: R1 = Address)
-            let addr = ctx.get_reg(Register::R1) as usize;
-            let mut bytes = Vec::new();
-            let mut curr = addr;
-            
-            // Read null-terminated string
-            loop {
-                match memory.read_byte(curr) {
-                    Ok(0) => break,
-                    Ok(b) => {
-                        bytes.push(b);
-                        curr += 1;
-                    }
-                    Err(_) => break, // Stop on error
-                }
-                // Safety limit
-                if bytes.len() > 1024 { break; }
-            }
-            
+            let bytes = read_string(memory, ctx.get_reg(Register::R1) as usize);
             let s = String::from_utf8_lossy(&bytes);
             if print_immediately {
                 println!("{}", s);
@@ -84,6 +83,32 @@ pub fn handle_syscall(ctx: &mut ExecutionContext, heap: &Heap, memory: &mut dyn
             }
             output.push(format!("{}", value));
         }
+        7 => {
+            // Find Resource (Arg: R1 = Name address, Ret: R0 = Address, R1 = Length)
+            // R0 is u64::MAX when there is no such resource
+            let name = read_string(memory, ctx.get_reg(Register::R1) as usize);
+            let (addr, len) = match resources.get(&*String::from_utf8_lossy(&name)) {
+                Some(&(addr, len)) => (addr as u64, len as u64),
+                None => (u64::MAX, 0),
+            };
+            ctx.set_reg(Register::R0, addr);
+            ctx.set_reg(Register::R1, len);
+        }
+        8 => {
+            // Set Timer (Args: R1 = Handler instruction, R2 = Interval; 0 stops it).
+            // The handler is called like a procedure and ends with `return`, not `iret`.
+            let interval = ctx.get_reg(Register::R2);
+            let handler = ctx.get_reg(Register::R1) as usize;
+            ctx.timer = (interval > 0).then_some(Timer { interval, handler, remaining: interval });
+        }
+        9 => {
+            // Set Trap Handler (Args: R1 = Vector, R2 = Handler instruction; all ones removes it)
+            let vector = ctx.get_reg(Register::R1) as u8;
+            match ctx.get_reg(Register::R2) {
+                u64::MAX => ctx.traps.remove(&vector),
+                handler => ctx.traps.insert(vector, handler as usize),
+            };
+        }
         _ => {
             let msg = format!("Unknown syscall ID: {}", id);
             if print_immediately {
@@ -91,4 +116,25 @@ pub fn handle_syscall(ctx: &mut ExecutionContext, heap: &Heap, memory: &mut dyn
             }
         }
     }
+    Ok(())
+}
+
+/// Read the null-terminated string at `addr`, stopping early at unreadable
+/// memory or after 1024 bytes
+fn read_string(memory: &dyn MemoryAccess, addr: usize) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    let mut curr = addr;
+    loop {
+        match memory.read_byte(curr) {
+            Ok(0) => break,
+            Ok(b) => {
+                bytes.push(b);
+                curr += 1;
+            }
+            Err(_) => break, // Stop on error
+        }
+        // Safety limit
+        if bytes.len() > 1024 { break; }
+    }
+    bytes
 }
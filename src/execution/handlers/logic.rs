@@ -46,3 +46,11 @@ pub fn handle_shr(ctx: &mut ExecutionContext, dest: Register, left: Register, ri
     ctx.set_reg(dest, result);
     ctx.flags.update_from_result(result, false);
 }
+
+/// Execute ISht: dest = left >> right, filling with the sign bit
+pub fn handle_isht(ctx: &mut ExecutionContext, dest: Register, left: Register, right: Register) {
+    let shift = ctx.get_reg(right) as u32;
+    let result = (ctx.get_reg(left) as i64).wrapping_shr(shift) as u64;
+    ctx.set_reg(dest, result);
+    ctx.flags.update_from_result(result, false);
+}
@@ -20,6 +20,38 @@ pub fn handle_store(ctx: &mut ExecutionContext, memory: &mut Memory, src: Regist
     memory.write_qword(addr, value).map_err(VmError::from)
 }
 
+/// Execute LoadLocal: dest = memory[BP + offset]
+pub fn handle_load_local(ctx: &mut ExecutionContext, memory: &Memory, dest: Register, offset: i32) -> Result<(), VmError> {
+    let addr = ctx.get_reg(Register::BP).wrapping_add(offset as i64 as u64) as usize;
+    let value = memory.read_qword(addr).map_err(VmError::from)?;
+    ctx.set_reg(dest, value);
+    Ok(())
+}
+
+/// Execute StoreLocal: memory[BP + offset] = src
+pub fn handle_store_local(ctx: &mut ExecutionContext, memory: &mut Memory, src: Register, offset: i32) -> Result<(), VmError> {
+    let addr = ctx.get_reg(Register::BP).wrapping_add(offset as i64 as u64) as usize;
+    let value = ctx.get_reg(src);
+    memory.write_qword(addr, value).map_err(VmError::from)
+}
+
+/// Execute LoadByte, LoadWord and LoadDWord: dest = the `size` bytes at
+/// memory[addr_reg], zero-extended
+pub fn handle_load_sized(ctx: &mut ExecutionContext, memory: &Memory, dest: Register, addr_reg: Register, size: usize) -> Result<(), VmError> {
+    let addr = ctx.get_reg(addr_reg) as usize;
+    let value = memory.read_sized(addr, size).map_err(VmError::from)?;
+    ctx.set_reg(dest, value);
+    Ok(())
+}
+
+/// Execute StoreByte, StoreWord and StoreDWord: memory[addr_reg] = the low
+/// `size` bytes of src
+pub fn handle_store_sized(ctx: &mut ExecutionContext, memory: &mut Memory, src: Register, addr_reg: Register, size: usize) -> Result<(), VmError> {
+    let addr = ctx.get_reg(addr_reg) as usize;
+    let value = ctx.get_reg(src);
+    memory.write_sized(addr, size, value).map_err(VmError::from)
+}
+
 /// Execute LoadIndexed: dest = memory[base_reg + index_reg * 8]
 pub fn handle_load_indexed(ctx: &mut ExecutionContext, memory: &Memory, dest: Register, base_reg: Register, index_reg: Register) -> Result<(), VmError> {
     let base = ctx.get_reg(base_reg) as usize;
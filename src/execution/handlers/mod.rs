@@ -5,6 +5,7 @@ pub mod logic;
 pub mod data_move;
 pub mod control;
 pub mod stack;
+pub mod trap;
 pub mod memory;
 pub mod memory_ext;
 pub mod io;
@@ -9,16 +9,40 @@ use crate::error::VmError;
 /// Execute Push: push register value onto stack
 pub fn handle_push(ctx: &mut ExecutionContext, stack: &mut Stack, memory: &mut Memory, src: Register) -> Result<(), VmError> {
     let value = ctx.get_reg(src);
-    Ok(stack.push(memory, value)?)
+    stack.push(memory, value)?;
+    ctx.set_reg(Register::SP, stack.pointer() as u64);
+    Ok(())
 }
 
 /// Execute Pop: pop top of stack into register
 pub fn handle_pop(ctx: &mut ExecutionContext, stack: &mut Stack, memory: &Memory, dest: Register) -> Result<(), VmError> {
     let value = stack.pop(memory)?;
+    ctx.set_reg(Register::SP, stack.pointer() as u64);
     ctx.set_reg(dest, value);
     Ok(())
 }
 
+/// Execute Enter: push BP, point BP at the saved value and push zeroed
+/// qwords covering `locals_size` bytes below it
+pub fn handle_enter(ctx: &mut ExecutionContext, stack: &mut Stack, memory: &mut Memory, locals_size: usize) -> Result<(), VmError> {
+    stack.push(memory, ctx.get_reg(Register::BP))?;
+    ctx.set_reg(Register::BP, stack.pointer() as u64);
+    for _ in 0..locals_size.div_ceil(8) {
+        stack.push(memory, 0)?;
+    }
+    ctx.set_reg(Register::SP, stack.pointer() as u64);
+    Ok(())
+}
+
+/// Execute Leave: drop the locals and restore the BP saved by Enter
+pub fn handle_leave(ctx: &mut ExecutionContext, stack: &mut Stack, memory: &Memory) -> Result<(), VmError> {
+    stack.set_pointer(ctx.get_reg(Register::BP) as usize);
+    let bp = stack.pop(memory)?;
+    ctx.set_reg(Register::BP, bp);
+    ctx.set_reg(Register::SP, stack.pointer() as u64);
+    Ok(())
+}
+
 /// Execute Peek: read top of stack without removing
 pub fn handle_peek(ctx: &mut ExecutionContext, stack: &Stack, memory: &Memory, dest: Register) -> Result<(), VmError> {
     let value = stack.peek(memory)?;
@@ -0,0 +1,38 @@
+//! Interrupt and trap handlers.
+
+use crate::core::{Flags, Register};
+use crate::execution::context::ExecutionContext;
+use crate::memory::Memory;
+use crate::memory::stack::Stack;
+use crate::error::{ErrorCode, VmError};
+
+/// Enter the handler registered for `vector`: push the flags, then the pc
+/// (already past the instruction that raised it). Returns false, leaving
+/// everything untouched, if there is no handler.
+pub fn enter_trap(ctx: &mut ExecutionContext, stack: &mut Stack, memory: &mut Memory, vector: u8) -> Result<bool, VmError> {
+    let Some(&handler) = ctx.traps.get(&vector) else { return Ok(false) };
+    stack.push(memory, ctx.flags.bits())?;
+    stack.push(memory, ctx.pc as u64)?;
+    ctx.set_reg(Register::SP, stack.pointer() as u64);
+    ctx.pc = handler;
+    Ok(true)
+}
+
+/// Execute Int: raise `vector`, which must have a handler
+pub fn handle_int(ctx: &mut ExecutionContext, stack: &mut Stack, memory: &mut Memory, vector: u8) -> Result<(), VmError> {
+    if enter_trap(ctx, stack, memory, vector)? {
+        Ok(())
+    } else {
+        Err(VmError::execution(ErrorCode::UnhandledInterrupt, format!("No handler for interrupt {}", vector)))
+    }
+}
+
+/// Execute IRet: pop the pc and flags `enter_trap` pushed
+pub fn handle_iret(ctx: &mut ExecutionContext, stack: &mut Stack, memory: &Memory) -> Result<(), VmError> {
+    let pc = stack.pop(memory)?;
+    let flags = stack.pop(memory)?;
+    ctx.set_reg(Register::SP, stack.pointer() as u64);
+    ctx.pc = pc as usize;
+    ctx.flags = Flags::from_bits(flags);
+    Ok(())
+}
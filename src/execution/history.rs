@@ -0,0 +1,343 @@
+//! Reverse-execution delta log.
+//!
+//! Each recorded step stores only what the instruction changed: the old
+//! values of the registers it wrote, the flags, pc and stack pointer, the
+//! return address it pushed or popped, the old contents of every byte it
+//! wrote, and its opcode and cycle cost. Steps can be undone one at a time
+//! without re-running the program. A `Checkpoint` is a full copy of the
+//! state instead, for spans of any number of instructions.
+//!
+//! Undoing also rewinds the instruction and cycle counters, the opcode
+//! frequencies and the `rand` generator. The heap's free list lives in
+//! memory, so the journal covers it.
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use crate::core::{Flags, Register, Rng};
+use crate::execution::context::{Interrupt, Timer};
+use crate::execution::{ExecutionContext, VM};
+use crate::instruction::{Instruction, Program};
+use crate::memory::Stack;
+use crate::error::VmResult;
+
+/// Default number of steps kept before the oldest are dropped
+pub const DEFAULT_HISTORY_LIMIT: usize = 100_000;
+
+/// VM state captured at a point in time, plus the memory writes made since
+#[derive(Debug, Clone)]
+pub struct Checkpoint {
+    ctx: ExecutionContext,
+    stack: Stack,
+    output_len: usize,
+    memory: Vec<(usize, u8)>,
+    instruction_count: u64,
+    cycles: u64,
+    instr_freq: HashMap<u8, u64>,
+    rng: Rng,
+}
+
+impl Checkpoint {
+    /// Capture the current state and start journaling memory writes
+    pub fn capture(vm: &mut VM) -> Self {
+        vm.memory.enable_journal();
+        vm.memory.take_journal();
+        Self {
+            ctx: vm.ctx.clone(),
+            stack: vm.stack.clone(),
+            output_len: vm.output.len(),
+            memory: Vec::new(),
+            instruction_count: vm.instruction_count,
+            cycles: vm.cycles,
+            instr_freq: vm.instr_freq.clone(),
+            rng: vm.rng,
+        }
+    }
+
+    /// Collect the memory writes made since the capture
+    fn seal(&mut self, vm: &mut VM) {
+        self.memory.extend(vm.memory.take_journal());
+    }
+
+    /// Roll the VM back to the captured state
+    pub fn restore(mut self, vm: &mut VM) {
+        self.seal(vm);
+        vm.memory.undo_journal(&self.memory);
+        vm.ctx = self.ctx;
+        vm.stack = self.stack;
+        vm.output.truncate(self.output_len);
+        vm.instruction_count = self.instruction_count;
+        vm.cycles = self.cycles;
+        vm.instr_freq = self.instr_freq;
+        vm.rng = self.rng;
+    }
+}
+
+/// What one instruction changed, with the values from before it
+#[derive(Debug, Clone)]
+struct Step {
+    /// Old value of each register the instruction wrote
+    registers: Vec<(u8, u64)>,
+    flags: Flags,
+    pc: usize,
+    halted: bool,
+    exit_code: i32,
+    trace: bool,
+    timer: Option<Timer>,
+    interrupt: Option<Interrupt>,
+    /// Call stack depth and return address on top before the instruction,
+    /// which pops at most one
+    call_depth: usize,
+    call_top: Option<usize>,
+    /// Trap vectors, kept for syscalls since only those change them
+    traps: Option<BTreeMap<u8, usize>>,
+    stack_pointer: usize,
+    output_len: usize,
+    memory: Vec<(usize, u8)>,
+    /// Opcode counted in `instr_freq` and the cycles it cost; `None` if
+    /// the step did not execute anything
+    counted: Option<(u8, u64)>,
+    rng: Rng,
+}
+
+impl Step {
+    /// Execute one instruction, recording what it changes
+    fn record(vm: &mut VM, program: &Program) -> (Self, VmResult<()>) {
+        vm.memory.enable_journal();
+        vm.memory.take_journal();
+        let registers = vm.ctx.registers;
+        let (count, cycles) = (vm.instruction_count, vm.cycles);
+        let syscall = program.instructions.get(vm.ctx.pc) == Some(&Instruction::Syscall);
+        let opcode = program.instructions.get(vm.ctx.pc).map(|instruction| instruction.opcode().to_u8());
+        let mut step = Self {
+            registers: Vec::new(),
+            flags: vm.ctx.flags,
+            pc: vm.ctx.pc,
+            halted: vm.ctx.halted,
+            exit_code: vm.ctx.exit_code,
+            trace: vm.ctx.trace,
+            timer: vm.ctx.timer,
+            interrupt: vm.ctx.interrupt,
+            call_depth: vm.ctx.call_stack.len(),
+            call_top: vm.ctx.call_stack.last().copied(),
+            traps: syscall.then(|| vm.ctx.traps.clone()),
+            stack_pointer: vm.stack.pointer(),
+            output_len: vm.output.len(),
+            memory: Vec::new(),
+            counted: None,
+            rng: vm.rng,
+        };
+
+        let result = vm.step(program);
+        step.memory = vm.memory.take_journal();
+        step.registers = (0..Register::COUNT)
+            .filter(|&index| vm.ctx.registers[index] != registers[index])
+            .map(|index| (index as u8, registers[index]))
+            .collect();
+        if vm.instruction_count != count {
+            step.counted = opcode.map(|opcode| (opcode, vm.cycles - cycles));
+        }
+        (step, result)
+    }
+
+    /// Put back everything the instruction changed
+    fn undo(self, vm: &mut VM) {
+        vm.memory.undo_journal(&self.memory);
+        for (index, value) in self.registers {
+            vm.ctx.registers[index as usize] = value;
+        }
+        let ctx = &mut vm.ctx;
+        ctx.flags = self.flags;
+        ctx.pc = self.pc;
+        ctx.halted = self.halted;
+        ctx.exit_code = self.exit_code;
+        ctx.trace = self.trace;
+        ctx.timer = self.timer;
+        ctx.interrupt = self.interrupt;
+        ctx.call_stack.truncate(self.call_depth.saturating_sub(1));
+        ctx.call_stack.extend(self.call_top);
+        if let Some(traps) = self.traps {
+            ctx.traps = traps;
+        }
+        vm.stack.set_pointer(self.stack_pointer);
+        vm.output.truncate(self.output_len);
+        if let Some((opcode, cost)) = self.counted {
+            vm.instruction_count -= 1;
+            vm.cycles -= cost;
+            if let Some(count) = vm.instr_freq.get_mut(&opcode) {
+                *count -= 1;
+                if *count == 0 {
+                    vm.instr_freq.remove(&opcode);
+                }
+            }
+        }
+        vm.rng = self.rng;
+    }
+}
+
+/// Bounded log of executed steps that can be rewound
+#[derive(Debug)]
+pub struct History {
+    deltas: VecDeque<Step>,
+    limit: usize,
+}
+
+impl History {
+    pub fn new(limit: usize) -> Self {
+        Self { deltas: VecDeque::new(), limit }
+    }
+
+    /// Execute one instruction, recording how to undo it.
+    /// The delta is kept even if the step fails so a faulting instruction can be rewound.
+    pub fn step(&mut self, vm: &mut VM, program: &Program) -> VmResult<()> {
+        let (delta, result) = Step::record(vm, program);
+
+        if self.deltas.len() == self.limit {
+            self.deltas.pop_front();
+        }
+        self.deltas.push_back(delta);
+        result
+    }
+
+    /// Undo the most recent step. Returns false if there is nothing to undo.
+    pub fn undo(&mut self, vm: &mut VM) -> bool {
+        match self.deltas.pop_back() {
+            Some(delta) => {
+                delta.undo(vm);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Program counter the VM will return to on the next undo
+    pub fn previous_pc(&self) -> Option<usize> {
+        self.deltas.back().map(|delta| delta.pc)
+    }
+
+    /// Number of steps that can be undone
+    pub fn len(&self) -> usize {
+        self.deltas.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.deltas.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.deltas.clear();
+    }
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self::new(DEFAULT_HISTORY_LIMIT)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::Register;
+    use crate::instruction::Instruction;
+    use crate::memory::MemoryAccess;
+
+    #[test]
+    fn test_undo_restores_registers_and_memory() {
+        let program = Program::from_instructions("t", vec![
+            Instruction::LoadImm { dest: Register::R0, value: 42 },
+            Instruction::Push { src: Register::R0 },
+            Instruction::LoadImm { dest: Register::R0, value: 7 },
+            Instruction::Halt,
+        ]);
+        let mut vm = VM::new();
+        vm.init(&program).unwrap();
+        let sp = vm.stack.pointer();
+        let mut history = History::default();
+
+        for _ in 0..4 {
+            history.step(&mut vm, &program).unwrap();
+        }
+        assert!(vm.ctx.halted);
+        let slot = vm.stack.pointer();
+        assert_eq!(vm.memory.read_qword(slot).unwrap(), 42);
+
+        assert!(history.undo(&mut vm));
+        assert!(!vm.ctx.halted);
+        assert!(history.undo(&mut vm));
+        assert_eq!(vm.ctx.get_reg(Register::R0), 42);
+        assert!(history.undo(&mut vm));
+        assert_eq!(vm.stack.pointer(), sp);
+        assert_eq!(vm.memory.read_qword(slot).unwrap(), 0);
+        assert!(history.undo(&mut vm));
+        assert_eq!(vm.ctx.pc, 0);
+        assert!(!history.undo(&mut vm));
+    }
+
+    #[test]
+    fn test_undo_rewinds_counters_and_rng() {
+        let program = Program::from_instructions("t", vec![
+            Instruction::Rand { dest: Register::R0 },
+            Instruction::Rand { dest: Register::R1 },
+            Instruction::Halt,
+        ]);
+        let mut vm = VM::new();
+        vm.rand_seed = Some(7);
+        vm.init(&program).unwrap();
+        let mut history = History::default();
+
+        history.step(&mut vm, &program).unwrap();
+        let (count, cycles, freq) = (vm.instruction_count, vm.cycles(), vm.instr_freq.clone());
+        history.step(&mut vm, &program).unwrap();
+        let second = vm.ctx.get_reg(Register::R1);
+
+        assert!(history.undo(&mut vm));
+        assert_eq!((vm.instruction_count, vm.cycles(), &vm.instr_freq), (count, cycles, &freq));
+        history.step(&mut vm, &program).unwrap();
+        assert_eq!(vm.ctx.get_reg(Register::R1), second);
+    }
+
+    #[test]
+    fn test_undo_calls_and_syscalls() {
+        let program = crate::assembler::assemble("\
+@r1 := 0
+@r2 := &on_divide
+@r0 := 9
+syscall
+call f
+halt
+f:
+return
+on_divide:
+iret
+", "t").unwrap();
+        let mut vm = VM::new();
+        vm.init(&program).unwrap();
+        let mut history = History::default();
+        let mut states = Vec::new();
+        while !vm.is_finished(&program) {
+            states.push(vm.ctx.clone());
+            history.step(&mut vm, &program).unwrap();
+        }
+        assert_eq!(vm.ctx.traps.len(), 1);
+
+        while let Some(before) = states.pop() {
+            assert!(history.undo(&mut vm));
+            assert_eq!((vm.ctx.pc, &vm.ctx.registers, &vm.ctx.call_stack), (before.pc, &before.registers, &before.call_stack));
+            assert_eq!((vm.ctx.flags, &vm.ctx.traps, vm.ctx.halted), (before.flags, &before.traps, before.halted));
+        }
+        assert_eq!(vm.instruction_count, 0);
+        assert!(vm.instr_freq.is_empty());
+    }
+
+    #[test]
+    fn test_limit_drops_oldest() {
+        let program = Program::from_instructions("t", vec![Instruction::Nop; 5]);
+        let mut vm = VM::new();
+        vm.init(&program).unwrap();
+        let mut history = History::new(2);
+        for _ in 0..5 {
+            history.step(&mut vm, &program).unwrap();
+        }
+        assert_eq!(history.len(), 2);
+        assert_eq!(history.previous_pc(), Some(4));
+    }
+}
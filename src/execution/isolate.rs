@@ -0,0 +1,231 @@
+//! Several VMs, each with its own memory, exchanging messages.
+//!
+//! `Isolates` runs its VMs round-robin on the calling thread. Programs
+//! talk through three syscalls:
+//!
+//! - 10, send: the value in R2 to the isolate numbered R1. R0 is 0, or
+//!   all ones if there is no such isolate.
+//! - 11, receive: the oldest message into R0 and its sender into R1,
+//!   waiting for one if none has arrived.
+//! - 12, self: the isolate's own number into R0.
+//!
+//! ```
+//! use alya_vm::assembler;
+//! use alya_vm::execution::{Isolates, VM};
+//!
+//! let ping = assembler::assemble("@r1 := 1\n@r2 := 41\n@r0 := 10\nsyscall\nhalt\n", "ping").unwrap();
+//! let pong = assembler::assemble("@r0 := 11\nsyscall\n@r0 += 1\nhalt\n", "pong").unwrap();
+//! let mut isolates = Isolates::new();
+//! isolates.spawn(VM::new(), ping).unwrap();
+//! let id = isolates.spawn(VM::new(), pong).unwrap();
+//! isolates.run(100).unwrap();
+//! assert_eq!(isolates.vm(id).ctx.get_reg(alya_vm::Register::R0), 42);
+//! ```
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex, MutexGuard};
+use crate::core::Register;
+use crate::error::{ErrorCode, VmError, VmResult};
+use crate::instruction::Program;
+use super::vm::instruction_limit_exceeded;
+use super::VM;
+
+/// Syscall id of send: the value in R2 to the isolate numbered R1
+pub const SYSCALL_SEND: u64 = 10;
+/// Syscall id of receive: the oldest message into R0 and its sender into R1
+pub const SYSCALL_RECEIVE: u64 = 11;
+/// Syscall id of self: the isolate's own number into R0
+pub const SYSCALL_SELF: u64 = 12;
+
+/// Queued messages, as (sender, value), and whether each isolate is
+/// waiting in a receive
+#[derive(Default)]
+struct Mailboxes {
+    queues: Vec<VecDeque<(usize, u64)>>,
+    waiting: Vec<bool>,
+}
+
+/// VMs scheduled together, numbered in the order they were spawned
+#[derive(Default)]
+pub struct Isolates {
+    members: Vec<(VM, Program)>,
+    mail: Arc<Mutex<Mailboxes>>,
+}
+
+fn lock(mail: &Mutex<Mailboxes>) -> MutexGuard<'_, Mailboxes> {
+    mail.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+impl Isolates {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add `vm` running `program` and return its number. Initializes the
+    /// VM and registers the message syscalls on it.
+    pub fn spawn(&mut self, mut vm: VM, program: Program) -> VmResult<usize> {
+        vm.init(&program)?;
+        let id = self.members.len();
+
+        let mail = Arc::clone(&self.mail);
+        vm.register_host_fn(SYSCALL_SEND, move |ctx, _| {
+            let mut mail = lock(&mail);
+            let to = ctx.get_reg(Register::R1) as usize;
+            let status = match mail.queues.get_mut(to) {
+                Some(queue) => {
+                    queue.push_back((id, ctx.get_reg(Register::R2)));
+                    mail.waiting[to] = false;
+                    0
+                }
+                None => u64::MAX,
+            };
+            ctx.set_reg(Register::R0, status);
+            Ok(())
+        });
+        let mail = Arc::clone(&self.mail);
+        vm.register_host_fn(SYSCALL_RECEIVE, move |ctx, _| {
+            let mut mail = lock(&mail);
+            match mail.queues[id].pop_front() {
+                Some((from, value)) => {
+                    ctx.set_reg(Register::R0, value);
+                    ctx.set_reg(Register::R1, from as u64);
+                }
+                None => {
+                    // Run the syscall again once a message arrives
+                    ctx.pc -= 1;
+                    mail.waiting[id] = true;
+                }
+            }
+            Ok(())
+        });
+        vm.register_host_fn(SYSCALL_SELF, move |ctx, _| {
+            ctx.set_reg(Register::R0, id as u64);
+            Ok(())
+        });
+
+        let mut mail = lock(&self.mail);
+        mail.queues.push(VecDeque::new());
+        mail.waiting.push(false);
+        drop(mail);
+        self.members.push((vm, program));
+        Ok(id)
+    }
+
+    /// Run every isolate to completion, `slice` instructions at a time.
+    /// Isolates still waiting for messages once the rest have finished,
+    /// such as servers answering requests, are left waiting. Fails on the
+    /// first error, naming the isolate it happened in, or if every isolate
+    /// is waiting and none has finished.
+    pub fn run(&mut self, slice: u64) -> VmResult<()> {
+        loop {
+            let mut running = false;
+            let mut progressed = false;
+            let mut finished = false;
+            for (id, (vm, program)) in self.members.iter_mut().enumerate() {
+                if vm.is_finished(program) {
+                    finished = true;
+                    continue;
+                }
+                running = true;
+                if lock(&self.mail).waiting[id] {
+                    continue;
+                }
+                progressed = true;
+                for _ in 0..slice.max(1) {
+                    if vm.is_finished(program) || lock(&self.mail).waiting[id] {
+                        break;
+                    }
+                    if vm.instruction_count >= vm.max_instructions {
+                        return Err(in_isolate(id, instruction_limit_exceeded(vm.max_instructions)));
+                    }
+                    vm.step(program).map_err(|e| in_isolate(id, e))?;
+                }
+            }
+            if !running || (!progressed && finished) {
+                return Ok(());
+            }
+            if !progressed {
+                return Err(VmError::execution(ErrorCode::Deadlock, "Every running isolate is waiting for a message"));
+            }
+        }
+    }
+
+    /// Number of isolates spawned
+    pub fn len(&self) -> usize {
+        self.members.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.members.is_empty()
+    }
+
+    /// The VM of isolate `id`
+    ///
+    /// # Panics
+    /// If there is no such isolate
+    pub fn vm(&self, id: usize) -> &VM {
+        &self.members[id].0
+    }
+}
+
+/// `error` with the isolate it happened in prepended to its message
+fn in_isolate(id: usize, error: VmError) -> VmError {
+    let mut wrapped = VmError::execution(error.code(), format!("Isolate {}: {}", id, error.message()));
+    if let Some(pc) = error.pc() {
+        wrapped = wrapped.with_pc(pc);
+    }
+    wrapped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler;
+
+    #[test]
+    fn test_isolates_exchange_messages() {
+        // The worker doubles whatever it receives and sends it back
+        let worker = assembler::assemble("\
+loop:
+@r0 := 11
+syscall
+@r2 := @r0 + @r0
+@r0 := 10
+syscall
+goto loop
+", "worker").unwrap();
+        let main = assembler::assemble("\
+@r0 := 12
+syscall
+@r1 := 1
+@r2 := 21
+@r0 := 10
+syscall
+@r0 := 11
+syscall
+print @r0
+print @r1
+halt
+", "main").unwrap();
+
+        let mut isolates = Isolates::new();
+        let mut vm = VM::new();
+        vm.print_immediately = false;
+        assert_eq!(isolates.spawn(vm, main).unwrap(), 0);
+        assert_eq!(isolates.spawn(VM::new(), worker).unwrap(), 1);
+        isolates.run(3).unwrap();
+        assert_eq!(isolates.vm(0).output(), ["42", "1"]);
+        assert!(isolates.vm(0).ctx.halted);
+        assert!(!isolates.vm(1).ctx.halted);
+        assert_eq!(isolates.len(), 2);
+    }
+
+    #[test]
+    fn test_isolates_deadlock() {
+        let receive = || assembler::assemble("@r0 := 11\nsyscall\nhalt\n", "receive").unwrap();
+        let mut isolates = Isolates::new();
+        isolates.spawn(VM::new(), receive()).unwrap();
+        isolates.spawn(VM::new(), receive()).unwrap();
+        assert_eq!(isolates.run(3).unwrap_err().code(), ErrorCode::Deadlock);
+    }
+}
@@ -0,0 +1,78 @@
+//! Iterator adapter that drives execution one instruction per item.
+
+use crate::core::Flags;
+use crate::error::{VmError, VmResult};
+use crate::instruction::{Instruction, Program};
+use super::VM;
+
+/// What one executed instruction did, as yielded by `VM::run_iter`
+#[derive(Debug, Clone, PartialEq)]
+pub struct StepInfo {
+    /// Index of the instruction that ran
+    pub pc: usize,
+    pub instruction: Instruction,
+    /// Flags after the instruction
+    pub flags: Flags,
+}
+
+/// Yields a `StepInfo` per executed instruction. Ends after the program
+/// halts or runs off its end; an error is yielded once and ends it too.
+pub struct RunIter<'a> {
+    vm: &'a mut VM,
+    program: &'a Program,
+    /// Error from `init`, yielded as the first item
+    pending: Option<VmError>,
+    done: bool,
+}
+
+impl Iterator for RunIter<'_> {
+    type Item = VmResult<StepInfo>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(e) = self.pending.take() {
+            self.done = true;
+            return Some(Err(e));
+        }
+        let pc = self.vm.ctx.pc;
+        if self.done || self.vm.is_finished(self.program) {
+            return None;
+        }
+
+        if let Err(e) = self.vm.step(self.program) {
+            self.done = true;
+            return Some(Err(e));
+        }
+        Some(Ok(StepInfo {
+            pc,
+            instruction: self.program.instructions[pc],
+            flags: self.vm.ctx.flags,
+        }))
+    }
+}
+
+impl VM {
+    /// Initialize the VM for `program` and return an iterator that executes
+    /// one instruction per `next()`, e.g. `vm.run_iter(&program).take(100)`
+    pub fn run_iter<'a>(&'a mut self, program: &'a Program) -> RunIter<'a> {
+        let pending = self.init(program).err();
+        RunIter { vm: self, program, pending, done: false }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::assembler;
+    use crate::execution::VM;
+
+    #[test]
+    fn test_run_iter() {
+        let program = assembler::assemble("@r0 := 0\nloop:\n@r0 += 1\nif @r0 < 100 goto loop\nhalt\n", "iter").unwrap();
+        let mut vm = VM::new();
+        assert_eq!(vm.run_iter(&program).take(5).count(), 5);
+
+        let steps: Vec<_> = vm.run_iter(&program).collect::<Result<_, _>>().unwrap();
+        assert!(vm.ctx.halted);
+        assert_eq!(steps.last().unwrap().pc, program.len() - 1);
+        assert_eq!(steps[0].pc, 0);
+    }
+}
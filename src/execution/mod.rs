@@ -4,9 +4,38 @@
 //! It dispatches instructions to handler functions.
 
 pub mod vm;
+pub mod bench;
+pub mod cost;
 pub mod debugger;
+pub mod digest;
+pub mod events;
+pub mod expr;
+pub mod grade;
+pub mod history;
+pub mod isolate;
+pub mod iter;
+pub mod prompt;
+pub mod remote;
+pub mod pool;
+pub mod profile;
+pub mod shared;
 mod context;
+mod dispatch;
 mod handlers;
 
-pub use vm::VM;
-pub use context::ExecutionContext;
+pub use vm::{HostFn, RunStatus, VM, MAX_INSTRUCTIONS};
+pub use bench::{bench, BenchReport};
+pub use cost::CostModel;
+pub use digest::{execution_digest, DigestOptions, ExecutionDigest};
+pub use events::{ObserverId, VmEvent, VmObserver};
+pub use grade::{grade_run, Expectations, GradeReport, Verdict};
+pub use isolate::Isolates;
+pub use iter::{RunIter, StepInfo};
+pub use pool::VmPool;
+pub use profile::{profile, Profile};
+pub use shared::{SharedVm, VmSnapshot};
+pub use history::{Checkpoint, History};
+pub use context::{
+    trap_vector, ExecutionContext, Interrupt, Timer,
+    TRAP_DIVISION_BY_ZERO, TRAP_INVALID_INSTRUCTION, TRAP_MEMORY_FAULT, TRAP_STACK_FAULT,
+};
@@ -0,0 +1,56 @@
+//! Pool of reusable VMs for running many short programs.
+//!
+//! Autograders and fuzzers run thousands of tiny programs. Allocating a
+//! fresh 64KB memory per program dominates their run time, so the pool
+//! hands out VMs whose buffers are kept between uses; `VM::init` then
+//! only re-zeroes the pages the previous program actually wrote.
+
+use super::VM;
+use crate::memory::MemoryAccess;
+
+/// A set of idle VMs sharing one memory size.
+pub struct VmPool {
+    memory_size: usize,
+    idle: Vec<VM>,
+    max_idle: usize,
+}
+
+impl VmPool {
+    /// Default number of idle VMs kept around
+    pub const DEFAULT_MAX_IDLE: usize = 16;
+
+    /// Create an empty pool producing VMs with the given memory size
+    pub fn new(memory_size: usize) -> Self {
+        Self {
+            memory_size,
+            idle: Vec::new(),
+            max_idle: Self::DEFAULT_MAX_IDLE,
+        }
+    }
+
+    /// Limit how many idle VMs the pool retains
+    pub fn with_max_idle(mut self, max_idle: usize) -> Self {
+        self.max_idle = max_idle;
+        self
+    }
+
+    /// Take a VM from the pool, creating one if none is idle.
+    /// Settings such as `print_immediately` and `aslr` carry over from its last use.
+    pub fn acquire(&mut self) -> VM {
+        self.idle
+            .pop()
+            .unwrap_or_else(|| VM::with_memory_size(self.memory_size))
+    }
+
+    /// Return a VM for later reuse. VMs of a different memory size are dropped.
+    pub fn release(&mut self, vm: VM) {
+        if self.idle.len() < self.max_idle && vm.memory.size() == self.memory_size {
+            self.idle.push(vm);
+        }
+    }
+
+    /// Number of idle VMs ready for reuse
+    pub fn idle_count(&self) -> usize {
+        self.idle.len()
+    }
+}
@@ -0,0 +1,130 @@
+//! Input source for the interactive debugger prompt.
+//!
+//! With the `readline` feature the prompt supports line editing, arrow-key
+//! history and tab completion of commands and register names; without it,
+//! lines are read from stdin as-is.
+
+use crate::core::Register;
+
+/// Debugger commands offered by tab completion
+pub const COMMANDS: &[&str] = &[
+    "break", "call", "catch", "continue", "delete", "display", "help", "ignore",
+    "info", "list", "next", "print", "quit", "rnext", "rstep", "save", "set",
+    "source", "step", "tbreak", "trace", "uncatch", "undisplay", "untrace",
+    "unwatch", "watch",
+];
+
+/// Completions for the word ending at `pos`: commands for the first word,
+/// register names for words starting with `@`. Returns the word start and candidates.
+pub fn complete(line: &str, pos: usize) -> (usize, Vec<String>) {
+    let line = &line[..pos];
+    let start = line.rfind(|c: char| c.is_whitespace() || "(*+-,".contains(c)).map_or(0, |i| i + 1);
+    let word = &line[start..];
+
+    let candidates = if line[..start].trim().is_empty() {
+        COMMANDS.iter().filter(|c| c.starts_with(word)).map(|c| c.to_string()).collect()
+    } else if let Some(prefix) = word.strip_prefix('@') {
+        let prefix = prefix.to_lowercase();
+        (0..Register::COUNT as u8)
+            .filter_map(|i| Register::from_u8(i).ok())
+            .map(|reg| reg.name().to_lowercase())
+            .filter(|name| name.starts_with(&prefix))
+            .map(|name| format!("@{}", name))
+            .collect()
+    } else {
+        Vec::new()
+    };
+
+    (start, candidates)
+}
+
+#[cfg(feature = "readline")]
+mod editor {
+    use rustyline::completion::Completer;
+    use rustyline::highlight::Highlighter;
+    use rustyline::hint::Hinter;
+    use rustyline::history::DefaultHistory;
+    use rustyline::validate::Validator;
+    use rustyline::{Context, Editor, Helper};
+
+    pub struct DebugHelper;
+
+    impl Completer for DebugHelper {
+        type Candidate = String;
+
+        fn complete(&self, line: &str, pos: usize, _ctx: &Context<'_>) -> rustyline::Result<(usize, Vec<String>)> {
+            Ok(super::complete(line, pos))
+        }
+    }
+
+    impl Hinter for DebugHelper {
+        type Hint = String;
+    }
+
+    impl Highlighter for DebugHelper {}
+    impl Validator for DebugHelper {}
+    impl Helper for DebugHelper {}
+
+    pub type LineEditor = Editor<DebugHelper, DefaultHistory>;
+
+    pub fn new() -> Option<LineEditor> {
+        let mut editor = LineEditor::new().ok()?;
+        editor.set_helper(Some(DebugHelper));
+        Some(editor)
+    }
+}
+
+/// Reads debugger commands, with line editing when available
+pub struct Prompt {
+    #[cfg(feature = "readline")]
+    editor: Option<editor::LineEditor>,
+}
+
+impl Prompt {
+    pub fn new() -> Self {
+        Self {
+            #[cfg(feature = "readline")]
+            editor: editor::new(),
+        }
+    }
+
+    /// Show the prompt and read one line; None at end of input
+    pub fn read_line(&mut self, prompt: &str) -> Option<String> {
+        #[cfg(feature = "readline")]
+        if let Some(editor) = self.editor.as_mut() {
+            let line = editor.readline(prompt).ok()?;
+            if !line.trim().is_empty() {
+                let _ = editor.add_history_entry(line.as_str());
+            }
+            return Some(line);
+        }
+
+        use std::io::{self, Write};
+        print!("{}", prompt);
+        io::stdout().flush().ok()?;
+        let mut input = String::new();
+        match io::stdin().read_line(&mut input) {
+            Ok(0) | Err(_) => None,
+            Ok(_) => Some(input),
+        }
+    }
+}
+
+impl Default for Prompt {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_complete_commands_and_registers() {
+        assert_eq!(complete("unw", 3), (0, vec!["unwatch".to_string()]));
+        assert_eq!(complete("p @r1", 5).1, vec!["@r1", "@r10", "@r11", "@r12", "@r13", "@r14", "@r15", "@r16", "@r17", "@r18", "@r19"]);
+        assert_eq!(complete("p *(@s", 6), (4, vec!["@sp".to_string()]));
+        assert!(complete("break ma", 8).1.is_empty());
+    }
+}
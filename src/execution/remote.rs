@@ -0,0 +1,189 @@
+//! Remote debugging over TCP.
+//!
+//! A headless VM process runs `serve`, which drives a `Debugger` with
+//! command lines received from a `RemoteClient`. The protocol is plain
+//! text: the client sends one command per line, and every reply is
+//! dot-terminated as in SMTP — reply lines starting with `.` gain an extra
+//! `.`, and a line holding a single `.` ends the reply. The first reply is
+//! a greeting sent on connect.
+//!
+//! Clients are not authenticated: anyone who can connect controls the VM.
+//! Listen on a loopback address and never expose the port to untrusted
+//! networks. Commands that read or write files on the server (`save` and
+//! `source`) are refused.
+
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use crate::error::VmResult;
+use crate::execution::debugger::Debugger;
+use crate::instruction::Program;
+
+/// Debugger commands that touch the server's file system
+const LOCAL_ONLY_COMMANDS: &[&str] = &["save", "source"];
+
+/// Writer that collects output so it can be framed into a reply
+#[derive(Clone, Default)]
+struct ReplyBuffer(Arc<Mutex<Vec<u8>>>);
+
+impl ReplyBuffer {
+    fn take(&self) -> String {
+        let bytes = std::mem::take(&mut *self.0.lock().unwrap());
+        String::from_utf8_lossy(&bytes).into_owned()
+    }
+}
+
+impl Write for ReplyBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Send `text` as one dot-terminated reply
+fn write_reply(out: &mut impl Write, text: &str) -> io::Result<()> {
+    for line in text.lines() {
+        if line.starts_with('.') {
+            out.write_all(b".")?;
+        }
+        out.write_all(line.as_bytes())?;
+        out.write_all(b"\n")?;
+    }
+    out.write_all(b".\n")?;
+    out.flush()
+}
+
+/// Read one dot-terminated reply
+fn read_reply(input: &mut impl BufRead) -> io::Result<String> {
+    let mut reply = String::new();
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "connection closed mid-reply"));
+        }
+        let line = line.trim_end_matches(['\r', '\n']);
+        if line == "." {
+            return Ok(reply);
+        }
+        reply.push_str(line.strip_prefix('.').unwrap_or(line));
+        reply.push('\n');
+    }
+}
+
+/// Accept one client on `listener` and serve debugger commands until it
+/// quits or disconnects. Program output is forwarded in the replies.
+pub fn serve(debugger: &mut Debugger, program: &Program, listener: &TcpListener) -> VmResult<()> {
+    let (stream, _) = listener.accept()?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = stream;
+
+    let buffer = ReplyBuffer::default();
+    let previous = debugger.set_output(Box::new(buffer.clone()));
+    let print_immediately = debugger.vm().print_immediately;
+    debugger.vm_mut().print_immediately = false;
+
+    let result = (|| -> VmResult<()> {
+        debugger.reset(program)?;
+        write_reply(&mut writer, &format!("Alya Debugger (v0.5) serving {}", program.name))?;
+
+        loop {
+            let mut line = String::new();
+            if reader.read_line(&mut line)? == 0 {
+                return Ok(());
+            }
+            let command = line.split_whitespace().next().unwrap_or_default();
+            if LOCAL_ONLY_COMMANDS.contains(&command) {
+                write_reply(&mut writer, &format!("Error: '{}' is not available to remote clients", command))?;
+                continue;
+            }
+            let keep_going = match debugger.execute(program, &line) {
+                Ok(keep_going) => keep_going,
+                Err(e) => {
+                    writeln!(buffer.clone(), "Debugger Error: {}", e)?;
+                    true
+                }
+            };
+            write_reply(&mut writer, &buffer.take())?;
+            if !keep_going {
+                return Ok(());
+            }
+        }
+    })();
+
+    debugger.set_output(previous);
+    debugger.vm_mut().print_immediately = print_immediately;
+    result
+}
+
+/// Client side of a remote debugging session
+pub struct RemoteClient {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+}
+
+impl RemoteClient {
+    /// Connect to a server, returning the client and the server's greeting
+    pub fn connect(addr: impl ToSocketAddrs) -> io::Result<(Self, String)> {
+        let writer = TcpStream::connect(addr)?;
+        let mut reader = BufReader::new(writer.try_clone()?);
+        let greeting = read_reply(&mut reader)?;
+        Ok((Self { reader, writer }, greeting))
+    }
+
+    /// Run one debugger command remotely and return its output
+    pub fn send(&mut self, command: &str) -> io::Result<String> {
+        writeln!(self.writer, "{}", command.trim_end())?;
+        self.writer.flush()?;
+        read_reply(&mut self.reader)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::Register;
+    use crate::execution::VM;
+    use crate::instruction::Instruction;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_reply_framing_round_trip() {
+        let mut wire = Vec::new();
+        write_reply(&mut wire, "plain\n.dotted\n..\n").unwrap();
+        assert_eq!(String::from_utf8_lossy(&wire), "plain\n..dotted\n...\n.\n");
+        assert_eq!(read_reply(&mut Cursor::new(wire)).unwrap(), "plain\n.dotted\n..\n");
+    }
+
+    #[test]
+    fn test_remote_session() {
+        let program = Program::from_instructions("remote", vec![
+            Instruction::LoadImm { dest: Register::R0, value: 1 },
+            Instruction::LoadImm { dest: Register::R1, value: 9 },
+            Instruction::Syscall,
+            Instruction::Halt,
+        ]);
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let server = std::thread::spawn(move || {
+            let mut dbg = Debugger::new(VM::new());
+            serve(&mut dbg, &program, &listener).unwrap();
+            dbg.vm().ctx.halted
+        });
+
+        let (mut client, greeting) = RemoteClient::connect(addr).unwrap();
+        assert!(greeting.contains("remote"));
+        assert!(client.send("break 2").unwrap().contains("Breakpoint 1 set at 0002"));
+        assert!(client.send("continue").unwrap().contains("Breakpoint 1 reached at 0002"));
+        assert!(client.send("print @r1 * 2").unwrap().contains("= 18"));
+        assert!(client.send("save /tmp/remote.alyadbg").unwrap().contains("not available to remote clients"));
+        assert!(client.send("source /etc/passwd").unwrap().contains("not available to remote clients"));
+        assert!(client.send("continue").unwrap().contains("9\n"));
+        client.send("quit").unwrap();
+        assert!(server.join().unwrap());
+    }
+}
@@ -0,0 +1,116 @@
+//! Sharing a VM between a worker thread and a UI thread.
+//!
+//! `VM` is `Send` but not meant to be touched from two threads at once.
+//! `SharedVm` keeps it behind a mutex: a worker runs the program in short
+//! slices, releasing the lock between them, while other threads take
+//! cheap `VmSnapshot`s for display.
+//!
+//! ```
+//! use alya_vm::assembler;
+//! use alya_vm::execution::{SharedVm, VM};
+//!
+//! let program = assembler::assemble("@r0 := 42\nhalt\n", "demo").unwrap();
+//! let shared = SharedVm::new(VM::new());
+//! let worker = shared.spawn(program, 1_000);
+//! worker.join().unwrap().unwrap();
+//! assert_eq!(shared.snapshot().registers[0], 42);
+//! ```
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+use crate::core::{Flags, Register};
+use crate::error::{ErrorCode, VmError, VmResult};
+use crate::instruction::Program;
+use super::{RunStatus, VM};
+
+/// Copy of the VM state visible to a front-end
+#[derive(Debug, Clone, PartialEq)]
+pub struct VmSnapshot {
+    pub registers: [u64; Register::COUNT],
+    pub flags: Flags,
+    pub pc: usize,
+    pub halted: bool,
+    pub instruction_count: u64,
+    pub output: Vec<String>,
+}
+
+/// A VM shared between threads. Clones refer to the same VM.
+#[derive(Clone)]
+pub struct SharedVm {
+    vm: Arc<Mutex<VM>>,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl SharedVm {
+    pub fn new(vm: VM) -> Self {
+        Self {
+            vm: Arc::new(Mutex::new(vm)),
+            cancelled: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Lock the VM for direct access. A panic on another thread holding
+    /// the lock does not make the VM unusable.
+    pub fn lock(&self) -> MutexGuard<'_, VM> {
+        self.vm.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Copy the current state
+    pub fn snapshot(&self) -> VmSnapshot {
+        self.lock().state()
+    }
+
+    /// Execute at most `fuel` instructions under one lock.
+    /// Returns true once the program has finished.
+    pub fn run_slice(&self, program: &Program, fuel: u64) -> VmResult<bool> {
+        Ok(self.lock().run_fuel(program, fuel)? == RunStatus::Finished)
+    }
+
+    /// Initialize the VM for `program` and run it on a new thread,
+    /// `slice` instructions per lock. Stops early after `cancel`.
+    pub fn spawn(&self, program: Program, slice: u64) -> JoinHandle<VmResult<()>> {
+        let shared = self.clone();
+        shared.cancelled.store(false, Ordering::SeqCst);
+        thread::spawn(move || {
+            shared.lock().init(&program)?;
+            while !shared.run_slice(&program, slice.max(1))? {
+                if shared.cancelled.load(Ordering::SeqCst) {
+                    return Err(VmError::execution(ErrorCode::Cancelled, "Execution cancelled"));
+                }
+                thread::yield_now();
+            }
+            Ok(())
+        })
+    }
+
+    /// Ask a running `spawn` worker to stop after its current slice
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler;
+
+    #[test]
+    fn test_worker_thread_with_snapshots() {
+        let program = assembler::assemble("@r0 := 0\nloop:\n@r0 += 1\nif @r0 < 100000 goto loop\nhalt\n", "shared").unwrap();
+        let shared = SharedVm::new(VM::new());
+        let worker = shared.spawn(program, 100);
+
+        let early = shared.snapshot();
+        worker.join().unwrap().unwrap();
+        let done = shared.snapshot();
+        assert!(done.halted);
+        assert_eq!(done.registers[0], 100_000);
+        assert!(early.registers[0] <= done.registers[0]);
+
+        let endless = assembler::assemble("loop:\ngoto loop\n", "endless").unwrap();
+        let worker = shared.spawn(endless, 100);
+        shared.cancel();
+        assert!(worker.join().unwrap().is_err());
+    }
+}
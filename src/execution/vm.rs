@@ -1,24 +1,55 @@
 //! Main VM facade — owns memory, stack, execution context, and runs programs.
 
 
-use crate::error::{VmError, VmResult};
+use std::collections::BTreeMap;
+use std::io::Write;
+use crate::error::{ErrorCode, VmError, VmResult};
 use crate::instruction::{Instruction, Program};
+use crate::instruction::validate::validate;
 use crate::memory::Memory;
 use crate::memory::stack::Stack;
 use super::context::ExecutionContext;
-use super::handlers::{arithmetic, logic, data_move, control, stack as stack_handler, memory as memory_handler, memory_ext, float, bitwise_ext};
+use super::events::{self, ObserverId, VmEvent, VmObserver};
+use super::shared::VmSnapshot;
+use super::cost::CostModel;
+use super::dispatch::{self, Op};
+use super::handlers::{control, trap};
 use crate::memory::heap::Heap;
+use crate::memory::{Aslr, MemoryAccess, MemoryLayout};
+use crate::core::{Opcode, Register, Rng};
 
 /// Default memory size: 64KB
 const DEFAULT_MEMORY_SIZE: usize = 65536;
 
-/// Stack region starts at the top of memory
+/// Default maximum instructions to execute (prevents infinite loops)
+pub const MAX_INSTRUCTIONS: u64 = 10_000_000;
 
+/// Error returned when a run exceeds its instruction limit
+pub(crate) fn instruction_limit_exceeded(limit: u64) -> VmError {
+    VmError::execution(ErrorCode::InstructionLimit, format!(
+        "Exceeded maximum instruction count ({}). Possible infinite loop.",
+        limit
+    ))
+}
 
-/// Maximum instructions to execute (prevents infinite loops)
-const MAX_INSTRUCTIONS: u64 = 10_000_000;
+/// A Rust function guest programs reach through `syscall`, registered with
+/// `VM::register_host_fn`. It reads its arguments from and returns results
+/// in registers, like the built-in syscalls.
+pub type HostFn = Box<dyn FnMut(&mut ExecutionContext, &mut dyn MemoryAccess) -> VmResult<()> + Send>;
+
+/// How a `VM::run_fuel` call ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RunStatus {
+    /// The program halted or ran off its end
+    Finished,
+    /// The fuel ran out first; call `run_fuel` again to resume
+    Paused,
+}
 
-/// The Alya Virtual Machine
+/// The Alya Virtual Machine.
+///
+/// `VM` is `Send`, so it can be moved to a worker thread; use
+/// `SharedVm` to inspect it from another thread while it runs.
 pub struct VM {
     pub ctx: ExecutionContext,
     pub memory: Memory,
@@ -28,348 +59,388 @@ pub struct VM {
     pub print_immediately: bool,
     pub instruction_count: u64,
     pub instr_freq: std::collections::HashMap<u8, u64>,
+    /// Cycles each opcode adds to `cycles`
+    pub cost_model: CostModel,
+    /// Virtual clock since `init`
+    pub(super) cycles: u64,
+    /// Instructions `run` executes before giving up
+    pub max_instructions: u64,
+    /// Address space layout randomization mode
+    pub aslr: Aslr,
+    /// Layout used by the current run
+    pub layout: MemoryLayout,
+    /// Seed that produced the current layout (None when ASLR is disabled)
+    aslr_seed: Option<u64>,
+    /// Seed for `rand`; `None` reuses the ASLR seed, or picks a fresh one
+    /// when ASLR is disabled
+    pub rand_seed: Option<u64>,
+    /// Generator behind `rand`, reseeded by `init`
+    pub(super) rng: Rng,
+    /// Set by a `breakpoint` instruction until `take_break_request`
+    pub(super) break_requested: bool,
+    /// Registered event observers with their ids
+    observers: Vec<(ObserverId, Box<dyn VmObserver>)>,
+    next_observer_id: usize,
+    /// Resources of the loaded program, for the find-resource syscall
+    pub(super) resources: BTreeMap<String, (usize, usize)>,
+    /// Syscalls registered by the embedder, by id
+    pub(super) host_fns: BTreeMap<u64, HostFn>,
+    /// Where instructions run while `ctx.trace` is set are logged
+    trace_writer: Option<Box<dyn Write + Send>>,
+    /// The program `step` runs, compiled by `init` or `recompile`
+    pub(super) ops: Vec<Op>,
 }
 
+// Everything the VM owns (observers included) must stay `Send`
+const _: fn() = || {
+    fn assert_send<T: Send>() {}
+    assert_send::<VM>();
+};
+
 impl VM {
     /// Create a new VM with default memory size
     pub fn new() -> Self {
-        let memory = Memory::new(DEFAULT_MEMORY_SIZE);
-        let stack = Stack::new(DEFAULT_MEMORY_SIZE);
-        let heap = Heap::new(0x8000, 0x4000); // 16KB from 0x8000
-        Self {
-            ctx: ExecutionContext::new(),
-            memory,
-            stack,
-            heap,
-            output: Vec::new(),
-            print_immediately: true,
-            instruction_count: 0,
-            instr_freq: std::collections::HashMap::new(),
-        }
+        Self::with_memory_size(DEFAULT_MEMORY_SIZE)
     }
 
     /// Create a new VM with specified memory size
     pub fn with_memory_size(size: usize) -> Self {
-        let memory = Memory::new(size);
-        let stack = Stack::new(size);
-        let heap = Heap::new(0x8000, 0x4000);
+        let layout = MemoryLayout::standard(size);
         Self {
             ctx: ExecutionContext::new(),
-            memory,
-            stack,
-            heap,
+            memory: Memory::new(size),
+            stack: Stack::new(layout.stack_base),
+            heap: Heap::new(layout.heap_start, layout.heap_size),
             output: Vec::new(),
             print_immediately: true,
             instruction_count: 0,
             instr_freq: std::collections::HashMap::new(),
+            cost_model: CostModel::default(),
+            cycles: 0,
+            max_instructions: MAX_INSTRUCTIONS,
+            aslr: Aslr::Disabled,
+            layout,
+            aslr_seed: None,
+            rand_seed: None,
+            rng: Rng::new(0),
+            break_requested: false,
+            observers: Vec::new(),
+            next_observer_id: 0,
+            resources: BTreeMap::new(),
+            host_fns: BTreeMap::new(),
+            trace_writer: None,
+            ops: Vec::new(),
         }
     }
 
-    /// Run a program to completion
-    pub fn run(&mut self, program: &Program) -> VmResult<()> {
+    /// Seed used to randomize the current layout, if ASLR is active.
+    /// Pass it back as `Aslr::Seeded` to reproduce the run.
+    pub fn aslr_seed(&self) -> Option<u64> {
+        self.aslr_seed
+    }
+
+    /// Whether a `breakpoint` instruction ran since the last call.
+    /// Debuggers poll this after each step to pause on software breakpoints.
+    pub fn take_break_request(&mut self) -> bool {
+        std::mem::take(&mut self.break_requested)
+    }
+
+    /// Handle `syscall` with `@r0` equal to `id` by calling `f`, in place
+    /// of any built-in syscall with that id. An error `f` returns stops
+    /// the program. Registrations last across runs.
+    ///
+    /// ```
+    /// use alya_vm::{assembler, Register, VM};
+    ///
+    /// let mut vm = VM::new();
+    /// vm.register_host_fn(100, |ctx, _memory| {
+    ///     ctx.set_reg(Register::R0, ctx.get_reg(Register::R1) * 2);
+    ///     Ok(())
+    /// });
+    /// let program = assembler::assemble("@r1 := 21\n@r0 := 100\nsyscall\nhalt\n", "demo").unwrap();
+    /// vm.run(&program).unwrap();
+    /// assert_eq!(vm.ctx.get_reg(Register::R0), 42);
+    /// ```
+    pub fn register_host_fn<F>(&mut self, id: u64, f: F)
+    where
+        F: FnMut(&mut ExecutionContext, &mut dyn MemoryAccess) -> VmResult<()> + Send + 'static,
+    {
+        self.host_fns.insert(id, Box::new(f));
+    }
+
+    /// Remove the host function registered for `id`. Returns false if
+    /// there was none.
+    pub fn unregister_host_fn(&mut self, id: u64) -> bool {
+        self.host_fns.remove(&id).is_some()
+    }
+
+    /// Log every instruction executed while tracing is on (`trace_on` up
+    /// to `trace_off`) to `writer`: its pc, its disassembly and the
+    /// registers it changed. Replaces any previous writer.
+    pub fn set_trace_writer(&mut self, writer: impl Write + Send + 'static) {
+        self.trace_writer = Some(Box::new(writer));
+    }
+
+    /// Stop logging traced instructions
+    pub fn clear_trace_writer(&mut self) {
+        self.trace_writer = None;
+    }
+
+    /// Run a program to completion, returning its exit status: the value
+    /// `halt @x` stopped with, or 0
+    pub fn run(&mut self, program: &Program) -> VmResult<i32> {
+        self.run_from(program, 0)
+    }
+
+    /// Run a program to completion, starting at instruction `entry`, and
+    /// return its exit status
+    pub fn run_from(&mut self, program: &Program, entry: usize) -> VmResult<i32> {
         self.init(program)?;
+        if entry > program.len() {
+            return Err(VmError::execution(ErrorCode::InvalidPc, format!(
+                "Entry point {} is outside the program ({} instructions)", entry, program.len()
+            )));
+        }
+        self.ctx.pc = entry;
 
-        let mut instruction_count: u64 = 0;
-        while !self.ctx.halted && self.ctx.pc < program.len() {
-            instruction_count += 1;
-            if instruction_count > MAX_INSTRUCTIONS {
-                return Err(VmError::Execution(format!(
-                    "Exceeded maximum instruction count ({}). Possible infinite loop.",
-                    MAX_INSTRUCTIONS
-                )));
-            }
+        match self.run_fuel(program, self.max_instructions)? {
+            RunStatus::Finished => Ok(self.ctx.exit_code),
+            RunStatus::Paused => Err(instruction_limit_exceeded(self.max_instructions)),
+        }
+    }
 
+    /// Execute at most `fuel` instructions of a program `init` set up,
+    /// continuing from wherever the last call stopped. Lets a host share
+    /// its time between programs it does not trust to finish.
+    pub fn run_fuel(&mut self, program: &Program, fuel: u64) -> VmResult<RunStatus> {
+        for _ in 0..fuel {
+            if self.is_finished(program) {
+                break;
+            }
             self.step(program)?;
         }
-        Ok(())
+        Ok(if self.is_finished(program) { RunStatus::Finished } else { RunStatus::Paused })
     }
 
-    /// Initialize VM for a program
+    /// Validate a program and reset the VM to run it from instruction 0:
+    /// fresh memory, registers, heap and stack, and the data section
+    /// loaded. `run` calls this itself; embedders driving execution with
+    /// `step` call it first.
+    ///
+    /// ```
+    /// use alya_vm::assembler;
+    /// use alya_vm::execution::VM;
+    ///
+    /// let program = assembler::assemble("@r0 := 1\n@r0 += 2\nhalt\n", "demo").unwrap();
+    /// let mut vm = VM::new();
+    /// vm.init(&program).unwrap();
+    /// while !vm.is_finished(&program) {
+    ///     vm.step(&program).unwrap();
+    /// }
+    /// assert_eq!(vm.state().registers[0], 3);
+    /// ```
     pub fn init(&mut self, program: &Program) -> VmResult<()> {
+        validate(program)?;
         self.ctx.reset();
+
+        // Choose the address space layout for this run
+        let size = self.memory.size();
+        self.aslr_seed = match self.aslr {
+            Aslr::Disabled => None,
+            Aslr::Random => Some(Rng::entropy_seed()),
+            Aslr::Seeded(seed) => Some(seed),
+        };
+        self.layout = match self.aslr_seed {
+            Some(seed) => MemoryLayout::randomized(size, seed),
+            None => MemoryLayout::standard(size),
+        };
+        self.rng = Rng::new(self.rand_seed.or(self.aslr_seed).unwrap_or_else(Rng::entropy_seed));
+        self.memory.apply_layout(&self.layout);
+        self.heap = Heap::new(self.layout.heap_start, self.layout.heap_size);
+        self.stack = Stack::new(self.layout.stack_base);
         
-        // Load data section into memory (at address 0)
-        self.memory.clear();
+        // Load data section into memory (at address 0).
+        // Only pages touched by the previous run need re-zeroing.
+        self.memory.reset();
         if let Err(e) = self.memory.load_program(&program.data) {
-             return Err(VmError::Execution(format!("Failed to load program data: {}", e)));
+             return Err(VmError::execution(ErrorCode::LoadFailed, format!("Failed to load program data: {}", e)));
         }
+        let writable = program.writable_range();
+        self.memory.set_data_segments(writable.start, writable.end);
+        self.resources = program.resources.clone();
+        self.ops = dispatch::compile_program(program);
 
         // Initialize heap
         if let Err(e) = self.heap.init(&mut self.memory) {
-            return Err(VmError::Execution(format!("Failed to initialize heap: {}", e)));
+            return Err(VmError::execution(ErrorCode::LoadFailed, format!("Failed to initialize heap: {}", e)));
         }
 
         // Initialize HP register
-        self.ctx.set_reg(crate::core::Register::HP, 0x8000);
+        self.ctx.set_reg(crate::core::Register::HP, self.layout.heap_start as u64);
+        // SP mirrors the stack pointer after every push and pop
+        self.ctx.set_reg(crate::core::Register::SP, self.layout.stack_base as u64);
 
         self.output.clear();
+        self.break_requested = false;
         self.instruction_count = 0;
+        self.cycles = 0;
         self.instr_freq.clear();
         Ok(())
     }
 
-    /// Execute a single instruction
+    /// Pick up edits to the instructions of the program `init` loaded,
+    /// keeping the registers, memory and pc
+    pub fn recompile(&mut self, program: &Program) -> VmResult<()> {
+        validate(program)?;
+        self.ops = dispatch::compile_program(program);
+        Ok(())
+    }
+
+    /// Execute the instruction at the pc. Does nothing once `is_finished`.
+    /// Unlike `run`, this does not enforce `max_instructions`.
+    ///
+    /// Runs the instructions `init` compiled, so `program` must be the one
+    /// passed to `init`. After editing its instructions, call `recompile`.
     pub fn step(&mut self, program: &Program) -> VmResult<()> {
-        if self.ctx.halted || self.ctx.pc >= program.len() {
+        if self.is_finished(program) {
             return Ok(());
         }
-
-        let instruction = program.get(self.ctx.pc)
-            .ok_or_else(|| VmError::Execution(format!(
+        let pc = self.ctx.pc;
+        let op = *self.ops.get(pc)
+            .ok_or_else(|| VmError::execution(ErrorCode::InvalidPc, format!(
                 "Invalid program counter: {}",
-                self.ctx.pc
-            )))?
-            .clone();
+                pc
+            )))?;
 
         // Advance PC before execution (jumps may override)
         self.ctx.pc += 1;
         
         // Profiling
         self.instruction_count += 1;
-        let opcode = instruction.opcode().to_u8();
-        *self.instr_freq.entry(opcode).or_insert(0) += 1;
-
-        self.execute_instruction(&instruction)
+        *self.instr_freq.entry(op.opcode.to_u8()).or_insert(0) += 1;
+        self.cycles += self.cost_model.cost(op.opcode);
+
+        let traced = (self.ctx.trace && self.trace_writer.is_some()).then_some(self.ctx.registers);
+        let result = if self.observers.is_empty() {
+            (op.handler)(self, &op)
+        } else {
+            self.execute_observed(pc, op, program.instructions[pc])
+        };
+        if let Some(registers) = traced {
+            self.write_trace(pc, &program.instructions[pc], &registers)?;
+        }
+        // A fault with a registered handler traps to it instead
+        let result = result.or_else(|e| match super::context::trap_vector(e.code()) {
+            Some(vector) => trap::enter_trap(&mut self.ctx, &mut self.stack, &mut self.memory, vector)?
+                .then_some(()).ok_or(e),
+            None => Err(e),
+        });
+        result.and_then(|()| if self.ctx.halted { Ok(()) } else { control::handle_timer(&mut self.ctx) })
+            .map_err(|e| e.with_pc(pc))
     }
 
-    /// Execute a single instruction
-    pub(crate) fn execute_instruction(&mut self, instruction: &Instruction) -> VmResult<()> {
-        match instruction {
-            // Control
-            Instruction::Halt => {
-                self.ctx.halted = true;
-            }
-            Instruction::Nop => {}
-
-            // Data Movement
-            Instruction::LoadImm { dest, value } => {
-                data_move::handle_load_imm(&mut self.ctx, *dest, *value);
-            }
-            Instruction::Move { dest, src } => {
-                data_move::handle_move(&mut self.ctx, *dest, *src);
-            }
-            Instruction::Swap { r1, r2 } => {
-                data_move::handle_swap(&mut self.ctx, *r1, *r2);
-            }
+    /// Cycles executed since `init`, as weighed by `cost_model`
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
 
-            // Arithmetic
-            Instruction::Add { dest, left, right } => {
-                arithmetic::handle_add(&mut self.ctx, *dest, *left, *right);
-            }
-            Instruction::Sub { dest, left, right } => {
-                arithmetic::handle_sub(&mut self.ctx, *dest, *left, *right);
-            }
-            Instruction::Mul { dest, left, right } => {
-                arithmetic::handle_mul(&mut self.ctx, *dest, *left, *right);
-            }
-            Instruction::Div { dest, left, right } => {
-                arithmetic::handle_div(&mut self.ctx, *dest, *left, *right)?;
-            }
-            Instruction::Mod { dest, left, right } => {
-                arithmetic::handle_mod(&mut self.ctx, *dest, *left, *right)?;
-            }
+    /// Whether `program` has halted or run off its end
+    pub fn is_finished(&self, program: &Program) -> bool {
+        self.ctx.halted || self.ctx.pc >= program.len()
+    }
 
-            // Compound Assignment
-            Instruction::AddAssign { dest, src } => {
-                arithmetic::handle_add_assign(&mut self.ctx, *dest, *src);
-            }
-            Instruction::SubAssign { dest, src } => {
-                arithmetic::handle_sub_assign(&mut self.ctx, *dest, *src);
-            }
-            Instruction::MulAssign { dest, src } => {
-                arithmetic::handle_mul_assign(&mut self.ctx, *dest, *src);
-            }
-            Instruction::DivAssign { dest, src } => {
-                arithmetic::handle_div_assign(&mut self.ctx, *dest, *src)?;
-            }
+    /// Copy of the registers, flags, pc and output
+    pub fn state(&self) -> VmSnapshot {
+        VmSnapshot {
+            registers: self.ctx.registers,
+            flags: self.ctx.flags,
+            pc: self.ctx.pc,
+            halted: self.ctx.halted,
+            instruction_count: self.instruction_count,
+            output: self.output.clone(),
+        }
+    }
 
-            // Bitwise
-            Instruction::And { dest, left, right } => {
-                logic::handle_and(&mut self.ctx, *dest, *left, *right);
-            }
-            Instruction::Or { dest, left, right } => {
-                logic::handle_or(&mut self.ctx, *dest, *left, *right);
-            }
-            Instruction::Xor { dest, left, right } => {
-                logic::handle_xor(&mut self.ctx, *dest, *left, *right);
-            }
-            Instruction::Not { dest, src } => {
-                logic::handle_not(&mut self.ctx, *dest, *src);
-            }
-            Instruction::Shl { dest, left, right } => {
-                logic::handle_shl(&mut self.ctx, *dest, *left, *right);
-            }
-            Instruction::Shr { dest, left, right } => {
-                logic::handle_shr(&mut self.ctx, *dest, *left, *right);
-            }
+    /// Executed instruction counts per opcode since `init`, most frequent first
+    pub fn opcode_profile(&self) -> Vec<(Opcode, u64)> {
+        let mut profile: Vec<(Opcode, u64)> = self.instr_freq.iter()
+            .filter_map(|(&code, &count)| Opcode::from_u8(code).ok().map(|op| (op, count)))
+            .collect();
+        profile.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.to_u8().cmp(&b.0.to_u8())));
+        profile
+    }
 
-            // Stack
-            Instruction::Push { src } => {
-                stack_handler::handle_push(&mut self.ctx, &mut self.stack, &mut self.memory, *src)?;
-            }
-            Instruction::Pop { dest } => {
-                stack_handler::handle_pop(&mut self.ctx, &mut self.stack, &self.memory, *dest)?;
-            }
-            Instruction::Peek { dest } => {
-                stack_handler::handle_peek(&mut self.ctx, &self.stack, &self.memory, *dest)?;
-            }
+    /// Register an observer to receive an event for each effect of every instruction
+    pub fn add_observer(&mut self, observer: impl VmObserver + 'static) -> ObserverId {
+        let id = ObserverId(self.next_observer_id);
+        self.next_observer_id += 1;
+        self.observers.push((id, Box::new(observer)));
+        id
+    }
 
-            // Memory
-            Instruction::Load { dest, addr_reg } => {
-                memory_handler::handle_load(&mut self.ctx, &self.memory, *dest, *addr_reg)?;
-            }
-            Instruction::Store { src, addr_reg } => {
-                memory_handler::handle_store(&mut self.ctx, &mut self.memory, *src, *addr_reg)?;
-            }
-            Instruction::LoadIndexed { dest, base_reg, index_reg } => {
-                memory_handler::handle_load_indexed(&mut self.ctx, &self.memory, *dest, *base_reg, *index_reg)?;
-            }
-            Instruction::StoreIndexed { src, base_reg, index_reg } => {
-                memory_handler::handle_store_indexed(&mut self.ctx, &mut self.memory, *src, *base_reg, *index_reg)?;
-            }
+    /// Unregister an observer. Returns false if it was not registered.
+    pub fn remove_observer(&mut self, id: ObserverId) -> bool {
+        let before = self.observers.len();
+        self.observers.retain(|(other, _)| *other != id);
+        self.observers.len() != before
+    }
 
-            // Memory Extensions
-            Instruction::Alloc { dest, size } => {
-                memory_ext::handle_alloc(&mut self.ctx, &self.heap, &mut self.memory, *dest, *size)?;
-            }
-            Instruction::Free { ptr } => {
-                memory_ext::handle_free(&mut self.ctx, &self.heap, &mut self.memory, *ptr)?;
-            }
-            Instruction::MemCopy { dest, src, size } => {
-                memory_ext::handle_memcpy(&mut self.ctx, &mut self.memory, *dest, *src, *size)?;
-            }
-            Instruction::MemSet { dest, value, size } => {
-                memory_ext::handle_memset(&mut self.ctx, &mut self.memory, *dest, *value, *size)?;
-            }
+    fn emit(&mut self, event: VmEvent) {
+        notify(&mut self.observers, event);
+    }
 
-            // Control Flow
-            Instruction::Jump { target } => {
-                control::handle_jump(&mut self.ctx, *target);
-            }
-            Instruction::Compare { left, right } => {
-                control::handle_compare(&mut self.ctx, *left, *right);
-            }
-            Instruction::JumpIfZero { target } => {
-                control::handle_jump_if_zero(&mut self.ctx, *target);
-            }
-            Instruction::JumpIfNotZero { target } => {
-                control::handle_jump_if_not_zero(&mut self.ctx, *target);
-            }
-            Instruction::JumpIfGt { target } => {
-                control::handle_jump_if_gt(&mut self.ctx, *target);
-            }
-            Instruction::JumpIfLt { target } => {
-                control::handle_jump_if_lt(&mut self.ctx, *target);
-            }
-            Instruction::JumpIfGe { target } => {
-                control::handle_jump_if_ge(&mut self.ctx, *target);
-            }
-            Instruction::JumpIfLe { target } => {
-                control::handle_jump_if_le(&mut self.ctx, *target);
-            }
-            Instruction::JumpIfEq { target } => {
-                control::handle_jump_if_eq(&mut self.ctx, *target);
-            }
-            Instruction::JumpIfNe { target } => {
-                control::handle_jump_if_ne(&mut self.ctx, *target);
-            }
-            Instruction::JumpIfAbove { target } => {
-                control::handle_jump_if_above(&mut self.ctx, *target);
-            }
-            Instruction::JumpIfBelow { target } => {
-                control::handle_jump_if_below(&mut self.ctx, *target);
-            }
-            Instruction::JumpIfAe { target } => {
-                control::handle_jump_if_ae(&mut self.ctx, *target);
-            }
-            Instruction::JumpIfBe { target } => {
-                control::handle_jump_if_be(&mut self.ctx, *target);
+    /// Log an instruction that ran at `pc` with the registers before it
+    fn write_trace(&mut self, pc: usize, instruction: &Instruction, before: &[u64]) -> VmResult<()> {
+        let Some(writer) = self.trace_writer.as_mut() else { return Ok(()) };
+        let mut line = format!("{:04x}  {}", pc, instruction.to_assembly());
+        for (code, (&old, &new)) in before.iter().zip(self.ctx.registers.iter()).enumerate() {
+            if old != new {
+                line.push_str(&format!("  {}={:#x}", Register::from_u8(code as u8)?.name(), new));
             }
+        }
+        writeln!(writer, "{}", line)?;
+        Ok(())
+    }
 
-            // Functions
-            Instruction::Call { target } => {
-                control::handle_call(&mut self.ctx, *target)?;
-            }
-            Instruction::Return => {
-                control::handle_return(&mut self.ctx)?;
-            }
+    /// Execute an instruction and report what it changed to the observers
+    fn execute_observed(&mut self, pc: usize, op: Op, instruction: Instruction) -> VmResult<()> {
+        let registers = self.ctx.registers;
+        let output_len = self.output.len();
+        let was_journaling = self.memory.is_journaling();
+        self.memory.enable_journal();
+        let journal_start = self.memory.journal_since(0).len();
+
+        if op.opcode == Opcode::Syscall {
+            let id = self.ctx.get_reg(Register::R0);
+            self.emit(VmEvent::SyscallEntered { id });
+        }
 
-            // Floating Point
-            Instruction::FAdd { dest, left, right } => {
-                float::handle_fadd(&mut self.ctx, *dest, *left, *right);
-            }
-            Instruction::FSub { dest, left, right } => {
-                float::handle_fsub(&mut self.ctx, *dest, *left, *right);
-            }
-            Instruction::FMul { dest, left, right } => {
-                float::handle_fmul(&mut self.ctx, *dest, *left, *right);
-            }
-            Instruction::FDiv { dest, left, right } => {
-                float::handle_fdiv(&mut self.ctx, *dest, *left, *right);
-            }
-            Instruction::FSqrt { dest, src } => {
-                float::handle_fsqrt(&mut self.ctx, *dest, *src);
-            }
-            Instruction::FAbs { dest, src } => {
-                float::handle_fabs(&mut self.ctx, *dest, *src);
-            }
-            Instruction::FNeg { dest, src } => {
-                float::handle_fneg(&mut self.ctx, *dest, *src);
-            }
-            Instruction::F2I { dest, src } => {
-                float::handle_f2i(&mut self.ctx, *dest, *src);
-            }
-            Instruction::I2F { dest, src } => {
-                float::handle_i2f(&mut self.ctx, *dest, *src);
-            }
-            Instruction::FCmp { left, right } => {
-                float::handle_fcmp(&mut self.ctx, *left, *right);
-            }
+        let result = (op.handler)(self, &op);
+        self.emit(VmEvent::InstructionExecuted { pc, instruction });
 
-            // Bitwise Extension
-            Instruction::PopCnt { dest, src } => {
-                bitwise_ext::handle_popcnt(&mut self.ctx, *dest, *src);
-            }
-            Instruction::Clz { dest, src } => {
-                bitwise_ext::handle_clz(&mut self.ctx, *dest, *src);
-            }
-            Instruction::Ctz { dest, src } => {
-                bitwise_ext::handle_ctz(&mut self.ctx, *dest, *src);
-            }
-            Instruction::BSwap { dest, src } => {
-                bitwise_ext::handle_bswap(&mut self.ctx, *dest, *src);
-            }
-            Instruction::RotL { dest, left, right } => {
-                bitwise_ext::handle_rotl(&mut self.ctx, *dest, *left, *right);
-            }
-            Instruction::RotR { dest, left, right } => {
-                bitwise_ext::handle_rotr(&mut self.ctx, *dest, *left, *right);
+        let changed = self.ctx.registers;
+        for (code, (&old, &new)) in registers.iter().zip(changed.iter()).enumerate() {
+            if old != new {
+                let reg = Register::from_u8(code as u8)?;
+                self.emit(VmEvent::RegisterChanged { reg, old, new });
             }
+        }
 
-            // System
-            Instruction::Syscall => {
-                // We need to pass output buffer.
-                // IO handler needs mutable access to output and print flags.
-                // We can't pass &mut self because self.ctx is already borrowed mutably?
-                // `execute_instruction` takes `&mut self`.
-                // But `self.ctx` is borrowed for `handle_syscall`?
-                // But `handle_syscall(ctx, output, flag)` takes separate borrows.
-                // `execute_instruction` has `&mut self`.
-                // `self.ctx` is a field. `self.output` is a field.
-                // Rust borrow checker allows splitting borrows if we access fields directly?
-                // But `execute_instruction` signatures matches on `instruction`.
-                // `instruction` is borrowed from `program`? No, `program` is passed to `run`, but `instruction` is cloned or ref?
-                // In `run`: `let instruction = ... .clone();`
-                // So `instruction` is owned or local ref.
-                
-                // Problem: `handle_xxx(&mut self.ctx, ...)`
-                // If I call `io::handle_syscall(&mut self.ctx, &self.heap, &mut self.memory, &mut self.output, self.print_immediately)`, it should work
-                // because I'm borrowing disjoint fields of `self`.
-                super::handlers::io::handle_syscall(&mut self.ctx, &self.heap, &mut self.memory, &mut self.output, self.print_immediately);
-            }
+        for (addr, len) in events::write_ranges(self.memory.journal_since(journal_start)) {
+            let bytes = self.memory.slice(addr, len).map(<[u8]>::to_vec).unwrap_or_default();
+            self.emit(VmEvent::MemoryWritten { addr, bytes });
+        }
+        if !was_journaling {
+            self.memory.disable_journal();
         }
 
-        Ok(())
+        for line in &self.output[output_len..] {
+            notify(&mut self.observers, VmEvent::Output(line.clone()));
+        }
+        if self.ctx.halted {
+            self.emit(VmEvent::Halted);
+        }
+        result
     }
 
     /// Get collected output
@@ -378,6 +449,13 @@ impl VM {
     }
 }
 
+/// Deliver an event to every observer
+fn notify(observers: &mut [(ObserverId, Box<dyn VmObserver>)], event: VmEvent) {
+    for (_, observer) in observers {
+        observer.on_event(&event);
+    }
+}
+
 impl Default for VM {
     fn default() -> Self {
         Self::new()
@@ -388,6 +466,7 @@ impl Default for VM {
 mod tests {
     use super::*;
     use crate::core::Register;
+    use crate::instruction::{Condition, ProgramBuilder};
 
     fn make_program(instructions: Vec<Instruction>) -> Program {
         Program::from_instructions("test", instructions)
@@ -438,6 +517,195 @@ mod tests {
         assert_eq!(vm.output(), &["30"]);
     }
 
+    #[test]
+    fn test_signed_arithmetic() {
+        // Pass -7 through the stack so the assembler cannot fold the operations
+        let source = "@n := -7\npush @n\n@a := pop\n@b := 2\n@q := @a / @b signed\n@r := @a % @b signed\n\
+            @s := @a >> @b signed\n@u := @a >> @b\nprint @q\nprint @r\nprint @s\nprint @u\nhalt\n";
+        let program = crate::assembler::assemble(source, "t").unwrap();
+        let mut vm = VM::new();
+        vm.print_immediately = false;
+        vm.run(&program).unwrap();
+        let expected = [-3i64 as u64, -1i64 as u64, -2i64 as u64, (-7i64 as u64) >> 2];
+        assert_eq!(vm.output(), expected.map(|value| value.to_string()));
+    }
+
+    #[test]
+    fn test_mulhi() {
+        let source = "@a := 0xffffffffffffffff\n@b := 3\nmulhi @h @a @b\n@l := @a * @b\nprint @h\nprint @l\nhalt\n";
+        let program = crate::assembler::assemble(source, "t").unwrap();
+        let mut vm = VM::new();
+        vm.print_immediately = false;
+        vm.run(&program).unwrap();
+        assert_eq!(vm.output(), ["2", &(u64::MAX - 2).to_string()]);
+
+        let mut instrs = vec![
+            Instruction::LoadImm { dest: Register::R0, value: 1 << 40 },
+            Instruction::LoadImm { dest: Register::R1, value: 1 << 30 },
+            Instruction::MulHi { dest: Register::R2, left: Register::R0, right: Register::R1 },
+        ];
+        instrs.extend(emit_print(Register::R2));
+        instrs.push(Instruction::Halt);
+        let mut vm = VM::new();
+        vm.print_immediately = false;
+        vm.run(&make_program(instrs)).unwrap();
+        assert_eq!(vm.output(), ["64"]);
+    }
+
+    #[test]
+    fn test_switch() {
+        let source = "@i := 0\ntop:\nswitch @i goto zero, one, two\n@v := 9\ngoto next\nzero:\n@v := 10\ngoto next\n\
+            one:\n@v := 11\ngoto next\ntwo:\n@v := 12\nnext:\nprint @v\n@i += 1\nif @i < 4 goto top\nhalt\n";
+        let program = crate::assembler::assemble(source, "t").unwrap();
+        let mut vm = VM::new();
+        vm.print_immediately = false;
+        vm.run(&program).unwrap();
+        assert_eq!(vm.output(), ["10", "11", "12", "9"]);
+
+        // The jump table reads back as a single switch statement
+        let listing = crate::instruction::disassembler::to_source(&program);
+        assert!(listing.contains("goto zero, one, two"), "{}", listing);
+        let mut vm = VM::new();
+        vm.print_immediately = false;
+        vm.run(&crate::assembler::assemble(&listing, "t").unwrap()).unwrap();
+        assert_eq!(vm.output(), ["10", "11", "12", "9"]);
+    }
+
+    #[test]
+    fn test_call_reg() {
+        let source = "@f := &inc\n@r1 := 5\ncall @f\nprint @r1\n@f := &dbl\ncall @f\nprint @r1\nhalt\n\
+            inc:\n@r1 += 1\nreturn\ndbl:\n@r1 += @r1\nreturn\n";
+        let program = crate::assembler::assemble(source, "t").unwrap();
+        let mut vm = VM::new();
+        vm.print_immediately = false;
+        vm.run(&program).unwrap();
+        assert_eq!(vm.output(), ["6", "12"]);
+
+        let stray = crate::assembler::assemble("@r5 := 9999\ncall @r5\nhalt\n", "t").unwrap();
+        let err = vm.run(&stray).unwrap_err();
+        assert_eq!((err.code(), err.pc()), (ErrorCode::InvalidPc, Some(1)));
+
+        // A handler for vector 2 catches it like any invalid instruction
+        let trapped = crate::assembler::assemble("@r1 := 2\n@r2 := &on_fault\n@r0 := 9\nsyscall\n\
+            @r5 := 9999\ncall @r5\nhalt\non_fault:\n@r6 := 1\nprint @r6\nhalt\n", "t").unwrap();
+        vm.run(&trapped).unwrap();
+        assert_eq!(vm.output(), ["1"]);
+    }
+
+    #[test]
+    fn test_enter_leave() {
+        // The local at BP - 8 survives the callee clobbering R1
+        let source = "@r1 := 7\ncall f\nprint @r0\nprint @bp\nhalt\n\
+            f:\nenter 12\n@a := @bp - 8\nstore @r1 at @a\n@r1 := 100\n@r0 := load @a\nleave\nreturn\n";
+        let program = crate::assembler::assemble(source, "t").unwrap();
+        crate::instruction::validate::validate(&program).unwrap();
+        let mut vm = VM::new();
+        vm.print_immediately = false;
+        let base = vm.stack.pointer();
+        vm.run(&program).unwrap();
+        assert_eq!(vm.output(), ["7", "0"]);
+        assert_eq!(vm.stack.pointer(), base);
+    }
+
+    #[test]
+    fn test_load_store_local() {
+        // The saved BP is at BP + 0; the two locals sit below it
+        let source = "@a := 5\n@b := 9\ncall f\nprint @r0\nhalt\n\
+            f:\nenter 16\nlocal -8 := @a\nlocal -16 := @b\n@a := 0\n@b := local -8\n@c := local -16\n@r0 := @b * @c\nleave\nreturn\n";
+        let program = crate::assembler::assemble(source, "t").unwrap();
+        let mut vm = VM::new();
+        vm.print_immediately = false;
+        vm.run(&program).unwrap();
+        assert_eq!(vm.output(), ["45"]);
+        assert_eq!(program.instructions.iter().filter(|i| matches!(i, Instruction::StoreLocal { offset: -16, .. })).count(), 1);
+
+        let err = crate::assembler::assemble("@a := local 0x100000000\n", "t").unwrap_err();
+        assert!(err.to_string().contains("32-bit signed offset"), "{}", err);
+    }
+
+    #[test]
+    fn test_test_flags() {
+        let source = "@a := 6\n@m := 1\ntest @a @m\njz clear\nprint @m\nclear:\n@m := 4\ntest @a @m\njnz set\nhalt\nset:\nprint @a\n\
+            @n := 0x8000000000000000\ntest @n @n\njlt negative\nhalt\nnegative:\nprint @m\nhalt\n";
+        let program = crate::assembler::assemble(source, "t").unwrap();
+        let mut vm = VM::new();
+        vm.print_immediately = false;
+        vm.run(&program).unwrap();
+        assert_eq!(vm.output(), ["6", "4"]);
+    }
+
+    #[test]
+    fn test_f32_operations() {
+        let source = "@a := 1\n@b := 3\npush @a\npush @b\n@b := pop\n@a := pop\ni2f @a @a\ni2f @b @b\nfdiv @q @a @b\n\
+            fdemote @x @a\nfdemote @y @b\nfdiv.f32 @z @x @y\nfpromote @w @z\nfcmp @q @w\njz wide\nprint @a\nwide:\n\
+            fdemote @q @q\nfcmp.f32 @q @z\njnz narrow\nprint @b\nnarrow:\nhalt\n";
+        let program = crate::assembler::assemble(source, "t").unwrap();
+        let mut vm = VM::new();
+        vm.print_immediately = false;
+        vm.run(&program).unwrap();
+        assert_eq!(vm.output().len(), 2);
+
+        let err = crate::assembler::assemble("fdiv.f16 @a @b @c\n", "t").unwrap_err();
+        assert!(err.to_string().contains("Unknown float width '.f16'"), "{}", err);
+    }
+
+    #[test]
+    fn test_float_rounding() {
+        let source = "@n := 5\n@d := 2\npush @n\n@n := pop\ni2f @n @n\ni2f @d @d\nfdiv @x @n @d\n\
+            ffloor @r @x\nf2i @r @r\nprint @r\nfceil @r @x\nf2i @r @r\nprint @r\n\
+            ftrunc @r @x\nf2i @r @r\nprint @r\nfround @r @x\nf2i @r @r\nprint @r\n\
+            fadd @x @x @d\nfround @r @x\nf2i @r @r\nprint @r\nhalt\n";
+        let program = crate::assembler::assemble(source, "t").unwrap();
+        let mut vm = VM::new();
+        vm.print_immediately = false;
+        vm.run(&program).unwrap();
+        assert_eq!(vm.output(), ["2", "3", "2", "2", "4"]);
+    }
+
+    #[test]
+    fn test_float_min_max_class() {
+        let source = "@a := 1\n@z := 0\npush @a\n@a := pop\ni2f @a @a\ni2f @z @z\n\
+            fneg @n @a\nfclass @c @n\nprint @c\nfdiv @i @a @z\nfclass @c @i\nprint @c\n\
+            fdiv @q @z @z\nfabs @q @q\nfclass @c @q\nprint @c\nfclass @c @z\nprint @c\n\
+            fmin @m @q @n\nfmax @m @m @z\nf2i @m @m\nprint @m\nfmax @m @n @i\nfcmp @m @i\njnz done\nprint @z\ndone:\nhalt\n";
+        let program = crate::assembler::assemble(source, "t").unwrap();
+        let mut vm = VM::new();
+        vm.print_immediately = false;
+        vm.run(&program).unwrap();
+        assert_eq!(vm.output(), ["48", "2", "1", "4", "0", "0"]);
+
+        let err = crate::assembler::assemble("fmin.f32 @a @b @c\n", "t").unwrap_err();
+        assert!(err.to_string().contains("'.f32' only applies to"), "{}", err);
+    }
+
+    #[test]
+    fn test_rand_is_reproducible() {
+        let program = crate::assembler::assemble("@a := rand\n@b := rand\nprint @a\nprint @b\nhalt\n", "t").unwrap();
+        let run = |seed| {
+            let mut vm = VM::new();
+            vm.print_immediately = false;
+            vm.rand_seed = Some(seed);
+            vm.run(&program).unwrap();
+            vm.output().to_vec()
+        };
+        let first = run(7);
+        assert_eq!(first, run(7));
+        assert_ne!(first[0], first[1]);
+        assert_ne!(first, run(8));
+    }
+
+    #[test]
+    fn test_feat_query() {
+        let program = crate::assembler::assemble("@f := featquery 0\n@g := featquery 9\nprint @f\nprint @g\nhalt\n", "t").unwrap();
+        let mut vm = VM::new();
+        vm.print_immediately = false;
+        vm.run(&program).unwrap();
+        assert_eq!(vm.output(), [crate::core::features::SUPPORTED_FEATURES.to_string(), "0".to_string()]);
+
+        let err = crate::assembler::assemble("@f := featquery 256\n", "t").unwrap_err();
+        assert!(err.to_string().contains("feature leaf"), "{}", err);
+    }
+
     #[test]
     fn test_stack_operations() {
         let mut instrs = vec![
@@ -476,36 +744,21 @@ mod tests {
         assert_eq!(vm.output(), &["42"]);
     }
 
-    // Need to adjust targets for manual syscall expansion (3 instrs vs 1)
     #[test]
     fn test_conditional_jump() {
-        // Old target 5 -> New target will be shifted
-        // 0: LoadImm 5
-        // 1: LoadImm 10
-        // 2: Compare
-        // 3: JumpIfLt -> Target ?
-        // 4: LoadImm 0
-        // 5: LoadImm 1
-        // 6: Print (3 instrs)
-        // 9: Halt
-        
-        // Target should be 5.
-        
-        let instructions = vec![
-            Instruction::LoadImm { dest: Register::R0, value: 5 },
-            Instruction::LoadImm { dest: Register::R1, value: 10 },
-            Instruction::Compare { left: Register::R0, right: Register::R1 },
-            Instruction::JumpIfLt { target: 5 },    // 3: r0 < r1, should jump
-            Instruction::LoadImm { dest: Register::R2, value: 0 }, // 4: skipped
-            Instruction::LoadImm { dest: Register::R2, value: 1 }, // 5: r0 < r1 is true
-            // Print R2
-            Instruction::Move { dest: Register::R1, src: Register::R2 }, // 6
-            Instruction::LoadImm { dest: Register::R0, value: 1 }, // 7
-            Instruction::Syscall, // 8
-            Instruction::Halt, // 9
-        ];
+        let program = ProgramBuilder::new("test")
+            .load_imm(Register::R0, 5)
+            .load_imm(Register::R1, 10)
+            .compare(Register::R0, Register::R1)
+            .jump_if(Condition::Lt, "less") // r0 < r1, should jump
+            .load_imm(Register::R2, 0)      // skipped
+            .label("less")
+            .load_imm(Register::R2, 1)
+            .print(Register::R2)
+            .halt()
+            .build()
+            .unwrap();
 
-        let program = make_program(instructions);
         let mut vm = VM::new();
         vm.print_immediately = false;
         vm.run(&program).unwrap();
@@ -515,41 +768,67 @@ mod tests {
 
     #[test]
     fn test_call_return() {
-         // 0: Jump to main (Target ?)
-         // 1: function: add 10 to R0
-         // 2: Add
-         // 3: Return
-         // Main:
-         // 4?
-         
-         // 0: Jump { target: 4 }
-         // 1: LoadImm R1, 10
-         // 2: Add R0, R0, R1
-         // 3: Return
-         // 4: LoadImm R0, 5
-         // 5: Call { target: 1 }
-         // 6: Print R0 -> Move(6), Load(7), Syscall(8)
-         // 9: Halt
-         
-        let instructions = vec![
-            Instruction::Jump { target: 4 },
-            Instruction::LoadImm { dest: Register::R1, value: 10 },
-            Instruction::Add { dest: Register::R0, left: Register::R0, right: Register::R1 },
-            Instruction::Return,
-            Instruction::LoadImm { dest: Register::R0, value: 5 },
-            Instruction::Call { target: 1 },
-            Instruction::Move { dest: Register::R1, src: Register::R0 },
+        let program = ProgramBuilder::new("test")
+            .jump_to("main")
+            .label("add_ten")
+            .load_imm(Register::R1, 10)
+            .add(Register::R0, Register::R0, Register::R1)
+            .ret()
+            .label("main")
+            .load_imm(Register::R0, 5)
+            .call("add_ten")
+            .print(Register::R0)
+            .halt()
+            .build()
+            .unwrap();
+
+        let mut vm = VM::new();
+        vm.print_immediately = false;
+        vm.run(&program).unwrap();
+
+        assert_eq!(vm.output(), &["15"]);
+    }
+
+    #[test]
+    fn test_error_reports_code_and_pc() {
+        let program = ProgramBuilder::new("test")
+            .load_imm(Register::R0, 1)
+            .ret()
+            .build()
+            .unwrap();
+
+        let err = VM::new().run(&program).unwrap_err();
+        assert_eq!(err.code(), ErrorCode::ReturnWithoutCall);
+        assert_eq!(err.pc(), Some(1));
+    }
+
+    #[test]
+    fn test_observer_events() {
+        use std::sync::{Arc, Mutex};
+        let program = make_program(vec![
+            Instruction::LoadImm { dest: Register::R2, value: 7 },
+            Instruction::Push { src: Register::R2 },
             Instruction::LoadImm { dest: Register::R0, value: 1 },
+            Instruction::Move { dest: Register::R1, src: Register::R2 },
             Instruction::Syscall,
             Instruction::Halt,
-        ];
-
-        let program = make_program(instructions);
+        ]);
+        let events = Arc::new(Mutex::new(Vec::new()));
         let mut vm = VM::new();
         vm.print_immediately = false;
+        let sink = events.clone();
+        let id = vm.add_observer(move |e: &VmEvent| sink.lock().unwrap().push(e.clone()));
         vm.run(&program).unwrap();
 
-        assert_eq!(vm.output(), &["15"]);
+        let events = events.lock().unwrap();
+        assert_eq!(events[0], VmEvent::InstructionExecuted { pc: 0, instruction: program.instructions[0] });
+        assert_eq!(events[1], VmEvent::RegisterChanged { reg: Register::R2, old: 0, new: 7 });
+        assert!(events.contains(&VmEvent::MemoryWritten { addr: vm.stack.pointer(), bytes: 7u64.to_le_bytes().to_vec() }));
+        assert!(events.contains(&VmEvent::SyscallEntered { id: 1 }));
+        assert!(events.contains(&VmEvent::Output("7".to_string())));
+        assert_eq!(events.last(), Some(&VmEvent::Halted));
+        assert!(vm.remove_observer(id));
+        assert!(!vm.remove_observer(id));
     }
 
     #[test]
@@ -573,4 +852,328 @@ mod tests {
 
         assert_eq!(vm.output(), &["42"]);
     }
+
+    #[test]
+    fn test_sized_memory_access() {
+        let source = ".data\nbuf: .qword -1\n.rodata\n@p := buf\n@v := 0x1234abcd\nstore.word @v at @p\n\
+            @b := load.byte @p\n@w := load.dword @p\n@q := load @p\nprint @b\nprint @w\nprint @q\nhalt\n";
+        let program = crate::assembler::assemble(source, "t").unwrap();
+        let mut vm = VM::new();
+        vm.print_immediately = false;
+        vm.run(&program).unwrap();
+        assert_eq!(vm.output(), ["205", &0xffffabcdu64.to_string(), &0xffffffffffffabcdu64.to_string()]);
+
+        let err = crate::assembler::assemble("@p := 0\n@v := load.half @p\n", "t").unwrap_err();
+        assert!(err.to_string().contains("Unknown access size '.half'"), "{}", err);
+    }
+
+    #[test]
+    fn test_aslr_seeded_layout_is_reproducible() {
+        let program = make_program(vec![Instruction::Halt]);
+
+        let mut vm = VM::new();
+        vm.aslr = Aslr::Seeded(0xC0FFEE);
+        vm.run(&program).unwrap();
+        let first = vm.layout;
+
+        vm.run(&program).unwrap();
+        assert_eq!(vm.layout, first);
+        assert_eq!(vm.aslr_seed(), Some(0xC0FFEE));
+        assert_eq!(vm.ctx.get_reg(Register::HP), first.heap_start as u64);
+    }
+
+    #[test]
+    fn test_aslr_hardcoded_heap_address_faults() {
+        let instructions = vec![
+            Instruction::LoadImm { dest: Register::R0, value: 0x8000 },
+            Instruction::LoadImm { dest: Register::R1, value: 42 },
+            Instruction::Store { src: Register::R1, addr_reg: Register::R0 },
+            Instruction::Halt,
+        ];
+
+        let program = make_program(instructions);
+        let mut vm = VM::new();
+        vm.print_immediately = false;
+        vm.aslr = Aslr::Seeded(1);
+        assert!(matches!(vm.run(&program), Err(VmError::Memory(_))));
+    }
+
+    #[test]
+    fn test_aslr_heap_alloc_still_works() {
+        let instructions = vec![
+            Instruction::LoadImm { dest: Register::R1, value: 16 },
+            Instruction::Alloc { dest: Register::R2, size: Register::R1 },
+            Instruction::Store { src: Register::R1, addr_reg: Register::R2 },
+            Instruction::Push { src: Register::R1 },
+            Instruction::Pop { dest: Register::R3 },
+            Instruction::Halt,
+        ];
+
+        let program = make_program(instructions);
+        let mut vm = VM::new();
+        vm.aslr = Aslr::Random;
+        vm.run(&program).unwrap();
+        assert!(vm.aslr_seed().is_some());
+        assert!(vm.ctx.get_reg(Register::R2) as usize > vm.layout.heap_start);
+        assert_eq!(vm.ctx.get_reg(Register::R3), 16);
+    }
+
+    #[test]
+    fn test_reused_vm_does_not_leak_memory_between_programs() {
+        let writer = make_program(vec![
+            Instruction::LoadImm { dest: Register::R0, value: 0x9000 },
+            Instruction::LoadImm { dest: Register::R1, value: 77 },
+            Instruction::Store { src: Register::R1, addr_reg: Register::R0 },
+            Instruction::Halt,
+        ]);
+        let reader = make_program(vec![
+            Instruction::LoadImm { dest: Register::R0, value: 0x9000 },
+            Instruction::Load { dest: Register::R1, addr_reg: Register::R0 },
+            Instruction::Halt,
+        ]);
+
+        let mut pool = crate::execution::VmPool::new(DEFAULT_MEMORY_SIZE);
+        let mut vm = pool.acquire();
+        vm.run(&writer).unwrap();
+        pool.release(vm);
+        assert_eq!(pool.idle_count(), 1);
+
+        let mut vm = pool.acquire();
+        vm.run(&reader).unwrap();
+        assert_eq!(vm.ctx.get_reg(Register::R1), 0);
+    }
+
+    #[test]
+    fn test_run_from_entry_with_instruction_limit() {
+        let program = make_program(vec![
+            Instruction::LoadImm { dest: Register::R0, value: 1 },
+            Instruction::Halt,
+            Instruction::LoadImm { dest: Register::R0, value: 2 },
+            Instruction::Jump { target: 2 },
+        ]);
+        let mut vm = VM::new();
+        vm.run(&program).unwrap();
+        assert_eq!(vm.ctx.get_reg(Register::R0), 1);
+
+        vm.max_instructions = 5;
+        let err = vm.run_from(&program, 2).unwrap_err();
+        assert_eq!(err.code(), ErrorCode::InstructionLimit);
+        assert_eq!(vm.ctx.get_reg(Register::R0), 2);
+        assert_eq!(vm.run_from(&program, 9).unwrap_err().code(), ErrorCode::InvalidPc);
+    }
+
+    #[test]
+    fn test_init_step_state() {
+        let mut instrs = vec![Instruction::LoadImm { dest: Register::R0, value: 6 }];
+        instrs.extend(emit_print(Register::R0));
+        instrs.push(Instruction::Halt);
+        let program = make_program(instrs);
+
+        let mut vm = VM::new();
+        vm.print_immediately = false;
+        vm.init(&program).unwrap();
+        assert_eq!((vm.state().pc, vm.is_finished(&program)), (0, false));
+        vm.step(&program).unwrap();
+        let state = vm.state();
+        assert_eq!((state.pc, state.registers[0], state.instruction_count), (1, 6, 1));
+
+        while !vm.is_finished(&program) {
+            vm.step(&program).unwrap();
+        }
+        let state = vm.state();
+        assert!(state.halted);
+        assert_eq!((state.pc, state.output), (program.len(), vec!["6".to_string()]));
+        vm.step(&program).unwrap();
+        assert_eq!(vm.state().instruction_count, 5);
+
+        vm.init(&program).unwrap();
+        let state = vm.state();
+        assert_eq!((state.pc, state.halted, state.instruction_count, state.output.len()), (0, false, 0, 0));
+    }
+
+    #[test]
+    fn test_recompile_after_edit() {
+        let mut program = make_program(vec![
+            Instruction::LoadImm { dest: Register::R0, value: 1 },
+            Instruction::LoadImm { dest: Register::R1, value: 1 },
+            Instruction::Halt,
+        ]);
+        let mut vm = VM::new();
+        vm.init(&program).unwrap();
+        vm.step(&program).unwrap();
+
+        // An edit in place takes effect once recompiled, keeping the state
+        program.instructions[1] = Instruction::LoadImm { dest: Register::R1, value: 2 };
+        vm.recompile(&program).unwrap();
+        vm.step(&program).unwrap();
+        assert_eq!((vm.ctx.get_reg(Register::R0), vm.ctx.get_reg(Register::R1)), (1, 2));
+    }
+
+    #[test]
+    fn test_exit_code() {
+        let program = crate::assembler::assemble("@a := 0\npush @a\n@b := pop\nif @b == 0 goto fail\nhalt\nfail:\n@c := 7\nhalt @c\n", "exit").unwrap();
+        let mut vm = VM::new();
+        assert_eq!(vm.run(&program).unwrap(), 7);
+        let program = make_program(vec![Instruction::Halt]);
+        assert_eq!(vm.run(&program).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_trace_writer() {
+        #[derive(Clone, Default)]
+        struct Buffer(std::sync::Arc<std::sync::Mutex<Vec<u8>>>);
+        impl Write for Buffer {
+            fn write(&mut self, bytes: &[u8]) -> std::io::Result<usize> {
+                self.0.lock().unwrap().write(bytes)
+            }
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let program = make_program(vec![
+            Instruction::LoadImm { dest: Register::R0, value: 1 },
+            Instruction::TraceOn,
+            Instruction::LoadImm { dest: Register::R1, value: 2 },
+            Instruction::Nop,
+            Instruction::TraceOff,
+            Instruction::LoadImm { dest: Register::R2, value: 3 },
+            Instruction::Halt,
+        ]);
+        let buffer = Buffer::default();
+        let mut vm = VM::new();
+        vm.set_trace_writer(buffer.clone());
+        vm.run(&program).unwrap();
+        let trace = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        assert_eq!(trace.lines().collect::<Vec<_>>(), [
+            "0002  loadimm r1, 0x2  r1=0x2",
+            "0003  nop",
+            "0004  trace_off",
+        ]);
+    }
+
+    #[test]
+    fn test_run_fuel_pauses_and_resumes() {
+        let program = make_program(vec![
+            Instruction::LoadImm { dest: Register::R1, value: 1 },
+            Instruction::AddAssign { dest: Register::R0, src: Register::R1 },
+            Instruction::AddAssign { dest: Register::R0, src: Register::R1 },
+            Instruction::AddAssign { dest: Register::R0, src: Register::R1 },
+            Instruction::Halt,
+        ]);
+        let mut vm = VM::new();
+        vm.init(&program).unwrap();
+        assert_eq!(vm.run_fuel(&program, 2).unwrap(), RunStatus::Paused);
+        assert_eq!(vm.ctx.get_reg(Register::R0), 1);
+        assert_eq!(vm.run_fuel(&program, 0).unwrap(), RunStatus::Paused);
+        assert_eq!(vm.run_fuel(&program, 2).unwrap(), RunStatus::Paused);
+        assert_eq!(vm.run_fuel(&program, 100).unwrap(), RunStatus::Finished);
+        assert_eq!((vm.ctx.get_reg(Register::R0), vm.instruction_count), (3, 5));
+        assert_eq!(vm.run_fuel(&program, 1).unwrap(), RunStatus::Finished);
+    }
+
+    #[test]
+    fn test_host_fn_syscall() {
+        let mut program = make_program(vec![
+            Instruction::LoadImm { dest: Register::R1, value: 0 },
+            Instruction::LoadImm { dest: Register::R0, value: 2 },
+            Instruction::Syscall,
+            Instruction::LoadImm { dest: Register::R0, value: 0x200 },
+            Instruction::Syscall,
+            Instruction::Halt,
+        ]);
+        program.data = b"abc\0".to_vec();
+        let mut vm = VM::new();
+        vm.print_immediately = false;
+        // Replaces the built-in print string: returns its length instead
+        vm.register_host_fn(2, |ctx, memory| {
+            let addr = ctx.get_reg(Register::R1) as usize;
+            let len = (addr..).take_while(|&a| memory.read_byte(a).is_ok_and(|b| b != 0)).count();
+            ctx.set_reg(Register::R1, len as u64);
+            Ok(())
+        });
+        vm.register_host_fn(0x200, |_, _| Err(VmError::execution(ErrorCode::Io, "denied")));
+        let err = vm.run(&program).unwrap_err();
+        assert_eq!((err.code(), err.pc()), (ErrorCode::Io, Some(4)));
+        assert!(vm.output().is_empty());
+        assert_eq!(vm.ctx.get_reg(Register::R1), 3);
+
+        assert!(vm.unregister_host_fn(0x200));
+        assert!(!vm.unregister_host_fn(0x200));
+        vm.run(&program).unwrap();
+    }
+
+    #[test]
+    fn test_timer_interrupt_preserves_flags() {
+        let program = make_program(vec![
+            Instruction::LoadImm { dest: Register::R1, value: 11 },
+            Instruction::LoadImm { dest: Register::R2, value: 2 },
+            Instruction::LoadImm { dest: Register::R0, value: 8 },
+            Instruction::Syscall,
+            Instruction::LoadImm { dest: Register::R5, value: 1 },
+            Instruction::LoadImm { dest: Register::R6, value: 50 },
+            Instruction::AddAssign { dest: Register::R3, src: Register::R5 },
+            Instruction::Compare { left: Register::R3, right: Register::R6 },
+            Instruction::JumpIfLt { target: 6 },
+            Instruction::Halt,
+            Instruction::Nop,
+            // Handler: counts interrupts and leaves the flags at "greater"
+            Instruction::AddAssign { dest: Register::R4, src: Register::R5 },
+            Instruction::Compare { left: Register::R6, right: Register::R5 },
+            Instruction::Return,
+        ]);
+        let mut vm = VM::new();
+        vm.run(&program).unwrap();
+        assert_eq!(vm.ctx.get_reg(Register::R3), 50);
+        // The arming syscall, 2 loads and 150 loop instructions count
+        // toward the timer: one interrupt per two
+        assert_eq!(vm.ctx.get_reg(Register::R4), 76);
+        assert!(vm.ctx.timer.is_some());
+
+        vm.init(&program).unwrap();
+        assert_eq!((vm.ctx.timer, vm.ctx.interrupt), (None, None));
+    }
+
+    #[test]
+    fn test_traps_and_software_interrupts() {
+        let program = crate::assembler::assemble("\
+@r1 := 0
+@r2 := &on_divide
+@r0 := 9
+syscall
+@r1 := 40
+@r2 := &on_int
+syscall
+@a := 1
+@b := 0
+compare @a @a
+@c := @a / @b
+jne bad
+int 40
+jne bad
+print @r5
+halt
+bad:
+halt
+on_divide:
+@r5 := 7
+compare @a @b
+iret
+on_int:
+@r5 += @r5
+compare @a @b
+iret
+", "t").unwrap();
+        let mut vm = VM::new();
+        vm.print_immediately = false;
+        vm.run(&program).unwrap();
+        assert_eq!(vm.output(), ["14"]);
+        assert_eq!(vm.ctx.get_reg(Register::SP), vm.layout.stack_base as u64);
+
+        let unhandled = make_program(vec![Instruction::Int { vector: 3 }]);
+        let err = vm.run(&unhandled).unwrap_err();
+        assert_eq!((err.code(), err.pc()), (ErrorCode::UnhandledInterrupt, Some(0)));
+        let divide = make_program(vec![Instruction::Div { dest: Register::R0, left: Register::R0, right: Register::R1 }]);
+        assert_eq!(vm.run(&divide).unwrap_err().code(), ErrorCode::DivisionByZero);
+    }
 }
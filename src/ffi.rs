@@ -0,0 +1,196 @@
+//! C ABI for embedding the VM (enabled with the `ffi` feature).
+//!
+//! A C program owns an opaque `AlyaVm` handle, assembles source into it and
+//! runs it. Functions returning `c_int` give `ALYA_OK` or `ALYA_ERROR`; after
+//! an error, `alya_vm_last_error` describes it. Build a C library with
+//! `cargo rustc --release --features ffi --crate-type cdylib` (or `staticlib`).
+//!
+//! ```c
+//! AlyaVm *vm = alya_vm_new();
+//! if (alya_assemble(vm, "@r0 := 42\nhalt\n") == ALYA_OK && alya_vm_run(vm) == ALYA_OK)
+//!     printf("%llu\n", alya_vm_get_register(vm, 0));
+//! alya_vm_free(vm);
+//! ```
+
+use std::ffi::{c_char, c_int, CStr, CString};
+use std::ptr;
+use crate::assembler;
+use crate::core::Register;
+use crate::error::{ErrorCode, VmError};
+use crate::execution::VM;
+use crate::instruction::Program;
+
+/// Returned by fallible calls on success
+pub const ALYA_OK: c_int = 0;
+/// Returned by fallible calls on failure
+pub const ALYA_ERROR: c_int = -1;
+
+/// VM handle owned by C code
+pub struct AlyaVm {
+    vm: VM,
+    program: Option<Program>,
+    last_error: Option<CString>,
+}
+
+impl AlyaVm {
+    /// Record `result` as the last error and turn it into a status code
+    fn status(&mut self, result: Result<(), VmError>) -> c_int {
+        match result {
+            Ok(()) => {
+                self.last_error = None;
+                ALYA_OK
+            }
+            Err(e) => {
+                self.last_error = CString::new(e.to_string().replace('\0', " ")).ok();
+                ALYA_ERROR
+            }
+        }
+    }
+}
+
+/// Create a VM. Program output is captured rather than printed.
+#[no_mangle]
+pub extern "C" fn alya_vm_new() -> *mut AlyaVm {
+    let mut vm = VM::new();
+    vm.print_immediately = false;
+    Box::into_raw(Box::new(AlyaVm { vm, program: None, last_error: None }))
+}
+
+/// Destroy a VM created by `alya_vm_new`.
+///
+/// # Safety
+/// `vm` must be null or a handle from `alya_vm_new` that is not used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn alya_vm_free(vm: *mut AlyaVm) {
+    if !vm.is_null() {
+        drop(Box::from_raw(vm));
+    }
+}
+
+/// Assemble null-terminated `.alya` source and load it as the VM's program.
+///
+/// # Safety
+/// `vm` must be a live handle and `source` a valid null-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn alya_assemble(vm: *mut AlyaVm, source: *const c_char) -> c_int {
+    let Some(handle) = vm.as_mut() else { return ALYA_ERROR };
+    if source.is_null() {
+        return handle.status(Err(VmError::assembler(ErrorCode::InvalidSource, "Source is null")));
+    }
+    let result = CStr::from_ptr(source).to_str()
+        .map_err(|e| VmError::assembler(ErrorCode::InvalidSource, format!("Source is not UTF-8: {}", e)))
+        .and_then(|text| assembler::assemble(text, "ffi"))
+        .map(|program| handle.program = Some(program));
+    handle.status(result)
+}
+
+/// Run the loaded program from the start. Registers are reset first.
+///
+/// # Safety
+/// `vm` must be a live handle.
+#[no_mangle]
+pub unsafe extern "C" fn alya_vm_run(vm: *mut AlyaVm) -> c_int {
+    let Some(handle) = vm.as_mut() else { return ALYA_ERROR };
+    let result = match &handle.program {
+        Some(program) => {
+            handle.vm.output.clear();
+            handle.vm.run(program).map(drop)
+        }
+        None => Err(VmError::execution(ErrorCode::NoProgram, "No program loaded")),
+    };
+    handle.status(result)
+}
+
+/// Read a register by its code (0–15 are r0–r15). Invalid codes read as 0.
+///
+/// # Safety
+/// `vm` must be a live handle.
+#[no_mangle]
+pub unsafe extern "C" fn alya_vm_get_register(vm: *const AlyaVm, reg: u8) -> u64 {
+    match (vm.as_ref(), Register::from_u8(reg)) {
+        (Some(handle), Ok(reg)) => handle.vm.ctx.get_reg(reg),
+        _ => 0,
+    }
+}
+
+/// Exit status of the last run: the value `halt @x` stopped with, or 0
+///
+/// # Safety
+/// `vm` must be a live handle.
+#[no_mangle]
+pub unsafe extern "C" fn alya_vm_exit_code(vm: *const AlyaVm) -> c_int {
+    vm.as_ref().map_or(0, |handle| handle.vm.ctx.exit_code)
+}
+
+/// Write a register by its code.
+///
+/// # Safety
+/// `vm` must be a live handle.
+#[no_mangle]
+pub unsafe extern "C" fn alya_vm_set_register(vm: *mut AlyaVm, reg: u8, value: u64) -> c_int {
+    let Some(handle) = vm.as_mut() else { return ALYA_ERROR };
+    let result = Register::from_u8(reg)
+        .map(|reg| handle.vm.ctx.set_reg(reg, value))
+        .map_err(VmError::from);
+    handle.status(result)
+}
+
+/// Output of the last run, one line per print. Free it with `alya_string_free`.
+///
+/// # Safety
+/// `vm` must be a live handle.
+#[no_mangle]
+pub unsafe extern "C" fn alya_vm_output(vm: *const AlyaVm) -> *mut c_char {
+    let Some(handle) = vm.as_ref() else { return ptr::null_mut() };
+    let text = handle.vm.output.iter().map(|line| format!("{}\n", line)).collect::<String>();
+    CString::new(text.replace('\0', " ")).map_or(ptr::null_mut(), CString::into_raw)
+}
+
+/// Message for the most recent failed call, or null. Owned by the VM and
+/// valid until the next call on it.
+///
+/// # Safety
+/// `vm` must be a live handle.
+#[no_mangle]
+pub unsafe extern "C" fn alya_vm_last_error(vm: *const AlyaVm) -> *const c_char {
+    vm.as_ref()
+        .and_then(|handle| handle.last_error.as_ref())
+        .map_or(ptr::null(), |e| e.as_ptr())
+}
+
+/// Free a string returned by `alya_vm_output`.
+///
+/// # Safety
+/// `s` must be null or a string from this library that is not used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn alya_string_free(s: *mut c_char) {
+    if !s.is_null() {
+        drop(CString::from_raw(s));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_assemble_run_and_read_back() {
+        unsafe {
+            let vm = alya_vm_new();
+            let source = CString::new("@r0 := 6\n@r1 := 7\n@r2 := @r0 * @r1\nprint @r2\nhalt\n").unwrap();
+            assert_eq!(alya_assemble(vm, source.as_ptr()), ALYA_OK);
+            assert_eq!(alya_vm_run(vm), ALYA_OK);
+            assert_eq!(alya_vm_get_register(vm, 2), 42);
+
+            let output = alya_vm_output(vm);
+            assert_eq!(CStr::from_ptr(output).to_str().unwrap(), "42\n");
+            alya_string_free(output);
+
+            assert_eq!(alya_vm_set_register(vm, 200, 1), ALYA_ERROR);
+            assert!(!alya_vm_last_error(vm).is_null());
+            let bad = CString::new("bogus line").unwrap();
+            assert_eq!(alya_assemble(vm, bad.as_ptr()), ALYA_ERROR);
+            alya_vm_free(vm);
+        }
+    }
+}
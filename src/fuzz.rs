@@ -0,0 +1,89 @@
+//! Fuzzing entry points.
+//!
+//! `fuzz_decode_and_run` feeds arbitrary bytes through `Instruction::decode`
+//! and the dispatcher. Any input may be rejected with an error, but none may
+//! panic, hang or allocate without bound. The cargo-fuzz targets in `fuzz/`
+//! call it; with the `arbitrary` feature, `Instruction` and `Register` can
+//! also be generated directly from fuzzer input.
+
+use crate::error::VmResult;
+use crate::execution::VM;
+use crate::instruction::{Instruction, Program};
+
+/// Longest input decoded; the rest is ignored
+pub const MAX_CODE_BYTES: usize = 4096;
+/// Instructions executed before the run is cut off
+pub const MAX_STEPS: u64 = 10_000;
+/// Guest memory for each run
+pub const MEMORY_SIZE: usize = 64 * 1024;
+
+/// Decode `bytes` as a code section and run it under hard caps on input
+/// size, executed instructions and memory. Output is captured, not printed.
+pub fn fuzz_decode_and_run(bytes: &[u8]) -> VmResult<()> {
+    let mut code = &bytes[..bytes.len().min(MAX_CODE_BYTES)];
+    let mut instructions = Vec::new();
+    while !code.is_empty() {
+        let (instruction, len) = Instruction::decode(code)?;
+        instructions.push(instruction);
+        code = &code[len..];
+    }
+    run_capped(&Program::from_instructions("fuzz", instructions))
+}
+
+/// Encode `instructions`, check that each decodes back to itself, then run
+/// them like `fuzz_decode_and_run`. Panics if the encoding does not round-trip.
+pub fn fuzz_encode_and_run(instructions: &[Instruction]) -> VmResult<()> {
+    let instructions = &instructions[..instructions.len().min(MAX_CODE_BYTES / 4)];
+    for instruction in instructions {
+        let bytes = instruction.encode();
+        let (decoded, len) = Instruction::decode(&bytes)
+            .unwrap_or_else(|e| panic!("{:?} does not decode: {}", instruction, e));
+        assert_eq!((&decoded, len), (instruction, bytes.len()), "encoding does not round-trip");
+    }
+    run_capped(&Program::from_instructions("fuzz", instructions.to_vec()))
+}
+
+/// Run `program` for at most `MAX_STEPS` instructions
+fn run_capped(program: &Program) -> VmResult<()> {
+    let mut vm = VM::with_memory_size(MEMORY_SIZE);
+    vm.print_immediately = false;
+    for step in vm.run_iter(program).take(MAX_STEPS as usize) {
+        step?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::Register;
+
+    #[test]
+    fn test_fuzz_entry_points() {
+        // Truncated LoadImm, unknown opcode, then an endless loop cut off by the step cap
+        assert!(fuzz_decode_and_run(&Instruction::LoadImm { dest: Register::R0, value: 1 << 40 }.encode()[..4]).is_err());
+        assert!(fuzz_decode_and_run(&[0xff]).is_err());
+        assert!(fuzz_decode_and_run(&Instruction::Jump { target: 0 }.encode()).is_ok());
+
+        let every_register = (0..Register::COUNT as u8).map(|code| Instruction::Push { src: Register::from_u8(code).unwrap() });
+        let program: Vec<Instruction> = every_register.chain([Instruction::Return]).collect();
+        assert!(fuzz_encode_and_run(&program).is_err());
+    }
+
+    #[cfg(feature = "arbitrary")]
+    #[test]
+    fn test_arbitrary_instructions_round_trip() {
+        use arbitrary::{Arbitrary, Unstructured};
+        let mut seed: u64 = 0x2545_f491_4f6c_dd1d;
+        for _ in 0..200 {
+            let bytes: Vec<u8> = (0..256).map(|_| {
+                seed ^= seed << 13;
+                seed ^= seed >> 7;
+                seed ^= seed << 17;
+                seed as u8
+            }).collect();
+            let instructions = Vec::<Instruction>::arbitrary(&mut Unstructured::new(&bytes)).unwrap();
+            let _ = fuzz_encode_and_run(&instructions);
+        }
+    }
+}
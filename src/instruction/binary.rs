@@ -1,10 +1,11 @@
 use crate::instruction::Instruction;
 use crate::core::{Opcode, Register};
-use crate::error::VmError;
+use crate::error::{ErrorCode, VmError};
 
 
 impl Instruction {
-    /// Encode instruction to bytes
+    /// Encode instruction to bytes. `LoadImm` takes the shortest of its
+    /// 1-, 4- and 8-byte immediate forms that holds the value.
     pub fn encode(&self) -> Vec<u8> {
         let mut bytes = Vec::new();
         
@@ -12,11 +13,20 @@ impl Instruction {
         bytes.push(opcode.to_u8());
         
         match self {
-            Instruction::Halt | Instruction::Nop | Instruction::Return | Instruction::Syscall => {}
+            Instruction::Halt | Instruction::Nop | Instruction::Return | Instruction::Syscall | Instruction::Leave |
+            Instruction::Breakpoint | Instruction::TraceOn | Instruction::TraceOff | Instruction::IRet => {}
             
             Instruction::LoadImm { dest, value } => {
                 bytes.push(dest.to_u8());
-                bytes.extend_from_slice(&value.to_le_bytes());
+                if let Ok(small) = i8::try_from(*value as i64) {
+                    bytes[0] = Opcode::LoadImm8.to_u8();
+                    bytes.push(small as u8);
+                } else if let Ok(small) = i32::try_from(*value as i64) {
+                    bytes[0] = Opcode::LoadImm32.to_u8();
+                    bytes.extend_from_slice(&small.to_le_bytes());
+                } else {
+                    bytes.extend_from_slice(&value.to_le_bytes());
+                }
             }
             
             Instruction::Move { dest, src } | 
@@ -25,13 +35,25 @@ impl Instruction {
                 bytes.push(src.to_u8());
             }
 
-            Instruction::Push { src } => {
+            Instruction::Push { src } |
+            Instruction::Exit { src } |
+            Instruction::CallReg { target_reg: src } => {
                 bytes.push(src.to_u8());
             }
 
             Instruction::Pop { dest } |
-            Instruction::Peek { dest } => {
+            Instruction::Peek { dest } |
+            Instruction::Rand { dest } => {
+                bytes.push(dest.to_u8());
+            }
+
+            Instruction::FeatQuery { dest, id } => {
                 bytes.push(dest.to_u8());
+                bytes.push(*id);
+            }
+
+            Instruction::Int { vector } => {
+                bytes.push(*vector);
             }
             
             Instruction::Swap { r1, r2 } => {
@@ -44,15 +66,25 @@ impl Instruction {
             Instruction::Mul { dest, left, right } |
             Instruction::Div { dest, left, right } |
             Instruction::Mod { dest, left, right } |
+            Instruction::IDiv { dest, left, right } |
+            Instruction::IMod { dest, left, right } |
+            Instruction::MulHi { dest, left, right } |
             Instruction::And { dest, left, right } |
             Instruction::Or { dest, left, right } |
             Instruction::Xor { dest, left, right } |
             Instruction::Shl { dest, left, right } |
             Instruction::Shr { dest, left, right } |
+            Instruction::ISht { dest, left, right } |
             Instruction::FAdd { dest, left, right } |
+            Instruction::FAdd32 { dest, left, right } |
+            Instruction::FSub32 { dest, left, right } |
+            Instruction::FMul32 { dest, left, right } |
+            Instruction::FDiv32 { dest, left, right } |
             Instruction::FSub { dest, left, right } |
             Instruction::FMul { dest, left, right } |
             Instruction::FDiv { dest, left, right } |
+            Instruction::FMin { dest, left, right } |
+            Instruction::FMax { dest, left, right } |
             Instruction::RotL { dest, left, right } |
             Instruction::RotR { dest, left, right } => {
                 bytes.push(dest.to_u8());
@@ -69,7 +101,15 @@ impl Instruction {
             Instruction::Ctz { dest, src } |
             Instruction::BSwap { dest, src } |
             Instruction::FSqrt { dest, src } |
+            Instruction::FSqrt32 { dest, src } |
+            Instruction::FDemote { dest, src } |
+            Instruction::FPromote { dest, src } |
             Instruction::FAbs { dest, src } |
+            Instruction::FClass { dest, src } |
+            Instruction::FFloor { dest, src } |
+            Instruction::FCeil { dest, src } |
+            Instruction::FTrunc { dest, src } |
+            Instruction::FRound { dest, src } |
             Instruction::FNeg { dest, src } |
             Instruction::F2I { dest, src } |
             Instruction::I2F { dest, src } => {
@@ -78,16 +118,24 @@ impl Instruction {
             }
 
             Instruction::FCmp { left, right } |
-            Instruction::Compare { left, right } => {
+            Instruction::FCmp32 { left, right } |
+            Instruction::Compare { left, right } |
+            Instruction::Test { left, right } => {
                 bytes.push(left.to_u8());
                 bytes.push(right.to_u8());
             }
 
-            Instruction::Load { dest, addr_reg } => {
+            Instruction::Load { dest, addr_reg } |
+            Instruction::LoadByte { dest, addr_reg } |
+            Instruction::LoadWord { dest, addr_reg } |
+            Instruction::LoadDWord { dest, addr_reg } => {
                  bytes.push(dest.to_u8());
                  bytes.push(addr_reg.to_u8());
             }
-            Instruction::Store { src, addr_reg } => {
+            Instruction::Store { src, addr_reg } |
+            Instruction::StoreByte { src, addr_reg } |
+            Instruction::StoreWord { src, addr_reg } |
+            Instruction::StoreDWord { src, addr_reg } => {
                  bytes.push(src.to_u8());
                  bytes.push(addr_reg.to_u8());
             }
@@ -133,16 +181,68 @@ impl Instruction {
                 bytes.push(src.to_u8());
                 bytes.push(size.to_u8());
             }
+            Instruction::Enter { locals_size } => {
+                bytes.extend_from_slice(&locals_size.to_le_bytes());
+            }
+            Instruction::LoadLocal { dest: reg, offset } |
+            Instruction::StoreLocal { src: reg, offset } => {
+                bytes.push(reg.to_u8());
+                bytes.extend_from_slice(&offset.to_le_bytes());
+            }
+            Instruction::Switch { index, count } => {
+                bytes.push(index.to_u8());
+                bytes.extend_from_slice(&count.to_le_bytes());
+            }
         }
         
         bytes
     }
     
+    /// Encode the instruction at `index`, giving a jump or call target as
+    /// an offset from `index` behind the `Relative` prefix so the code can
+    /// be moved without patching it. Targets too far away for an i32
+    /// offset are encoded absolute.
+    pub fn encode_relative(&self, index: usize) -> Vec<u8> {
+        let offset = self.target().and_then(|target| i32::try_from(target as i64 - index as i64).ok());
+        match offset {
+            Some(offset) => {
+                let mut bytes = vec![Opcode::Relative.to_u8(), self.opcode().to_u8()];
+                bytes.extend_from_slice(&offset.to_le_bytes());
+                bytes
+            }
+            None => self.encode(),
+        }
+    }
+
+    /// Decode the instruction at `index`, resolving a relative target
+    /// against it. Returns (Instruction, bytes_read).
+    pub fn decode_at(bytes: &[u8], index: usize) -> Result<(Instruction, usize), VmError> {
+        if bytes.first() != Some(&Opcode::Relative.to_u8()) {
+            return Self::decode(bytes);
+        }
+        if bytes.len() < 6 {
+            return Err(VmError::execution(ErrorCode::TruncatedBytecode, "Unexpected end of bytecode"));
+        }
+        let offset = i32::from_le_bytes([bytes[2], bytes[3], bytes[4], bytes[5]]);
+        let target = usize::try_from(index as i64 + offset as i64).map_err(|_| VmError::execution(
+            ErrorCode::InvalidEncoding, format!("Relative target {} is before the start of the code", offset)
+        ))?;
+        let mut absolute = vec![bytes[1]];
+        absolute.extend_from_slice(&(target as u64).to_le_bytes());
+        match Self::decode(&absolute) {
+            Ok((instruction, _)) if instruction.target().is_some() => Ok((instruction, 6)),
+            _ => Err(VmError::execution(ErrorCode::InvalidEncoding, format!(
+                "Opcode {:#04x} has no relative form", bytes[1]
+            ))),
+        }
+    }
+
     /// Helper to get opcode from instruction
     pub fn opcode(&self) -> Opcode {
         match self {
             Instruction::Halt => Opcode::Halt,
             Instruction::Nop => Opcode::Nop,
+            Instruction::Exit { .. } => Opcode::Exit,
             Instruction::LoadImm { .. } => Opcode::LoadImm,
             Instruction::Move { .. } => Opcode::Move,
             Instruction::Swap { .. } => Opcode::Swap,
@@ -151,6 +251,9 @@ impl Instruction {
             Instruction::Mul { .. } => Opcode::Mul,
             Instruction::Div { .. } => Opcode::Div,
             Instruction::Mod { .. } => Opcode::Mod,
+            Instruction::IDiv { .. } => Opcode::IDiv,
+            Instruction::IMod { .. } => Opcode::IMod,
+            Instruction::MulHi { .. } => Opcode::MulHi,
             Instruction::AddAssign { .. } => Opcode::AddAssign,
             Instruction::SubAssign { .. } => Opcode::SubAssign,
             Instruction::MulAssign { .. } => Opcode::MulAssign,
@@ -161,9 +264,14 @@ impl Instruction {
             Instruction::Not { .. } => Opcode::Not,
             Instruction::Shl { .. } => Opcode::Shl,
             Instruction::Shr { .. } => Opcode::Shr,
+            Instruction::ISht { .. } => Opcode::ISht,
             Instruction::Push { .. } => Opcode::Push,
             Instruction::Pop { .. } => Opcode::Pop,
             Instruction::Peek { .. } => Opcode::Peek,
+            Instruction::Enter { .. } => Opcode::Enter,
+            Instruction::Leave => Opcode::Leave,
+            Instruction::LoadLocal { .. } => Opcode::LoadLocal,
+            Instruction::StoreLocal { .. } => Opcode::StoreLocal,
             Instruction::Load { .. } => Opcode::Load,
             Instruction::Store { .. } => Opcode::Store,
             Instruction::LoadIndexed { .. } => Opcode::LoadIndexed,
@@ -180,25 +288,56 @@ impl Instruction {
             Instruction::JumpIfAbove { .. } => Opcode::JumpIfAbove,
             Instruction::JumpIfBelow { .. } => Opcode::JumpIfBelow,
             Instruction::JumpIfAe { .. } => Opcode::JumpIfAe,
+            Instruction::Switch { .. } => Opcode::Switch,
+            Instruction::CallReg { .. } => Opcode::CallReg,
             Instruction::JumpIfBe { .. } => Opcode::JumpIfBe,
             Instruction::Compare { .. } => Opcode::Compare,
+            Instruction::Test { .. } => Opcode::Test,
             Instruction::Call { .. } => Opcode::Call,
             Instruction::Return => Opcode::Return,
             Instruction::Syscall => Opcode::Syscall,
+            Instruction::Rand { .. } => Opcode::Rand,
+            Instruction::FeatQuery { .. } => Opcode::FeatQuery,
+            Instruction::Int { .. } => Opcode::Int,
+            Instruction::IRet => Opcode::IRet,
+            Instruction::Breakpoint => Opcode::Breakpoint,
+            Instruction::TraceOn => Opcode::TraceOn,
+            Instruction::TraceOff => Opcode::TraceOff,
             Instruction::Alloc { .. } => Opcode::Alloc,
             Instruction::Free { .. } => Opcode::Free,
             Instruction::MemCopy { .. } => Opcode::MemCopy,
             Instruction::MemSet { .. } => Opcode::MemSet,
+            Instruction::LoadByte { .. } => Opcode::LoadByte,
+            Instruction::StoreByte { .. } => Opcode::StoreByte,
+            Instruction::LoadWord { .. } => Opcode::LoadWord,
+            Instruction::StoreWord { .. } => Opcode::StoreWord,
+            Instruction::LoadDWord { .. } => Opcode::LoadDWord,
+            Instruction::StoreDWord { .. } => Opcode::StoreDWord,
             Instruction::FAdd { .. } => Opcode::FAdd,
             Instruction::FSub { .. } => Opcode::FSub,
             Instruction::FMul { .. } => Opcode::FMul,
             Instruction::FDiv { .. } => Opcode::FDiv,
+            Instruction::FMin { .. } => Opcode::FMin,
+            Instruction::FMax { .. } => Opcode::FMax,
+            Instruction::FClass { .. } => Opcode::FClass,
             Instruction::FSqrt { .. } => Opcode::FSqrt,
             Instruction::FAbs { .. } => Opcode::FAbs,
+            Instruction::FFloor { .. } => Opcode::FFloor,
+            Instruction::FCeil { .. } => Opcode::FCeil,
+            Instruction::FTrunc { .. } => Opcode::FTrunc,
+            Instruction::FRound { .. } => Opcode::FRound,
             Instruction::FNeg { .. } => Opcode::FNeg,
             Instruction::F2I { .. } => Opcode::F2I,
             Instruction::I2F { .. } => Opcode::I2F,
             Instruction::FCmp { .. } => Opcode::FCmp,
+            Instruction::FAdd32 { .. } => Opcode::FAdd32,
+            Instruction::FSub32 { .. } => Opcode::FSub32,
+            Instruction::FMul32 { .. } => Opcode::FMul32,
+            Instruction::FDiv32 { .. } => Opcode::FDiv32,
+            Instruction::FSqrt32 { .. } => Opcode::FSqrt32,
+            Instruction::FCmp32 { .. } => Opcode::FCmp32,
+            Instruction::FDemote { .. } => Opcode::FDemote,
+            Instruction::FPromote { .. } => Opcode::FPromote,
             Instruction::PopCnt { .. } => Opcode::PopCnt,
             Instruction::Clz { .. } => Opcode::Clz,
             Instruction::Ctz { .. } => Opcode::Ctz,
@@ -209,14 +348,15 @@ impl Instruction {
     }
 
     /// Decode instruction from bytes. Returns (Instruction, bytes_read).
+    /// Relative forms need the instruction's index; see `decode_at`.
     pub fn decode(bytes: &[u8]) -> Result<(Instruction, usize), VmError> {
         if bytes.is_empty() {
-            return Err(VmError::Execution("Unexpected end of bytecode".to_string()));
+            return Err(VmError::execution(ErrorCode::TruncatedBytecode, "Unexpected end of bytecode"));
         }
         
         let opcode_byte = bytes[0];
         let opcode = Opcode::from_u8(opcode_byte)
-            .map_err(|e| VmError::Execution(format!("Invalid opcode: {}", e)))?;
+            .map_err(|e| VmError::execution(ErrorCode::InvalidEncoding, format!("Invalid opcode: {}", e)))?;
             
         let mut pos = 1;
         
@@ -225,10 +365,15 @@ impl Instruction {
             Opcode::Nop => Instruction::Nop,
             Opcode::Return => Instruction::Return,
             Opcode::Syscall => Instruction::Syscall,
+            Opcode::Leave => Instruction::Leave,
+            Opcode::IRet => Instruction::IRet,
+            Opcode::Breakpoint => Instruction::Breakpoint,
+            Opcode::TraceOn => Instruction::TraceOn,
+            Opcode::TraceOff => Instruction::TraceOff,
             
             Opcode::LoadImm => {
-                if bytes.len() < pos + 9 { return Err(VmError::Execution("Unexpected end of bytecode".to_string())); }
-                let dest = Register::from_u8(bytes[pos]).map_err(|e| VmError::Execution(e.to_string()))?;
+                if bytes.len() < pos + 9 { return Err(VmError::execution(ErrorCode::TruncatedBytecode, "Unexpected end of bytecode")); }
+                let dest = Register::from_u8(bytes[pos]).map_err(|e| VmError::execution(ErrorCode::InvalidEncoding, e.to_string()))?;
                 pos += 1;
                 let mut buf = [0u8; 8];
                 buf.copy_from_slice(&bytes[pos..pos+8]);
@@ -236,31 +381,49 @@ impl Instruction {
                 pos += 8;
                 Instruction::LoadImm { dest, value }
             }
+            Opcode::LoadImm8 => {
+                if bytes.len() < pos + 2 { return Err(VmError::execution(ErrorCode::TruncatedBytecode, "Unexpected end of bytecode")); }
+                let dest = Register::from_u8(bytes[pos]).map_err(|e| VmError::execution(ErrorCode::InvalidEncoding, e.to_string()))?;
+                let value = bytes[pos+1] as i8 as i64 as u64;
+                pos += 2;
+                Instruction::LoadImm { dest, value }
+            }
+            Opcode::LoadImm32 => {
+                if bytes.len() < pos + 5 { return Err(VmError::execution(ErrorCode::TruncatedBytecode, "Unexpected end of bytecode")); }
+                let dest = Register::from_u8(bytes[pos]).map_err(|e| VmError::execution(ErrorCode::InvalidEncoding, e.to_string()))?;
+                let mut buf = [0u8; 4];
+                buf.copy_from_slice(&bytes[pos+1..pos+5]);
+                let value = i32::from_le_bytes(buf) as i64 as u64;
+                pos += 5;
+                Instruction::LoadImm { dest, value }
+            }
             
             Opcode::Move => {
-                if bytes.len() < pos + 2 { return Err(VmError::Execution("Unexpected end of bytecode".to_string())); }
-                let dest = Register::from_u8(bytes[pos]).map_err(|e| VmError::Execution(e.to_string()))?;
-                let src = Register::from_u8(bytes[pos+1]).map_err(|e| VmError::Execution(e.to_string()))?;
+                if bytes.len() < pos + 2 { return Err(VmError::execution(ErrorCode::TruncatedBytecode, "Unexpected end of bytecode")); }
+                let dest = Register::from_u8(bytes[pos]).map_err(|e| VmError::execution(ErrorCode::InvalidEncoding, e.to_string()))?;
+                let src = Register::from_u8(bytes[pos+1]).map_err(|e| VmError::execution(ErrorCode::InvalidEncoding, e.to_string()))?;
                 pos += 2;
                 Instruction::Move { dest, src }
             }
             
             Opcode::Swap => {
-                if bytes.len() < pos + 2 { return Err(VmError::Execution("Unexpected end of bytecode".to_string())); }
-                let r1 = Register::from_u8(bytes[pos]).map_err(|e| VmError::Execution(e.to_string()))?;
-                let r2 = Register::from_u8(bytes[pos+1]).map_err(|e| VmError::Execution(e.to_string()))?;
+                if bytes.len() < pos + 2 { return Err(VmError::execution(ErrorCode::TruncatedBytecode, "Unexpected end of bytecode")); }
+                let r1 = Register::from_u8(bytes[pos]).map_err(|e| VmError::execution(ErrorCode::InvalidEncoding, e.to_string()))?;
+                let r2 = Register::from_u8(bytes[pos+1]).map_err(|e| VmError::execution(ErrorCode::InvalidEncoding, e.to_string()))?;
                 pos += 2;
                 Instruction::Swap { r1, r2 }
             }
             
             Opcode::Add | Opcode::Sub | Opcode::Mul | Opcode::Div | Opcode::Mod |
-            Opcode::And | Opcode::Or | Opcode::Xor | Opcode::Shl | Opcode::Shr |
-            Opcode::FAdd | Opcode::FSub | Opcode::FMul | Opcode::FDiv |
+            Opcode::IDiv | Opcode::IMod | Opcode::MulHi |
+            Opcode::And | Opcode::Or | Opcode::Xor | Opcode::Shl | Opcode::Shr | Opcode::ISht |
+            Opcode::FAdd | Opcode::FSub | Opcode::FMul | Opcode::FDiv | Opcode::FMin | Opcode::FMax |
+            Opcode::FAdd32 | Opcode::FSub32 | Opcode::FMul32 | Opcode::FDiv32 |
             Opcode::RotL | Opcode::RotR => {
-                if bytes.len() < pos + 3 { return Err(VmError::Execution("Unexpected end of bytecode".to_string())); }
-                let dest = Register::from_u8(bytes[pos]).map_err(|e| VmError::Execution(e.to_string()))?;
-                let left = Register::from_u8(bytes[pos+1]).map_err(|e| VmError::Execution(e.to_string()))?;
-                let right = Register::from_u8(bytes[pos+2]).map_err(|e| VmError::Execution(e.to_string()))?;
+                if bytes.len() < pos + 3 { return Err(VmError::execution(ErrorCode::TruncatedBytecode, "Unexpected end of bytecode")); }
+                let dest = Register::from_u8(bytes[pos]).map_err(|e| VmError::execution(ErrorCode::InvalidEncoding, e.to_string()))?;
+                let left = Register::from_u8(bytes[pos+1]).map_err(|e| VmError::execution(ErrorCode::InvalidEncoding, e.to_string()))?;
+                let right = Register::from_u8(bytes[pos+2]).map_err(|e| VmError::execution(ErrorCode::InvalidEncoding, e.to_string()))?;
                 pos += 3;
                 
                 match opcode {
@@ -269,15 +432,25 @@ impl Instruction {
                     Opcode::Mul => Instruction::Mul { dest, left, right },
                     Opcode::Div => Instruction::Div { dest, left, right },
                     Opcode::Mod => Instruction::Mod { dest, left, right },
+                    Opcode::IDiv => Instruction::IDiv { dest, left, right },
+                    Opcode::IMod => Instruction::IMod { dest, left, right },
+                    Opcode::MulHi => Instruction::MulHi { dest, left, right },
                     Opcode::And => Instruction::And { dest, left, right },
                     Opcode::Or  => Instruction::Or  { dest, left, right },
                     Opcode::Xor => Instruction::Xor { dest, left, right },
                     Opcode::Shl => Instruction::Shl { dest, left, right },
                     Opcode::Shr => Instruction::Shr { dest, left, right },
+                    Opcode::ISht => Instruction::ISht { dest, left, right },
                     Opcode::FAdd => Instruction::FAdd { dest, left, right },
+                    Opcode::FAdd32 => Instruction::FAdd32 { dest, left, right },
+                    Opcode::FSub32 => Instruction::FSub32 { dest, left, right },
+                    Opcode::FMul32 => Instruction::FMul32 { dest, left, right },
+                    Opcode::FDiv32 => Instruction::FDiv32 { dest, left, right },
                     Opcode::FSub => Instruction::FSub { dest, left, right },
                     Opcode::FMul => Instruction::FMul { dest, left, right },
                     Opcode::FDiv => Instruction::FDiv { dest, left, right },
+                    Opcode::FMin => Instruction::FMin { dest, left, right },
+                    Opcode::FMax => Instruction::FMax { dest, left, right },
                     Opcode::RotL => Instruction::RotL { dest, left, right },
                     Opcode::RotR => Instruction::RotR { dest, left, right },
                     _ => unreachable!(),
@@ -285,9 +458,9 @@ impl Instruction {
             }
             
             Opcode::AddAssign | Opcode::SubAssign | Opcode::MulAssign | Opcode::DivAssign => {
-                if bytes.len() < pos + 2 { return Err(VmError::Execution("Unexpected end of bytecode".to_string())); }
-                let dest = Register::from_u8(bytes[pos]).map_err(|e| VmError::Execution(e.to_string()))?;
-                let src = Register::from_u8(bytes[pos+1]).map_err(|e| VmError::Execution(e.to_string()))?;
+                if bytes.len() < pos + 2 { return Err(VmError::execution(ErrorCode::TruncatedBytecode, "Unexpected end of bytecode")); }
+                let dest = Register::from_u8(bytes[pos]).map_err(|e| VmError::execution(ErrorCode::InvalidEncoding, e.to_string()))?;
+                let src = Register::from_u8(bytes[pos+1]).map_err(|e| VmError::execution(ErrorCode::InvalidEncoding, e.to_string()))?;
                 pos += 2;
                 match opcode {
                     Opcode::AddAssign => Instruction::AddAssign { dest, src },
@@ -299,10 +472,12 @@ impl Instruction {
             }
             
             Opcode::Not | Opcode::PopCnt | Opcode::Clz | Opcode::Ctz | Opcode::BSwap |
-            Opcode::FSqrt | Opcode::FAbs | Opcode::FNeg | Opcode::F2I | Opcode::I2F => {
-                if bytes.len() < pos + 2 { return Err(VmError::Execution("Unexpected end of bytecode".to_string())); }
-                let dest = Register::from_u8(bytes[pos]).map_err(|e| VmError::Execution(e.to_string()))?;
-                let src = Register::from_u8(bytes[pos+1]).map_err(|e| VmError::Execution(e.to_string()))?;
+            Opcode::FSqrt | Opcode::FAbs | Opcode::FNeg | Opcode::F2I | Opcode::I2F |
+            Opcode::FSqrt32 | Opcode::FDemote | Opcode::FPromote |
+            Opcode::FFloor | Opcode::FCeil | Opcode::FTrunc | Opcode::FRound | Opcode::FClass => {
+                if bytes.len() < pos + 2 { return Err(VmError::execution(ErrorCode::TruncatedBytecode, "Unexpected end of bytecode")); }
+                let dest = Register::from_u8(bytes[pos]).map_err(|e| VmError::execution(ErrorCode::InvalidEncoding, e.to_string()))?;
+                let src = Register::from_u8(bytes[pos+1]).map_err(|e| VmError::execution(ErrorCode::InvalidEncoding, e.to_string()))?;
                 pos += 2;
                 match opcode {
                     Opcode::Not => Instruction::Not { dest, src },
@@ -311,7 +486,15 @@ impl Instruction {
                     Opcode::Ctz => Instruction::Ctz { dest, src },
                     Opcode::BSwap => Instruction::BSwap { dest, src },
                     Opcode::FSqrt => Instruction::FSqrt { dest, src },
+                    Opcode::FSqrt32 => Instruction::FSqrt32 { dest, src },
+                    Opcode::FDemote => Instruction::FDemote { dest, src },
+                    Opcode::FPromote => Instruction::FPromote { dest, src },
                     Opcode::FAbs => Instruction::FAbs { dest, src },
+                    Opcode::FClass => Instruction::FClass { dest, src },
+                    Opcode::FFloor => Instruction::FFloor { dest, src },
+                    Opcode::FCeil => Instruction::FCeil { dest, src },
+                    Opcode::FTrunc => Instruction::FTrunc { dest, src },
+                    Opcode::FRound => Instruction::FRound { dest, src },
                     Opcode::FNeg => Instruction::FNeg { dest, src },
                     Opcode::F2I => Instruction::F2I { dest, src },
                     Opcode::I2F => Instruction::I2F { dest, src },
@@ -320,53 +503,116 @@ impl Instruction {
             }
             
             Opcode::Push => {
-                if bytes.len() < pos + 1 { return Err(VmError::Execution("Unexpected end of bytecode".to_string())); }
-                let src = Register::from_u8(bytes[pos]).map_err(|e| VmError::Execution(e.to_string()))?;
+                if bytes.len() < pos + 1 { return Err(VmError::execution(ErrorCode::TruncatedBytecode, "Unexpected end of bytecode")); }
+                let src = Register::from_u8(bytes[pos]).map_err(|e| VmError::execution(ErrorCode::InvalidEncoding, e.to_string()))?;
                 pos += 1;
                 Instruction::Push { src }
             }
+            Opcode::Exit => {
+                if bytes.len() < pos + 1 { return Err(VmError::execution(ErrorCode::TruncatedBytecode, "Unexpected end of bytecode")); }
+                let src = Register::from_u8(bytes[pos]).map_err(|e| VmError::execution(ErrorCode::InvalidEncoding, e.to_string()))?;
+                pos += 1;
+                Instruction::Exit { src }
+            }
+            Opcode::CallReg => {
+                if bytes.len() < pos + 1 { return Err(VmError::execution(ErrorCode::TruncatedBytecode, "Unexpected end of bytecode")); }
+                let target_reg = Register::from_u8(bytes[pos]).map_err(|e| VmError::execution(ErrorCode::InvalidEncoding, e.to_string()))?;
+                pos += 1;
+                Instruction::CallReg { target_reg }
+            }
             
             Opcode::Pop => {
-                if bytes.len() < pos + 1 { return Err(VmError::Execution("Unexpected end of bytecode".to_string())); }
-                let dest = Register::from_u8(bytes[pos]).map_err(|e| VmError::Execution(e.to_string()))?;
+                if bytes.len() < pos + 1 { return Err(VmError::execution(ErrorCode::TruncatedBytecode, "Unexpected end of bytecode")); }
+                let dest = Register::from_u8(bytes[pos]).map_err(|e| VmError::execution(ErrorCode::InvalidEncoding, e.to_string()))?;
                 pos += 1;
                 Instruction::Pop { dest }
             }
             Opcode::Peek => {
-                if bytes.len() < pos + 1 { return Err(VmError::Execution("Unexpected end of bytecode".to_string())); }
-                let dest = Register::from_u8(bytes[pos]).map_err(|e| VmError::Execution(e.to_string()))?;
+                if bytes.len() < pos + 1 { return Err(VmError::execution(ErrorCode::TruncatedBytecode, "Unexpected end of bytecode")); }
+                let dest = Register::from_u8(bytes[pos]).map_err(|e| VmError::execution(ErrorCode::InvalidEncoding, e.to_string()))?;
                 pos += 1;
                 Instruction::Peek { dest }
             }
+            Opcode::Rand => {
+                if bytes.len() < pos + 1 { return Err(VmError::execution(ErrorCode::TruncatedBytecode, "Unexpected end of bytecode")); }
+                let dest = Register::from_u8(bytes[pos]).map_err(|e| VmError::execution(ErrorCode::InvalidEncoding, e.to_string()))?;
+                pos += 1;
+                Instruction::Rand { dest }
+            }
+            Opcode::FeatQuery => {
+                if bytes.len() < pos + 2 { return Err(VmError::execution(ErrorCode::TruncatedBytecode, "Unexpected end of bytecode")); }
+                let dest = Register::from_u8(bytes[pos]).map_err(|e| VmError::execution(ErrorCode::InvalidEncoding, e.to_string()))?;
+                let id = bytes[pos + 1];
+                pos += 2;
+                Instruction::FeatQuery { dest, id }
+            }
+            Opcode::Int => {
+                if bytes.len() < pos + 1 { return Err(VmError::execution(ErrorCode::TruncatedBytecode, "Unexpected end of bytecode")); }
+                let vector = bytes[pos];
+                pos += 1;
+                Instruction::Int { vector }
+            }
+            Opcode::Enter => {
+                if bytes.len() < pos + 4 { return Err(VmError::execution(ErrorCode::TruncatedBytecode, "Unexpected end of bytecode")); }
+                let mut buf = [0u8; 4];
+                buf.copy_from_slice(&bytes[pos..pos+4]);
+                pos += 4;
+                Instruction::Enter { locals_size: u32::from_le_bytes(buf) }
+            }
+            Opcode::LoadLocal | Opcode::StoreLocal => {
+                if bytes.len() < pos + 5 { return Err(VmError::execution(ErrorCode::TruncatedBytecode, "Unexpected end of bytecode")); }
+                let reg = Register::from_u8(bytes[pos]).map_err(|e| VmError::execution(ErrorCode::InvalidEncoding, e.to_string()))?;
+                let mut buf = [0u8; 4];
+                buf.copy_from_slice(&bytes[pos+1..pos+5]);
+                pos += 5;
+                let offset = i32::from_le_bytes(buf);
+                if opcode == Opcode::LoadLocal {
+                    Instruction::LoadLocal { dest: reg, offset }
+                } else {
+                    Instruction::StoreLocal { src: reg, offset }
+                }
+            }
             
-            Opcode::Load => {
-                if bytes.len() < pos + 2 { return Err(VmError::Execution("Unexpected end of bytecode".to_string())); }
-                let dest = Register::from_u8(bytes[pos]).map_err(|e| VmError::Execution(e.to_string()))?;
-                let addr_reg = Register::from_u8(bytes[pos+1]).map_err(|e| VmError::Execution(e.to_string()))?;
+            Opcode::Load | Opcode::LoadByte | Opcode::LoadWord | Opcode::LoadDWord => {
+                if bytes.len() < pos + 2 { return Err(VmError::execution(ErrorCode::TruncatedBytecode, "Unexpected end of bytecode")); }
+                let dest = Register::from_u8(bytes[pos]).map_err(|e| VmError::execution(ErrorCode::InvalidEncoding, e.to_string()))?;
+                let addr_reg = Register::from_u8(bytes[pos+1]).map_err(|e| VmError::execution(ErrorCode::InvalidEncoding, e.to_string()))?;
                 pos += 2;
-                Instruction::Load { dest, addr_reg }
+                match opcode {
+                    Opcode::Load => Instruction::Load { dest, addr_reg },
+                    Opcode::LoadByte => Instruction::LoadByte { dest, addr_reg },
+                    Opcode::LoadWord => Instruction::LoadWord { dest, addr_reg },
+                    Opcode::LoadDWord => Instruction::LoadDWord { dest, addr_reg },
+                    _ => unreachable!(),
+                }
             }
-            Opcode::Store => {
-                if bytes.len() < pos + 2 { return Err(VmError::Execution("Unexpected end of bytecode".to_string())); }
-                let src = Register::from_u8(bytes[pos]).map_err(|e| VmError::Execution(e.to_string()))?;
-                let addr_reg = Register::from_u8(bytes[pos+1]).map_err(|e| VmError::Execution(e.to_string()))?;
+            Opcode::Store | Opcode::StoreByte | Opcode::StoreWord | Opcode::StoreDWord => {
+                if bytes.len() < pos + 2 { return Err(VmError::execution(ErrorCode::TruncatedBytecode, "Unexpected end of bytecode")); }
+                let src = Register::from_u8(bytes[pos]).map_err(|e| VmError::execution(ErrorCode::InvalidEncoding, e.to_string()))?;
+                let addr_reg = Register::from_u8(bytes[pos+1]).map_err(|e| VmError::execution(ErrorCode::InvalidEncoding, e.to_string()))?;
                 pos += 2;
-                Instruction::Store { src, addr_reg }
+                match opcode {
+                    Opcode::Store => Instruction::Store { src, addr_reg },
+                    Opcode::StoreByte => Instruction::StoreByte { src, addr_reg },
+                    Opcode::StoreWord => Instruction::StoreWord { src, addr_reg },
+                    Opcode::StoreDWord => Instruction::StoreDWord { src, addr_reg },
+                    _ => unreachable!(),
+                }
             }
             
             Opcode::LoadIndexed => {
-                if bytes.len() < pos + 3 { return Err(VmError::Execution("Unexpected end of bytecode".to_string())); }
-                let dest = Register::from_u8(bytes[pos]).map_err(|e| VmError::Execution(e.to_string()))?;
-                let base_reg = Register::from_u8(bytes[pos+1]).map_err(|e| VmError::Execution(e.to_string()))?;
-                let index_reg = Register::from_u8(bytes[pos+2]).map_err(|e| VmError::Execution(e.to_string()))?;
+                if bytes.len() < pos + 3 { return Err(VmError::execution(ErrorCode::TruncatedBytecode, "Unexpected end of bytecode")); }
+                let dest = Register::from_u8(bytes[pos]).map_err(|e| VmError::execution(ErrorCode::InvalidEncoding, e.to_string()))?;
+                let base_reg = Register::from_u8(bytes[pos+1]).map_err(|e| VmError::execution(ErrorCode::InvalidEncoding, e.to_string()))?;
+                let index_reg = Register::from_u8(bytes[pos+2]).map_err(|e| VmError::execution(ErrorCode::InvalidEncoding, e.to_string()))?;
                 pos += 3;
                 Instruction::LoadIndexed { dest, base_reg, index_reg }
             }
             Opcode::StoreIndexed => {
-                if bytes.len() < pos + 3 { return Err(VmError::Execution("Unexpected end of bytecode".to_string())); }
-                let src = Register::from_u8(bytes[pos]).map_err(|e| VmError::Execution(e.to_string()))?;
-                let base_reg = Register::from_u8(bytes[pos+1]).map_err(|e| VmError::Execution(e.to_string()))?;
-                let index_reg = Register::from_u8(bytes[pos+2]).map_err(|e| VmError::Execution(e.to_string()))?;
+                if bytes.len() < pos + 3 { return Err(VmError::execution(ErrorCode::TruncatedBytecode, "Unexpected end of bytecode")); }
+                let src = Register::from_u8(bytes[pos]).map_err(|e| VmError::execution(ErrorCode::InvalidEncoding, e.to_string()))?;
+                let base_reg = Register::from_u8(bytes[pos+1]).map_err(|e| VmError::execution(ErrorCode::InvalidEncoding, e.to_string()))?;
+                let index_reg = Register::from_u8(bytes[pos+2]).map_err(|e| VmError::execution(ErrorCode::InvalidEncoding, e.to_string()))?;
                 pos += 3;
                 Instruction::StoreIndexed { src, base_reg, index_reg }
             }
@@ -377,7 +623,7 @@ impl Instruction {
             Opcode::JumpIfAbove | Opcode::JumpIfBelow | 
             Opcode::JumpIfAe | Opcode::JumpIfBe |
             Opcode::Call => {
-                if bytes.len() < pos + 8 { return Err(VmError::Execution("Unexpected end of bytecode".to_string())); }
+                if bytes.len() < pos + 8 { return Err(VmError::execution(ErrorCode::TruncatedBytecode, "Unexpected end of bytecode")); }
                 let mut buf = [0u8; 8];
                 buf.copy_from_slice(&bytes[pos..pos+8]);
                 let target_u64 = u64::from_le_bytes(buf);
@@ -401,50 +647,61 @@ impl Instruction {
                     _ => unreachable!(),
                 }
             }
+
+            Opcode::Switch => {
+                if bytes.len() < pos + 5 { return Err(VmError::execution(ErrorCode::TruncatedBytecode, "Unexpected end of bytecode")); }
+                let index = Register::from_u8(bytes[pos]).map_err(|e| VmError::execution(ErrorCode::InvalidEncoding, e.to_string()))?;
+                let mut buf = [0u8; 4];
+                buf.copy_from_slice(&bytes[pos+1..pos+5]);
+                pos += 5;
+                Instruction::Switch { index, count: u32::from_le_bytes(buf) }
+            }
             
-            Opcode::Compare | Opcode::FCmp => {
-                if bytes.len() < pos + 2 { return Err(VmError::Execution("Unexpected end of bytecode".to_string())); }
-                let left = Register::from_u8(bytes[pos]).map_err(|e| VmError::Execution(e.to_string()))?;
-                let right = Register::from_u8(bytes[pos+1]).map_err(|e| VmError::Execution(e.to_string()))?;
+            Opcode::Compare | Opcode::Test | Opcode::FCmp | Opcode::FCmp32 => {
+                if bytes.len() < pos + 2 { return Err(VmError::execution(ErrorCode::TruncatedBytecode, "Unexpected end of bytecode")); }
+                let left = Register::from_u8(bytes[pos]).map_err(|e| VmError::execution(ErrorCode::InvalidEncoding, e.to_string()))?;
+                let right = Register::from_u8(bytes[pos+1]).map_err(|e| VmError::execution(ErrorCode::InvalidEncoding, e.to_string()))?;
                 pos += 2;
                 match opcode {
                     Opcode::Compare => Instruction::Compare { left, right },
+                    Opcode::Test => Instruction::Test { left, right },
                     Opcode::FCmp => Instruction::FCmp { left, right },
+                    Opcode::FCmp32 => Instruction::FCmp32 { left, right },
                     _ => unreachable!(),
                 }
             }
 
             Opcode::Alloc => {
-                if bytes.len() < pos + 2 { return Err(VmError::Execution("Unexpected end of bytecode".to_string())); }
-                let dest = Register::from_u8(bytes[pos]).map_err(|e| VmError::Execution(e.to_string()))?;
-                let size = Register::from_u8(bytes[pos+1]).map_err(|e| VmError::Execution(e.to_string()))?;
+                if bytes.len() < pos + 2 { return Err(VmError::execution(ErrorCode::TruncatedBytecode, "Unexpected end of bytecode")); }
+                let dest = Register::from_u8(bytes[pos]).map_err(|e| VmError::execution(ErrorCode::InvalidEncoding, e.to_string()))?;
+                let size = Register::from_u8(bytes[pos+1]).map_err(|e| VmError::execution(ErrorCode::InvalidEncoding, e.to_string()))?;
                 pos += 2;
                 Instruction::Alloc { dest, size }
             }
             Opcode::Free => {
-                if bytes.len() < pos + 1 { return Err(VmError::Execution("Unexpected end of bytecode".to_string())); }
-                let ptr = Register::from_u8(bytes[pos]).map_err(|e| VmError::Execution(e.to_string()))?;
+                if bytes.len() < pos + 1 { return Err(VmError::execution(ErrorCode::TruncatedBytecode, "Unexpected end of bytecode")); }
+                let ptr = Register::from_u8(bytes[pos]).map_err(|e| VmError::execution(ErrorCode::InvalidEncoding, e.to_string()))?;
                 pos += 1;
                 Instruction::Free { ptr }
             }
             Opcode::MemCopy => {
-                if bytes.len() < pos + 3 { return Err(VmError::Execution("Unexpected end of bytecode".to_string())); }
-                let dest = Register::from_u8(bytes[pos]).map_err(|e| VmError::Execution(e.to_string()))?;
-                let src = Register::from_u8(bytes[pos+1]).map_err(|e| VmError::Execution(e.to_string()))?;
-                let size = Register::from_u8(bytes[pos+2]).map_err(|e| VmError::Execution(e.to_string()))?;
+                if bytes.len() < pos + 3 { return Err(VmError::execution(ErrorCode::TruncatedBytecode, "Unexpected end of bytecode")); }
+                let dest = Register::from_u8(bytes[pos]).map_err(|e| VmError::execution(ErrorCode::InvalidEncoding, e.to_string()))?;
+                let src = Register::from_u8(bytes[pos+1]).map_err(|e| VmError::execution(ErrorCode::InvalidEncoding, e.to_string()))?;
+                let size = Register::from_u8(bytes[pos+2]).map_err(|e| VmError::execution(ErrorCode::InvalidEncoding, e.to_string()))?;
                 pos += 3;
                 Instruction::MemCopy { dest, src, size }
             }
             Opcode::MemSet => {
-                if bytes.len() < pos + 3 { return Err(VmError::Execution("Unexpected end of bytecode".to_string())); }
-                let dest = Register::from_u8(bytes[pos]).map_err(|e| VmError::Execution(e.to_string()))?;
-                let value = Register::from_u8(bytes[pos+1]).map_err(|e| VmError::Execution(e.to_string()))?;
-                let size = Register::from_u8(bytes[pos+2]).map_err(|e| VmError::Execution(e.to_string()))?;
+                if bytes.len() < pos + 3 { return Err(VmError::execution(ErrorCode::TruncatedBytecode, "Unexpected end of bytecode")); }
+                let dest = Register::from_u8(bytes[pos]).map_err(|e| VmError::execution(ErrorCode::InvalidEncoding, e.to_string()))?;
+                let value = Register::from_u8(bytes[pos+1]).map_err(|e| VmError::execution(ErrorCode::InvalidEncoding, e.to_string()))?;
+                let size = Register::from_u8(bytes[pos+2]).map_err(|e| VmError::execution(ErrorCode::InvalidEncoding, e.to_string()))?;
                 pos += 3;
                 Instruction::MemSet { dest, value, size }
             }
             
-            _ => return Err(VmError::Execution(format!("Unsupported opcode for decoding: {:?}", opcode))),
+            _ => return Err(VmError::execution(ErrorCode::InvalidEncoding, format!("Unsupported opcode for decoding: {:?}", opcode))),
         };
         
         Ok((instr, pos))
@@ -481,6 +738,15 @@ mod tests {
         let (decoded, len) = Instruction::decode(&bytes).unwrap();
         assert_eq!(instr, decoded);
         assert_eq!(bytes.len(), len);
+
+        // Values that sign-extend from 1 or 4 bytes take the short forms
+        for (value, size) in [(0, 3), (0x7F, 3), (u64::MAX, 3), (0x80, 6), (0x7FFF_FFFF, 6), (-0x8000_0000i64 as u64, 6), (0x8000_0000, 10)] {
+            let instr = Instruction::LoadImm { dest: Register::R3, value };
+            let bytes = instr.encode();
+            assert_eq!(bytes.len(), size, "{:#x}", value);
+            assert_eq!(Instruction::decode(&bytes).unwrap(), (instr, size));
+            assert!(Instruction::decode(&bytes[..size - 1]).is_err());
+        }
     }
 
     #[test]
@@ -493,6 +759,121 @@ mod tests {
         assert_eq!(bytes.len(), len);
     }
 
+    #[test]
+    fn test_encode_decode_relative() {
+        let instr = Instruction::JumpIfLt { target: 3 };
+        let bytes = instr.encode_relative(10);
+        assert_eq!(bytes, [0x7A, 0x74, 0xF9, 0xFF, 0xFF, 0xFF]);
+        assert_eq!(Instruction::decode_at(&bytes, 10).unwrap(), (instr, 6));
+        assert_eq!(Instruction::decode_at(&bytes, 12).unwrap().0, Instruction::JumpIfLt { target: 5 });
+        assert!(Instruction::decode_at(&bytes, 2).is_err());
+        assert!(Instruction::decode(&bytes).is_err());
+        assert!(Instruction::decode_at(&[0x7A, 0x10, 0, 0, 0, 0], 0).is_err());
+        assert_eq!(Instruction::Halt.encode_relative(4), Instruction::Halt.encode());
+    }
+
+    /// One instruction of every kind, with distinct operands so a swapped
+    /// field shows up as a mismatch
+    fn every_instruction() -> Vec<Instruction> {
+        use Register::{R1 as A, R2 as B, R3 as C};
+        let x = Register::general(40).unwrap();
+        vec![
+            Instruction::Halt, Instruction::Nop, Instruction::Exit { src: x },
+            Instruction::LoadImm { dest: x, value: 5 },
+            Instruction::LoadImm { dest: x, value: 0x1234 },
+            Instruction::LoadImm { dest: x, value: 0x1234_5678_9ABC },
+            Instruction::Move { dest: A, src: x }, Instruction::Swap { r1: A, r2: B },
+            Instruction::Add { dest: A, left: B, right: C }, Instruction::Sub { dest: A, left: B, right: C },
+            Instruction::Mul { dest: A, left: B, right: C }, Instruction::Div { dest: A, left: B, right: C },
+            Instruction::Mod { dest: A, left: B, right: C }, Instruction::IDiv { dest: A, left: B, right: C },
+            Instruction::IMod { dest: A, left: B, right: C }, Instruction::MulHi { dest: A, left: B, right: C },
+            Instruction::AddAssign { dest: A, src: B }, Instruction::SubAssign { dest: A, src: B },
+            Instruction::MulAssign { dest: A, src: B }, Instruction::DivAssign { dest: A, src: B },
+            Instruction::And { dest: A, left: B, right: C }, Instruction::Or { dest: A, left: B, right: C },
+            Instruction::Xor { dest: A, left: B, right: C }, Instruction::Not { dest: A, src: B },
+            Instruction::Shl { dest: A, left: B, right: C }, Instruction::Shr { dest: A, left: B, right: C },
+            Instruction::ISht { dest: A, left: B, right: C },
+            Instruction::Push { src: A }, Instruction::Pop { dest: A }, Instruction::Peek { dest: A },
+            Instruction::Enter { locals_size: 24 }, Instruction::Leave,
+            Instruction::LoadLocal { dest: A, offset: -16 }, Instruction::StoreLocal { src: A, offset: 8 },
+            Instruction::Load { dest: A, addr_reg: B }, Instruction::Store { src: A, addr_reg: B },
+            Instruction::LoadIndexed { dest: A, base_reg: B, index_reg: C },
+            Instruction::StoreIndexed { src: A, base_reg: B, index_reg: C },
+            Instruction::Alloc { dest: A, size: B }, Instruction::Free { ptr: A },
+            Instruction::MemCopy { dest: A, src: B, size: C }, Instruction::MemSet { dest: A, value: B, size: C },
+            Instruction::LoadByte { dest: A, addr_reg: B }, Instruction::LoadWord { dest: A, addr_reg: B },
+            Instruction::LoadDWord { dest: A, addr_reg: B }, Instruction::StoreByte { src: A, addr_reg: B },
+            Instruction::StoreWord { src: A, addr_reg: B }, Instruction::StoreDWord { src: A, addr_reg: B },
+            Instruction::FAdd { dest: A, left: B, right: C }, Instruction::FSub { dest: A, left: B, right: C },
+            Instruction::FMul { dest: A, left: B, right: C }, Instruction::FDiv { dest: A, left: B, right: C },
+            Instruction::FMin { dest: A, left: B, right: C }, Instruction::FMax { dest: A, left: B, right: C },
+            Instruction::FSqrt { dest: A, src: B }, Instruction::FAbs { dest: A, src: B },
+            Instruction::FClass { dest: A, src: B }, Instruction::FFloor { dest: A, src: B },
+            Instruction::FCeil { dest: A, src: B }, Instruction::FTrunc { dest: A, src: B },
+            Instruction::FRound { dest: A, src: B }, Instruction::FNeg { dest: A, src: B },
+            Instruction::F2I { dest: A, src: B }, Instruction::I2F { dest: A, src: B },
+            Instruction::FCmp { left: A, right: B },
+            Instruction::FAdd32 { dest: A, left: B, right: C }, Instruction::FSub32 { dest: A, left: B, right: C },
+            Instruction::FMul32 { dest: A, left: B, right: C }, Instruction::FDiv32 { dest: A, left: B, right: C },
+            Instruction::FSqrt32 { dest: A, src: B }, Instruction::FCmp32 { left: A, right: B },
+            Instruction::FDemote { dest: A, src: B }, Instruction::FPromote { dest: A, src: B },
+            Instruction::PopCnt { dest: A, src: B }, Instruction::Clz { dest: A, src: B },
+            Instruction::Ctz { dest: A, src: B }, Instruction::BSwap { dest: A, src: B },
+            Instruction::RotL { dest: A, left: B, right: C }, Instruction::RotR { dest: A, left: B, right: C },
+            Instruction::Jump { target: 1 }, Instruction::Compare { left: A, right: B },
+            Instruction::Test { left: A, right: B },
+            Instruction::JumpIfZero { target: 2 }, Instruction::JumpIfNotZero { target: 3 },
+            Instruction::JumpIfGt { target: 4 }, Instruction::JumpIfLt { target: 5 },
+            Instruction::JumpIfGe { target: 6 }, Instruction::JumpIfLe { target: 7 },
+            Instruction::JumpIfEq { target: 8 }, Instruction::JumpIfNe { target: 9 },
+            Instruction::JumpIfAbove { target: 10 }, Instruction::JumpIfBelow { target: 11 },
+            Instruction::JumpIfAe { target: 12 }, Instruction::JumpIfBe { target: 13 },
+            Instruction::Switch { index: A, count: 3 },
+            Instruction::Jump { target: 2 }, Instruction::Jump { target: 4 }, Instruction::Jump { target: 6 },
+            Instruction::Call { target: 14 }, Instruction::CallReg { target_reg: A }, Instruction::Return,
+            Instruction::Syscall, Instruction::Rand { dest: A }, Instruction::FeatQuery { dest: A, id: 7 },
+            Instruction::Int { vector: 33 }, Instruction::IRet,
+            Instruction::Breakpoint, Instruction::TraceOn, Instruction::TraceOff,
+        ]
+    }
+
+    #[test]
+    fn test_encode_decode_every_opcode() {
+        let mut seen = std::collections::HashSet::new();
+        for (index, instr) in every_instruction().into_iter().enumerate() {
+            let bytes = instr.encode();
+            assert_eq!(Instruction::decode(&bytes).unwrap(), (instr, bytes.len()), "{:?}", instr);
+            assert!(Instruction::decode(&bytes[..bytes.len() - 1]).is_err(), "{:?}", instr);
+            let relative = instr.encode_relative(index);
+            assert_eq!(Instruction::decode_at(&relative, index).unwrap(), (instr, relative.len()));
+            seen.insert(bytes[0]);
+            if relative[0] == Opcode::Relative.to_u8() {
+                seen.insert(relative[0]);
+            }
+        }
+
+        // Every opcode the decoder accepts is produced by some instruction
+        let opcodes: std::collections::HashSet<u8> = (0..=u8::MAX).filter(|&b| Opcode::from_u8(b).is_ok()).collect();
+        let mut missing: Vec<_> = opcodes.difference(&seen).map(|&b| Opcode::from_u8(b).unwrap().name()).collect();
+        missing.sort();
+        assert!(missing.is_empty(), "no instruction encodes {:?}", missing);
+        for b in opcodes {
+            assert_eq!(Opcode::from_u8(b).unwrap().to_u8(), b);
+        }
+
+        // 32-bit fields keep their full range
+        for instr in [Instruction::Switch { index: Register::R1, count: u32::MAX }, Instruction::Enter { locals_size: u32::MAX }] {
+            assert_eq!(Instruction::decode(&instr.encode()).unwrap().0, instr);
+        }
+    }
+
+    #[test]
+    fn test_source_round_trip_every_instruction() {
+        let instructions = every_instruction();
+        let source = crate::instruction::disassembler::to_source(&crate::instruction::Program::from_instructions("t", instructions.clone()));
+        assert_eq!(crate::assembler::assemble(&source, "t").unwrap().instructions, instructions);
+    }
+
     #[test]
     fn test_encode_decode_jump() {
         let instr = Instruction::Jump { target: 0xDEADBEEF };
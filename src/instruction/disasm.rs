@@ -0,0 +1,114 @@
+//! Structured disassembly, for GUIs, the debugger and other tools that lay
+//! out their own listing. `listing` renders the text `alya disassemble`
+//! prints.
+
+use std::fmt::Write;
+use crate::error::VmResult;
+use crate::instruction::{Instruction, Program};
+use super::disassembler::{self, Operand};
+
+/// One decoded instruction
+#[derive(Debug, Clone, PartialEq)]
+pub struct DisasmLine {
+    /// Instruction index, the unit of jump targets and the pc
+    pub index: usize,
+    /// Byte offset in the code section
+    pub offset: usize,
+    /// Encoded instruction
+    pub bytes: Vec<u8>,
+    /// Opcode name, such as `loadimm`
+    pub mnemonic: &'static str,
+    pub operands: Vec<Operand>,
+    /// Assembly text, such as `loadimm r1, 0x7`
+    pub text: String,
+    /// Symbol naming this instruction, if any
+    pub label: Option<String>,
+    /// Source line, when the program has a line table
+    pub line: Option<usize>,
+    /// Included file the source line is in; `None` for the assembled file
+    pub file: Option<String>,
+}
+
+impl DisasmLine {
+    fn new(index: usize, offset: usize, instruction: &Instruction, bytes: Vec<u8>) -> Self {
+        DisasmLine {
+            index,
+            offset,
+            bytes,
+            mnemonic: instruction.opcode().name(),
+            operands: instruction.operands(),
+            text: instruction.to_assembly(),
+            label: None,
+            line: None,
+            file: None,
+        }
+    }
+}
+
+/// One line per instruction of `program`, with its labels and source lines
+pub fn disassemble(program: &Program) -> Vec<DisasmLine> {
+    let mut offset = 0;
+    program.instructions.iter().enumerate().map(|(index, instruction)| {
+        let bytes = program.encode_instruction(index);
+        let mut line = DisasmLine::new(index, offset, instruction, bytes);
+        offset += line.bytes.len();
+        line.label = program.symbol_at(index).map(str::to_string);
+        if let Some((file, source_line)) = program.location(index) {
+            line.line = Some(source_line);
+            line.file = (program.file_index(index) != 0).then(|| file.to_string());
+        }
+        line
+    }).collect()
+}
+
+/// Decode a raw code section, taking source lines from `line_table`
+pub fn decode(code: &[u8], line_table: &[usize]) -> VmResult<Vec<DisasmLine>> {
+    let mut lines = Vec::new();
+    let mut offset = 0;
+    while offset < code.len() {
+        let (instruction, len) = Instruction::decode_at(&code[offset..], lines.len())?;
+        let mut line = DisasmLine::new(lines.len(), offset, &instruction, code[offset..offset + len].to_vec());
+        line.line = line_table.get(line.index).copied();
+        lines.push(line);
+        offset += len;
+    }
+    Ok(lines)
+}
+
+/// Full text listing of the program `name`: code size, the data section,
+/// then the program as reassemblable source
+pub fn listing(name: &str, program: &Program) -> String {
+    let code_size = program.code().len();
+    let mut out = String::new();
+    let _ = writeln!(out, "; Disassembly of '{}'", name);
+    let _ = writeln!(out, "; Code size: {} bytes\n", code_size);
+    out.push_str(&disassembler::annotate_data(&program.data, &program.instructions));
+    if !program.data.is_empty() {
+        out.push('\n');
+    }
+    out.push_str(&disassembler::to_source(program));
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler;
+    use crate::core::Register;
+
+    #[test]
+    fn test_disassemble() {
+        let program = assembler::assemble("@a := 7\nstart:\nprint @a\nhalt\n", "t").unwrap();
+        let lines = disassemble(&program);
+        assert_eq!(lines.len(), program.len());
+        assert_eq!((lines[0].mnemonic, lines[0].offset, lines[0].line), ("loadimm", 0, Some(1)));
+        assert_eq!(lines[0].operands, [Operand::Register(Register::R0), Operand::Immediate(7)]);
+        assert_eq!(lines[1].offset, lines[0].bytes.len());
+        assert_eq!((lines[1].label.as_deref(), lines[1].line), (Some("start"), Some(3)));
+
+        let code: Vec<u8> = lines.iter().flat_map(|line| line.bytes.clone()).collect();
+        let decoded = decode(&code, &program.line_table).unwrap();
+        assert_eq!(decoded.iter().map(|line| &line.text).collect::<Vec<_>>(), lines.iter().map(|line| &line.text).collect::<Vec<_>>());
+        assert!(listing("t.bin", &program).starts_with("; Disassembly of 't.bin'\n; Code size: "));
+    }
+}
@@ -1,4 +1,21 @@
-use crate::instruction::Instruction;
+use std::collections::BTreeSet;
+use std::fmt::Write;
+use crate::core::Register;
+use crate::error::VmResult;
+use crate::instruction::{Instruction, Program};
+
+/// One operand of a decoded instruction, in encoding order
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Operand {
+    Register(Register),
+    Immediate(u64),
+    Target(usize),
+}
+
+/// Label synthesized for a jump or call target
+pub fn label_for(target: usize) -> String {
+    format!("L_{:04x}", target)
+}
 
 impl Instruction {
     /// Convert instruction to assembly string
@@ -14,6 +31,9 @@ impl Instruction {
             Instruction::Mul { dest, left, right } => format!("mul {}, {}, {}", dest.name(), left.name(), right.name()),
             Instruction::Div { dest, left, right } => format!("div {}, {}, {}", dest.name(), left.name(), right.name()),
             Instruction::Mod { dest, left, right } => format!("mod {}, {}, {}", dest.name(), left.name(), right.name()),
+            Instruction::IDiv { dest, left, right } => format!("idiv {}, {}, {}", dest.name(), left.name(), right.name()),
+            Instruction::IMod { dest, left, right } => format!("imod {}, {}, {}", dest.name(), left.name(), right.name()),
+            Instruction::MulHi { dest, left, right } => format!("mulhi {}, {}, {}", dest.name(), left.name(), right.name()),
             Instruction::AddAssign { dest, src } => format!("addassign {}, {}", dest.name(), src.name()),
             Instruction::SubAssign { dest, src } => format!("subassign {}, {}", dest.name(), src.name()),
             Instruction::MulAssign { dest, src } => format!("mulassign {}, {}", dest.name(), src.name()),
@@ -24,11 +44,28 @@ impl Instruction {
             Instruction::Not { dest, src } => format!("not {}, {}", dest.name(), src.name()),
             Instruction::Shl { dest, left, right } => format!("shl {}, {}, {}", dest.name(), left.name(), right.name()),
             Instruction::Shr { dest, left, right } => format!("shr {}, {}, {}", dest.name(), left.name(), right.name()),
+            Instruction::ISht { dest, left, right } => format!("isht {}, {}, {}", dest.name(), left.name(), right.name()),
             Instruction::Push { src } => format!("push {}", src.name()),
+            Instruction::Exit { src } => format!("exit {}", src.name()),
             Instruction::Pop { dest } => format!("pop {}", dest.name()),
             Instruction::Peek { dest } => format!("peek {}", dest.name()),
+            Instruction::Rand { dest } => format!("rand {}", dest.name()),
+            Instruction::FeatQuery { dest, id } => format!("featquery {}, {}", dest.name(), id),
+            Instruction::Enter { locals_size } => format!("enter {}", locals_size),
+            Instruction::LoadLocal { dest, offset } => format!("loadlocal {}, [bp{:+}]", dest.name(), offset),
+            Instruction::StoreLocal { src, offset } => format!("storelocal {}, [bp{:+}]", src.name(), offset),
+            Instruction::Leave => "leave".to_string(),
+            Instruction::Breakpoint => "breakpoint".to_string(),
+            Instruction::TraceOn => "trace_on".to_string(),
+            Instruction::TraceOff => "trace_off".to_string(),
             Instruction::Load { dest, addr_reg } => format!("load {}, [{}]", dest.name(), addr_reg.name()),
             Instruction::Store { src, addr_reg } => format!("store {}, [{}]", src.name(), addr_reg.name()),
+            Instruction::LoadByte { dest, addr_reg } => format!("loadbyte {}, [{}]", dest.name(), addr_reg.name()),
+            Instruction::LoadWord { dest, addr_reg } => format!("loadword {}, [{}]", dest.name(), addr_reg.name()),
+            Instruction::LoadDWord { dest, addr_reg } => format!("loaddword {}, [{}]", dest.name(), addr_reg.name()),
+            Instruction::StoreByte { src, addr_reg } => format!("storebyte {}, [{}]", src.name(), addr_reg.name()),
+            Instruction::StoreWord { src, addr_reg } => format!("storeword {}, [{}]", src.name(), addr_reg.name()),
+            Instruction::StoreDWord { src, addr_reg } => format!("storedword {}, [{}]", src.name(), addr_reg.name()),
             Instruction::LoadIndexed { dest, base_reg, index_reg } => format!("loadindexed {}, [{} + {} * 8]", dest.name(), base_reg.name(), index_reg.name()),
             Instruction::StoreIndexed { src, base_reg, index_reg } => format!("storeindexed {}, [{} + {} * 8]", src.name(), base_reg.name(), index_reg.name()),
             Instruction::Alloc { dest, size } => format!("alloc {}, {}", dest.name(), size.name()),
@@ -39,12 +76,27 @@ impl Instruction {
             Instruction::FSub { dest, left, right } => format!("fsub {}, {}, {}", dest.name(), left.name(), right.name()),
             Instruction::FMul { dest, left, right } => format!("fmul {}, {}, {}", dest.name(), left.name(), right.name()),
             Instruction::FDiv { dest, left, right } => format!("fdiv {}, {}, {}", dest.name(), left.name(), right.name()),
+            Instruction::FMin { dest, left, right } => format!("fmin {}, {}, {}", dest.name(), left.name(), right.name()),
+            Instruction::FMax { dest, left, right } => format!("fmax {}, {}, {}", dest.name(), left.name(), right.name()),
             Instruction::FSqrt { dest, src } => format!("fsqrt {}, {}", dest.name(), src.name()),
             Instruction::FAbs { dest, src } => format!("fabs {}, {}", dest.name(), src.name()),
+            Instruction::FClass { dest, src } => format!("fclass {}, {}", dest.name(), src.name()),
+            Instruction::FFloor { dest, src } => format!("ffloor {}, {}", dest.name(), src.name()),
+            Instruction::FCeil { dest, src } => format!("fceil {}, {}", dest.name(), src.name()),
+            Instruction::FTrunc { dest, src } => format!("ftrunc {}, {}", dest.name(), src.name()),
+            Instruction::FRound { dest, src } => format!("fround {}, {}", dest.name(), src.name()),
             Instruction::FNeg { dest, src } => format!("fneg {}, {}", dest.name(), src.name()),
             Instruction::F2I { dest, src } => format!("f2i {}, {}", dest.name(), src.name()),
             Instruction::I2F { dest, src } => format!("i2f {}, {}", dest.name(), src.name()),
             Instruction::FCmp { left, right } => format!("fcmp {}, {}", left.name(), right.name()),
+            Instruction::FAdd32 { dest, left, right } => format!("fadd32 {}, {}, {}", dest.name(), left.name(), right.name()),
+            Instruction::FSub32 { dest, left, right } => format!("fsub32 {}, {}, {}", dest.name(), left.name(), right.name()),
+            Instruction::FMul32 { dest, left, right } => format!("fmul32 {}, {}, {}", dest.name(), left.name(), right.name()),
+            Instruction::FDiv32 { dest, left, right } => format!("fdiv32 {}, {}, {}", dest.name(), left.name(), right.name()),
+            Instruction::FSqrt32 { dest, src } => format!("fsqrt32 {}, {}", dest.name(), src.name()),
+            Instruction::FCmp32 { left, right } => format!("fcmp32 {}, {}", left.name(), right.name()),
+            Instruction::FDemote { dest, src } => format!("fdemote {}, {}", dest.name(), src.name()),
+            Instruction::FPromote { dest, src } => format!("fpromote {}, {}", dest.name(), src.name()),
             Instruction::PopCnt { dest, src } => format!("popcnt {}, {}", dest.name(), src.name()),
             Instruction::Clz { dest, src } => format!("clz {}, {}", dest.name(), src.name()),
             Instruction::Ctz { dest, src } => format!("ctz {}, {}", dest.name(), src.name()),
@@ -53,6 +105,7 @@ impl Instruction {
             Instruction::RotR { dest, left, right } => format!("rotr {}, {}, {}", dest.name(), left.name(), right.name()),
             Instruction::Jump { target } => format!("jump 0x{:x}", target),
             Instruction::Compare { left, right } => format!("compare {}, {}", left.name(), right.name()),
+            Instruction::Test { left, right } => format!("test {}, {}", left.name(), right.name()),
             Instruction::JumpIfZero { target } => format!("jz 0x{:x}", target),
             Instruction::JumpIfNotZero { target } => format!("jnz 0x{:x}", target),
             Instruction::JumpIfGt { target } => format!("jgt 0x{:x}", target),
@@ -65,9 +118,658 @@ impl Instruction {
             Instruction::JumpIfBelow { target } => format!("jb 0x{:x}", target),
             Instruction::JumpIfAe { target } => format!("jae 0x{:x}", target),
             Instruction::JumpIfBe { target } => format!("jbe 0x{:x}", target),
+            Instruction::Switch { index, count } => format!("switch {}, {}", index.name(), count),
             Instruction::Call { target } => format!("call 0x{:x}", target),
+            Instruction::CallReg { target_reg } => format!("callreg {}", target_reg.name()),
+            Instruction::Return => "return".to_string(),
+            Instruction::Syscall => "syscall".to_string(),
+            Instruction::Int { vector } => format!("int {}", vector),
+            Instruction::IRet => "iret".to_string(),
+        }
+    }
+
+    /// Jump or call target, if this instruction transfers control
+    pub fn target(&self) -> Option<usize> {
+        match self {
+            Instruction::Jump { target }
+            | Instruction::Call { target }
+            | Instruction::JumpIfZero { target }
+            | Instruction::JumpIfNotZero { target }
+            | Instruction::JumpIfGt { target }
+            | Instruction::JumpIfLt { target }
+            | Instruction::JumpIfGe { target }
+            | Instruction::JumpIfLe { target }
+            | Instruction::JumpIfEq { target }
+            | Instruction::JumpIfNe { target }
+            | Instruction::JumpIfAbove { target }
+            | Instruction::JumpIfBelow { target }
+            | Instruction::JumpIfAe { target }
+            | Instruction::JumpIfBe { target } => Some(*target),
+            _ => None,
+        }
+    }
+
+    /// Mutable jump or call target, for patching once labels are resolved
+    pub fn target_mut(&mut self) -> Option<&mut usize> {
+        match self {
+            Instruction::Jump { target }
+            | Instruction::Call { target }
+            | Instruction::JumpIfZero { target }
+            | Instruction::JumpIfNotZero { target }
+            | Instruction::JumpIfGt { target }
+            | Instruction::JumpIfLt { target }
+            | Instruction::JumpIfGe { target }
+            | Instruction::JumpIfLe { target }
+            | Instruction::JumpIfEq { target }
+            | Instruction::JumpIfNe { target }
+            | Instruction::JumpIfAbove { target }
+            | Instruction::JumpIfBelow { target }
+            | Instruction::JumpIfAe { target }
+            | Instruction::JumpIfBe { target } => Some(target),
+            _ => None,
+        }
+    }
+
+    /// Bytes a load or store instruction reads or writes
+    pub fn access_size(&self) -> Option<usize> {
+        match self {
+            Instruction::Load { .. } | Instruction::Store { .. }
+            | Instruction::LoadIndexed { .. } | Instruction::StoreIndexed { .. } => Some(8),
+            Instruction::LoadByte { .. } | Instruction::StoreByte { .. } => Some(1),
+            Instruction::LoadWord { .. } | Instruction::StoreWord { .. } => Some(2),
+            Instruction::LoadDWord { .. } | Instruction::StoreDWord { .. } => Some(4),
+            _ => None,
+        }
+    }
+
+    /// Operands in the order they are encoded
+    pub fn operands(&self) -> Vec<Operand> {
+        use Operand::Register as R;
+        match self {
+            Instruction::Halt | Instruction::Nop | Instruction::Return | Instruction::Syscall | Instruction::Leave
+            | Instruction::Breakpoint | Instruction::TraceOn | Instruction::TraceOff | Instruction::IRet => vec![],
+            Instruction::Int { vector } => vec![Operand::Immediate(*vector as u64)],
+            Instruction::Enter { locals_size } => vec![Operand::Immediate(*locals_size as u64)],
+            Instruction::LoadLocal { dest: reg, offset }
+            | Instruction::StoreLocal { src: reg, offset } => vec![R(*reg), Operand::Immediate(*offset as i64 as u64)],
+            Instruction::LoadImm { dest, value } => vec![R(*dest), Operand::Immediate(*value)],
+            Instruction::FeatQuery { dest, id } => vec![R(*dest), Operand::Immediate(*id as u64)],
+            Instruction::Push { src: reg }
+            | Instruction::Exit { src: reg }
+            | Instruction::Pop { dest: reg }
+            | Instruction::Peek { dest: reg }
+            | Instruction::Rand { dest: reg }
+            | Instruction::CallReg { target_reg: reg }
+            | Instruction::Free { ptr: reg } => vec![R(*reg)],
+            Instruction::Move { dest: a, src: b }
+            | Instruction::Not { dest: a, src: b }
+            | Instruction::Swap { r1: a, r2: b }
+            | Instruction::AddAssign { dest: a, src: b }
+            | Instruction::SubAssign { dest: a, src: b }
+            | Instruction::MulAssign { dest: a, src: b }
+            | Instruction::DivAssign { dest: a, src: b }
+            | Instruction::PopCnt { dest: a, src: b }
+            | Instruction::Clz { dest: a, src: b }
+            | Instruction::Ctz { dest: a, src: b }
+            | Instruction::BSwap { dest: a, src: b }
+            | Instruction::FSqrt { dest: a, src: b }
+            | Instruction::FSqrt32 { dest: a, src: b }
+            | Instruction::FDemote { dest: a, src: b }
+            | Instruction::FPromote { dest: a, src: b }
+            | Instruction::FAbs { dest: a, src: b }
+            | Instruction::FClass { dest: a, src: b }
+            | Instruction::FFloor { dest: a, src: b }
+            | Instruction::FCeil { dest: a, src: b }
+            | Instruction::FTrunc { dest: a, src: b }
+            | Instruction::FRound { dest: a, src: b }
+            | Instruction::FNeg { dest: a, src: b }
+            | Instruction::F2I { dest: a, src: b }
+            | Instruction::I2F { dest: a, src: b }
+            | Instruction::FCmp { left: a, right: b }
+            | Instruction::FCmp32 { left: a, right: b }
+            | Instruction::Compare { left: a, right: b }
+            | Instruction::Test { left: a, right: b }
+            | Instruction::Load { dest: a, addr_reg: b }
+            | Instruction::Store { src: a, addr_reg: b }
+            | Instruction::LoadByte { dest: a, addr_reg: b }
+            | Instruction::LoadWord { dest: a, addr_reg: b }
+            | Instruction::LoadDWord { dest: a, addr_reg: b }
+            | Instruction::StoreByte { src: a, addr_reg: b }
+            | Instruction::StoreWord { src: a, addr_reg: b }
+            | Instruction::StoreDWord { src: a, addr_reg: b }
+            | Instruction::Alloc { dest: a, size: b } => vec![R(*a), R(*b)],
+            Instruction::Switch { index, count } => vec![R(*index), Operand::Immediate(*count as u64)],
+            Instruction::Add { dest: a, left: b, right: c }
+            | Instruction::Sub { dest: a, left: b, right: c }
+            | Instruction::Mul { dest: a, left: b, right: c }
+            | Instruction::Div { dest: a, left: b, right: c }
+            | Instruction::Mod { dest: a, left: b, right: c }
+            | Instruction::IDiv { dest: a, left: b, right: c }
+            | Instruction::IMod { dest: a, left: b, right: c }
+            | Instruction::MulHi { dest: a, left: b, right: c }
+            | Instruction::And { dest: a, left: b, right: c }
+            | Instruction::Or { dest: a, left: b, right: c }
+            | Instruction::Xor { dest: a, left: b, right: c }
+            | Instruction::Shl { dest: a, left: b, right: c }
+            | Instruction::Shr { dest: a, left: b, right: c }
+            | Instruction::ISht { dest: a, left: b, right: c }
+            | Instruction::FAdd { dest: a, left: b, right: c }
+            | Instruction::FAdd32 { dest: a, left: b, right: c }
+            | Instruction::FSub32 { dest: a, left: b, right: c }
+            | Instruction::FMul32 { dest: a, left: b, right: c }
+            | Instruction::FDiv32 { dest: a, left: b, right: c }
+            | Instruction::FSub { dest: a, left: b, right: c }
+            | Instruction::FMul { dest: a, left: b, right: c }
+            | Instruction::FDiv { dest: a, left: b, right: c }
+            | Instruction::FMin { dest: a, left: b, right: c }
+            | Instruction::FMax { dest: a, left: b, right: c }
+            | Instruction::RotL { dest: a, left: b, right: c }
+            | Instruction::RotR { dest: a, left: b, right: c }
+            | Instruction::LoadIndexed { dest: a, base_reg: b, index_reg: c }
+            | Instruction::StoreIndexed { src: a, base_reg: b, index_reg: c }
+            | Instruction::MemCopy { dest: a, src: b, size: c }
+            | Instruction::MemSet { dest: a, value: b, size: c } => vec![R(*a), R(*b), R(*c)],
+            _ => self.target().map(Operand::Target).into_iter().collect(),
+        }
+    }
+
+    /// Operator and `unsigned` suffix of an `if` statement compiling to this jump
+    fn if_condition(&self) -> Option<(&'static str, &'static str)> {
+        match self {
+            Instruction::JumpIfEq { .. } => Some(("==", "")),
+            Instruction::JumpIfNe { .. } => Some(("!=", "")),
+            Instruction::JumpIfGt { .. } => Some((">", "")),
+            Instruction::JumpIfLt { .. } => Some(("<", "")),
+            Instruction::JumpIfGe { .. } => Some((">=", "")),
+            Instruction::JumpIfLe { .. } => Some(("<=", "")),
+            Instruction::JumpIfAbove { .. } => Some((">", " unsigned")),
+            Instruction::JumpIfBelow { .. } => Some(("<", " unsigned")),
+            Instruction::JumpIfAe { .. } => Some((">=", " unsigned")),
+            Instruction::JumpIfBe { .. } => Some(("<=", " unsigned")),
+            _ => None,
+        }
+    }
+
+    /// Convert instruction to a `.alya` source statement, naming targets with `label_for`
+    pub fn to_source(&self) -> String {
+        self.to_source_with(&label_for)
+    }
+
+    /// Convert instruction to a `.alya` source statement, naming targets with `label`
+    pub fn to_source_with(&self, label: &dyn Fn(usize) -> String) -> String {
+        match self {
+            Instruction::Halt => "halt".to_string(),
+            Instruction::Nop => "nop".to_string(),
+            Instruction::LoadImm { dest, value } if *value > 0xffff => format!("{} := 0x{:x}", dest, value),
+            Instruction::LoadImm { dest, value } => format!("{} := {}", dest, value),
+            Instruction::Move { dest, src } => format!("{} := {}", dest, src),
+            Instruction::Swap { r1, r2 } => format!("{} <=> {}", r1, r2),
+            Instruction::Add { dest, left, right } => format!("{} := {} + {}", dest, left, right),
+            Instruction::Sub { dest, left, right } => format!("{} := {} - {}", dest, left, right),
+            Instruction::Mul { dest, left, right } => format!("{} := {} * {}", dest, left, right),
+            Instruction::Div { dest, left, right } => format!("{} := {} / {}", dest, left, right),
+            Instruction::Mod { dest, left, right } => format!("{} := {} % {}", dest, left, right),
+            Instruction::IDiv { dest, left, right } => format!("{} := {} / {} signed", dest, left, right),
+            Instruction::IMod { dest, left, right } => format!("{} := {} % {} signed", dest, left, right),
+            Instruction::MulHi { dest, left, right } => format!("mulhi {} {} {}", dest, left, right),
+            Instruction::AddAssign { dest, src } => format!("{} += {}", dest, src),
+            Instruction::SubAssign { dest, src } => format!("{} -= {}", dest, src),
+            Instruction::MulAssign { dest, src } => format!("{} *= {}", dest, src),
+            Instruction::DivAssign { dest, src } => format!("{} /= {}", dest, src),
+            Instruction::And { dest, left, right } => format!("{} := {} & {}", dest, left, right),
+            Instruction::Or { dest, left, right } => format!("{} := {} | {}", dest, left, right),
+            Instruction::Xor { dest, left, right } => format!("{} := {} ^ {}", dest, left, right),
+            Instruction::Not { dest, src } => format!("{} := ~{}", dest, src),
+            Instruction::Shl { dest, left, right } => format!("{} := {} << {}", dest, left, right),
+            Instruction::Shr { dest, left, right } => format!("{} := {} >> {}", dest, left, right),
+            Instruction::ISht { dest, left, right } => format!("{} := {} >> {} signed", dest, left, right),
+            Instruction::Push { src } => format!("push {}", src),
+            Instruction::Exit { src } => format!("halt {}", src),
+            Instruction::Pop { dest } => format!("{} := pop", dest),
+            Instruction::Peek { dest } => format!("{} := peek", dest),
+            Instruction::Rand { dest } => format!("{} := rand", dest),
+            Instruction::FeatQuery { dest, id } => format!("{} := featquery {}", dest, id),
+            Instruction::Enter { locals_size } => format!("enter {}", locals_size),
+            Instruction::LoadLocal { dest, offset } => format!("{} := local {}", dest, offset),
+            Instruction::StoreLocal { src, offset } => format!("local {} := {}", offset, src),
+            Instruction::Leave => "leave".to_string(),
+            Instruction::Breakpoint => "breakpoint".to_string(),
+            Instruction::TraceOn => "trace_on".to_string(),
+            Instruction::TraceOff => "trace_off".to_string(),
+            Instruction::Load { dest, addr_reg } => format!("{} := load {}", dest, addr_reg),
+            Instruction::Store { src, addr_reg } => format!("store {} at {}", src, addr_reg),
+            Instruction::LoadByte { dest, addr_reg } => format!("{} := load.byte {}", dest, addr_reg),
+            Instruction::LoadWord { dest, addr_reg } => format!("{} := load.word {}", dest, addr_reg),
+            Instruction::LoadDWord { dest, addr_reg } => format!("{} := load.dword {}", dest, addr_reg),
+            Instruction::StoreByte { src, addr_reg } => format!("store.byte {} at {}", src, addr_reg),
+            Instruction::StoreWord { src, addr_reg } => format!("store.word {} at {}", src, addr_reg),
+            Instruction::StoreDWord { src, addr_reg } => format!("store.dword {} at {}", src, addr_reg),
+            Instruction::LoadIndexed { dest, base_reg, index_reg } => format!("{} := {}[{}]", dest, base_reg, index_reg),
+            Instruction::StoreIndexed { src, base_reg, index_reg } => format!("{}[{}] := {}", base_reg, index_reg, src),
+            Instruction::Alloc { dest, size } => format!("{} := alloc {}", dest, size),
+            Instruction::Free { ptr } => format!("free {}", ptr),
+            Instruction::MemCopy { dest, src, size } => format!("memcpy {} {} {}", dest, src, size),
+            Instruction::MemSet { dest, value, size } => format!("memset {} {} {}", dest, value, size),
+            Instruction::FAdd { dest, left, right } => format!("fadd {} {} {}", dest, left, right),
+            Instruction::FSub { dest, left, right } => format!("fsub {} {} {}", dest, left, right),
+            Instruction::FMul { dest, left, right } => format!("fmul {} {} {}", dest, left, right),
+            Instruction::FDiv { dest, left, right } => format!("fdiv {} {} {}", dest, left, right),
+            Instruction::FMin { dest, left, right } => format!("fmin {} {} {}", dest, left, right),
+            Instruction::FMax { dest, left, right } => format!("fmax {} {} {}", dest, left, right),
+            Instruction::FSqrt { dest, src } => format!("fsqrt {} {}", dest, src),
+            Instruction::FAbs { dest, src } => format!("fabs {} {}", dest, src),
+            Instruction::FClass { dest, src } => format!("fclass {} {}", dest, src),
+            Instruction::FFloor { dest, src } => format!("ffloor {} {}", dest, src),
+            Instruction::FCeil { dest, src } => format!("fceil {} {}", dest, src),
+            Instruction::FTrunc { dest, src } => format!("ftrunc {} {}", dest, src),
+            Instruction::FRound { dest, src } => format!("fround {} {}", dest, src),
+            Instruction::FNeg { dest, src } => format!("fneg {} {}", dest, src),
+            Instruction::F2I { dest, src } => format!("f2i {} {}", dest, src),
+            Instruction::I2F { dest, src } => format!("i2f {} {}", dest, src),
+            Instruction::FCmp { left, right } => format!("fcmp {} {}", left, right),
+            Instruction::FAdd32 { dest, left, right } => format!("fadd.f32 {} {} {}", dest, left, right),
+            Instruction::FSub32 { dest, left, right } => format!("fsub.f32 {} {} {}", dest, left, right),
+            Instruction::FMul32 { dest, left, right } => format!("fmul.f32 {} {} {}", dest, left, right),
+            Instruction::FDiv32 { dest, left, right } => format!("fdiv.f32 {} {} {}", dest, left, right),
+            Instruction::FSqrt32 { dest, src } => format!("fsqrt.f32 {} {}", dest, src),
+            Instruction::FCmp32 { left, right } => format!("fcmp.f32 {} {}", left, right),
+            Instruction::FDemote { dest, src } => format!("fdemote {} {}", dest, src),
+            Instruction::FPromote { dest, src } => format!("fpromote {} {}", dest, src),
+            Instruction::PopCnt { dest, src } => format!("popcnt {} {}", dest, src),
+            Instruction::Clz { dest, src } => format!("clz {} {}", dest, src),
+            Instruction::Ctz { dest, src } => format!("ctz {} {}", dest, src),
+            Instruction::BSwap { dest, src } => format!("bswap {} {}", dest, src),
+            Instruction::RotL { dest, left, right } => format!("rotl {} {} {}", dest, left, right),
+            Instruction::RotR { dest, left, right } => format!("rotr {} {} {}", dest, left, right),
+            Instruction::Jump { target } => format!("goto {}", label(*target)),
+            Instruction::Compare { left, right } => format!("compare {} {}", left, right),
+            Instruction::Test { left, right } => format!("test {} {}", left, right),
+            Instruction::JumpIfZero { target } => format!("jz {}", label(*target)),
+            Instruction::JumpIfNotZero { target } => format!("jnz {}", label(*target)),
+            Instruction::JumpIfGt { target } => format!("jgt {}", label(*target)),
+            Instruction::JumpIfLt { target } => format!("jlt {}", label(*target)),
+            Instruction::JumpIfGe { target } => format!("jge {}", label(*target)),
+            Instruction::JumpIfLe { target } => format!("jle {}", label(*target)),
+            Instruction::JumpIfEq { target } => format!("jeq {}", label(*target)),
+            Instruction::JumpIfNe { target } => format!("jne {}", label(*target)),
+            Instruction::JumpIfAbove { target } => format!("ja {}", label(*target)),
+            Instruction::JumpIfBelow { target } => format!("jb {}", label(*target)),
+            Instruction::JumpIfAe { target } => format!("jae {}", label(*target)),
+            Instruction::JumpIfBe { target } => format!("jbe {}", label(*target)),
+            Instruction::Switch { index, count } => format!("switch {} over {}", index, count),
+            Instruction::Call { target } => format!("call {}", label(*target)),
+            Instruction::CallReg { target_reg } => format!("call {}", target_reg),
             Instruction::Return => "return".to_string(),
             Instruction::Syscall => "syscall".to_string(),
+            Instruction::Int { vector } => format!("int {}", vector),
+            Instruction::IRet => "iret".to_string(),
+        }
+    }
+}
+
+/// Render a program as `.alya` source that reassembles to the same code
+/// and data. The data section comes first as directives, with a `d_XXXX:`
+/// label on each item an instruction loads, and those loads read `&d_XXXX`.
+/// Symbols become labels and name the jumps and calls into them; other
+/// targets get an `L_XXXX:` label. A compare followed by a conditional jump
+/// is folded back into an `if ... goto` statement, and source lines from the
+/// line table are kept as trailing comments, after a comment naming the
+/// register of each variable.
+pub fn to_source(program: &Program) -> String {
+    let instructions = &program.instructions;
+    let targets: BTreeSet<usize> = instructions.iter().filter_map(Instruction::target).collect();
+    let label = |target: usize| program.symbol_at(target).map_or_else(|| label_for(target), str::to_string);
+    let mut out = String::new();
+    if !program.variables.is_empty() {
+        let variables: Vec<String> = program.variables.iter().map(|(name, reg)| format!("{} ({})", name, reg.name())).collect();
+        let _ = writeln!(out, "; variables: {}\n", variables.join(", "));
+    }
+    let data_labels = data_source(program, &mut out);
+
+    let mut i = 0;
+    while i <= instructions.len() {
+        let mut names = program.symbols.iter().filter(|&(_, &idx)| idx == i).map(|(name, _)| name).peekable();
+        if names.peek().is_some() {
+            if i > 0 {
+                out.push('\n');
+            }
+            for name in names {
+                let _ = writeln!(out, "{}:", name);
+            }
+        } else if targets.contains(&i) {
+            let _ = writeln!(out, "{}:", label_for(i));
+        }
+
+        // A jump to the end of the program still needs a label to land on
+        if i == instructions.len() {
+            break;
+        }
+
+        let fused = match (&instructions[i], instructions.get(i + 1)) {
+            (Instruction::Compare { left, right }, Some(jump)) if !targets.contains(&(i + 1)) => {
+                jump.if_condition().zip(jump.target()).map(|((op, suffix), target)| {
+                    format!("if {} {} {}{} goto {}", left, op, right, suffix, label(target))
+                }).map(|statement| (statement, 2))
+            }
+            (Instruction::Switch { index, count }, _) => {
+                // The jump table reads back as the label list
+                let count = *count as usize;
+                let table = instructions.get(i + 1..i + 1 + count).unwrap_or_default();
+                let labels: Option<Vec<String>> = table.iter().map(|jump| match jump {
+                    Instruction::Jump { target } => Some(label(*target)),
+                    _ => None,
+                }).collect();
+                labels.filter(|labels| !labels.is_empty() && labels.len() == count && (i + 1..i + 1 + count).all(|j| !targets.contains(&j)))
+                    .map(|labels| (format!("switch {} goto {}", index, labels.join(", ")), count + 1))
+            }
+            _ => None,
+        };
+        let fused = fused.or_else(|| match instructions[i] {
+            Instruction::LoadImm { dest, value } if data_labels.contains(&(value as usize)) => {
+                Some((format!("{} := &{}", dest, data_label_for(value as usize)), 1))
+            }
+            _ => None,
+        });
+        let (statement, width) = fused.unwrap_or_else(|| (instructions[i].to_source_with(&label), 1));
+
+        match program.location(i) {
+            Some((file, line)) if program.file_index(i) != 0 => { let _ = writeln!(out, "    {:<36} ; {}:{}", statement, file, line); }
+            Some((_, line)) => { let _ = writeln!(out, "    {:<36} ; line {}", statement, line); }
+            None => { let _ = writeln!(out, "    {}", statement); }
+        }
+        i += width;
+    }
+
+    out
+}
+
+/// Name given to the data item at `offset` in source listings
+pub fn data_label_for(offset: usize) -> String {
+    format!("d_{:04x}", offset)
+}
+
+/// Write the data section as directives that rebuild it byte for byte, and
+/// return the offsets given a label because some instruction loads them
+fn data_source(program: &Program, out: &mut String) -> BTreeSet<usize> {
+    let data = &program.data;
+    let writable = program.writable_range();
+    let mut labels = BTreeSet::new();
+    for (range, directive) in [(0..writable.start, None), (writable, Some(".data"))] {
+        let entries = section_entries(data, range.start, range.end, &program.instructions);
+        if entries.is_empty() {
+            continue;
+        }
+        if let Some(directive) = directive {
+            let _ = writeln!(out, "{}", directive);
+        }
+        for entry in entries {
+            let name = if entry.loaded_by.is_empty() {
+                String::new()
+            } else {
+                labels.insert(entry.offset);
+                format!("{}: ", data_label_for(entry.offset))
+            };
+            let bytes = &data[entry.offset..entry.offset + entry.item.len()];
+            // Strings cannot hold quotes or line breaks, and `.qword` aligns
+            let statement = match &entry.item {
+                DataItem::String(text) if !text.contains(['"', '\n', '\r']) => format!(".string \"{}\"", text),
+                DataItem::Qwords(values) if entry.offset.is_multiple_of(8) => {
+                    let values: Vec<String> = values.iter().map(|v| format!("0x{:x}", v)).collect();
+                    format!(".qword {}", values.join(", "))
+                }
+                _ => {
+                    let bytes: Vec<String> = bytes.iter().map(|b| format!("0x{:02x}", b)).collect();
+                    format!(".byte {}", bytes.join(", "))
+                }
+            };
+            let _ = writeln!(out, "{}{}", name, statement);
+        }
+        out.push('\n');
+    }
+    labels
+}
+
+/// Kind of run recognised in the data section
+#[derive(Debug, Clone, PartialEq)]
+pub enum DataItem {
+    /// Null-terminated string (the terminator is not included)
+    String(String),
+    /// Table of little-endian qwords
+    Qwords(Vec<u64>),
+    /// Bytes that fit neither shape
+    Bytes(Vec<u8>),
+}
+
+impl DataItem {
+    /// Size in bytes, including a string's terminator
+    pub fn len(&self) -> usize {
+        match self {
+            DataItem::String(text) => text.len() + 1,
+            DataItem::Qwords(values) => values.len() * 8,
+            DataItem::Bytes(bytes) => bytes.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// A data section item with the instructions that load its address
+#[derive(Debug, Clone, PartialEq)]
+pub struct DataEntry {
+    pub offset: usize,
+    pub item: DataItem,
+    pub loaded_by: Vec<usize>,
+}
+
+/// Length of the null-terminated string at `offset`, if there is one.
+/// An empty string only counts when some instruction loads its address.
+fn string_at(data: &[u8], offset: usize, referenced: &BTreeSet<usize>) -> Option<usize> {
+    let printable = |b: u8| (0x20..0x7f).contains(&b) || matches!(b, b'\n' | b'\t' | b'\r');
+    let end = offset + data[offset..].iter().take_while(|&&b| printable(b)).count();
+    let terminated = data.get(end) == Some(&0);
+    (terminated && (end > offset || referenced.contains(&offset))).then_some(end - offset + 1)
+}
+
+/// Split the data section into strings, qword tables and loose bytes, and
+/// cross-reference the `LoadImm` instructions that load each item's address.
+/// The data section is loaded at address 0, so offsets double as addresses.
+pub fn data_entries(data: &[u8], instructions: &[Instruction]) -> Vec<DataEntry> {
+    section_entries(data, 0, data.len(), instructions)
+}
+
+/// `data_entries` for `data[start..end]`, which is split on its own
+fn section_entries(data: &[u8], start: usize, end: usize, instructions: &[Instruction]) -> Vec<DataEntry> {
+    let loads: Vec<(usize, usize)> = instructions.iter().enumerate()
+        .filter_map(|(i, instr)| match instr {
+            Instruction::LoadImm { value, .. } if (start..end).contains(&(*value as usize)) => Some((i, *value as usize)),
+            _ => None,
+        })
+        .collect();
+    let referenced: BTreeSet<usize> = loads.iter().map(|&(_, addr)| addr).collect();
+    let data = &data[..end];
+
+    let mut items = Vec::new();
+    let mut loose = Vec::new();
+    let mut offset = start;
+    while offset < data.len() {
+        let start = offset;
+        let item = if let Some(len) = string_at(data, offset, &referenced) {
+            offset += len;
+            DataItem::String(String::from_utf8_lossy(&data[start..offset - 1]).into_owned())
+        } else if offset + 8 <= data.len() {
+            let mut values = Vec::new();
+            // Short printable runs are common inside qwords, so only a referenced
+            // address or a longer string ends the table
+            let ends_table = |at: usize| referenced.contains(&at) || string_at(data, at, &referenced).is_some_and(|len| len > 3);
+            while offset + 8 <= data.len() && (offset == start || !ends_table(offset)) {
+                values.push(u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap()));
+                offset += 8;
+            }
+            DataItem::Qwords(values)
+        } else {
+            loose.push(data[offset]);
+            offset += 1;
+            continue;
+        };
+
+        if !loose.is_empty() {
+            let bytes = std::mem::take(&mut loose);
+            items.push((start - bytes.len(), DataItem::Bytes(bytes)));
         }
+        items.push((start, item));
+    }
+    if !loose.is_empty() {
+        items.push((data.len() - loose.len(), DataItem::Bytes(loose)));
+    }
+
+    items.into_iter()
+        .map(|(offset, item)| {
+            let loaded_by = loads.iter().filter(|&&(_, addr)| addr == offset).map(|&(i, _)| i).collect();
+            DataEntry { offset, item, loaded_by }
+        })
+        .collect()
+}
+
+/// Describe the data section as `;` comments, so the listing still assembles
+pub fn annotate_data(data: &[u8], instructions: &[Instruction]) -> String {
+    let mut out = String::new();
+    if data.is_empty() {
+        return out;
+    }
+
+    let _ = writeln!(out, "; Data section: {} bytes", data.len());
+    for entry in data_entries(data, instructions) {
+        let desc = match &entry.item {
+            DataItem::String(text) => format!("string {:?}", text),
+            DataItem::Qwords(values) => {
+                let values: Vec<String> = values.iter().map(|v| format!("0x{:x}", v)).collect();
+                format!("qword[{}] {}", values.len(), values.join(", "))
+            }
+            DataItem::Bytes(bytes) => {
+                let bytes: Vec<String> = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+                format!("bytes {}", bytes.join(" "))
+            }
+        };
+        if entry.loaded_by.is_empty() {
+            let _ = writeln!(out, ";   {:04x}  {}", entry.offset, desc);
+        } else {
+            let refs: Vec<String> = entry.loaded_by.iter().map(|i| format!("{:04x}", i)).collect();
+            let _ = writeln!(out, ";   {:04x}  {:<40} loaded by {}", entry.offset, desc, refs.join(", "));
+        }
+    }
+    out
+}
+
+/// Quote a string as a JSON string literal
+pub(crate) fn json_string(text: &str) -> String {
+    let mut out = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            c if (c as u32) < 0x20 => { let _ = write!(out, "\\u{:04x}", c as u32); }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Decode `code` into a JSON document with one record per instruction:
+/// its index, byte offset, opcode name, operands, raw bytes and source line.
+pub fn to_json(name: &str, code: &[u8], line_table: &[usize]) -> VmResult<String> {
+    let records: Vec<String> = super::disasm::decode(code, line_table)?.iter().map(|line| {
+        let operands: Vec<String> = line.operands.iter().map(|op| match op {
+            Operand::Register(reg) => format!("{{\"register\":{}}}", json_string(reg.name())),
+            Operand::Immediate(value) => format!("{{\"immediate\":{}}}", value),
+            Operand::Target(target) => format!("{{\"target\":{}}}", target),
+        }).collect();
+        let bytes: Vec<String> = line.bytes.iter().map(|b| b.to_string()).collect();
+        format!(
+            "{{\"index\":{},\"offset\":{},\"opcode\":{},\"operands\":[{}],\"bytes\":[{}],\"line\":{}}}",
+            line.index, line.offset, json_string(line.mnemonic), operands.join(","), bytes.join(","),
+            line.line.map_or("null".to_string(), |line| line.to_string()),
+        )
+    }).collect();
+
+    Ok(format!(
+        "{{\"name\":{},\"code_size\":{},\"instructions\":[\n  {}\n]}}\n",
+        json_string(name), code.len(), records.join(",\n  "),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler;
+    use crate::core::Register;
+
+    #[test]
+    fn test_source_round_trip() {
+        let source = "@n := 5\nloop:\n@n -= 1\nif @n > 0 goto loop\ncall f\n@p := alloc @n\nhalt\nf:\nreturn\n";
+        let program = assembler::assemble(source, "t").unwrap();
+        let listing = to_source(&program);
+        assert!(listing.starts_with("; variables: n (r0), p (r2)\n"));
+        assert!(listing.contains("\nloop:\n") && listing.contains("\nf:\n"));
+        assert!(listing.contains("if @r0 > @r1 goto loop"));
+        assert!(listing.contains("call f "));
+        assert_eq!(assembler::assemble(&listing, "t").unwrap().instructions, program.instructions);
+
+        // Branches the assembler never fuses still reassemble
+        let raw = vec![
+            Instruction::Compare { left: Register::R0, right: Register::R1 },
+            Instruction::JumpIfZero { target: 3 },
+            Instruction::JumpIfBelow { target: 1 },
+            Instruction::Jump { target: 4 },
+        ];
+        let listing = to_source(&Program::from_instructions("t", raw.clone()));
+        assert!(listing.contains("jz L_0003") && listing.ends_with("L_0004:\n"));
+        assert_eq!(assembler::assemble(&listing, "t").unwrap().instructions, raw);
+
+        // The data section is rebuilt byte for byte, and loads name it
+        let source = "msg: .string \"hi\"\nodd: .byte 1\ntable: .qword 7, 8\nquote: .byte 0x22, 0\n\
+            .data\ncount: .qword 3\n@a := msg\n@b := table\n@c := count\nhalt\n";
+        let program = assembler::assemble(source, "t").unwrap();
+        let listing = to_source(&program);
+        assert!(listing.contains("d_0000: .string \"hi\"") && listing.contains("@r0 := &d_0000"));
+        let again = assembler::assemble(&listing, "t").unwrap();
+        assert_eq!(again.instructions, program.instructions);
+        assert_eq!((again.data, again.writable_len), (program.data, program.writable_len));
+    }
+
+    #[test]
+    fn test_to_json() {
+        let code: Vec<u8> = [
+            Instruction::LoadImm { dest: Register::R1, value: 7 },
+            Instruction::Jump { target: 0 },
+        ].iter().flat_map(Instruction::encode).collect();
+        let json = to_json("a \"b\"", &code, &[3]).unwrap();
+        assert!(json.starts_with("{\"name\":\"a \\\"b\\\"\",\"code_size\":12,"));
+        assert!(json.contains("\"index\":0,\"offset\":0,\"opcode\":\"loadimm\",\"operands\":[{\"register\":\"r1\"},{\"immediate\":7}]"));
+        assert!(json.contains("\"offset\":3,\"opcode\":\"jump\",\"operands\":[{\"target\":0}],\"bytes\":[112,0,0,0,0,0,0,0,0],\"line\":null"));
+    }
+
+    #[test]
+    fn test_data_entries() {
+        let mut data = b"Hi\0\0".to_vec();
+        data.extend_from_slice(&7u64.to_le_bytes());
+        data.extend_from_slice(&9u64.to_le_bytes());
+        data.push(0xff);
+        let instructions = vec![
+            Instruction::LoadImm { dest: Register::R0, value: 0 },
+            Instruction::LoadImm { dest: Register::R1, value: 3 },
+            Instruction::LoadImm { dest: Register::R2, value: 4 },
+            Instruction::LoadImm { dest: Register::R3, value: 500 },
+        ];
+        let entries = data_entries(&data, &instructions);
+        assert_eq!(entries, vec![
+            DataEntry { offset: 0, item: DataItem::String("Hi".into()), loaded_by: vec![0] },
+            DataEntry { offset: 3, item: DataItem::String(String::new()), loaded_by: vec![1] },
+            DataEntry { offset: 4, item: DataItem::Qwords(vec![7, 9]), loaded_by: vec![2] },
+            DataEntry { offset: 20, item: DataItem::Bytes(vec![0xff]), loaded_by: vec![] },
+        ]);
     }
 }
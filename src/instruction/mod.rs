@@ -2,13 +2,17 @@
 //!
 //! Provides:
 //! - Instruction enum (data-only representation)
-//! - Program container
+//! - Program container and builder
+//! - Structured disassembly and listings
+//! - Load-time validation
 
 mod types;
 mod program;
 
 pub use types::Instruction;
-pub use program::Program;
+pub use program::{Condition, Metadata, Program, ProgramBuilder};
 
 pub mod binary;
+pub mod disasm;
 pub mod disassembler;
+pub mod validate;
@@ -1,14 +1,69 @@
 //! Program container — a sequence of instructions.
 
+use std::collections::BTreeMap;
+use std::path::Path;
 use super::Instruction;
+use super::disassembler::Operand;
+use crate::core::Register;
+use crate::error::{ErrorCode, VmError};
 
 /// A program is a named sequence of instructions.
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Program {
     pub name: String,
     pub instructions: Vec<Instruction>,
+    /// Data section, loaded at address 0: read-only data (`.rodata`)
+    /// followed by `writable_len` bytes the program may write (`.data`)
     pub data: Vec<u8>,
+    /// Length of the writable data at the end of `data`
+    pub writable_len: usize,
+    /// Encode jump and call targets relative to the instruction, so the
+    /// code section can be placed anywhere (see `Instruction::encode_relative`)
+    pub relative_jumps: bool,
     pub line_table: Vec<usize>,
+    /// Label name to instruction index (debug info)
+    pub symbols: BTreeMap<String, usize>,
+    /// Source files that instructions came from; index 0 is the assembled
+    /// file and the rest are files it included (debug info)
+    pub files: Vec<String>,
+    /// Index into `files` of each instruction's source line. Empty when
+    /// every line is from the assembled file.
+    pub file_table: Vec<usize>,
+    /// Variable name to the register the assembler gave it (debug info).
+    /// Variables whose live ranges do not overlap may share a register.
+    pub variables: BTreeMap<String, Register>,
+    /// Resource name to the data-section offset and length of the file
+    /// embedded by `.resource`
+    pub resources: BTreeMap<String, (usize, usize)>,
+    /// Where the program came from, when the toolchain recorded it
+    pub metadata: Option<Metadata>,
+}
+
+/// Description of a program recorded by the toolchain that built it
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Metadata {
+    /// Program name, usually the source file name without its extension
+    pub name: String,
+    /// Toolchain that built the program, such as `alya 0.1.0`
+    pub toolchain: String,
+    /// Build time in seconds since the Unix epoch, if recorded
+    pub built: Option<u64>,
+    /// Free-form description supplied by the author
+    pub description: Option<String>,
+}
+
+impl Metadata {
+    /// Metadata naming this toolchain, with no build time or description
+    pub fn new(name: impl Into<String>) -> Self {
+        Metadata {
+            name: name.into(),
+            toolchain: format!("alya {}", env!("CARGO_PKG_VERSION")),
+            built: None,
+            description: None,
+        }
+    }
 }
 
 impl Program {
@@ -18,7 +73,15 @@ impl Program {
             name: name.into(),
             instructions: Vec::new(),
             data: Vec::new(),
+            writable_len: 0,
+            relative_jumps: false,
             line_table: Vec::new(),
+            symbols: BTreeMap::new(),
+            files: Vec::new(),
+            file_table: Vec::new(),
+            variables: BTreeMap::new(),
+            resources: BTreeMap::new(),
+            metadata: None,
         }
     }
 
@@ -28,7 +91,15 @@ impl Program {
             name: name.into(),
             instructions,
             data,
+            writable_len: 0,
+            relative_jumps: false,
             line_table: Vec::new(),
+            symbols: BTreeMap::new(),
+            files: Vec::new(),
+            file_table: Vec::new(),
+            variables: BTreeMap::new(),
+            resources: BTreeMap::new(),
+            metadata: None,
         }
     }
 
@@ -38,10 +109,31 @@ impl Program {
             name: name.into(),
             instructions,
             data: Vec::new(),
+            writable_len: 0,
+            relative_jumps: false,
             line_table: Vec::new(),
+            symbols: BTreeMap::new(),
+            files: Vec::new(),
+            file_table: Vec::new(),
+            variables: BTreeMap::new(),
+            resources: BTreeMap::new(),
+            metadata: None,
         }
     }
 
+    /// Encoded instruction at `index`, in the program's jump encoding
+    pub fn encode_instruction(&self, index: usize) -> Vec<u8> {
+        match self.relative_jumps {
+            true => self.instructions[index].encode_relative(index),
+            false => self.instructions[index].encode(),
+        }
+    }
+
+    /// The encoded code section
+    pub fn code(&self) -> Vec<u8> {
+        (0..self.len()).flat_map(|index| self.encode_instruction(index)).collect()
+    }
+
     /// Add an instruction
     pub fn push(&mut self, instruction: Instruction) {
         self.instructions.push(instruction);
@@ -57,8 +149,354 @@ impl Program {
         self.instructions.len()
     }
 
+    /// Find the first instruction generated for a line of the assembled file.
+    /// Falls forward to the next line with code, like most debuggers do.
+    pub fn index_for_line(&self, line: usize) -> Option<usize> {
+        self.index_for_file_line(0, line)
+    }
+
+    /// Like `index_for_line`, for a line of `files[file]`
+    pub fn index_for_file_line(&self, file: usize, line: usize) -> Option<usize> {
+        self.line_table.iter()
+            .enumerate()
+            .filter(|&(idx, &l)| l >= line && self.file_index(idx) == file)
+            .min_by_key(|&(idx, &l)| (l, idx))
+            .map(|(idx, _)| idx)
+    }
+
+    /// Index into `files` of the file an instruction came from
+    pub fn file_index(&self, index: usize) -> usize {
+        self.file_table.get(index).copied().unwrap_or(0)
+    }
+
+    /// Source file and line of an instruction, if the line table has it.
+    /// Programs without a file list name the file after the program.
+    pub fn location(&self, index: usize) -> Option<(&str, usize)> {
+        let line = *self.line_table.get(index)?;
+        let file = self.files.get(self.file_index(index)).map_or(self.name.as_str(), String::as_str);
+        Some((file, line))
+    }
+
+    /// Index into `files` of the file named `name`, matching a bare file
+    /// name against a path that ends with it
+    pub fn find_file(&self, name: &str) -> Option<usize> {
+        self.files.iter().position(|file| file == name)
+            .or_else(|| self.files.iter().position(|file| Path::new(file).file_name() == Some(name.as_ref())))
+    }
+
+    /// Names of the variables given `reg`, in alphabetical order
+    pub fn variables_in(&self, reg: Register) -> Vec<&str> {
+        self.variables.iter().filter(|&(_, &r)| r == reg).map(|(name, _)| name.as_str()).collect()
+    }
+
+    /// Name of a symbol located exactly at an instruction index
+    pub fn symbol_at(&self, index: usize) -> Option<&str> {
+        self.symbols.iter()
+            .find(|&(_, &idx)| idx == index)
+            .map(|(name, _)| name.as_str())
+    }
+
+    /// Whether any instruction names one of the extended registers R16-R63
+    pub fn uses_extended_registers(&self) -> bool {
+        self.instructions.iter().flat_map(Instruction::operands).any(|operand| {
+            matches!(operand, Operand::Register(reg) if reg.is_extended())
+        })
+    }
+
     /// Check if program is empty
     pub fn is_empty(&self) -> bool {
         self.instructions.is_empty()
     }
+
+    /// Address range of the writable data
+    pub fn writable_range(&self) -> std::ops::Range<usize> {
+        self.data.len() - self.writable_len..self.data.len()
+    }
+
+    /// Drop the line, symbol, file and variable tables. Resources and
+    /// metadata stay, since the program may still need them.
+    pub fn strip_debug_info(&mut self) {
+        self.line_table.clear();
+        self.symbols.clear();
+        self.files.clear();
+        self.file_table.clear();
+        self.variables.clear();
+    }
+}
+
+/// Flag test performed by a conditional jump
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Condition {
+    Zero,
+    NotZero,
+    Eq,
+    Ne,
+    Gt,
+    Lt,
+    Ge,
+    Le,
+    /// Unsigned greater than
+    Above,
+    /// Unsigned less than
+    Below,
+    /// Unsigned greater or equal
+    AboveEq,
+    /// Unsigned less or equal
+    BelowEq,
+}
+
+impl Condition {
+    /// The conditional jump testing this condition
+    pub fn jump(self, target: usize) -> Instruction {
+        match self {
+            Condition::Zero => Instruction::JumpIfZero { target },
+            Condition::NotZero => Instruction::JumpIfNotZero { target },
+            Condition::Eq => Instruction::JumpIfEq { target },
+            Condition::Ne => Instruction::JumpIfNe { target },
+            Condition::Gt => Instruction::JumpIfGt { target },
+            Condition::Lt => Instruction::JumpIfLt { target },
+            Condition::Ge => Instruction::JumpIfGe { target },
+            Condition::Le => Instruction::JumpIfLe { target },
+            Condition::Above => Instruction::JumpIfAbove { target },
+            Condition::Below => Instruction::JumpIfBelow { target },
+            Condition::AboveEq => Instruction::JumpIfAe { target },
+            Condition::BelowEq => Instruction::JumpIfBe { target },
+        }
+    }
+}
+
+/// Builds a program from Rust code, resolving jump and call labels on `build()`.
+///
+/// ```
+/// use alya_vm::Register::*;
+/// use alya_vm::instruction::{Condition, ProgramBuilder};
+///
+/// let program = ProgramBuilder::new("count")
+///     .load_imm(R0, 0)
+///     .load_imm(R1, 10)
+///     .label("loop")
+///     .add_imm(R0, 1)
+///     .compare(R0, R1)
+///     .jump_if(Condition::Lt, "loop")
+///     .halt()
+///     .build()
+///     .unwrap();
+/// assert_eq!(program.symbols["loop"], 2);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct ProgramBuilder {
+    name: String,
+    instructions: Vec<Instruction>,
+    labels: BTreeMap<String, usize>,
+    /// Instruction index and label name of each unresolved target
+    fixups: Vec<(usize, String)>,
+    duplicate: Option<String>,
+}
+
+impl ProgramBuilder {
+    pub fn new(name: impl Into<String>) -> Self {
+        Self { name: name.into(), ..Self::default() }
+    }
+
+    /// Define a label at the next instruction
+    pub fn label(&mut self, name: &str) -> &mut Self {
+        if self.labels.insert(name.to_string(), self.instructions.len()).is_some() {
+            self.duplicate.get_or_insert_with(|| name.to_string());
+        }
+        self
+    }
+
+    /// Append any instruction as-is
+    pub fn emit(&mut self, instruction: Instruction) -> &mut Self {
+        self.instructions.push(instruction);
+        self
+    }
+
+    /// Append a jump or call whose target is filled in from `label`
+    fn emit_to(&mut self, instruction: Instruction, label: &str) -> &mut Self {
+        self.fixups.push((self.instructions.len(), label.to_string()));
+        self.emit(instruction)
+    }
+
+    pub fn jump_to(&mut self, label: &str) -> &mut Self {
+        self.emit_to(Instruction::Jump { target: 0 }, label)
+    }
+
+    pub fn jump_if(&mut self, condition: Condition, label: &str) -> &mut Self {
+        self.emit_to(condition.jump(0), label)
+    }
+
+    pub fn call(&mut self, label: &str) -> &mut Self {
+        self.emit_to(Instruction::Call { target: 0 }, label)
+    }
+
+    pub fn load_imm(&mut self, dest: Register, value: u64) -> &mut Self {
+        self.emit(Instruction::LoadImm { dest, value })
+    }
+
+    pub fn mov(&mut self, dest: Register, src: Register) -> &mut Self {
+        self.emit(Instruction::Move { dest, src })
+    }
+
+    pub fn add(&mut self, dest: Register, left: Register, right: Register) -> &mut Self {
+        self.emit(Instruction::Add { dest, left, right })
+    }
+
+    pub fn sub(&mut self, dest: Register, left: Register, right: Register) -> &mut Self {
+        self.emit(Instruction::Sub { dest, left, right })
+    }
+
+    pub fn mul(&mut self, dest: Register, left: Register, right: Register) -> &mut Self {
+        self.emit(Instruction::Mul { dest, left, right })
+    }
+
+    pub fn div(&mut self, dest: Register, left: Register, right: Register) -> &mut Self {
+        self.emit(Instruction::Div { dest, left, right })
+    }
+
+    /// Add an immediate to a register, using R15 as scratch
+    pub fn add_imm(&mut self, dest: Register, value: u64) -> &mut Self {
+        self.load_imm(Register::R15, value)
+            .emit(Instruction::AddAssign { dest, src: Register::R15 })
+    }
+
+    pub fn compare(&mut self, left: Register, right: Register) -> &mut Self {
+        self.emit(Instruction::Compare { left, right })
+    }
+
+    pub fn push(&mut self, src: Register) -> &mut Self {
+        self.emit(Instruction::Push { src })
+    }
+
+    pub fn pop(&mut self, dest: Register) -> &mut Self {
+        self.emit(Instruction::Pop { dest })
+    }
+
+    pub fn load(&mut self, dest: Register, addr_reg: Register) -> &mut Self {
+        self.emit(Instruction::Load { dest, addr_reg })
+    }
+
+    pub fn store(&mut self, src: Register, addr_reg: Register) -> &mut Self {
+        self.emit(Instruction::Store { src, addr_reg })
+    }
+
+    /// Print a register as an integer, preserving R0 and R1 like `print @reg` does
+    pub fn print(&mut self, reg: Register) -> &mut Self {
+        self.push(Register::R0)
+            .push(Register::R1)
+            .mov(Register::R1, reg)
+            .load_imm(Register::R0, 1)
+            .syscall()
+            .pop(Register::R1)
+            .pop(Register::R0)
+    }
+
+    pub fn syscall(&mut self) -> &mut Self {
+        self.emit(Instruction::Syscall)
+    }
+
+    pub fn ret(&mut self) -> &mut Self {
+        self.emit(Instruction::Return)
+    }
+
+    pub fn halt(&mut self) -> &mut Self {
+        self.emit(Instruction::Halt)
+    }
+
+    /// Resolve labels and produce the program. Labels become its symbols.
+    pub fn build(&self) -> Result<Program, VmError> {
+        if let Some(name) = &self.duplicate {
+            return Err(VmError::assembler(ErrorCode::DuplicateLabel, format!("Duplicate label: '{}'", name)));
+        }
+
+        let mut instructions = self.instructions.clone();
+        for (index, label) in &self.fixups {
+            let target = self.labels.get(label)
+                .ok_or_else(|| VmError::assembler(ErrorCode::UndefinedLabel, format!("Undefined label: '{}'", label)))?;
+            if let Some(slot) = instructions[*index].target_mut() {
+                *slot = *target;
+            }
+        }
+
+        let mut program = Program::from_instructions(self.name.clone(), instructions);
+        program.symbols = self.labels.clone();
+        Ok(program)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_index_for_line() {
+        let mut program = Program::from_instructions("t", vec![Instruction::Nop; 4]);
+        program.line_table = vec![2, 2, 5, 7];
+        assert_eq!(program.index_for_line(2), Some(0));
+        assert_eq!(program.index_for_line(3), Some(2));
+        assert_eq!(program.index_for_line(7), Some(3));
+        assert_eq!(program.index_for_line(8), None);
+
+        // Lines 2 and 3 of an included file
+        program.files = vec!["t".to_string(), "lib/util.alya".to_string()];
+        program.file_table = vec![0, 1, 1, 0];
+        program.line_table = vec![1, 2, 3, 4];
+        assert_eq!(program.index_for_line(2), Some(3));
+        assert_eq!(program.find_file("util.alya"), Some(1));
+        assert_eq!(program.index_for_file_line(1, 2), Some(1));
+        assert_eq!(program.location(2), Some(("lib/util.alya", 3)));
+    }
+
+    #[test]
+    fn test_strip_debug_info() {
+        let mut program = crate::assembler::assemble("start:\n@a := 1\nprint @a\nhalt\n", "t").unwrap();
+        let instructions = program.instructions.clone();
+        program.strip_debug_info();
+        assert_eq!(program.instructions, instructions);
+        assert!(program.line_table.is_empty() && program.symbols.is_empty() && program.variables.is_empty());
+        assert_eq!(program.location(0), None);
+        assert!(program.metadata.is_some());
+    }
+
+    #[test]
+    fn test_builder_resolves_labels() {
+        let program = ProgramBuilder::new("t")
+            .jump_to("main")
+            .label("double")
+            .add(Register::R0, Register::R0, Register::R0)
+            .ret()
+            .label("main")
+            .call("double")
+            .halt()
+            .build()
+            .unwrap();
+        assert_eq!(program.instructions[0], Instruction::Jump { target: 3 });
+        assert_eq!(program.instructions[3], Instruction::Call { target: 1 });
+        assert_eq!(program.symbol_at(3), Some("main"));
+
+        let err = ProgramBuilder::new("t").jump_to("nowhere").build().unwrap_err();
+        assert_eq!(err, VmError::assembler(ErrorCode::UndefinedLabel, "Undefined label: 'nowhere'"));
+        assert!(ProgramBuilder::new("t").label("a").label("a").build().is_err());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_round_trip() {
+        use crate::core::Register;
+        let mut program = Program::from_instructions("t", vec![
+            Instruction::LoadImm { dest: Register::R0, value: 7 },
+            Instruction::Call { target: 0 },
+        ]);
+        program.symbols.insert("start".to_string(), 0);
+
+        let json = serde_json::to_string(&program).unwrap();
+        let back: Program = serde_json::from_str(&json).unwrap();
+        assert_eq!(back.instructions, program.instructions);
+        assert_eq!(back.symbols, program.symbols);
+
+        let mut ctx = crate::execution::ExecutionContext::new();
+        ctx.set_reg(Register::F15, 3);
+        let back: crate::execution::ExecutionContext = serde_json::from_str(&serde_json::to_string(&ctx).unwrap()).unwrap();
+        assert_eq!(back.get_reg(Register::F15), 3);
+    }
 }
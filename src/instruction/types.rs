@@ -1,15 +1,19 @@
 //! Instruction type definitions.
 //!
-//! Each variant holds just the data needed for execution.
-//! The VM's executor dispatches on these variants.
+//! Each variant holds just the data needed for execution, so instructions
+//! are `Copy`. The VM pre-decodes them into its own dispatch table.
 
 use crate::core::Register;
 
 /// A single VM instruction with its operands.
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub enum Instruction {
     // === Control ===
     Halt,
+    /// Halt with the value of `src` as the program's exit status
+    Exit { src: Register },
     Nop,
 
     // === Data Movement ===
@@ -27,6 +31,11 @@ pub enum Instruction {
     Mul { dest: Register, left: Register, right: Register },
     Div { dest: Register, left: Register, right: Register },
     Mod { dest: Register, left: Register, right: Register },
+    /// Signed (two's complement) division and remainder
+    IDiv { dest: Register, left: Register, right: Register },
+    IMod { dest: Register, left: Register, right: Register },
+    /// Upper 64 bits of the unsigned 128-bit product
+    MulHi { dest: Register, left: Register, right: Register },
 
     // === Compound Assignment ===
     /// dest += src (or immediate)
@@ -42,11 +51,22 @@ pub enum Instruction {
     Not { dest: Register, src: Register },
     Shl { dest: Register, left: Register, right: Register },
     Shr { dest: Register, left: Register, right: Register },
+    /// Arithmetic shift right, copying the sign bit
+    ISht { dest: Register, left: Register, right: Register },
 
     // === Stack ===
     Push { src: Register },
     Pop { dest: Register },
     Peek { dest: Register },
+    /// Push BP, point BP at it and reserve `locals_size` bytes (rounded up
+    /// to whole qwords) of zeroed locals below it
+    Enter { locals_size: u32 },
+    /// Drop the frame `Enter` set up: SP back to BP, then pop BP
+    Leave,
+    /// Load the qword at BP + offset
+    LoadLocal { dest: Register, offset: i32 },
+    /// Store a qword at BP + offset
+    StoreLocal { src: Register, offset: i32 },
 
     // === Memory ===
     /// Load from memory address in src register into dest
@@ -65,18 +85,44 @@ pub enum Instruction {
     MemCopy { dest: Register, src: Register, size: Register },
     /// memset(dst_reg, value_reg, size_reg)
     MemSet { dest: Register, value: Register, size: Register },
+    /// Load 8, 16 or 32 bits from the address in addr_reg, zero-extended
+    LoadByte { dest: Register, addr_reg: Register },
+    LoadWord { dest: Register, addr_reg: Register },
+    LoadDWord { dest: Register, addr_reg: Register },
+    /// Store the low 8, 16 or 32 bits of src to the address in addr_reg
+    StoreByte { src: Register, addr_reg: Register },
+    StoreWord { src: Register, addr_reg: Register },
+    StoreDWord { src: Register, addr_reg: Register },
 
     // === Floating Point ===
     FAdd { dest: Register, left: Register, right: Register },
     FSub { dest: Register, left: Register, right: Register },
     FMul { dest: Register, left: Register, right: Register },
     FDiv { dest: Register, left: Register, right: Register },
+    FMin { dest: Register, left: Register, right: Register },
+    FMax { dest: Register, left: Register, right: Register },
     FSqrt { dest: Register, src: Register },
     FAbs { dest: Register, src: Register },
+    FClass { dest: Register, src: Register },
+    FFloor { dest: Register, src: Register },
+    FCeil { dest: Register, src: Register },
+    FTrunc { dest: Register, src: Register },
+    FRound { dest: Register, src: Register },
     FNeg { dest: Register, src: Register },
     F2I { dest: Register, src: Register },
     I2F { dest: Register, src: Register },
     FCmp { left: Register, right: Register },
+    /// Single precision: operands and results are f32 in the low 32 bits
+    FAdd32 { dest: Register, left: Register, right: Register },
+    FSub32 { dest: Register, left: Register, right: Register },
+    FMul32 { dest: Register, left: Register, right: Register },
+    FDiv32 { dest: Register, left: Register, right: Register },
+    FSqrt32 { dest: Register, src: Register },
+    FCmp32 { left: Register, right: Register },
+    /// Round an f64 to the nearest f32
+    FDemote { dest: Register, src: Register },
+    /// Widen an f32 to f64, exactly
+    FPromote { dest: Register, src: Register },
 
     // === Bit Manipulation ===
     PopCnt { dest: Register, src: Register },
@@ -91,6 +137,8 @@ pub enum Instruction {
     Jump { target: usize },
     /// Compare two registers, set flags
     Compare { left: Register, right: Register },
+    /// Set flags from `left & right`, discarding the result
+    Test { left: Register, right: Register },
     /// Conditional jumps (use flags set by Compare)
     JumpIfZero { target: usize },
     JumpIfNotZero { target: usize },
@@ -104,14 +152,36 @@ pub enum Instruction {
     JumpIfBelow { target: usize },
     JumpIfAe { target: usize },
     JumpIfBe { target: usize },
+    /// Multi-way jump: the next `count` instructions are a table of jumps;
+    /// take the one `index` selects, or skip the table if it is out of range
+    Switch { index: Register, count: u32 },
 
     // === Functions ===
     /// Call: push return address, jump to target
     Call { target: usize },
+    /// Call the instruction index held in a register
+    CallReg { target_reg: Register },
     /// Return: pop return address, jump back
     Return,
 
     // === System ===
     /// System Call
     Syscall,
+    /// Next value of the VM's seeded random number generator
+    Rand { dest: Register },
+    /// Load the `core::features` word selected by `id`
+    FeatQuery { dest: Register, id: u8 },
+    /// Software interrupt: push the flags and the next instruction, then
+    /// jump to the handler registered for `vector`
+    Int { vector: u8 },
+    /// Return from an interrupt or trap handler: pop the pc and flags
+    IRet,
+
+    // === Debug ===
+    /// Software breakpoint: pauses a debugger, does nothing otherwise
+    Breakpoint,
+    /// Turn on instruction tracing (`ctx.trace`)
+    TraceOn,
+    /// Turn off instruction tracing
+    TraceOff,
 }
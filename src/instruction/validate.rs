@@ -0,0 +1,305 @@
+//! Load-time bytecode validation.
+//!
+//! `validate` checks a program before it runs, so that mistakes a compiler
+//! or hand-written bytecode can make are reported with the offending
+//! instruction instead of surfacing as a fault midway through a run:
+//!
+//! - jump and call targets lie inside the program (its end counts, and halts),
+//!   as do `switch` jump tables
+//! - constant addresses below the heap that are loaded from or printed lie
+//!   inside the data section, and those stored to lie inside its writable
+//!   part (`.data`)
+//! - no `pop`/`peek` can run while the stack is certainly empty
+//! - no `return` is reachable from the entry point outside any call
+//!
+//! Registers need no check of their own: decoding rejects register numbers
+//! the VM does not have, and a `Program` cannot hold one.
+//!
+//! Addresses are only checked where a `loadimm` feeds them within a basic
+//! block, so the pass never rejects a program over a value it cannot see.
+
+use crate::core::Register;
+use crate::error::{ErrorCode, VmError, VmResult};
+use crate::memory::layout::HEAP_START;
+use super::disassembler::Operand;
+use super::{Instruction, Program};
+
+/// Syscall id that prints the string at R1
+const PRINT_STRING: u64 = 2;
+
+/// Check `program` for invalid targets, data references, stack underflow
+/// and unbalanced returns
+pub fn validate(program: &Program) -> VmResult<()> {
+    check_targets(program)?;
+    check_data_references(program)?;
+    check_stack(program)?;
+    check_returns(program)
+}
+
+/// Decode a raw code section, reporting the byte offset of any bad
+/// encoding, then `validate` the result
+pub fn validate_bytes(code: &[u8]) -> VmResult<Program> {
+    let mut instructions = Vec::new();
+    let mut offset = 0;
+    while offset < code.len() {
+        let (instruction, len) = Instruction::decode_at(&code[offset..], instructions.len()).map_err(|e| match e {
+            VmError::Execution(mut e) => {
+                e.message = format!("At byte {:#x}: {}", offset, e.message);
+                e.pc = Some(instructions.len());
+                VmError::Execution(e)
+            }
+            other => other,
+        })?;
+        instructions.push(instruction);
+        offset += len;
+    }
+    let program = Program::from_instructions("bytes", instructions);
+    validate(&program)?;
+    Ok(program)
+}
+
+fn check_targets(program: &Program) -> VmResult<()> {
+    for (pc, instruction) in program.instructions.iter().enumerate() {
+        if let Some(target) = instruction.target() {
+            if target > program.len() {
+                return Err(VmError::execution(ErrorCode::InvalidTarget, format!(
+                    "{} target {} is outside the program ({} instructions)",
+                    instruction.opcode().name(), target, program.len()
+                )).with_pc(pc));
+            }
+        }
+        if let Instruction::Switch { count, .. } = instruction {
+            if pc + *count as usize >= program.len() {
+                return Err(VmError::execution(ErrorCode::InvalidTarget, format!(
+                    "switch table of {} jump(s) runs past the end of the program ({} instructions)", count, program.len()
+                )).with_pc(pc));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn check_data_references(program: &Program) -> VmResult<()> {
+    // Indexed by pc; targets past the end start no block that runs
+    let mut block_starts = vec![false; program.instructions.len()];
+    for (pc, instruction) in program.instructions.iter().enumerate() {
+        let start = match instruction {
+            Instruction::Switch { count, .. } => Some(pc + 1 + *count as usize),
+            other => other.target(),
+        };
+        if let Some(start) = start.and_then(|start| block_starts.get_mut(start)) {
+            *start = true;
+        }
+    }
+    let data_len = program.data.len();
+    let mut known = [None::<u64>; Register::COUNT];
+
+    let read_past_data = |pc: usize, addr: u64, len: u64| {
+        VmError::execution(ErrorCode::DataReference, format!(
+            "Reads {} byte(s) at {:#x}, past the end of the {}-byte data section", len, addr, data_len
+        )).with_pc(pc).with_address(addr as usize)
+    };
+
+    for (pc, instruction) in program.instructions.iter().enumerate() {
+        if block_starts[pc] {
+            known = [None; Register::COUNT];
+        }
+        let value = |reg: &Register| known[reg.to_u8() as usize];
+        let in_data_region = |addr: u64| addr < HEAP_START as u64;
+
+        match instruction {
+            Instruction::Load { addr_reg, .. }
+            | Instruction::LoadByte { addr_reg, .. }
+            | Instruction::LoadWord { addr_reg, .. }
+            | Instruction::LoadDWord { addr_reg, .. } => {
+                let len = instruction.access_size().unwrap_or(8) as u64;
+                if let Some(addr) = value(addr_reg).filter(|&a| in_data_region(a)) {
+                    if addr + len > data_len as u64 {
+                        return Err(read_past_data(pc, addr, len));
+                    }
+                }
+            }
+            Instruction::LoadIndexed { base_reg, index_reg, .. } => {
+                if let (Some(base), Some(index)) = (value(base_reg), value(index_reg)) {
+                    let addr = base.wrapping_add(index.wrapping_mul(8));
+                    if in_data_region(addr) && addr + 8 > data_len as u64 {
+                        return Err(read_past_data(pc, addr, 8));
+                    }
+                }
+            }
+            Instruction::Store { addr_reg, .. }
+            | Instruction::StoreByte { addr_reg, .. }
+            | Instruction::StoreWord { addr_reg, .. }
+            | Instruction::StoreDWord { addr_reg, .. } => {
+                let len = instruction.access_size().unwrap_or(8) as u64;
+                let writable = program.writable_range();
+                let in_writable = |addr: u64| addr >= writable.start as u64 && addr + len <= writable.end as u64;
+                if let Some(addr) = value(addr_reg).filter(|&a| in_data_region(a) && !in_writable(a)) {
+                    return Err(VmError::execution(ErrorCode::DataReference, format!(
+                        "Stores to {:#x} outside the writable data section", addr
+                    )).with_pc(pc).with_address(addr as usize));
+                }
+            }
+            Instruction::Syscall if value(&Register::R0) == Some(PRINT_STRING) => {
+                if let Some(addr) = value(&Register::R1).filter(|&a| in_data_region(a)) {
+                    if addr >= data_len as u64 {
+                        return Err(read_past_data(pc, addr, 1));
+                    }
+                }
+            }
+            _ => {}
+        }
+
+        // Track which registers hold constants after this instruction
+        match instruction {
+            Instruction::LoadImm { dest, value } => known[dest.to_u8() as usize] = Some(*value),
+            Instruction::Move { dest, src } => known[dest.to_u8() as usize] = value(src),
+            Instruction::Syscall => known[Register::R0.to_u8() as usize] = None,
+            Instruction::Call { .. } | Instruction::CallReg { .. } => known = [None; Register::COUNT],
+            Instruction::Enter { .. } | Instruction::Leave => known[Register::BP.to_u8() as usize] = None,
+            other => {
+                for operand in other.operands() {
+                    if let Operand::Register(reg) = operand {
+                        known[reg.to_u8() as usize] = None;
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Stack depth on entry to an instruction
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Depth {
+    Known(usize),
+    /// Reached with different depths, or from a call
+    Unknown,
+}
+
+fn check_stack(program: &Program) -> VmResult<()> {
+    let len = program.len();
+    let mut depth: Vec<Option<Depth>> = vec![None; len];
+    let mut worklist = Vec::new();
+
+    let enter = |depth: &mut Vec<Option<Depth>>, worklist: &mut Vec<usize>, pc: usize, incoming: Depth| {
+        if pc >= len {
+            return;
+        }
+        let merged = match depth[pc] {
+            None => incoming,
+            Some(current) if current == incoming => return,
+            Some(_) => Depth::Unknown,
+        };
+        if depth[pc] != Some(merged) {
+            depth[pc] = Some(merged);
+            worklist.push(pc);
+        }
+    };
+
+    enter(&mut depth, &mut worklist, 0, Depth::Known(0));
+    while let Some(pc) = worklist.pop() {
+        let before = depth[pc].unwrap_or(Depth::Unknown);
+        let instruction = &program.instructions[pc];
+
+        let after = match (instruction, before) {
+            (Instruction::Pop { .. } | Instruction::Peek { .. }, Depth::Known(0)) => {
+                return Err(VmError::execution(ErrorCode::StackUnderflow, format!(
+                    "{} on an empty stack", instruction.opcode().name()
+                )).with_pc(pc));
+            }
+            (Instruction::Push { .. }, Depth::Known(n)) => Depth::Known(n + 1),
+            (Instruction::Pop { .. }, Depth::Known(n)) => Depth::Known(n - 1),
+            (Instruction::Enter { locals_size }, Depth::Known(n)) => Depth::Known(n + 1 + locals_size.div_ceil(8) as usize),
+            // Where BP points is not tracked
+            (Instruction::Leave, _) => Depth::Unknown,
+            _ => before,
+        };
+
+        match instruction {
+            Instruction::Halt | Instruction::Exit { .. } | Instruction::Return | Instruction::IRet => {}
+            Instruction::Jump { target } => enter(&mut depth, &mut worklist, *target, after),
+            Instruction::Switch { count, .. } => {
+                for next in pc + 1..=pc + 1 + *count as usize {
+                    enter(&mut depth, &mut worklist, next, after);
+                }
+            }
+            Instruction::Call { target } => {
+                // Callees may push or pop on the caller's behalf
+                enter(&mut depth, &mut worklist, *target, Depth::Unknown);
+                enter(&mut depth, &mut worklist, pc + 1, Depth::Unknown);
+            }
+            Instruction::CallReg { .. } => enter(&mut depth, &mut worklist, pc + 1, Depth::Unknown),
+            other => {
+                if let Some(target) = other.target() {
+                    enter(&mut depth, &mut worklist, target, after);
+                }
+                enter(&mut depth, &mut worklist, pc + 1, after);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Follow the code reachable from the entry point without entering a call.
+/// A `return` there would pop an empty call stack.
+fn check_returns(program: &Program) -> VmResult<()> {
+    let mut seen = vec![false; program.len()];
+    let mut worklist = vec![0];
+    while let Some(pc) = worklist.pop() {
+        if pc >= program.len() || seen[pc] {
+            continue;
+        }
+        seen[pc] = true;
+        match &program.instructions[pc] {
+            Instruction::Halt | Instruction::Exit { .. } | Instruction::IRet => {}
+            Instruction::Return => {
+                return Err(VmError::execution(
+                    ErrorCode::ReturnWithoutCall, "return is reachable from the entry point outside any call",
+                ).with_pc(pc));
+            }
+            Instruction::Jump { target } => worklist.push(*target),
+            Instruction::Switch { count, .. } => worklist.extend(pc + 1..=pc + 1 + *count as usize),
+            // The callee comes back to the next instruction
+            Instruction::Call { .. } | Instruction::CallReg { .. } => worklist.push(pc + 1),
+            other => {
+                worklist.extend(other.target());
+                worklist.push(pc + 1);
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler;
+    use crate::instruction::ProgramBuilder;
+
+    #[test]
+    fn test_validate_examples_and_faults() {
+        let program = assembler::assemble("@r0 := 1\nprint @r0\nloop:\n@r0 -= 1\nif @r0 > 0 goto loop\nhalt\n", "ok").unwrap();
+        assert!(validate(&program).is_ok());
+
+        let bad_target = Program::from_instructions("t", vec![Instruction::Jump { target: 5 }]);
+        assert_eq!(validate(&bad_target).unwrap_err().code(), ErrorCode::InvalidTarget);
+
+        let underflow = ProgramBuilder::new("u").load_imm(Register::R0, 1).pop(Register::R1).build().unwrap();
+        let err = validate(&underflow).unwrap_err();
+        assert_eq!((err.code(), err.pc()), (ErrorCode::StackUnderflow, Some(1)));
+
+        let past_data = ProgramBuilder::new("d").load_imm(Register::R1, 0x40).load(Register::R0, Register::R1).build().unwrap();
+        let err = validate(&past_data).unwrap_err();
+        assert_eq!((err.code(), err.address()), (ErrorCode::DataReference, Some(0x40)));
+
+        let stray_return = ProgramBuilder::new("r").call("f").jump_to("f").label("f").ret().build().unwrap();
+        let err = validate(&stray_return).unwrap_err();
+        assert_eq!((err.code(), err.pc()), (ErrorCode::ReturnWithoutCall, Some(2)));
+
+        let mut truncated = Instruction::LoadImm { dest: Register::R0, value: 1 << 40 }.encode();
+        truncated.truncate(5);
+        let err = validate_bytes(&[Instruction::Nop.encode(), truncated].concat()).unwrap_err();
+        assert_eq!((err.code(), err.pc()), (ErrorCode::TruncatedBytecode, Some(1)));
+    }
+}
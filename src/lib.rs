@@ -10,6 +10,13 @@
 //! - `instruction` — Instruction types + program container
 //! - `execution` — VM execution engine
 //! - `assembler` — Source-to-instruction assembler pipeline
+//! - `loader` — Reading and writing `.bin` files
+//! - `linker` — Object files and linking them into one program
+//! - `archive` — Static library archives of object files
+//! - `analysis` — Call graph, call depth and reachability of bytecode
+//! - `fuzz` — Capped decode-and-run entry points for fuzzers
+//! - `ffi` — C ABI for embedding (with the `ffi` feature)
+//! - `wasm` — Browser playground bindings (with the `wasm` feature)
 
 pub mod core;
 pub mod error;
@@ -17,6 +24,15 @@ pub mod memory;
 pub mod instruction;
 pub mod execution;
 pub mod assembler;
+pub mod loader;
+pub mod linker;
+pub mod archive;
+pub mod analysis;
+pub mod fuzz;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+#[cfg(feature = "wasm")]
+pub mod wasm;
 
 // Re-export commonly used types
 pub use core::{Register, Opcode, Flags};
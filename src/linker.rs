@@ -0,0 +1,335 @@
+//! Object files and the linker that combines them into one program.
+//!
+//! `assembler::assemble_object` produces an `ObjectFile`: code and data as
+//! in a binary, numbered from the object's own start, plus what is needed
+//! to move them. `link` lays the objects' code out one after another, in
+//! order, so the first object's code runs first, and their data the same
+//! way, read-only parts first, then writable (`.data`) parts. It then
+//!
+//! - shifts every jump and call target, and every code address loaded
+//!   with `&label`, by the object's code offset,
+//! - moves every data address the object loads to where its data went, and
+//! - points each relocation at the label it names.
+//!
+//! Each object's labels and data labels are visible to the others, apart
+//! from names starting with `__`, which the assembler generates. A name
+//! defined by several objects is only an error when a relocation refers to
+//! it. Argument counts of procedures called across objects are not checked,
+//! and a resource embedded by several objects resolves to the first one.
+//! The linked program keeps the first object's metadata, and encodes jumps
+//! relative when every object does.
+//!
+//! An object file is written like a binary (see `loader`) with the magic
+//! `ALYO`, and four more sections between the data and the line table:
+//! data labels (name, offset), data references (instruction index), code
+//! references (instruction index) and relocations (instruction index,
+//! name). Its symbol table is required.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use crate::error::{ErrorCode, VmError, VmResult};
+use crate::instruction::{Instruction, Program};
+use crate::loader::{self, Reader};
+
+/// First four bytes of every object file
+pub const OBJECT_MAGIC: &[u8; 4] = b"ALYO";
+
+/// An instruction that refers to a label defined in another object
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Relocation {
+    /// Jump, call, or address-loading `LoadImm` to patch
+    pub index: usize,
+    /// Label or data label it refers to
+    pub symbol: String,
+}
+
+/// Assembled code that still has to be linked
+#[derive(Debug, Clone)]
+pub struct ObjectFile {
+    /// Instructions, data and debug info; `symbols` lists the code labels
+    pub program: Program,
+    /// Data label name to data-section offset
+    pub data_symbols: BTreeMap<String, usize>,
+    /// Instructions that load a data-section address
+    pub data_refs: Vec<usize>,
+    /// Instructions that load a code address
+    pub code_refs: Vec<usize>,
+    /// Instructions that refer to labels defined elsewhere
+    pub relocations: Vec<Relocation>,
+}
+
+impl ObjectFile {
+    /// Read an object file. The program is named `binary` until the caller
+    /// renames it.
+    pub fn load(bytes: &[u8]) -> VmResult<Self> {
+        let mut reader = Reader::after_header(bytes, OBJECT_MAGIC, "object file")?;
+        let mut program = loader::read_code_and_data(&mut reader)?;
+
+        let mut data_symbols = BTreeMap::new();
+        for _ in 0..reader.count("data label")? {
+            let name = reader.section("data label")?;
+            data_symbols.insert(String::from_utf8_lossy(name).to_string(), reader.count("data label")?);
+        }
+        let mut data_refs = Vec::new();
+        for _ in 0..reader.count("data reference")? {
+            data_refs.push(reader.count("data reference")?);
+        }
+        let mut code_refs = Vec::new();
+        for _ in 0..reader.count("code reference")? {
+            code_refs.push(reader.count("code reference")?);
+        }
+        let mut relocations = Vec::new();
+        for _ in 0..reader.count("relocation")? {
+            let index = reader.count("relocation")?;
+            let symbol = String::from_utf8_lossy(reader.section("relocation")?).to_string();
+            relocations.push(Relocation { index, symbol });
+        }
+
+        loader::read_debug_info(&mut reader, &mut program);
+        let references = data_refs.iter().chain(&code_refs).chain(relocations.iter().map(|r| &r.index));
+        if let Some(index) = references.into_iter().find(|&&index| index >= program.len()) {
+            return Err(loader::invalid(format!("Object file refers to instruction {} of {}", index, program.len())));
+        }
+        Ok(ObjectFile { program, data_symbols, data_refs, code_refs, relocations })
+    }
+
+    /// Encode the object in the format `load` reads
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = loader::header(OBJECT_MAGIC);
+        loader::write_code_and_data(&mut bytes, &self.program);
+
+        loader::write_u64(&mut bytes, self.data_symbols.len());
+        for (name, &offset) in &self.data_symbols {
+            loader::write_bytes(&mut bytes, name.as_bytes());
+            loader::write_u64(&mut bytes, offset);
+        }
+        loader::write_u64(&mut bytes, self.data_refs.len());
+        for &index in &self.data_refs {
+            loader::write_u64(&mut bytes, index);
+        }
+        loader::write_u64(&mut bytes, self.code_refs.len());
+        for &index in &self.code_refs {
+            loader::write_u64(&mut bytes, index);
+        }
+        loader::write_u64(&mut bytes, self.relocations.len());
+        for relocation in &self.relocations {
+            loader::write_u64(&mut bytes, relocation.index);
+            loader::write_bytes(&mut bytes, relocation.symbol.as_bytes());
+        }
+
+        loader::write_debug_info(&mut bytes, &self.program);
+        bytes
+    }
+}
+
+/// Where a label ended up in the linked program
+#[derive(Debug, Clone, Copy)]
+enum Symbol {
+    Code(usize),
+    Data(usize),
+}
+
+/// Where one object's read-only and writable data go in the linked program
+struct DataLayout {
+    rodata_len: usize,
+    rodata_base: usize,
+    data_base: usize,
+}
+
+impl DataLayout {
+    /// Linked address of the object's data-section `offset`
+    fn address(&self, offset: usize) -> usize {
+        match offset.checked_sub(self.rodata_len) {
+            Some(offset) => self.data_base + offset,
+            None => self.rodata_base + offset,
+        }
+    }
+}
+
+/// Combine objects into one program named `linked`. Errors name the
+/// source line of the instruction whose label could not be resolved.
+pub fn link(objects: &[ObjectFile]) -> VmResult<Program> {
+    let mut code_bases = Vec::with_capacity(objects.len());
+    let mut layouts = Vec::with_capacity(objects.len());
+    let (mut code_len, mut data_len) = (0, 0usize);
+    for object in objects {
+        // Keep each object's qwords aligned as the assembler laid them out
+        data_len = data_len.next_multiple_of(8);
+        code_bases.push(code_len);
+        layouts.push(DataLayout { rodata_len: object.program.writable_range().start, rodata_base: data_len, data_base: 0 });
+        code_len += object.program.len();
+        data_len += object.program.writable_range().start;
+    }
+    let rodata_end = data_len;
+    for (object, layout) in objects.iter().zip(&mut layouts) {
+        data_len = data_len.next_multiple_of(8);
+        layout.data_base = data_len;
+        data_len += object.program.writable_len;
+    }
+
+    let mut globals: HashMap<&str, Vec<(usize, Symbol)>> = HashMap::new();
+    for (i, object) in objects.iter().enumerate() {
+        let code = object.program.symbols.iter().map(|(name, &index)| (name, Symbol::Code(code_bases[i] + index)));
+        let data = object.data_symbols.iter().map(|(name, &offset)| (name, Symbol::Data(layouts[i].address(offset))));
+        for (name, symbol) in code.chain(data).filter(|(name, _)| !name.starts_with("__")) {
+            globals.entry(name).or_default().push((i, symbol));
+        }
+    }
+
+    let mut program = Program::new("linked");
+    program.data = vec![0; data_len];
+    program.writable_len = data_len - rodata_end;
+    program.relative_jumps = !objects.is_empty() && objects.iter().all(|object| object.program.relative_jumps);
+    program.metadata = objects.first().and_then(|object| object.program.metadata.clone());
+    let debug_info = objects.iter().all(|object| object.program.line_table.len() == object.program.len());
+    for (i, object) in objects.iter().enumerate() {
+        let relocations: HashMap<usize, &str> = object.relocations.iter()
+            .map(|relocation| (relocation.index, relocation.symbol.as_str()))
+            .collect();
+        let data_refs: HashSet<usize> = object.data_refs.iter().copied().collect();
+        let code_refs: HashSet<usize> = object.code_refs.iter().copied().collect();
+        for (index, instruction) in object.program.instructions.iter().enumerate() {
+            let mut instruction = *instruction;
+            let code_ref = code_refs.contains(&index);
+            match relocations.get(&index) {
+                Some(name) => resolve(&mut instruction, name, code_ref, &globals, objects, object, index)?,
+                None => {
+                    if let Some(target) = instruction.target_mut() {
+                        *target += code_bases[i];
+                    }
+                    if let Instruction::LoadImm { value, .. } = &mut instruction {
+                        if data_refs.contains(&index) {
+                            *value = layouts[i].address(*value as usize) as u64;
+                        } else if code_ref {
+                            *value += code_bases[i] as u64;
+                        }
+                    }
+                }
+            }
+            program.instructions.push(instruction);
+        }
+
+        let (rodata, data) = object.program.data.split_at(layouts[i].rodata_len);
+        program.data[layouts[i].rodata_base..layouts[i].rodata_base + rodata.len()].copy_from_slice(rodata);
+        program.data[layouts[i].data_base..layouts[i].data_base + data.len()].copy_from_slice(data);
+        for (name, &index) in &object.program.symbols {
+            program.symbols.entry(name.clone()).or_insert(code_bases[i] + index);
+        }
+        for (name, &reg) in &object.program.variables {
+            program.variables.entry(name.clone()).or_insert(reg);
+        }
+        for (name, &(offset, len)) in &object.program.resources {
+            program.resources.entry(name.clone()).or_insert((layouts[i].address(offset), len));
+        }
+        if debug_info {
+            program.line_table.extend(&object.program.line_table);
+            for index in 0..object.program.len() {
+                let file = object.program.location(index).map_or(object.program.name.as_str(), |(file, _)| file);
+                let file = match program.files.iter().position(|name| name == file) {
+                    Some(position) => position,
+                    None => {
+                        program.files.push(file.to_string());
+                        program.files.len() - 1
+                    }
+                };
+                program.file_table.push(file);
+            }
+        }
+    }
+    Ok(program)
+}
+
+/// Point the instruction at `index` of `object` at the label `name`.
+/// `code_ref` marks a `LoadImm` that takes the address of a code label.
+fn resolve(
+    instruction: &mut Instruction,
+    name: &str,
+    code_ref: bool,
+    globals: &HashMap<&str, Vec<(usize, Symbol)>>,
+    objects: &[ObjectFile],
+    object: &ObjectFile,
+    index: usize,
+) -> VmResult<()> {
+    let error = |code, message: String| {
+        let error = VmError::assembler(code, message);
+        match object.program.location(index) {
+            Some((file, line)) => error.in_file(file).at_line(line),
+            None => error.in_file(&object.program.name),
+        }
+    };
+    let symbol = match globals.get(name).map_or(&[][..], Vec::as_slice) {
+        [] => return Err(error(ErrorCode::UndefinedLabel, format!("Undefined symbol '{}'", name))),
+        [(_, symbol)] => *symbol,
+        definitions => {
+            let names: Vec<&str> = definitions.iter().map(|&(i, _)| objects[i].program.name.as_str()).collect();
+            return Err(error(ErrorCode::DuplicateLabel, format!(
+                "Symbol '{}' is defined in more than one object: {}", name, names.join(", ")
+            )));
+        }
+    };
+    match (instruction, symbol) {
+        (Instruction::LoadImm { value, .. }, Symbol::Code(target)) if code_ref => *value = target as u64,
+        (Instruction::LoadImm { value, .. }, Symbol::Data(address)) if !code_ref => *value = address as u64,
+        (instruction, Symbol::Code(target)) if instruction.target().is_some() => {
+            *instruction.target_mut().unwrap() = target;
+        }
+        (_, Symbol::Code(_)) => {
+            return Err(error(ErrorCode::UndefinedConstant, format!("'{}' is a code label, not a data label", name)));
+        }
+        (_, Symbol::Data(_)) => {
+            return Err(error(ErrorCode::UndefinedLabel, format!("'{}' is a data label, not a code label", name)));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler;
+
+    #[test]
+    fn test_link_objects() {
+        let main = "title: .string \"main\"\n@n := 5\n@sq := call square(@n)\nprint @sq\n@s := greeting\n@c := load @s\nprint @c\n\
+            call shout\nhalt\n";
+        let lib = "msg: .string \"lib\"\ngreeting: .qword 7\nproc square(x)\n@y := @x * @x\nreturn @y\nendproc\n\
+            shout:\n@m := msg\n@c := load @m\nprint @c\nreturn\n";
+        let main = assembler::assemble_object(main, "main.alya").unwrap();
+        let lib = assembler::assemble_object(lib, "lib.alya").unwrap();
+        assert_eq!(main.relocations.iter().map(|r| r.symbol.as_str()).collect::<Vec<_>>(), ["square", "greeting", "shout"]);
+
+        let lib = ObjectFile::load(&lib.encode()).unwrap();
+        let program = link(&[main.clone(), lib.clone()]).unwrap();
+        let mut vm = crate::execution::VM::new();
+        vm.run(&program).unwrap();
+        assert_eq!(vm.output(), ["25", "7", &(u32::from_le_bytes(*b"lib\0")).to_string()]);
+        assert_eq!(program.location(program.symbols["square"]).map(|(file, _)| file), Some("lib.alya"));
+
+        let err = link(std::slice::from_ref(&main)).unwrap_err();
+        assert_eq!(err.code(), ErrorCode::UndefinedLabel);
+        assert!(err.to_string().contains("main.alya:3: Undefined symbol 'square'"), "{}", err);
+        let err = link(&[main, lib.clone(), lib]).unwrap_err();
+        assert!(err.to_string().contains("'square' is defined in more than one object"), "{}", err);
+        let a = assembler::assemble_object(".data\ncount: .qword 5\n.rodata\nname: .string \"a\"\n@p := count\n@c := load @p\nprint @c\ncall bump\nhalt\n", "a.alya").unwrap();
+        let b = assembler::assemble_object("label: .string \"b\"\n.data\nstep: .qword 2\n.rodata\nbump:\n@p := step\n@s := load @p\nprint @s\nreturn\n", "b.alya").unwrap();
+        let program = link(&[a, b]).unwrap();
+        assert_eq!((program.writable_range(), &program.data[..2]), (16..32, &b"a\0"[..]));
+        let mut vm = crate::execution::VM::new();
+        vm.print_immediately = false;
+        vm.run(&program).unwrap();
+        assert_eq!(vm.output(), ["5", "2"]);
+    }
+
+    #[test]
+    fn test_link_code_addresses() {
+        let main = assembler::assemble_object("@f := &twice\n@r1 := 4\ncall @f\nprint @r1\nhalt\n", "main.alya").unwrap();
+        // The library's own code address moves with it
+        let lib = assembler::assemble_object("twice:\n@g := &add\ncall @g\nreturn\nadd:\n@r1 += @r1\nreturn\n", "lib.alya").unwrap();
+        assert_eq!(main.relocations.iter().map(|r| r.symbol.as_str()).collect::<Vec<_>>(), ["twice"]);
+        let lib = ObjectFile::load(&lib.encode()).unwrap();
+        let program = link(&[main, lib]).unwrap();
+        let mut vm = crate::execution::VM::new();
+        vm.print_immediately = false;
+        vm.run(&program).unwrap();
+        assert_eq!(vm.output(), ["8"]);
+    }
+}
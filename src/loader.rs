@@ -0,0 +1,391 @@
+//! Reading and writing the `.bin` files produced by `alya assemble`.
+//!
+//! A binary is the `ALYA` magic and a little-endian u16 version, followed
+//! by sections that each start with a u64 count:
+//!
+//! - code: byte length, then the encoded instructions; jump and call
+//!   targets are absolute, or relative behind the `Relative` prefix
+//! - data: byte length, then the data section
+//! - lines: one u64 source line per instruction
+//! - symbols: (name length, name, instruction index) per label
+//!
+//! Any number of tagged sections follow, each a u64 kind and a
+//! length-prefixed payload, so readers can skip kinds they do not know:
+//!
+//! - files (1): (name length, name) per source file, then one u64 file
+//!   index per instruction; only written for programs with included files
+//! - variables (2): (name length, name, register number) per variable
+//! - resources (3): (name length, name, data offset, length) per file
+//!   embedded with `.resource`
+//! - metadata (4): name, toolchain and description (each length-prefixed,
+//!   an empty description meaning none), then the build time in seconds
+//!   since the Unix epoch, 0 if not recorded
+//! - writable data (5): how many bytes at the end of the data section the
+//!   program may write; without it all data is read-only
+//!
+//! Only code and data are required. The other sections are optional and
+//! a binary may stop after any of them.
+
+use std::collections::BTreeMap;
+use crate::core::{Opcode, Register};
+use crate::error::{ErrorCode, VmError, VmResult};
+use crate::instruction::{Instruction, Metadata, Program};
+
+/// Tagged section holding `Program::files` and `Program::file_table`
+const FILES_SECTION: usize = 1;
+/// Tagged section holding `Program::variables`
+const VARIABLES_SECTION: usize = 2;
+/// Tagged section holding `Program::resources`
+const RESOURCES_SECTION: usize = 3;
+/// Tagged section holding `Program::metadata`
+const METADATA_SECTION: usize = 4;
+/// Tagged section holding `Program::writable_len`
+const WRITABLE_SECTION: usize = 5;
+
+/// First four bytes of every binary
+pub const MAGIC: &[u8; 4] = b"ALYA";
+/// Newest format version this module reads, and the one it writes
+pub const VERSION: u16 = 1;
+
+/// Read a binary into a program with its data and whatever debug info it
+/// carries. The program is named `binary`; callers usually rename it after
+/// the file it came from.
+pub fn load(bytes: &[u8]) -> VmResult<Program> {
+    let mut reader = Reader::after_header(bytes, MAGIC, "binary format")?;
+    let mut program = read_code_and_data(&mut reader)?;
+    read_debug_info(&mut reader, &mut program);
+    Ok(program)
+}
+
+/// Read just the metadata of a binary, without decoding its code
+pub fn load_metadata(bytes: &[u8]) -> VmResult<Option<Metadata>> {
+    let mut reader = Reader::after_header(bytes, MAGIC, "binary format")?;
+    reader.section("code")?;
+    reader.section("data")?;
+    let mut program = Program::new("binary");
+    read_debug_info(&mut reader, &mut program);
+    Ok(program.metadata)
+}
+
+/// Encode a program in the format `load` reads. Debug sections are
+/// written for whatever debug info the program has.
+pub fn encode(program: &Program) -> Vec<u8> {
+    let mut bytes = header(MAGIC);
+    write_code_and_data(&mut bytes, program);
+    write_debug_info(&mut bytes, program);
+    bytes
+}
+
+/// Magic and version that start a file
+pub(crate) fn header(magic: &[u8; 4]) -> Vec<u8> {
+    let mut bytes = magic.to_vec();
+    bytes.extend_from_slice(&VERSION.to_le_bytes());
+    bytes
+}
+
+pub(crate) fn write_code_and_data(bytes: &mut Vec<u8>, program: &Program) {
+    write_bytes(bytes, &program.code());
+    write_bytes(bytes, &program.data);
+}
+
+/// Write the line and symbol tables and the tagged sections for whatever
+/// other debug info, resources and metadata the program has
+pub(crate) fn write_debug_info(bytes: &mut Vec<u8>, program: &Program) {
+    write_u64(bytes, program.line_table.len());
+    for &line in &program.line_table {
+        write_u64(bytes, line);
+    }
+
+    write_u64(bytes, program.symbols.len());
+    for (name, &index) in &program.symbols {
+        write_bytes(bytes, name.as_bytes());
+        write_u64(bytes, index);
+    }
+
+    if !program.file_table.is_empty() {
+        let mut section = Vec::new();
+        write_u64(&mut section, program.files.len());
+        for name in &program.files {
+            write_bytes(&mut section, name.as_bytes());
+        }
+        write_u64(&mut section, program.file_table.len());
+        for &file in &program.file_table {
+            write_u64(&mut section, file);
+        }
+        write_u64(bytes, FILES_SECTION);
+        write_bytes(bytes, &section);
+    }
+
+    if !program.variables.is_empty() {
+        let mut section = Vec::new();
+        write_u64(&mut section, program.variables.len());
+        for (name, reg) in &program.variables {
+            write_bytes(&mut section, name.as_bytes());
+            write_u64(&mut section, reg.to_u8() as usize);
+        }
+        write_u64(bytes, VARIABLES_SECTION);
+        write_bytes(bytes, &section);
+    }
+
+    if !program.resources.is_empty() {
+        let mut section = Vec::new();
+        write_u64(&mut section, program.resources.len());
+        for (name, &(offset, len)) in &program.resources {
+            write_bytes(&mut section, name.as_bytes());
+            write_u64(&mut section, offset);
+            write_u64(&mut section, len);
+        }
+        write_u64(bytes, RESOURCES_SECTION);
+        write_bytes(bytes, &section);
+    }
+
+    if program.writable_len > 0 {
+        let mut section = Vec::new();
+        write_u64(&mut section, program.writable_len);
+        write_u64(bytes, WRITABLE_SECTION);
+        write_bytes(bytes, &section);
+    }
+
+    if let Some(metadata) = &program.metadata {
+        let mut section = Vec::new();
+        write_bytes(&mut section, metadata.name.as_bytes());
+        write_bytes(&mut section, metadata.toolchain.as_bytes());
+        write_bytes(&mut section, metadata.description.as_deref().unwrap_or("").as_bytes());
+        write_u64(&mut section, metadata.built.unwrap_or(0) as usize);
+        write_u64(bytes, METADATA_SECTION);
+        write_bytes(bytes, &section);
+    }
+}
+
+pub(crate) fn invalid(message: impl Into<String>) -> VmError {
+    VmError::execution(ErrorCode::InvalidBinary, message)
+}
+
+pub(crate) fn write_u64(bytes: &mut Vec<u8>, value: usize) {
+    bytes.extend_from_slice(&(value as u64).to_le_bytes());
+}
+
+/// Write a length-prefixed byte string
+pub(crate) fn write_bytes(bytes: &mut Vec<u8>, value: &[u8]) {
+    write_u64(bytes, value.len());
+    bytes.extend_from_slice(value);
+}
+
+/// Cursor over the sections of a binary
+pub(crate) struct Reader<'a> {
+    bytes: &'a [u8],
+    cursor: usize,
+}
+
+impl<'a> Reader<'a> {
+    /// Check the magic and version of a file and start reading after them.
+    /// `kind` names the file in errors.
+    pub(crate) fn after_header(bytes: &'a [u8], magic: &[u8; 4], kind: &str) -> VmResult<Self> {
+        if bytes.len() < 6 || &bytes[0..4] != magic {
+            return Err(invalid(format!(
+                "Invalid {} (missing {} header)", kind, String::from_utf8_lossy(magic)
+            )));
+        }
+        let version = u16::from_le_bytes([bytes[4], bytes[5]]);
+        if version > VERSION {
+            return Err(invalid(format!("Unsupported {} version {}", kind, version)));
+        }
+        Ok(Reader { bytes, cursor: 6 })
+    }
+
+    pub(crate) fn u64(&mut self) -> Option<usize> {
+        let bytes = self.bytes.get(self.cursor..self.cursor.checked_add(8)?)?;
+        self.cursor += 8;
+        Some(u64::from_le_bytes(bytes.try_into().unwrap()) as usize)
+    }
+
+    /// A length-prefixed byte string
+    pub(crate) fn bytes(&mut self) -> Option<&'a [u8]> {
+        let start = self.cursor;
+        let len = self.u64()?;
+        match self.bytes.get(self.cursor..self.cursor.checked_add(len)?) {
+            Some(bytes) => {
+                self.cursor += len;
+                Some(bytes)
+            }
+            None => {
+                self.cursor = start;
+                None
+            }
+        }
+    }
+
+    /// A required length-prefixed section, which must be complete
+    pub(crate) fn section(&mut self, name: &str) -> VmResult<&'a [u8]> {
+        self.bytes().ok_or_else(|| truncated(name))
+    }
+
+    /// A required u64
+    pub(crate) fn count(&mut self, name: &str) -> VmResult<usize> {
+        self.u64().ok_or_else(|| truncated(name))
+    }
+}
+
+fn truncated(section: &str) -> VmError {
+    invalid(format!("Binary truncated in its {} section", section))
+}
+
+/// Read the code and data sections into a program named `binary`
+pub(crate) fn read_code_and_data(reader: &mut Reader) -> VmResult<Program> {
+    let code = reader.section("code")?;
+    let data = reader.section("data")?.to_vec();
+
+    let mut instructions = Vec::new();
+    let mut relative_jumps = false;
+    let mut offset = 0;
+    while offset < code.len() {
+        let (instruction, len) = Instruction::decode_at(&code[offset..], instructions.len()).map_err(|e| {
+            VmError::execution(e.code(), format!("Corrupt binary at offset {}: {}", offset, e.message()))
+        })?;
+        relative_jumps |= code[offset] == Opcode::Relative.to_u8();
+        instructions.push(instruction);
+        offset += len;
+    }
+    let mut program = Program::with_data("binary", instructions, data);
+    program.relative_jumps = relative_jumps;
+    Ok(program)
+}
+
+/// Read the optional line and symbol tables and tagged sections. A table
+/// cut short keeps the entries read so far; a file table that does not
+/// cover every instruction is dropped.
+pub(crate) fn read_debug_info(reader: &mut Reader, program: &mut Program) {
+    let Some(count) = reader.u64() else { return };
+    for _ in 0..count {
+        let Some(line) = reader.u64() else { return };
+        program.line_table.push(line);
+    }
+
+    let Some(count) = reader.u64() else { return };
+    let mut symbols = BTreeMap::new();
+    for _ in 0..count {
+        let Some((name, index)) = reader.bytes().zip(reader.u64()) else { break };
+        symbols.insert(String::from_utf8_lossy(name).to_string(), index);
+    }
+    program.symbols = symbols;
+
+    while let Some((kind, payload)) = reader.u64().zip(reader.bytes()) {
+        let mut section = Reader { bytes: payload, cursor: 0 };
+        match kind {
+            FILES_SECTION => read_files(&mut section, program),
+            VARIABLES_SECTION => read_variables(&mut section, program),
+            RESOURCES_SECTION => read_resources(&mut section, program),
+            METADATA_SECTION => read_metadata(&mut section, program),
+            WRITABLE_SECTION => {
+                program.writable_len = section.u64().filter(|&len| len <= program.data.len()).unwrap_or(0);
+            }
+            // Written by a newer version
+            _ => {}
+        }
+    }
+}
+
+fn read_files(section: &mut Reader, program: &mut Program) {
+    let mut files = Vec::new();
+    for _ in 0..section.u64().unwrap_or(0) {
+        let Some(name) = section.bytes() else { return };
+        files.push(String::from_utf8_lossy(name).to_string());
+    }
+    let mut file_table = Vec::new();
+    for _ in 0..section.u64().unwrap_or(0) {
+        let Some(file) = section.u64() else { return };
+        file_table.push(file);
+    }
+    if file_table.len() == program.len() && file_table.iter().all(|&file| file < files.len()) {
+        program.files = files;
+        program.file_table = file_table;
+    }
+}
+
+fn read_variables(section: &mut Reader, program: &mut Program) {
+    for _ in 0..section.u64().unwrap_or(0) {
+        let Some((name, reg)) = section.bytes().zip(section.u64()) else { return };
+        // Skip registers this version does not have
+        if let Some(reg) = u8::try_from(reg).ok().and_then(|reg| Register::from_u8(reg).ok()) {
+            program.variables.insert(String::from_utf8_lossy(name).to_string(), reg);
+        }
+    }
+}
+
+fn read_resources(section: &mut Reader, program: &mut Program) {
+    for _ in 0..section.u64().unwrap_or(0) {
+        let Some(((name, offset), len)) = section.bytes().zip(section.u64()).zip(section.u64()) else { return };
+        // Skip resources that do not fit in the data section
+        if offset.checked_add(len).is_some_and(|end| end <= program.data.len()) {
+            program.resources.insert(String::from_utf8_lossy(name).to_string(), (offset, len));
+        }
+    }
+}
+
+fn read_metadata(section: &mut Reader, program: &mut Program) {
+    let text = |bytes: &[u8]| String::from_utf8_lossy(bytes).to_string();
+    let (Some(name), Some(toolchain), Some(description)) = (section.bytes(), section.bytes(), section.bytes()) else { return };
+    program.metadata = Some(Metadata {
+        name: text(name),
+        toolchain: text(toolchain),
+        built: section.u64().filter(|&built| built != 0).map(|built| built as u64),
+        description: (!description.is_empty()).then(|| text(description)),
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler;
+
+    #[test]
+    fn test_load_relative_jumps() {
+        let mut program = assembler::assemble("@i := 3\nloop:\nprint @i\n@i := @i - 1\nif @i > 0 goto loop\nhalt\n", "t").unwrap();
+        let absolute = encode(&program);
+        program.relative_jumps = true;
+        let relative = encode(&program);
+        assert!(relative.len() < absolute.len());
+        let loaded = load(&relative).unwrap();
+        assert!(loaded.relative_jumps && !load(&absolute).unwrap().relative_jumps);
+        assert_eq!(loaded.instructions, program.instructions);
+    }
+
+    #[test]
+    fn test_load_round_trip() {
+        let mut program = assembler::assemble("start:\n@a := 2\nmsg: .string \"hi\"\nprint @a\nhalt\n", "t").unwrap();
+        program.files = vec!["t".to_string(), "lib.alya".to_string()];
+        program.file_table = vec![0; program.len()];
+        program.file_table[1] = 1;
+        program.variables.insert("a".to_string(), Register::R2);
+        program.resources.insert("greeting".to_string(), (0, 2));
+        program.resources.insert("past the end".to_string(), (2, 9));
+        program.writable_len = 3;
+        program.metadata = Some(Metadata { built: Some(1_700_000_000), description: Some("demo".to_string()), ..Metadata::new("t") });
+        let bytes = encode(&program);
+        let loaded = load(&bytes).unwrap();
+        assert_eq!(loaded.instructions, program.instructions);
+        assert_eq!((&loaded.data, &loaded.line_table, &loaded.symbols), (&program.data, &program.line_table, &program.symbols));
+        assert_eq!((&loaded.files, &loaded.file_table), (&program.files, &program.file_table));
+        assert_eq!(loaded.variables, program.variables);
+        assert_eq!(loaded.resources.into_iter().collect::<Vec<_>>(), [("greeting".to_string(), (0, 2))]);
+        assert_eq!(loaded.metadata, program.metadata);
+        assert_eq!(loaded.writable_len, 3);
+        assert_eq!(load_metadata(&bytes).unwrap(), program.metadata);
+
+        // Binaries from before the symbol table still load
+        let code_len = program.code().len();
+        let lines_end = 6 + 8 + code_len + 8 + program.data.len() + 8 + 8 * program.len();
+        let loaded = load(&bytes[..lines_end]).unwrap();
+        assert_eq!((loaded.line_table.len(), loaded.symbols.len()), (program.len(), 0));
+
+        for (bytes, message) in [
+            (&b"ALYB\x01\x00"[..], "missing ALYA header"),
+            (&b"ALYA\x09\x00"[..], "Unsupported binary format version 9"),
+            (&bytes[..20], "truncated in its code section"),
+        ] {
+            let err = load(bytes).unwrap_err();
+            assert_eq!(err.code(), ErrorCode::InvalidBinary);
+            assert!(err.to_string().contains(message), "{}", err);
+        }
+    }
+}
@@ -1,333 +1,880 @@
-use std::env;
+use std::borrow::Cow;
 use std::fs;
-use std::io::Write;
+use std::io::{self, Write};
+use std::net::TcpListener;
+use std::path::Path;
 use std::process;
-use alya_vm::assembler;
-use alya_vm::instruction::{Instruction, Program};
-use alya_vm::execution::{VM, debugger::Debugger};
-use alya_vm::error::VmError;
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use alya_vm::{analysis, archive, assembler, linker, loader};
+use alya_vm::archive::{Archive, Member, ARCHIVE_MAGIC};
+use alya_vm::linker::ObjectFile;
+use alya_vm::assembler::warnings::{Warning, WarningKind};
+use alya_vm::instruction::{disasm, disassembler, Instruction, Program};
+use alya_vm::instruction::validate::validate;
+use alya_vm::execution::{bench, grade_run, profile, Expectations, VM, MAX_INSTRUCTIONS, debugger::Debugger, prompt::Prompt, remote::{self, RemoteClient}};
+use alya_vm::error::{ErrorCode, ExecutionError, VmError};
+use alya_vm::memory::Aslr;
+use alya_vm::memory::layout::MIN_MEMORY_SIZE;
+
+/// Alya VM toolchain: assemble, run, debug and inspect Alya programs
+#[derive(Parser)]
+#[command(name = "alya", version)]
+struct Cli {
+    /// Print only results and errors, not progress messages
+    #[arg(short, long, global = true)]
+    quiet: bool,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Compile text to binary
+    Assemble(AssembleArgs),
+    /// Combine object files into one binary
+    Link {
+        /// Object files, in the order their code is laid out, and archives
+        /// to pull the labels they still need from
+        #[arg(required = true)]
+        objects: Vec<String>,
+        /// Binary to write
+        #[arg(short, long, default_value = "out.bin")]
+        output: String,
+    },
+    /// Bundle object files into a static library archive
+    Archive {
+        /// Object files to bundle
+        #[arg(required = true)]
+        objects: Vec<String>,
+        /// Archive to write
+        #[arg(short, long, default_value = "lib.alib")]
+        output: String,
+    },
+    /// Assemble and validate without writing output
+    Check {
+        source: String,
+        /// Report errors as `path:line:column` text, or as JSON lines on stdout
+        #[arg(long, value_enum, default_value_t)]
+        message_format: MessageFormat,
+        /// Enable a warning (repeatable)
+        #[arg(short = 'W', value_enum, value_name = "WARNING")]
+        warn: Vec<WarningFlag>,
+    },
+    /// Print the parsed statements of a source file as JSON
+    Ast {
+        source: String,
+    },
+    /// Execute a binary file
+    Run(RunArgs),
+    /// Convert binary back to .alya source
+    #[command(visible_alias = "disasm")]
+    Disassemble {
+        program: String,
+        /// Print one JSON record per instruction instead of source
+        #[arg(long)]
+        json: bool,
+    },
+    /// Check a binary's targets, data references, stack use and returns without running it
+    Verify {
+        program: String,
+    },
+    /// Remove the line, symbol and variable tables from a binary
+    Strip {
+        program: String,
+        /// Binary to write (default: replace PROGRAM)
+        #[arg(short, long)]
+        output: Option<String>,
+    },
+    /// Show a binary's metadata and the size of each section
+    Info {
+        program: String,
+    },
+    /// Write a binary as a Rust or C source array for embedding in a host
+    Embed {
+        program: String,
+        /// Language of the generated source
+        #[arg(long, value_enum, default_value_t)]
+        lang: EmbedLanguage,
+        /// Name of the array (default PROGRAM for Rust, program for C)
+        #[arg(long)]
+        name: Option<String>,
+        /// Source file to write, or - for standard output
+        #[arg(short, long, default_value = "-")]
+        output: String,
+    },
+    /// Start the debugger (interactive sessions load program.alyadbg if present)
+    Debug {
+        program: String,
+        /// Run debugger commands from a file instead of prompting
+        #[arg(long, value_name = "CMDS", conflicts_with = "listen")]
+        script: Option<String>,
+        /// Serve the debugger to one remote client. Clients are not
+        /// authenticated, so listen on a loopback address such as 127.0.0.1:9000
+        #[arg(long, value_name = "ADDR")]
+        listen: Option<String>,
+    },
+    /// Time repeated runs and report opcode hot spots
+    Bench {
+        program: String,
+        #[arg(long, default_value_t = 10)]
+        iterations: u32,
+    },
+    /// Report hot lines and opcodes
+    Profile {
+        program: String,
+        /// Also write folded stacks for flamegraph tools to this file
+        #[arg(long, value_name = "OUT")]
+        folded: Option<String>,
+    },
+    /// Report call graph, call depth and dead code
+    Analyze {
+        program: String,
+    },
+    /// Attach to a remote debugger
+    Connect {
+        #[arg(value_name = "HOST:PORT")]
+        addr: String,
+    },
+}
+
+#[derive(Args)]
+struct AssembleArgs {
+    /// Source file, or `-` for standard input
+    source: String,
+    /// Binary to write, or `-` for standard output
+    #[arg(default_value = "out.bin")]
+    output: String,
+    /// Same as OUTPUT
+    #[arg(short = 'o', long = "output", value_name = "OUTPUT", conflicts_with = "output")]
+    output_flag: Option<String>,
+    /// Write an object file for `link`, leaving undefined labels to other objects
+    #[arg(short = 'c', long)]
+    object: bool,
+    /// Leave the line, symbol and variable tables out of the binary
+    #[arg(long)]
+    no_debug_info: bool,
+    /// Encode jump and call targets relative to each instruction
+    #[arg(long)]
+    relative_jumps: bool,
+    /// Description to record in the binary's metadata
+    #[arg(long)]
+    description: Option<String>,
+    /// Report errors as text, or as JSON lines on stdout
+    #[arg(long, value_enum, default_value_t)]
+    message_format: MessageFormat,
+    /// Enable a warning (repeatable)
+    #[arg(short = 'W', value_enum, value_name = "WARNING")]
+    warn: Vec<WarningFlag>,
+}
+
+#[derive(Args)]
+struct RunArgs {
+    program: String,
+    /// Randomize the heap and stack placement
+    #[arg(long)]
+    aslr: bool,
+    /// Reproduce a randomized layout and `rand` sequence (implies --aslr);
+    /// decimal or 0x hex
+    #[arg(long, value_parser = parse_seed)]
+    seed: Option<u64>,
+    /// Guest memory in bytes [default: 65536]
+    #[arg(long, value_parser = parse_memory_size)]
+    memory_size: Option<usize>,
+    /// Fail after executing this many instructions
+    #[arg(long, visible_alias = "limit-instructions", default_value_t = MAX_INSTRUCTIONS)]
+    max_instructions: u64,
+    /// Label or instruction index to start at. Indices are hex, with or
+    /// without `0x`, as listings and the debugger show them
+    #[arg(long)]
+    entry: Option<String>,
+    /// Log the instructions run between `trace_on` and `trace_off` to stderr
+    #[arg(long)]
+    trace: bool,
+    /// Report faults as text, or as JSON lines on stderr
+    #[arg(long, value_enum, default_value_t)]
+    message_format: MessageFormat,
+    /// Grade the run against this expected output; prints a JSON report
+    /// and exits 0 (pass), 3 (fail) or 4 (instruction limit hit)
+    #[arg(long, value_name = "FILE")]
+    expect_output: Option<String>,
+    /// Grade the run against this exit status (the `halt @x` status, 0
+    /// for a plain finish, 1 for a failure)
+    #[arg(long, value_name = "CODE")]
+    expect_exit: Option<i32>,
+}
+
+/// How assemble, check and run report errors
+#[derive(Debug, Clone, Copy, Default, PartialEq, ValueEnum)]
+enum MessageFormat {
+    /// Messages for people
+    #[default]
+    Human,
+    /// One JSON object per line, for editors and CI
+    Json,
+}
+
+/// Source language written by `embed`
+#[derive(Debug, Clone, Copy, Default, PartialEq, ValueEnum)]
+enum EmbedLanguage {
+    /// A `pub const NAME: &[u8]`
+    #[default]
+    Rust,
+    /// A `const unsigned char name[]` and its length
+    C,
+}
+
+/// Warnings enabled with `-W`
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+enum WarningFlag {
+    /// Every warning below
+    All,
+    /// Labels nothing jumps to, calls or loads
+    UnusedLabel,
+    /// Variables written but never read
+    UnusedVariable,
+    /// Variables spilled to the stack
+    Spill,
+    /// Fail if any enabled warning is reported
+    Error,
+}
+
+impl WarningFlag {
+    fn enables(self, kind: WarningKind) -> bool {
+        match self {
+            WarningFlag::All => true,
+            WarningFlag::UnusedLabel => kind == WarningKind::UnusedLabel,
+            WarningFlag::UnusedVariable => kind == WarningKind::UnusedVariable,
+            WarningFlag::Spill => kind == WarningKind::Spill,
+            WarningFlag::Error => false,
+        }
+    }
+}
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
+    let cli = Cli::parse();
+    let quiet = cli.quiet;
+
+    match cli.command {
+        Command::Assemble(args) => assemble_file(&args, quiet),
+        Command::Link { objects, output } => link_objects(&objects, &output, quiet),
+        Command::Archive { objects, output } => archive_objects(&objects, &output, quiet),
+        Command::Check { source, message_format, warn } => check_file(&source, message_format, &warn),
+        Command::Ast { source } => print_ast(&source),
+        Command::Run(args) => run_binary(&args, quiet),
+        Command::Disassemble { program, json } => disassemble_binary(&program, json),
+        Command::Verify { program } => verify_binary(&program, quiet),
+        Command::Strip { program, output } => strip_binary(&program, output.as_deref().unwrap_or(&program), quiet),
+        Command::Info { program } => print_info(&program),
+        Command::Embed { program, lang, name, output } => embed_binary(&program, lang, name, &output),
+        Command::Debug { program, script, listen } => {
+            let mode = match (script, listen) {
+                (Some(script), _) => DebugMode::Script(script),
+                (None, Some(addr)) => DebugMode::Listen(addr),
+                (None, None) => DebugMode::Interactive,
+            };
+            run_debugger(&program, mode);
+        }
+        Command::Bench { program, iterations } => bench_binary(&program, iterations),
+        Command::Profile { program, folded } => profile_binary(&program, folded.as_deref(), quiet),
+        Command::Analyze { program } => analyze_binary(&program),
+        Command::Connect { addr } => connect_debugger(&addr),
+    }
+}
 
-    if args.len() < 3 {
-        print_usage();
-        process::exit(1);
+/// Parse a seed for `--seed`, in decimal or `0x`-prefixed hex
+fn parse_seed(value: &str) -> Result<u64, String> {
+    let seed = match value.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => value.parse::<u64>(),
+    };
+    seed.map_err(|e| e.to_string())
+}
+
+/// Parse `--memory-size`, which must leave room for data, heap and stack
+fn parse_memory_size(value: &str) -> Result<usize, String> {
+    let size: usize = value.parse().map_err(|e: std::num::ParseIntError| e.to_string())?;
+    if size < MIN_MEMORY_SIZE {
+        return Err(format!("must be at least {} bytes", MIN_MEMORY_SIZE));
     }
+    Ok(size)
+}
 
-    let command = &args[1];
-    let filename = &args[2];
+/// Source text of the file an assembler error is in: the input itself, or
+/// the included file it names
+fn error_source<'a>(e: &VmError, source: &'a str) -> Cow<'a, str> {
+    match e.file() {
+        Some(file) => Cow::Owned(fs::read_to_string(file).unwrap_or_default()),
+        None => Cow::Borrowed(source),
+    }
+}
 
-    match command.as_str() {
-        "assemble" => {
-            // Usage: alya assemble input.alya [output.bin]
-            let output_file = if args.len() >= 4 { &args[3] } else { "out.bin" };
-            assemble_file(filename, output_file);
+#[cfg(feature = "miette")]
+fn report_assembly_error(e: VmError, path: &str, source: &str) {
+    let named = miette::NamedSource::new(e.file().unwrap_or(path), error_source(&e, source).into_owned());
+    let report = miette::Report::new(e).with_source_code(named);
+    eprintln!("{:?}", report);
+}
+
+/// `path:line:column` of an assembler error, or just the path if it has no line
+fn error_location(e: &VmError, path: &str, source: &str) -> String {
+    match e.line() {
+        Some(line) => {
+            let column = e.column(&error_source(e, source)).unwrap_or(1);
+            format!("{}:{}:{}", e.file().unwrap_or(path), line, column)
         }
-        "run" => {
-            // Usage: alya run program.bin
-            run_binary(filename);
+        None => path.to_string(),
+    }
+}
+
+#[cfg(not(feature = "miette"))]
+fn report_assembly_error(e: VmError, path: &str, source: &str) {
+    eprintln!("Assembly error: {}", e);
+    if let Some(snippet) = e.snippet(&error_source(&e, source)) {
+        eprintln!(" --> {}\n{}", error_location(&e, path, source), snippet);
+    }
+}
+
+/// Print the warnings that `flags` enables. Returns true if `-W error`
+/// should fail the command.
+fn report_warnings(warnings: &[Warning], flags: &[WarningFlag], path: &str, format: MessageFormat) -> bool {
+    let mut reported = false;
+    for warning in warnings.iter().filter(|w| flags.iter().any(|flag| flag.enables(w.kind))) {
+        match format {
+            MessageFormat::Human => eprintln!(
+                "{}:{}: warning[{}]: {}",
+                warning.file.as_deref().unwrap_or(path), warning.line, warning.kind, warning.message
+            ),
+            MessageFormat::Json => println!("{}", warning.to_json(path)),
         }
-        "disassemble" | "disasm" => {
-            // Usage: alya disassemble program.bin
-            disassemble_binary(filename);
+        reported = true;
+    }
+    reported && flags.contains(&WarningFlag::Error)
+}
+
+fn assemble_file(args: &AssembleArgs, quiet: bool) {
+    let (input_path, output_path) = (args.source.as_str(), args.output_flag.as_deref().unwrap_or(&args.output));
+    let (format, warn, debug_info) = (args.message_format, &args.warn[..], !args.no_debug_info);
+    let from_stdin = input_path == "-";
+    let input_path = if from_stdin { "<stdin>" } else { input_path };
+    let source = if from_stdin { io::read_to_string(io::stdin()) } else { fs::read_to_string(input_path) };
+    let source = source.unwrap_or_else(|e| {
+        match format {
+            MessageFormat::Human => eprintln!("Error reading file '{}': {}", input_path, e),
+            MessageFormat::Json => println!("{}", VmError::from(e).to_json(input_path, None, &[])),
         }
-        "debug" => {
-            // Usage: alya debug program.bin
-            run_debugger(filename);
+        process::exit(1);
+    });
+    // Progress messages would corrupt a binary written to stdout
+    let to_stdout = output_path == "-";
+    let quiet = quiet || to_stdout;
+
+    if format == MessageFormat::Human && !quiet {
+        println!("Assembling '{}'...", input_path);
+    }
+    let fail = |e: VmError| -> ! {
+        match format {
+            MessageFormat::Human => report_assembly_error(e, input_path, &source),
+            MessageFormat::Json => println!("{}", e.to_json(input_path, Some(&error_source(&e, &source)), &[])),
         }
-        _ => {
-            eprintln!("Unknown command: {}", command);
-            print_usage();
+        process::exit(1);
+    };
+    let check_warnings = |warnings: &[Warning]| {
+        if report_warnings(warnings, warn, input_path, format) {
             process::exit(1);
         }
+    };
+
+    let (bytes, program) = if args.object {
+        let (mut object, warnings) = assembler::assemble_object_with_warnings(&source, input_path).unwrap_or_else(|e| fail(e));
+        check_warnings(&warnings);
+        record_metadata(&mut object.program, args.description.as_deref());
+        object.program.relative_jumps = args.relative_jumps;
+        // The linker needs the symbols, so only the line and file tables go
+        if !debug_info {
+            object.program.line_table.clear();
+            object.program.files.clear();
+            object.program.file_table.clear();
+            object.program.variables.clear();
+        }
+        (object.encode(), object.program)
+    } else {
+        let (mut program, warnings) = assembler::assemble_with_warnings(&source, input_path).unwrap_or_else(|e| fail(e));
+        check_warnings(&warnings);
+        record_metadata(&mut program, args.description.as_deref());
+        program.relative_jumps = args.relative_jumps;
+        if !debug_info {
+            program.strip_debug_info();
+        }
+        (loader::encode(&program), program)
+    };
+    let code_bytes = program.code().len();
+    let written = if to_stdout { io::stdout().write_all(&bytes) } else { fs::write(output_path, &bytes) };
+    if let Err(e) = written {
+        eprintln!("Error writing '{}': {}", output_path, e);
+        process::exit(1);
     }
-}
 
-fn print_usage() {
-    eprintln!("Alya VM Toolchain");
-    eprintln!("Usage:");
-    eprintln!("  alya assemble <source.alya> [output.bin]  Compile text to binary");
-    eprintln!("  alya run <program.bin>                    Execute binary file");
-    eprintln!("  alya disassemble <program.bin>            Convert binary back to assembly");
-    eprintln!("  alya debug <program.bin>                  Start interactive debugger");
+    if format == MessageFormat::Json || quiet {
+        return;
+    }
+    println!("Successfully wrote {} code bytes, {} data bytes, and {} debug entries to '{}'", 
+             code_bytes, program.data.len(), program.line_table.len(), output_path);
 }
 
-fn assemble_file(input_path: &str, output_path: &str) {
-    let source = fs::read_to_string(input_path).unwrap_or_else(|e| {
-        eprintln!("Error reading file '{}': {}", input_path, e);
-        process::exit(1);
+/// Add the build time, from `SOURCE_DATE_EPOCH` for reproducible builds,
+/// and `description` to the metadata the assembler recorded
+fn record_metadata(program: &mut Program, description: Option<&str>) {
+    let built = std::env::var("SOURCE_DATE_EPOCH").ok().and_then(|epoch| epoch.parse().ok()).or_else(|| {
+        std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).ok().map(|since| since.as_secs())
     });
+    if let Some(metadata) = &mut program.metadata {
+        metadata.built = built;
+        metadata.description = description.map(str::to_string);
+    }
+}
 
-    println!("Assembling '{}'...", input_path);
-    let program = assembler::assemble(&source, input_path).unwrap_or_else(|e| {
-        eprintln!("Assembly error: {}", e);
+fn link_objects(paths: &[String], output_path: &str, quiet: bool) {
+    let mut objects = Vec::new();
+    let mut archives = Vec::new();
+    for path in paths {
+        let bytes = fs::read(path).unwrap_or_else(|e| {
+            eprintln!("Error reading '{}': {}", path, e);
+            process::exit(1);
+        });
+        if bytes.starts_with(ARCHIVE_MAGIC) {
+            let mut archive = Archive::load(&bytes).unwrap_or_else(|e| {
+                eprintln!("Invalid archive '{}': {}", path, e);
+                process::exit(1);
+            });
+            for member in &mut archive.members {
+                member.object.program.name = format!("{}({})", path, member.name);
+            }
+            archives.push(archive);
+        } else {
+            objects.push(load_object(path, &bytes));
+        }
+    }
+    let objects = archive::select_members(&objects, &archives);
+
+    let mut program = linker::link(&objects).unwrap_or_else(|e| {
+        let location = match (e.file(), e.line()) {
+            (Some(file), Some(line)) => format!("{}:{}: ", file, line),
+            (Some(file), None) => format!("{}: ", file),
+            _ => String::new(),
+        };
+        eprintln!("{}error[{}]: {}", location, e.code(), e.message());
         process::exit(1);
     });
+    program.name = output_path.to_string();
+    if let Err(e) = fs::write(output_path, loader::encode(&program)) {
+        eprintln!("Error writing '{}': {}", output_path, e);
+        process::exit(1);
+    }
+    if !quiet {
+        println!("Linked {} object(s) into {} instructions in '{}'", objects.len(), program.len(), output_path);
+    }
+}
 
-    // Serialize all instructions to bytes
-    let mut code_bytes = Vec::new();
-    for instr in &program.instructions {
-        code_bytes.extend_from_slice(&instr.encode());
-    }
-
-    // Write to file with header and debug info
-    let mut file = fs::File::create(output_path).unwrap();
-    
-    // Header
-    file.write_all(b"ALYA").unwrap();
-    file.write_all(&1u16.to_le_bytes()).unwrap(); // Version 1
-    
-    // Code Section
-    let code_size = code_bytes.len() as u64;
-    file.write_all(&code_size.to_le_bytes()).unwrap();
-    file.write_all(&code_bytes).unwrap();
-    
-    // Data Section
-    let data_size = program.data.len() as u64;
-    file.write_all(&data_size.to_le_bytes()).unwrap();
-    file.write_all(&program.data).unwrap();
-
-    // Debug Section: Line Table
-    let line_count = program.line_table.len() as u64;
-    file.write_all(&line_count.to_le_bytes()).unwrap();
-    for &line in &program.line_table {
-        file.write_all(&(line as u64).to_le_bytes()).unwrap();
+fn archive_objects(paths: &[String], output_path: &str, quiet: bool) {
+    let members = paths.iter().map(|path| {
+        let bytes = fs::read(path).unwrap_or_else(|e| {
+            eprintln!("Error reading object '{}': {}", path, e);
+            process::exit(1);
+        });
+        let name = Path::new(path).file_name().map_or(path.clone(), |name| name.to_string_lossy().to_string());
+        Member { name, object: load_object(path, &bytes) }
+    }).collect();
+    let archive = Archive::new(members);
+    if let Err(e) = fs::write(output_path, archive.encode()) {
+        eprintln!("Error writing '{}': {}", output_path, e);
+        process::exit(1);
+    }
+    if !quiet {
+        println!("Archived {} object(s) defining {} label(s) in '{}'", archive.members.len(), archive.index.len(), output_path);
     }
+}
 
-    println!("Successfully wrote {} code bytes, {} data bytes, and {} debug entries to '{}'", 
-             code_size, data_size, line_count, output_path);
+fn load_object(path: &str, bytes: &[u8]) -> ObjectFile {
+    let mut object = ObjectFile::load(bytes).unwrap_or_else(|e| {
+        eprintln!("Invalid object '{}': {}", path, e);
+        process::exit(1);
+    });
+    object.program.name = path.to_string();
+    object
 }
 
-fn run_binary(input_path: &str) {
-    let raw_bytes = fs::read(input_path).unwrap_or_else(|e| {
-        eprintln!("Error reading binary '{}': {}", input_path, e);
+/// Print errors as `path:line:column: error[code]: message` (or JSON lines)
+/// and exit 1 if any
+fn check_file(input_path: &str, format: MessageFormat, warn: &[WarningFlag]) {
+    let source = fs::read_to_string(input_path).unwrap_or_else(|e| {
+        match format {
+            MessageFormat::Human => eprintln!("{}: error[{}]: cannot read file: {}", input_path, ErrorCode::Io, e),
+            MessageFormat::Json => println!("{}", VmError::from(e).to_json(input_path, None, &[])),
+        }
         process::exit(1);
     });
 
-    if raw_bytes.len() < 6 {
-        eprintln!("Binary too short (missing header)");
+    let e = match assembler::check_with_warnings(&source, input_path) {
+        Ok((_, warnings)) => {
+            if report_warnings(&warnings, warn, input_path, format) {
+                process::exit(1);
+            }
+            return;
+        }
+        Err(e) => e,
+    };
+    if format == MessageFormat::Json {
+        println!("{}", e.to_json(input_path, Some(&error_source(&e, &source)), &[]));
         process::exit(1);
     }
+    println!("{}: error[{}]: {}", error_location(&e, input_path, &source), e.code(), e.message());
+    if let Some(snippet) = e.snippet(&error_source(&e, &source)) {
+        println!("{}", snippet);
+    }
+    process::exit(1);
+}
 
-    // Check magic
-    if &raw_bytes[0..4] != b"ALYA" {
-        // Fallback or legacy check? 
-        // For now, let's enforce the new format.
-        eprintln!("Invalid binary format (missing ALYA header)");
+fn print_ast(input_path: &str) {
+    let source = fs::read_to_string(input_path).unwrap_or_else(|e| {
+        eprintln!("Error reading file '{}': {}", input_path, e);
         process::exit(1);
+    });
+    match assembler::parse_to_json(&source, input_path) {
+        Ok(json) => print!("{}", json),
+        Err(e) => {
+            report_assembly_error(e, input_path, &source);
+            process::exit(1);
+        }
     }
+}
 
-    let version = u16::from_le_bytes([raw_bytes[4], raw_bytes[5]]);
-    if version != 1 {
-        eprintln!("Unsupported binary version: {}", version);
-        process::exit(1);
+fn run_binary(args: &RunArgs, quiet: bool) {
+    let program = load_binary(&args.program);
+    let entry = match &args.entry {
+        None => 0,
+        Some(entry) => program.symbols.get(entry).copied()
+            .or_else(|| usize::from_str_radix(entry.trim_start_matches("0x"), 16).ok())
+            .unwrap_or_else(|| {
+                eprintln!("Unknown entry point: {}", entry);
+                process::exit(1);
+            }),
+    };
+
+    let mut vm = args.memory_size.map_or_else(VM::new, VM::with_memory_size);
+    vm.max_instructions = args.max_instructions;
+    vm.aslr = match (args.seed, args.aslr) {
+        (Some(seed), _) => Aslr::Seeded(seed),
+        (None, true) => Aslr::Random,
+        (None, false) => Aslr::Disabled,
+    };
+    if args.trace {
+        vm.set_trace_writer(std::io::stderr());
+    }
+
+    if args.expect_output.is_some() || args.expect_exit.is_some() {
+        let output = args.expect_output.as_ref().map(|path| fs::read_to_string(path).unwrap_or_else(|e| {
+            eprintln!("Error reading expected output '{}': {}", path, e);
+            process::exit(1);
+        }));
+        let expectations = Expectations { output, exit_code: args.expect_exit };
+        vm.print_immediately = false;
+        let result = vm.run_from(&program, entry);
+        let report = grade_run(&vm, result, &expectations);
+        println!("{}", report.to_json());
+        process::exit(report.verdict.exit_code());
     }
 
-    let mut cursor = 6;
-
-    // Read code size
-    if cursor + 8 > raw_bytes.len() { process::exit(1); }
-    let code_size = u64::from_le_bytes(raw_bytes[cursor..cursor+8].try_into().unwrap()) as usize;
-    cursor += 8;
-
-    if cursor + code_size > raw_bytes.len() { process::exit(1); }
-    let code_slice = &raw_bytes[cursor..cursor+code_size];
-    cursor += code_size;
-
-    // Read data size
-    if cursor + 8 > raw_bytes.len() { process::exit(1); }
-    let data_size = u64::from_le_bytes(raw_bytes[cursor..cursor+8].try_into().unwrap()) as usize;
-    cursor += 8;
-
-    if cursor + data_size > raw_bytes.len() { process::exit(1); }
-    let data_slice = &raw_bytes[cursor..cursor+data_size];
-    cursor += data_size;
-
-    // Read line table
-    let mut line_table = Vec::new();
-    if cursor + 8 <= raw_bytes.len() {
-        let line_count = u64::from_le_bytes(raw_bytes[cursor..cursor+8].try_into().unwrap()) as usize;
-        cursor += 8;
-        
-        for _ in 0..line_count {
-            if cursor + 8 > raw_bytes.len() { break; }
-            let line = u64::from_le_bytes(raw_bytes[cursor..cursor+8].try_into().unwrap()) as usize;
-            line_table.push(line);
-            cursor += 8;
+    let result = vm.run_from(&program, entry);
+    let format = args.message_format;
+    if let Some(seed) = vm.aslr_seed().filter(|_| !quiet) {
+        let note = format!("ASLR seed: {:#x} (heap at {:#x}, stack top at {:#x})",
+                           seed, vm.layout.heap_start, vm.layout.stack_base);
+        match format {
+            MessageFormat::Human => eprintln!("{}", note),
+            MessageFormat::Json => eprintln!("{{\"level\":\"note\",\"message\":\"{}\"}}", note),
         }
     }
 
-    // Decode instructions
-    let mut instructions = Vec::new();
-    let mut pc = 0;
-    while pc < code_slice.len() {
-        match Instruction::decode(&code_slice[pc..]) {
-            Ok((instr, len)) => {
-                instructions.push(instr);
-                pc += len;
+    match result {
+        // `halt @x` sets the process exit status. The shell only sees the
+        // low byte, so statuses it cannot show exit 255 rather than wrap to 0.
+        Ok(code) => if code != 0 {
+            process::exit(if (0..=255).contains(&code) { code } else { 255 });
+        },
+        Err(VmError::Halted) => {},
+        Err(e) => {
+            match format {
+                MessageFormat::Human => eprintln!("Runtime Error: {}", e),
+                MessageFormat::Json => {
+                    eprintln!("{}", with_fault_pc(e, &vm).to_json(&args.program, None, &program.line_table));
+                }
             }
+            process::exit(1);
+        }
+    }
+}
+
+/// Attach the instruction that was executing to runtime faults that do
+/// not record one (memory and stack errors), so they can be mapped to a line
+fn with_fault_pc(e: VmError, vm: &VM) -> VmError {
+    if e.pc().is_some() || vm.instruction_count == 0 {
+        return e;
+    }
+    VmError::Execution(ExecutionError {
+        code: e.code(),
+        message: e.message(),
+        pc: vm.ctx.pc.checked_sub(1),
+        address: e.address(),
+    })
+}
+
+fn disassemble_binary(input_path: &str, json: bool) {
+    let program = load_binary(input_path);
+    let code: Vec<u8> = program.instructions.iter().flat_map(Instruction::encode).collect();
+
+    if json {
+        match disassembler::to_json(input_path, &code, &program.line_table) {
+            Ok(doc) => print!("{}", doc),
             Err(e) => {
-                eprintln!("Corrupt binary at offset {}: {}", pc, e);
+                eprintln!("Corrupt binary: {}", e);
                 process::exit(1);
             }
         }
+        return;
+    }
+
+    print!("{}", disasm::listing(input_path, &program));
+}
+
+fn verify_binary(input_path: &str, quiet: bool) {
+    let program = load_binary(input_path);
+    if let Err(e) = validate(&program) {
+        let location = e.pc().map_or(String::new(), |pc| match program.location(pc) {
+            Some((file, line)) if program.file_index(pc) != 0 => format!(" at {:04x} ({}:{})", pc, file, line),
+            Some((_, line)) => format!(" at {:04x} (line {})", pc, line),
+            None => format!(" at {:04x}", pc),
+        });
+        eprintln!("{}: error[{}]{}: {}", input_path, e.code(), location, e.message());
+        process::exit(1);
+    }
+    if !quiet {
+        println!("'{}' verified: {} instructions", input_path, program.len());
     }
+}
+
+fn strip_binary(input_path: &str, output_path: &str, quiet: bool) {
+    let before = fs::metadata(input_path).map_or(0, |metadata| metadata.len());
+    let mut program = load_binary(input_path);
+    program.strip_debug_info();
+    let bytes = loader::encode(&program);
+    if let Err(e) = fs::write(output_path, &bytes) {
+        eprintln!("Error writing '{}': {}", output_path, e);
+        process::exit(1);
+    }
+    if !quiet {
+        println!("Stripped '{}' from {} to {} bytes", output_path, before, bytes.len());
+    }
+}
 
-    let mut program = Program::with_data(input_path, instructions, data_slice.to_vec());
-    program.line_table = line_table;
-    let mut vm = VM::new();
-    
-    if let Err(e) = vm.run(&program) {
-        match e {
-            VmError::Halted => {}, 
-            _ => eprintln!("Runtime Error: {}", e),
+fn print_info(input_path: &str) {
+    let program = load_binary(input_path);
+    match &program.metadata {
+        Some(metadata) => {
+            println!("Name:         {}", metadata.name);
+            println!("Toolchain:    {}", metadata.toolchain);
+            if let Some(built) = metadata.built {
+                println!("Built:        {}", format_utc(built));
+            }
+            if let Some(description) = &metadata.description {
+                println!("Description:  {}", description);
+            }
         }
+        None => println!("No metadata recorded"),
+    }
+    let code_bytes = program.code().len();
+    println!("Instructions: {} ({} bytes)", program.len(), code_bytes);
+    println!("Data:         {} bytes ({} writable)", program.data.len(), program.writable_len);
+    println!("Line table:   {}", if program.line_table.is_empty() { "no" } else { "yes" });
+    println!("Symbols:      {}", program.symbols.len());
+    println!("Variables:    {}", program.variables.len());
+    for (name, &(offset, len)) in &program.resources {
+        println!("Resource:     {} ({} bytes at 0x{:x})", name, len, offset);
     }
 }
 
-fn disassemble_binary(input_path: &str) {
-    let raw_bytes = fs::read(input_path).unwrap_or_else(|e| {
+/// `YYYY-MM-DD HH:MM:SS UTC` for seconds since the Unix epoch
+fn format_utc(secs: u64) -> String {
+    let (days, time) = ((secs / 86400) as i64, secs % 86400);
+    // Civil date from days since 1970-01-01, in 400-year eras starting in March
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!("{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC", year, month, day, time / 3600, time / 60 % 60, time % 60)
+}
+
+fn embed_binary(input_path: &str, lang: EmbedLanguage, name: Option<String>, output_path: &str) {
+    let bytes = fs::read(input_path).unwrap_or_else(|e| {
         eprintln!("Error reading binary '{}': {}", input_path, e);
         process::exit(1);
     });
-
-    if raw_bytes.len() < 6 {
-        eprintln!("Binary too short (missing header)");
+    // Refuse to embed something the VM could not load
+    if let Err(e) = loader::load(&bytes) {
+        eprintln!("Invalid binary '{}': {}", input_path, e);
         process::exit(1);
     }
-
-    if &raw_bytes[0..4] != b"ALYA" {
-        eprintln!("Invalid binary format");
+    let name = name.unwrap_or_else(|| if lang == EmbedLanguage::Rust { "PROGRAM" } else { "program" }.to_string());
+    let is_identifier = name.chars().next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if !is_identifier {
+        eprintln!("'{}' is not a valid identifier", name);
         process::exit(1);
     }
 
-    let mut cursor = 6;
-
-    // Read code size
-    if cursor + 8 > raw_bytes.len() { process::exit(1); }
-    let code_size = u64::from_le_bytes(raw_bytes[cursor..cursor+8].try_into().unwrap()) as usize;
-    cursor += 8;
-
-    let code_slice = &raw_bytes[cursor..cursor+code_size];
-    cursor += code_size;
-
-    // Skip data
-    if cursor + 8 > raw_bytes.len() { process::exit(1); }
-    let data_size = u64::from_le_bytes(raw_bytes[cursor..cursor+8].try_into().unwrap()) as usize;
-    cursor += 8 + data_size;
+    let rows: Vec<String> = bytes.chunks(12).map(|row| {
+        let row: Vec<String> = row.iter().map(|b| format!("0x{:02x},", b)).collect();
+        format!("    {}\n", row.join(" "))
+    }).collect();
+    let rows = rows.concat();
+    let source = match lang {
+        EmbedLanguage::Rust => format!(
+            "// Generated by `alya embed` from '{}'\npub const {}: &[u8] = &[\n{}];\n", input_path, name, rows
+        ),
+        EmbedLanguage::C => format!(
+            "/* Generated by `alya embed` from '{}' */\n#include <stddef.h>\n\n\
+             const unsigned char {}[] = {{\n{}}};\nconst size_t {}_len = sizeof {};\n",
+            input_path, name, rows, name, name
+        ),
+    };
+
+    let written = if output_path == "-" { io::stdout().write_all(source.as_bytes()) } else { fs::write(output_path, source) };
+    if let Err(e) = written {
+        eprintln!("Error writing '{}': {}", output_path, e);
+        process::exit(1);
+    }
+}
 
-    // Read line table
-    let mut line_table = Vec::new();
-    if cursor + 8 <= raw_bytes.len() {
-        let line_count = u64::from_le_bytes(raw_bytes[cursor..cursor+8].try_into().unwrap()) as usize;
-        cursor += 8;
-        for _ in 0..line_count {
-            if cursor + 8 > raw_bytes.len() { break; }
-            let line = u64::from_le_bytes(raw_bytes[cursor..cursor+8].try_into().unwrap()) as usize;
-            line_table.push(line);
-            cursor += 8;
+fn bench_binary(input_path: &str, iterations: u32) {
+    let program = load_binary(input_path);
+    match bench(&program, iterations) {
+        Ok(report) => print!("{}", report.summary(8)),
+        Err(e) => {
+            eprintln!("Runtime Error: {}", e);
+            process::exit(1);
         }
     }
-    
-    println!("; Disassembly of '{}'", input_path);
-    println!("; Code size: {} bytes", code_size);
-    println!("");
-
-    let mut pc = 0;
-    let mut instr_idx = 0;
-    while pc < code_slice.len() {
-        match Instruction::decode(&code_slice[pc..]) {
-            Ok((instr, len)) => {
-                let line_info = if let Some(&line) = line_table.get(instr_idx) {
-                    format!("; line {}", line)
-                } else {
-                    "".to_string()
-                };
-                println!("{:04x}:  {:<30} {}", instr_idx, instr.to_assembly(), line_info);
-                pc += len;
-                instr_idx += 1;
-            }
-            Err(e) => {
-                eprintln!("Corrupt binary at offset {}: {}", pc, e);
-                process::exit(1);
-            }
+}
+
+fn profile_binary(input_path: &str, folded_path: Option<&str>, quiet: bool) {
+    let program = load_binary(input_path);
+    let profile = match profile(&program) {
+        Ok(profile) => profile,
+        Err(e) => {
+            eprintln!("Runtime Error: {}", e);
+            process::exit(1);
+        }
+    };
+    print!("{}", profile.report(&program, 8));
+    if let Some(path) = folded_path {
+        if let Err(e) = fs::write(path, profile.folded(&program)) {
+            eprintln!("Error writing {}: {}", path, e);
+            process::exit(1);
+        }
+        if !quiet {
+            println!("Folded stacks written to {}", path);
         }
     }
 }
 
-fn run_debugger(input_path: &str) {
+fn analyze_binary(input_path: &str) {
+    let program = load_binary(input_path);
+    print!("{}", analysis::analyze(&program).report(&program));
+}
+
+/// Read a binary into a program with its data and debug info.
+/// Exits with a message if the file is unreadable or malformed.
+fn load_binary(input_path: &str) -> Program {
     let raw_bytes = fs::read(input_path).unwrap_or_else(|e| {
         eprintln!("Error reading binary '{}': {}", input_path, e);
         process::exit(1);
     });
-
-    if raw_bytes.len() < 6 {
-        eprintln!("Binary too short");
+    let mut program = loader::load(&raw_bytes).unwrap_or_else(|e| {
+        eprintln!("Invalid binary '{}': {}", input_path, e);
         process::exit(1);
-    }
+    });
+    program.name = input_path.to_string();
+    program
+}
 
-    // New format parsing
-    if &raw_bytes[0..4] != b"ALYA" {
-        eprintln!("Invalid binary format");
-        process::exit(1);
-    }
+/// How `alya debug` takes its commands
+enum DebugMode {
+    Interactive,
+    Script(String),
+    Listen(String),
+}
 
-    let mut cursor = 6;
-
-    // Code
-    if cursor + 8 > raw_bytes.len() { process::exit(1); }
-    let code_size = u64::from_le_bytes(raw_bytes[cursor..cursor+8].try_into().unwrap()) as usize;
-    cursor += 8;
-
-    if cursor + code_size > raw_bytes.len() { process::exit(1); }
-    let code_slice = &raw_bytes[cursor..cursor+code_size];
-    cursor += code_size;
-
-    // Data
-    if cursor + 8 > raw_bytes.len() { process::exit(1); }
-    let data_size = u64::from_le_bytes(raw_bytes[cursor..cursor+8].try_into().unwrap()) as usize;
-    cursor += 8;
-
-    if cursor + data_size > raw_bytes.len() { process::exit(1); }
-    let data_slice = &raw_bytes[cursor..cursor+data_size];
-    cursor += data_size;
-
-    // Line Table
-    let mut line_table = Vec::new();
-    if cursor + 8 <= raw_bytes.len() {
-        let line_count = u64::from_le_bytes(raw_bytes[cursor..cursor+8].try_into().unwrap()) as usize;
-        cursor += 8;
-        for _ in 0..line_count {
-            if cursor + 8 > raw_bytes.len() { break; }
-            let line = u64::from_le_bytes(raw_bytes[cursor..cursor+8].try_into().unwrap()) as usize;
-            line_table.push(line);
-            cursor += 8;
-        }
-    }
+fn run_debugger(input_path: &str, mode: DebugMode) {
+    let program = load_binary(input_path);
+    let vm = VM::new();
 
-    let mut instructions = Vec::new();
-    let mut pc = 0;
-    while pc < code_slice.len() {
-        match Instruction::decode(&code_slice[pc..]) {
-            Ok((instr, len)) => {
-                instructions.push(instr);
-                pc += len;
+    match mode {
+        DebugMode::Interactive => {}
+        DebugMode::Script(script_path) => {
+            let script = fs::read_to_string(&script_path).unwrap_or_else(|e| {
+                eprintln!("Error reading script '{}': {}", script_path, e);
+                process::exit(1);
+            });
+            if let Err(e) = Debugger::new(vm).run_script(&program, &script) {
+                eprintln!("Debugger Error: {}", e);
+                process::exit(1);
             }
-            Err(e) => {
-                eprintln!("Corrupt binary: {}", e);
+            return;
+        }
+        DebugMode::Listen(addr) => {
+            let listener = TcpListener::bind(&addr).unwrap_or_else(|e| {
+                eprintln!("Cannot listen on {}: {}", addr, e);
+                process::exit(1);
+            });
+            eprintln!("Waiting for debugger client on {}", listener.local_addr().map(|a| a.to_string()).unwrap_or(addr));
+            if let Err(e) = remote::serve(&mut Debugger::new(vm), &program, &listener) {
+                eprintln!("Debugger Error: {}", e);
                 process::exit(1);
             }
+            return;
         }
     }
 
-    let mut program = Program::with_data(input_path, instructions, data_slice.to_vec());
-    program.line_table = line_table;
-    
-    let vm = VM::new();
-    let mut dbg = Debugger::new(vm);
-    
+    let session = Path::new(input_path).with_extension("alyadbg");
+    let mut dbg = Debugger::new(vm).with_session_file(session);
+
     if let Err(e) = dbg.run(&program) {
         eprintln!("Debugger Error: {}", e);
     }
 }
+
+fn connect_debugger(addr: &str) {
+    let (mut client, greeting) = RemoteClient::connect(addr).unwrap_or_else(|e| {
+        eprintln!("Cannot connect to {}: {}", addr, e);
+        process::exit(1);
+    });
+    print!("{}", greeting);
+
+    let mut prompt = Prompt::new();
+    while let Some(line) = prompt.read_line("(remote) ") {
+        match client.send(&line) {
+            Ok(reply) => print!("{}", reply),
+            Err(e) => {
+                eprintln!("Connection lost: {}", e);
+                process::exit(1);
+            }
+        }
+        if matches!(line.trim(), "quit" | "q") {
+            break;
+        }
+    }
+}
@@ -33,7 +33,7 @@ impl Address {
 
     /// Check if address is aligned to a boundary
     pub const fn is_aligned(self, alignment: usize) -> bool {
-        self.0 % alignment == 0
+        self.0.is_multiple_of(alignment)
     }
 }
 
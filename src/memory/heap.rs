@@ -51,16 +51,16 @@ impl Heap {
 
     fn read_block<M: MemoryAccess + ?Sized>(&self, memory: &M, addr: usize) -> Result<Block, MemoryError> {
         let mut bytes = [0u8; 24];
-        for i in 0..24 {
-            bytes[i] = memory.read_byte(addr + i)?;
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = memory.read_byte(addr + i)?;
         }
         Ok(Block::from_bytes(&bytes))
     }
 
     fn write_block<M: MemoryAccess + ?Sized>(&self, memory: &mut M, addr: usize, block: Block) -> Result<(), MemoryError> {
         let bytes = block.to_bytes();
-        for i in 0..24 {
-            memory.write_byte(addr + i, bytes[i])?;
+        for (i, &byte) in bytes.iter().enumerate() {
+            memory.write_byte(addr + i, byte)?;
         }
         Ok(())
     }
@@ -0,0 +1,115 @@
+//! Address space layout — where the heap and stack live in memory.
+//!
+//! The standard layout is fixed. With ASLR enabled, the heap start and the
+//! stack top are shifted by a seeded random amount each run, and the skipped
+//! bytes belong to no segment, so hardcoded addresses fault immediately.
+
+use crate::core::Rng;
+
+/// Start of the heap in the standard layout
+pub const HEAP_START: usize = 0x8000;
+/// Size of the heap in the standard layout
+pub const HEAP_SIZE: usize = 0x4000;
+/// Lowest address of the stack segment
+pub const STACK_LIMIT: usize = 0xC000;
+/// Largest shift ASLR may apply to a region base
+pub const ASLR_MAX_SHIFT: usize = 0x1000;
+/// Alignment of randomized region bases
+pub const ASLR_ALIGN: usize = 0x10;
+/// Smallest memory that leaves at least 4 KiB of stack under every layout
+pub const MIN_MEMORY_SIZE: usize = STACK_LIMIT + 2 * ASLR_MAX_SHIFT;
+
+/// Address space layout randomization mode
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Aslr {
+    /// Use the standard fixed layout
+    #[default]
+    Disabled,
+    /// Pick a fresh seed on every run
+    Random,
+    /// Use a fixed seed (reproduces a previous run's layout)
+    Seeded(u64),
+}
+
+/// Placement of the heap and stack regions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryLayout {
+    /// First byte of the heap
+    pub heap_start: usize,
+    /// Heap size in bytes
+    pub heap_size: usize,
+    /// Top of the stack (the stack grows downward from here)
+    pub stack_base: usize,
+}
+
+impl MemoryLayout {
+    /// Standard fixed layout for a memory of the given size
+    pub fn standard(memory_size: usize) -> Self {
+        Self {
+            heap_start: HEAP_START,
+            heap_size: HEAP_SIZE,
+            stack_base: memory_size,
+        }
+    }
+
+    /// Randomized layout derived from `seed`.
+    /// Both bases move by a nonzero, aligned amount of at most `ASLR_MAX_SHIFT`
+    /// and at most half their region, so each keeps at least half its space.
+    /// A stack with no room above `STACK_LIMIT` in a small memory stays put.
+    pub fn randomized(memory_size: usize, seed: u64) -> Self {
+        let mut rng = Rng::new(seed);
+        let heap_shift = random_shift(&mut rng, HEAP_SIZE / 2);
+        let stack_shift = random_shift(&mut rng, memory_size.saturating_sub(STACK_LIMIT) / 2);
+
+        Self {
+            heap_start: HEAP_START + heap_shift,
+            heap_size: HEAP_SIZE - heap_shift,
+            stack_base: memory_size - stack_shift,
+        }
+    }
+}
+
+/// Nonzero aligned shift of at most `ASLR_MAX_SHIFT` and `room`, or 0 when
+/// `room` is smaller than the alignment
+fn random_shift(rng: &mut Rng, room: usize) -> usize {
+    let slots = (room.min(ASLR_MAX_SHIFT) / ASLR_ALIGN) as u64;
+    if slots == 0 {
+        return 0;
+    }
+    (rng.next_below(slots) as usize + 1) * ASLR_ALIGN
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_randomized_layout_reproducible() {
+        let a = MemoryLayout::randomized(0x10000, 42);
+        let b = MemoryLayout::randomized(0x10000, 42);
+        assert_eq!(a, b);
+        assert_ne!(a, MemoryLayout::standard(0x10000));
+    }
+
+    #[test]
+    fn test_randomized_layout_bounds() {
+        for seed in 0..64 {
+            let layout = MemoryLayout::randomized(0x10000, seed);
+            assert!(layout.heap_start > HEAP_START);
+            assert_eq!(layout.heap_start + layout.heap_size, HEAP_START + HEAP_SIZE);
+            assert!(layout.stack_base < 0x10000);
+            assert!(layout.stack_base > STACK_LIMIT);
+            assert_eq!(layout.heap_start % ASLR_ALIGN, 0);
+            assert_eq!(layout.stack_base % ASLR_ALIGN, 0);
+        }
+    }
+
+    #[test]
+    fn test_randomized_layout_small_memory() {
+        for seed in 0..64 {
+            assert_eq!(MemoryLayout::randomized(0x1000, seed).stack_base, 0x1000);
+            let layout = MemoryLayout::randomized(STACK_LIMIT + 0x100, seed);
+            assert!(layout.stack_base >= STACK_LIMIT + 0x80 && layout.stack_base < STACK_LIMIT + 0x100);
+        }
+    }
+}
@@ -1,6 +1,7 @@
 //! Main memory manager implementation.
 
 use super::MemoryAccess;
+use super::layout::{MemoryLayout, HEAP_START, HEAP_SIZE, STACK_LIMIT};
 use std::fmt;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -18,10 +19,32 @@ pub struct Segment {
     pub permissions: u8, // Bitmask of MemoryPermission
 }
 
+/// A write that changed a watched memory range
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WatchHit {
+    /// Start of the watched range
+    pub address: usize,
+    /// Value of the range before the write (little-endian)
+    pub old: u64,
+    /// Value of the range after the write (little-endian)
+    pub new: u64,
+}
+
+/// Granularity of dirty tracking, in bytes
+pub const PAGE_SIZE: usize = 256;
+
 /// Main memory storage
 pub struct Memory {
     bytes: Vec<u8>,
     segments: Vec<Segment>,
+    /// Pages written since the last reset (indexed by address / PAGE_SIZE)
+    dirty: Vec<bool>,
+    /// Watched ranges as (start, len), len <= 8
+    watches: Vec<(usize, usize)>,
+    /// Changes to watched ranges not yet collected
+    watch_hits: Vec<WatchHit>,
+    /// Previous contents of written bytes as (address, old byte), when journaling
+    journal: Option<Vec<(usize, u8)>>,
 }
 
 impl Memory {
@@ -44,15 +67,15 @@ impl Memory {
             // Heap Segment (RW)
             segments.push(Segment {
                 name: "Heap".to_string(),
-                start: 0x8000,
-                end: 0xBFFF,
+                start: HEAP_START,
+                end: HEAP_START + HEAP_SIZE - 1,
                 permissions: MemoryPermission::Read as u8 | MemoryPermission::Write as u8,
             });
 
             // Stack Segment (RW)
             segments.push(Segment {
                 name: "Stack".to_string(),
-                start: 0xC000,
+                start: STACK_LIMIT,
                 end: size.saturating_sub(1),
                 permissions: MemoryPermission::Read as u8 | MemoryPermission::Write as u8,
             });
@@ -69,12 +92,174 @@ impl Memory {
         Self {
             bytes: vec![0; size],
             segments,
+            dirty: vec![false; size.div_ceil(PAGE_SIZE)],
+            watches: Vec::new(),
+            watch_hits: Vec::new(),
+            journal: None,
+        }
+    }
+
+    /// Move the heap and stack segment bounds to match a layout.
+    /// Has no effect on small memories, which use a single general segment.
+    pub fn apply_layout(&mut self, layout: &MemoryLayout) {
+        for segment in &mut self.segments {
+            match segment.name.as_str() {
+                "Heap" => {
+                    segment.start = layout.heap_start;
+                    segment.end = layout.heap_start + layout.heap_size - 1;
+                }
+                "Stack" => {
+                    segment.end = layout.stack_base.saturating_sub(1);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Mark the data section of a program: `0..rodata_end` read-only and
+    /// `rodata_end..data_end` writable, replacing the previous program's
+    pub fn set_data_segments(&mut self, rodata_end: usize, data_end: usize) {
+        self.segments.retain(|segment| segment.name != "Rodata" && segment.name != "Data");
+        // Segments are searched in order, so these take precedence
+        if data_end > rodata_end {
+            self.segments.insert(0, Segment {
+                name: "Data".to_string(),
+                start: rodata_end,
+                end: data_end - 1,
+                permissions: MemoryPermission::Read as u8 | MemoryPermission::Write as u8,
+            });
+        }
+        if rodata_end > 0 {
+            self.segments.insert(0, Segment {
+                name: "Rodata".to_string(),
+                start: 0,
+                end: rodata_end - 1,
+                permissions: MemoryPermission::Read as u8,
+            });
         }
     }
 
     /// Clear all memory (set to zero)
     pub fn clear(&mut self) {
         self.bytes.fill(0);
+        self.dirty.fill(false);
+    }
+
+    /// Zero only the pages written since the last reset or clear.
+    /// Much cheaper than `clear` when a short program touched little memory.
+    pub fn reset(&mut self) {
+        for (page, dirty) in self.dirty.iter_mut().enumerate() {
+            if *dirty {
+                let start = page * PAGE_SIZE;
+                let end = (start + PAGE_SIZE).min(self.bytes.len());
+                self.bytes[start..end].fill(0);
+                *dirty = false;
+            }
+        }
+    }
+
+    /// Number of pages written since the last reset
+    pub fn dirty_pages(&self) -> usize {
+        self.dirty.iter().filter(|&&d| d).count()
+    }
+
+    /// Watch `len` bytes (at most 8) starting at `addr` for changes
+    pub fn add_watch(&mut self, addr: usize, len: usize) {
+        let len = len.clamp(1, 8);
+        if !self.watches.contains(&(addr, len)) {
+            self.watches.push((addr, len));
+        }
+    }
+
+    /// Stop watching the range starting at `addr`
+    pub fn remove_watch(&mut self, addr: usize) {
+        self.watches.retain(|&(start, _)| start != addr);
+    }
+
+    /// Collect the watched-range changes recorded since the last call
+    pub fn take_watch_hits(&mut self) -> Vec<WatchHit> {
+        std::mem::take(&mut self.watch_hits)
+    }
+
+    /// Start recording the previous value of every byte written, for undo
+    pub fn enable_journal(&mut self) {
+        self.journal.get_or_insert_with(Vec::new);
+    }
+
+    /// Stop recording writes and drop any pending journal entries
+    pub fn disable_journal(&mut self) {
+        self.journal = None;
+    }
+
+    /// Collect the journal entries recorded since the last call, oldest first
+    pub fn take_journal(&mut self) -> Vec<(usize, u8)> {
+        self.journal.as_mut().map(std::mem::take).unwrap_or_default()
+    }
+
+    /// Whether writes are currently being journaled
+    pub fn is_journaling(&self) -> bool {
+        self.journal.is_some()
+    }
+
+    /// Journal entries recorded after the first `start`, without taking them
+    pub fn journal_since(&self, start: usize) -> &[(usize, u8)] {
+        self.journal.as_deref().map_or(&[], |journal| journal.get(start..).unwrap_or(&[]))
+    }
+
+    /// Restore bytes from journal entries, ignoring permissions
+    pub fn undo_journal(&mut self, entries: &[(usize, u8)]) {
+        for &(addr, old) in entries.iter().rev() {
+            self.bytes[addr] = old;
+            self.mark_dirty(addr, 1);
+        }
+    }
+
+    /// Save the bytes about to be overwritten if journaling
+    fn journal_write(&mut self, addr: usize, len: usize) {
+        if let Some(journal) = self.journal.as_mut() {
+            journal.extend((addr..addr + len).map(|a| (a, self.bytes[a])));
+        }
+    }
+
+    /// Read up to 8 bytes as a little-endian value, ignoring permissions
+    fn peek_raw(&self, addr: usize, len: usize) -> u64 {
+        let mut value = 0u64;
+        for i in 0..len {
+            let byte = self.bytes.get(addr + i).copied().unwrap_or(0);
+            value |= (byte as u64) << (8 * i);
+        }
+        value
+    }
+
+    /// Snapshot the watched ranges overlapping a pending write
+    fn watch_snapshot(&self, addr: usize, len: usize) -> Vec<(usize, usize, u64)> {
+        self.watches
+            .iter()
+            .filter(|&&(start, wlen)| start < addr + len && addr < start + wlen)
+            .map(|&(start, wlen)| (start, wlen, self.peek_raw(start, wlen)))
+            .collect()
+    }
+
+    /// Compare snapshots against current contents and record changes
+    fn record_watch_hits(&mut self, snapshot: Vec<(usize, usize, u64)>) {
+        for (start, wlen, old) in snapshot {
+            let new = self.peek_raw(start, wlen);
+            if new != old {
+                self.watch_hits.push(WatchHit { address: start, old, new });
+            }
+        }
+    }
+
+    /// Record that `addr..addr + len` has been written
+    fn mark_dirty(&mut self, addr: usize, len: usize) {
+        if len == 0 {
+            return;
+        }
+        let first = addr / PAGE_SIZE;
+        let last = (addr + len - 1) / PAGE_SIZE;
+        for page in first..=last {
+            self.dirty[page] = true;
+        }
     }
 
     /// Load program data into memory at address 0
@@ -87,6 +272,7 @@ impl Memory {
         }
 
         self.bytes[..data.len()].copy_from_slice(data);
+        self.mark_dirty(0, data.len());
         Ok(())
     }
 
@@ -128,6 +314,25 @@ impl Memory {
         })
     }
 
+    /// Read `size` bytes (at most 8) as a little-endian value
+    pub fn read_sized(&self, addr: usize, size: usize) -> Result<u64, MemoryError> {
+        self.check_access(addr, size, MemoryPermission::Read)?;
+        let mut buf = [0u8; 8];
+        buf[..size].copy_from_slice(&self.bytes[addr..addr + size]);
+        Ok(u64::from_le_bytes(buf))
+    }
+
+    /// Write the low `size` bytes (at most 8) of `value`, little-endian
+    pub fn write_sized(&mut self, addr: usize, size: usize, value: u64) -> Result<(), MemoryError> {
+        self.check_access(addr, size, MemoryPermission::Write)?;
+        let snapshot = self.watch_snapshot(addr, size);
+        self.journal_write(addr, size);
+        self.bytes[addr..addr + size].copy_from_slice(&value.to_le_bytes()[..size]);
+        self.mark_dirty(addr, size);
+        self.record_watch_hits(snapshot);
+        Ok(())
+    }
+
     /// Get a slice of memory for reading (checked)
     pub fn slice(&self, start: usize, len: usize) -> Result<&[u8], MemoryError> {
         self.check_access(start, len, MemoryPermission::Read)?;
@@ -143,7 +348,11 @@ impl MemoryAccess for Memory {
 
     fn write_byte(&mut self, addr: usize, value: u8) -> Result<(), MemoryError> {
         self.check_access(addr, 1, MemoryPermission::Write)?;
+        let snapshot = self.watch_snapshot(addr, 1);
+        self.journal_write(addr, 1);
         self.bytes[addr] = value;
+        self.mark_dirty(addr, 1);
+        self.record_watch_hits(snapshot);
         Ok(())
     }
 
@@ -159,12 +368,16 @@ impl MemoryAccess for Memory {
 
     fn write_qword(&mut self, addr: usize, value: u64) -> Result<(), MemoryError> {
         self.check_access(addr, 8, MemoryPermission::Write)?;
+        let snapshot = self.watch_snapshot(addr, 8);
+        self.journal_write(addr, 8);
 
         // Fast path: direct pointer access
         unsafe {
             let ptr = self.bytes.as_mut_ptr().add(addr) as *mut u64;
             std::ptr::write_unaligned(ptr, value.to_le());
         }
+        self.mark_dirty(addr, 8);
+        self.record_watch_hits(snapshot);
         Ok(())
     }
 
@@ -181,6 +394,7 @@ impl fmt::Debug for Memory {
 
 /// Memory-related errors
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum MemoryError {
     OutOfBounds { address: usize, size: usize },
     ProgramTooLarge { program_size: usize, memory_size: usize },
@@ -243,6 +457,49 @@ mod tests {
         assert_eq!(mem.read_byte(7).unwrap(), 0x01);
     }
 
+    #[test]
+    fn test_apply_layout_unmaps_gaps() {
+        let mut mem = Memory::new(0x10000);
+        let layout = MemoryLayout {
+            heap_start: HEAP_START + 0x100,
+            heap_size: HEAP_SIZE - 0x100,
+            stack_base: 0x10000 - 0x40,
+        };
+        mem.apply_layout(&layout);
+
+        assert!(mem.write_qword(HEAP_START, 1).is_err());
+        assert!(mem.write_qword(HEAP_START + 0x100, 1).is_ok());
+        assert!(mem.write_qword(0x10000 - 0x40, 1).is_err());
+        assert!(mem.write_qword(0x10000 - 0x48, 1).is_ok());
+    }
+
+    #[test]
+    fn test_reset_zeros_only_dirty_pages() {
+        let mut mem = Memory::new(4 * PAGE_SIZE);
+        mem.write_qword(PAGE_SIZE - 4, u64::MAX).unwrap(); // spans two pages
+        mem.write_byte(3 * PAGE_SIZE, 7).unwrap();
+        assert_eq!(mem.dirty_pages(), 3);
+
+        mem.reset();
+        assert_eq!(mem.dirty_pages(), 0);
+        assert_eq!(mem.read_qword(PAGE_SIZE - 4).unwrap(), 0);
+        assert_eq!(mem.read_byte(3 * PAGE_SIZE).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_watch_hits() {
+        let mut mem = Memory::new(256);
+        mem.add_watch(16, 8);
+
+        mem.write_qword(0, 1).unwrap(); // outside the watch
+        mem.write_byte(17, 0xAB).unwrap();
+        mem.write_byte(17, 0xAB).unwrap(); // unchanged, no hit
+
+        let hits = mem.take_watch_hits();
+        assert_eq!(hits, vec![WatchHit { address: 16, old: 0, new: 0xAB00 }]);
+        assert!(mem.take_watch_hits().is_empty());
+    }
+
     #[test]
     fn test_program_loading() {
         let mut mem = Memory::new(256);
@@ -252,4 +509,20 @@ mod tests {
         assert_eq!(mem.read_byte(0).unwrap(), 0x10);
         assert_eq!(mem.read_byte(3).unwrap(), 0x40);
     }
+
+    #[test]
+    fn test_journal_undo() {
+        let mut mem = Memory::new(256);
+        mem.write_qword(0, 7).unwrap();
+
+        mem.enable_journal();
+        mem.write_qword(0, 0x1122).unwrap();
+        mem.write_byte(0, 0xff).unwrap();
+        let entries = mem.take_journal();
+        assert_eq!(entries.len(), 9);
+
+        mem.undo_journal(&entries);
+        assert_eq!(mem.read_qword(0).unwrap(), 7);
+        assert!(mem.take_journal().is_empty());
+    }
 }
@@ -4,15 +4,18 @@
 //! - Main memory manager
 //! - Stack operations
 //! - Address validation
+//! - Address space layout (incl. ASLR)
 
 pub mod manager;
 pub mod heap;
 pub mod stack;
 pub mod address;
+pub mod layout;
 
-pub use manager::{Memory, MemoryError};
+pub use manager::{Memory, MemoryError, WatchHit};
 pub use stack::{Stack, StackError};
 pub use address::{Address, AddressError};
+pub use layout::{Aslr, MemoryLayout};
 
 /// Trait for memory operations (allows mocking in tests)
 pub trait MemoryAccess {
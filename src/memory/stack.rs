@@ -5,6 +5,7 @@ use std::fmt;
 
 /// Stack manager that operates on memory.
 /// The stack grows downward from the top of memory.
+#[derive(Debug, Clone)]
 pub struct Stack {
     pointer: usize,
     base: usize,
@@ -82,6 +83,7 @@ impl Stack {
 
 /// Stack-related errors
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum StackError {
     Overflow,
     Underflow,
@@ -0,0 +1,159 @@
+//! WebAssembly bindings for a browser playground (enabled with the `wasm` feature).
+//!
+//! JavaScript drives a `Playground`: assemble source, then `run` with a fuel
+//! budget or `step` one instruction at a time, reading registers, memory and
+//! captured output in between. Build with
+//! `wasm-pack build --target web -- --features wasm`.
+//!
+//! ```js
+//! const pg = new Playground();
+//! pg.assemble("@r0 := 42\nprint @r0\nhalt\n");
+//! while (!pg.run(10_000n)) { /* yield to the UI */ }
+//! console.log(pg.take_output(), pg.register(0));
+//! ```
+
+use wasm_bindgen::prelude::*;
+use crate::assembler;
+use crate::core::Register;
+use crate::error::{VmError, VmResult};
+use crate::execution::VM;
+use crate::instruction::Program;
+use crate::memory::MemoryAccess;
+
+fn to_js(e: VmError) -> JsError {
+    JsError::new(&e.to_string())
+}
+
+/// A VM plus the program loaded into it
+#[wasm_bindgen]
+pub struct Playground {
+    vm: VM,
+    program: Program,
+}
+
+impl Playground {
+    fn try_assemble(&mut self, source: &str) -> VmResult<()> {
+        self.program = assembler::assemble(source, "playground")?;
+        self.try_reset()
+    }
+
+    fn try_reset(&mut self) -> VmResult<()> {
+        self.vm.output.clear();
+        self.vm.init(&self.program)
+    }
+
+    fn try_run(&mut self, fuel: u64) -> VmResult<bool> {
+        for _ in 0..fuel {
+            if self.finished() {
+                break;
+            }
+            self.vm.step(&self.program)?;
+        }
+        Ok(self.finished())
+    }
+
+    fn try_step(&mut self) -> VmResult<bool> {
+        if !self.finished() {
+            self.vm.step(&self.program)?;
+        }
+        Ok(self.finished())
+    }
+
+    fn try_register(&self, code: u8) -> VmResult<u64> {
+        Ok(self.vm.ctx.get_reg(Register::from_u8(code)?))
+    }
+
+    fn try_read_memory(&self, addr: usize, len: usize) -> VmResult<Vec<u8>> {
+        (addr..addr.saturating_add(len))
+            .map(|a| self.vm.memory.read_byte(a).map_err(VmError::from))
+            .collect()
+    }
+}
+
+#[wasm_bindgen]
+impl Playground {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Playground {
+        let mut vm = VM::new();
+        vm.print_immediately = false;
+        Playground { vm, program: Program::new("playground") }
+    }
+
+    /// Assemble source and load it, ready to run from the start
+    pub fn assemble(&mut self, source: &str) -> Result<(), JsError> {
+        self.try_assemble(source).map_err(to_js)
+    }
+
+    /// Restart the loaded program and clear its output
+    pub fn reset(&mut self) -> Result<(), JsError> {
+        self.try_reset().map_err(to_js)
+    }
+
+    /// Execute at most `fuel` instructions. Returns true once the program has finished.
+    pub fn run(&mut self, fuel: u64) -> Result<bool, JsError> {
+        self.try_run(fuel).map_err(to_js)
+    }
+
+    /// Execute one instruction. Returns true once the program has finished.
+    pub fn step(&mut self) -> Result<bool, JsError> {
+        self.try_step().map_err(to_js)
+    }
+
+    /// Whether the program has halted or run off its end
+    pub fn finished(&self) -> bool {
+        self.vm.ctx.halted || self.vm.ctx.pc >= self.program.len()
+    }
+
+    /// Index of the next instruction
+    pub fn pc(&self) -> usize {
+        self.vm.ctx.pc
+    }
+
+    /// Source line of the next instruction, if known
+    pub fn line(&self) -> Option<usize> {
+        self.program.line_table.get(self.vm.ctx.pc).copied()
+    }
+
+    /// Read a register by its code (0–15 are r0–r15)
+    pub fn register(&self, code: u8) -> Result<u64, JsError> {
+        self.try_register(code).map_err(to_js)
+    }
+
+    /// All registers, indexed by register code
+    pub fn registers(&self) -> Vec<u64> {
+        self.vm.ctx.registers.to_vec()
+    }
+
+    /// Read `len` bytes of guest memory, honouring segment permissions
+    pub fn read_memory(&self, addr: usize, len: usize) -> Result<Vec<u8>, JsError> {
+        self.try_read_memory(addr, len).map_err(to_js)
+    }
+
+    /// Output printed since the last call, one line per print
+    pub fn take_output(&mut self) -> String {
+        self.vm.output.drain(..).map(|line| line + "\n").collect()
+    }
+}
+
+impl Default for Playground {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run_with_fuel() {
+        let mut pg = Playground::new();
+        pg.try_assemble("@r0 := 0\nloop:\n@r0 += 1\nif @r0 < 100 goto loop\nprint @r0\nhalt\n").unwrap();
+        assert!(!pg.try_run(10).unwrap());
+        assert!(pg.try_register(0).unwrap() < 10);
+        while !pg.try_run(50).unwrap() {}
+        assert_eq!(pg.take_output(), "100\n");
+        assert_eq!(pg.try_read_memory(0, 4).unwrap().len(), 4);
+        assert!(pg.try_register(99).is_err());
+    }
+}